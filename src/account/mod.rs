@@ -1,104 +1,625 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
 use tokio::sync::mpsc::Receiver;
-use crate::transaction::{Transaction, Type};
+use crate::amount::{self, Amount, ZERO};
+use crate::audit::{Balance, Entry, Outcome};
+use crate::logger;
+use crate::policy::{Action, Facts, Policy};
+use crate::report::Volumes;
+use crate::transaction::{DisputeState, Transaction, Type};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Error {
     FundsExceeded,
     DepositTooLow,
     OperationNotSupported,
     TxNotFound,
     AccountLocked,
+    AccountFrozen,
+    AccountNotFrozen,
+    AccountNotLocked,
     TxNotDisputed,
     TxAlreadyDisputed,
-    Handle(Account)
+    TxNotPending,
+    TxNotAuthorized,
+    TxNotCaptured,
+    TxNotRefundable,
+    RefundExceeded,
+    // The amount of a partial dispute exceeds what is left to dispute of the transaction.
+    DisputeExceeded,
+    // The amount of a partial dispute is zero or negative, which would hold nothing, or release
+    // funds that were never held.
+    DisputeNotPositive,
+    TxNotReversible,
+    TxNotChargedBack,
+    TxNotDisputable,
+    // The sequence number of the transaction is lower than the one of a transaction handled before.
+    OutOfSequence,
+    // A report row that could not be parsed.
+    InvalidRow,
+    // A rule of the policy rejected the transaction, or routed it to the dead letters.
+    PolicyRejected,
+    PolicyRouted,
+    // The tx id was already used by an earlier transaction, as remembered by the replay protection.
+    TxDuplicated,
+    // The transaction moves funds but has no amount, e.g. it was built without one by an
+    // embedding application.
+    MissingAmount,
+    // The amount of the transaction is negative, e.g. a withdrawal that would credit the account,
+    // which only the transactions built at runtime can have.
+    NegativeAmount,
+    // The transaction would take a balance of the account, or its total, out of range.
+    Overflow,
+    Handle(Box<Account>)
+}
+
+impl Error {
+    // Short reason of the error, as reported in the audit trail.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::FundsExceeded => "funds_exceeded",
+            Error::DepositTooLow => "insufficient_funds",
+            Error::OperationNotSupported => "operation_not_supported",
+            Error::TxNotFound => "tx_not_found",
+            Error::AccountLocked => "account_locked",
+            Error::AccountFrozen => "account_frozen",
+            Error::AccountNotFrozen => "account_not_frozen",
+            Error::AccountNotLocked => "account_not_locked",
+            Error::TxNotDisputed => "tx_not_disputed",
+            Error::TxAlreadyDisputed => "tx_already_disputed",
+            Error::TxNotPending => "tx_not_pending",
+            Error::TxNotAuthorized => "tx_not_authorized",
+            Error::TxNotCaptured => "tx_not_captured",
+            Error::TxNotRefundable => "tx_not_refundable",
+            Error::RefundExceeded => "refund_exceeded",
+            Error::DisputeExceeded => "dispute_exceeded",
+            Error::DisputeNotPositive => "dispute_not_positive",
+            Error::TxNotReversible => "tx_not_reversible",
+            Error::TxNotChargedBack => "tx_not_charged_back",
+            Error::TxNotDisputable => "tx_not_disputable",
+            Error::OutOfSequence => "out_of_sequence",
+            Error::InvalidRow => "invalid_row",
+            Error::PolicyRejected => "policy_rejected",
+            Error::PolicyRouted => "policy_routed",
+            Error::TxDuplicated => "tx_duplicated",
+            Error::MissingAmount => "missing_amount",
+            Error::NegativeAmount => "negative_amount",
+            Error::Overflow => "amount_overflow",
+            Error::Handle(account) => match account.status() {
+                Status::Closed => "account_closed",
+                _ => "account_locked"
+            }
+        }
+    }
+}
+
+// How restrictive an administrative freeze is.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FreezeMode {
+    // Funds can come in, but not go out.
+    #[default]
+    Withdrawals,
+    // Nothing but an `unfreeze` is accepted.
+    Full
+}
+
+// What a dispute does when the available funds can not cover the disputed amount, because
+// they were already spent.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SpentDisputePolicy {
+    // The dispute is rejected.
+    #[default]
+    Reject,
+    // The whole amount is held, and the available funds go negative.
+    AllowNegative,
+    // Only what is left of the available funds is held.
+    HoldRemaining
+}
+
+// Rules the account admins apply the transactions with, shared by all accounts of a run.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    pub freeze_mode: FreezeMode,
+    // Withdrawals are first moved to the pending bucket, until a `settle` finalizes them or a
+    // `cancel` returns them to the available funds.
+    pub two_step_withdrawals: bool,
+    // Number of further transactions of an account after which its uncaptured authorizations
+    // expire, releasing the held amount.
+    pub authorization_window: Option<u64>,
+    // Number of further transactions of an account after which the transactions whose dispute
+    // was settled are dropped from its history, to keep the memory of long runs flat. A charged
+    // back transaction can not be represented anymore once dropped.
+    pub settled_window: Option<u64>,
+    // Number of seconds, as of the account clock, after which the transactions are moved from the
+    // history to the archive, if the account admin has one. Transactions with an ongoing dispute,
+    // withdrawal or authorization are kept until they settle, and archived transactions can not
+    // be disputed anymore.
+    pub archive_age: Option<u64>,
+    // A successful representment unlocks the account.
+    pub unlock_on_representment: bool,
+    // A resolved transaction can be disputed again.
+    pub redisputes: bool,
+    // The `unlock` administrative records lift the lock of the charged back accounts, which the
+    // classic semantics keep locked for good.
+    pub admin_unlock: bool,
+    // Disputes with one of these reason codes lock the account right away.
+    pub lock_reasons: Vec<String>,
+    // Number of seconds after which the open disputes are resolved automatically.
+    pub dispute_timeout: Option<u64>,
+    pub spent_dispute_policy: SpentDisputePolicy,
+    // Follow the classic payments engine semantics: only deposits can be disputed, disputes can
+    // make the available funds negative and locked accounts ignore everything.
+    pub spec: bool,
+    // Acceptance rules every transaction is screened with, before being applied.
+    pub policy: Option<Arc<Policy>>,
+}
+
+impl Rules {
+    fn spent_dispute_policy(&self) -> SpentDisputePolicy {
+        if self.spec {
+            SpentDisputePolicy::AllowNegative
+        } else {
+            self.spent_dispute_policy
+        }
+    }
+}
+
+// Lifecycle state of an account. Each status comes with the set of operations it allows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Status {
+    Active,
+    // Administratively frozen, until unfrozen.
+    Frozen(FreezeMode),
+    // Locked as a consequence of a chargeback, until the chargeback is represented. The open
+    // disputes can still be settled.
+    Locked,
+    Closed
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Active => "active",
+            Status::Frozen(_) => "frozen",
+            Status::Locked => "locked",
+            Status::Closed => "closed"
+        }
+    }
+
+    // Parses a status as written by `as_str`, a plain `frozen` being a freeze of the withdrawals.
+    // The freeze modes can be told apart as `frozen-withdrawals` and `frozen-full`.
+    pub fn parse(value: &str) -> Option<Status> {
+        match value {
+            "active" => Some(Status::Active),
+            "frozen" | "frozen-withdrawals" => Some(Status::Frozen(FreezeMode::Withdrawals)),
+            "frozen-full" => Some(Status::Frozen(FreezeMode::Full)),
+            "locked" => Some(Status::Locked),
+            "closed" => Some(Status::Closed),
+            _ => None
+        }
+    }
+
+    // The allowed operations matrix.
+    pub fn allows(&self, ttype: &Type) -> bool {
+        match self {
+            Status::Active => *ttype != Type::Unfreeze,
+            Status::Frozen(FreezeMode::Withdrawals) => *ttype != Type::Withdrawal,
+            Status::Frozen(FreezeMode::Full) => matches!(ttype, Type::Freeze | Type::Unfreeze),
+            Status::Locked => matches!(ttype, Type::Resolve | Type::Chargeback | Type::Representment | Type::Unlock),
+            Status::Closed => false
+        }
+    }
+
+    // Terminal statuses do not allow any operation, ever again.
+    pub fn is_terminal(&self) -> bool {
+        *self == Status::Closed
+    }
+
+    // Rank of the status by the operations it blocks, the terminal one ranking last.
+    fn restrictiveness(&self) -> u8 {
+        match self {
+            Status::Active => 0,
+            Status::Frozen(FreezeMode::Withdrawals) => 1,
+            Status::Frozen(FreezeMode::Full) => 2,
+            Status::Locked => 3,
+            Status::Closed => 4
+        }
+    }
+}
+
+// What applying a transaction would do to its account: the balances and the status before and
+// after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectedEffect {
+    pub before: Balance,
+    pub after: Balance,
+    pub from: Status,
+    pub to: Status,
+}
+
+impl ProjectedEffect {
+    // Whether the transaction changes the status of the account, e.g. locks it.
+    pub fn transitions(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+// A dispute that was neither resolved, nor charged back yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dispute {
+    pub tx_id: u32,
+    pub amount: Amount,
+    pub reason: Option<String>,
+    // Time of the account clock when the dispute was opened, if the inputs have timestamps.
+    pub opened_at: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Account {
     client_id: u16,
-    available: f64,
-    held: f64,
-    locked: bool
+    available: Amount,
+    held: Amount,
+    pending: Amount,
+    status: Status,
+    tenant: Option<String>,
+    // Open disputes, in the order they were opened.
+    disputes: Vec<Dispute>,
+    // Deepest the available funds ever went below zero, as a positive amount.
+    max_exposure: Amount,
+    // Number of transactions handled for the account, rejected ones included.
+    transactions: u64,
+    rejected: u64,
+    volumes: Volumes
 }
 
 impl Account {
-    pub fn new(client_id: u16, available: f64, held: f64, locked: bool) -> Self {
-        Account {client_id, available, held, locked}
+    pub fn new(client_id: u16, available: Amount, held: Amount, status: Status) -> Self {
+        Account {client_id, available, held, pending: ZERO, status, tenant: None, disputes: Vec::new(),
+                 max_exposure: ZERO, transactions: 0, rejected: 0, volumes: Volumes::default()}
     }
 
-    pub fn new_unlocked(client_id: u16, available: f64, held: f64) -> Self {
-        Account {client_id, available, held, locked: false}
+    pub fn new_unlocked(client_id: u16, available: Amount, held: Amount) -> Self {
+        Account::new(client_id, available, held, Status::Active)
     }
 
     pub fn client_id(&self) -> u16 {
         self.client_id
     }
 
-    pub fn available(&self) -> f64 {
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn set_tenant(&mut self, tenant: Option<String>) {
+        self.tenant = tenant;
+    }
+
+    pub fn available(&self) -> Amount {
         self.available
     }
 
-    pub fn held(&self) -> f64 {
+    pub fn held(&self) -> Amount {
         self.held
     }
 
-    pub fn total(&self) -> f64 {
-        self.available + self.held
+    // Funds of withdrawals that were not settled yet.
+    pub fn pending(&self) -> Amount {
+        self.pending
+    }
+
+    // Pending funds did not leave the account yet, so they are part of the total.
+    pub fn total(&self) -> Amount {
+        self.available + self.held + self.pending
+    }
+
+    pub fn balance(&self) -> Balance {
+        Balance { available: self.available, held: self.held, total: self.total() }
     }
 
-    pub fn add_available(&mut self, amount: f64) -> Result<()> {
-        self.available += amount;
+    // Sets the balances, unless one of them, or their total, is out of range.
+    fn set_balances(&mut self, available: Option<Amount>, held: Option<Amount>, pending: Option<Amount>) -> Result<()> {
+        let (available, held, pending) = match (available, held, pending) {
+            (Some(available), Some(held), Some(pending)) => (available, held, pending),
+            _ => return Err(Error::Overflow)
+        };
+        available.checked_add(held).and_then(|total| total.checked_add(pending)).ok_or(Error::Overflow)?;
+        self.available = available;
+        self.held = held;
+        self.pending = pending;
         Ok(())
     }
 
-    pub fn sub_available(&mut self, amount: f64) -> Result<()> {
+    pub fn add_available(&mut self, amount: Amount) -> Result<()> {
+        self.set_balances(self.available.checked_add(amount), Some(self.held), Some(self.pending))
+    }
+
+    // Takes the amount out of the available funds, even if they go negative.
+    pub fn debit_available(&mut self, amount: Amount) -> Result<()> {
+        let available = self.available.checked_sub(amount);
+        let exposure = available.and_then(|available| ZERO.checked_sub(available)).ok_or(Error::Overflow)?;
+        self.set_balances(available, Some(self.held), Some(self.pending))?;
+        self.max_exposure = self.max_exposure.max(exposure);
+        Ok(())
+    }
+
+    pub fn transactions(&self) -> u64 {
+        self.transactions
+    }
+
+    // Number of transactions handled for the account that were rejected.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    pub fn volumes(&self) -> &Volumes {
+        &self.volumes
+    }
+
+    pub fn max_exposure(&self) -> Amount {
+        self.max_exposure
+    }
+
+    // Absorbs the funds, the open disputes and the counters of another account, which keeps the
+    // most restrictive of the two statuses. The account is left as it was if the funds would not
+    // fit in it.
+    pub fn merge(&mut self, other: Account) -> Result<()> {
+        let available = self.available.checked_add(other.available);
+        let exposure = available.and_then(|available| ZERO.checked_sub(available)).ok_or(Error::Overflow)?;
+        self.set_balances(available, self.held.checked_add(other.held), self.pending.checked_add(other.pending))?;
+        if other.status.restrictiveness() > self.status.restrictiveness() {
+            self.status = other.status;
+        }
+        self.disputes.extend(other.disputes);
+        self.max_exposure = self.max_exposure.max(other.max_exposure).max(exposure);
+        self.transactions += other.transactions;
+        self.rejected += other.rejected;
+        self.volumes.merge(&other.volumes);
+        Ok(())
+    }
+
+    // Whether the available funds are negative, or ever were.
+    pub fn is_exposed(&self) -> bool {
+        self.max_exposure > ZERO
+    }
+
+    pub fn sub_available(&mut self, amount: Amount) -> Result<()> {
         if self.available < amount {
             return Err(Error::DepositTooLow)
         }
 
-        self.available -= amount;
-        Ok(())
+        self.set_balances(self.available.checked_sub(amount), Some(self.held), Some(self.pending))
     }
 
-    pub fn add_held(&mut self, amount: f64) -> Result<()> {
-        self.held += amount;
-        Ok(())
+    pub fn add_held(&mut self, amount: Amount) -> Result<()> {
+        self.set_balances(Some(self.available), self.held.checked_add(amount), Some(self.pending))
     }
 
-    pub fn sub_held(&mut self, amount: f64) -> Result<()> {
+    pub fn sub_held(&mut self, amount: Amount) -> Result<()> {
         if self.held < amount {
             return Err(Error::DepositTooLow)
         }
 
-        self.held -= amount;
-        Ok(())
+        self.set_balances(Some(self.available), self.held.checked_sub(amount), Some(self.pending))
+    }
+
+    pub fn add_pending(&mut self, amount: Amount) -> Result<()> {
+        self.set_balances(Some(self.available), Some(self.held), self.pending.checked_add(amount))
+    }
+
+    pub fn sub_pending(&mut self, amount: Amount) -> Result<()> {
+        if self.pending < amount {
+            return Err(Error::DepositTooLow)
+        }
+
+        self.set_balances(Some(self.available), Some(self.held), self.pending.checked_sub(amount))
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: Status) {
+        self.status = status;
     }
 
     pub fn is_locked(&self) -> bool {
-        self.locked
+        self.status == Status::Locked
     }
 
     pub fn set_locked(&mut self, locked: bool) {
-        self.locked = locked;
+        self.status = if locked { Status::Locked } else { Status::Active };
+    }
+
+    pub fn open_disputes(&self) -> &[Dispute] {
+        &self.disputes
+    }
+
+    // Restores a dispute that was open when the account was saved to a state file.
+    pub fn restore_dispute(&mut self, dispute: Dispute) {
+        self.disputes.push(dispute);
+    }
+
+    // Restores the exposure of an account saved to a state file.
+    pub fn restore_max_exposure(&mut self, max_exposure: Amount) {
+        self.max_exposure = self.max_exposure.max(max_exposure);
+    }
+
+    fn close_dispute(&mut self, tx_id: u32) {
+        self.disputes.retain(|dispute| dispute.tx_id != tx_id);
+    }
+}
+
+// Writes the account as a `client,available,held,total,locked,status` report row. The pending
+// funds are accounted for in the total only.
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{},{},{}", self.client_id, amount::fmt(self.available), amount::fmt(self.held),
+               amount::fmt(self.total()), self.is_locked(), self.status.as_str())
+    }
+}
+
+// Parses a report row, the inverse of `Display`. The `status` column can be left out, as in the
+// spec mode rows, in which case the account is either locked or active.
+impl FromStr for Account {
+    type Err = Error;
+
+    fn from_str(row: &str) -> Result<Account> {
+        let fields: Vec<&str> = row.trim_end_matches(&['\r', '\n'][..]).split(',').collect();
+        if fields.len() != 5 && fields.len() != 6 {
+            return Err(Error::InvalidRow);
+        }
+        let client_id = fields[0].parse().map_err(|_| Error::InvalidRow)?;
+        let available = amount::parse(fields[1]).ok_or(Error::InvalidRow)?;
+        let held = amount::parse(fields[2]).ok_or(Error::InvalidRow)?;
+        amount::parse(fields[3]).ok_or(Error::InvalidRow)?;
+        let locked: bool = fields[4].parse().map_err(|_| Error::InvalidRow)?;
+        let status = match fields.get(5) {
+            Some(status) => Status::parse(status).ok_or(Error::InvalidRow)?,
+            None if locked => Status::Locked,
+            None => Status::Active
+        };
+        Ok(Account::new(client_id, available, held, status))
     }
 }
 
 pub struct AccountAdmin {
     account: Account,
     tx_history: HashMap<u32, Transaction>,
-    receiver: Receiver<Transaction>
+    receiver: Receiver<Transaction>,
+    // Priority lane of the administrative operations, received ahead of the transactions queued
+    // on the receiver.
+    control: Option<Receiver<Transaction>>,
+    rules: Rules,
+    // Number of transactions applied so far.
+    applied: u64,
+    // Uncaptured authorizations, in the order they were applied, with the value of `applied`
+    // at that moment.
+    authorizations: VecDeque<(u64, u32)>,
+    // Transactions whose dispute was settled, in the order they were settled, with the value of
+    // `applied` at that moment.
+    settled: VecDeque<(u64, u32)>,
+    // Transactions of the history with a timestamp, in the order they were applied, along with
+    // the time they become old enough to be archived.
+    archivable: VecDeque<(u64, u32)>,
+    archive: Option<std::sync::mpsc::Sender<Transaction>>,
+    audit: Option<Sender<Entry>>,
+    // Details about the transaction being applied, for the audit trail.
+    note: Option<String>,
+    // Latest timestamp of the transactions applied so far. Time only moves forward, so
+    // transactions that are late do not take it back.
+    clock: Option<u64>,
+    // Highest sequence number of the transactions handled so far, when the inputs number them.
+    sequence: Option<u64>
 }
 
 impl AccountAdmin {
     pub fn new(id: u16, receiver: Receiver<Transaction>) -> AccountAdmin {
         AccountAdmin {
-            account: Account::new_unlocked(id, 0.0, 0.0),
+            account: Account::new_unlocked(id, ZERO, ZERO),
             tx_history: HashMap::new(),
-            receiver
+            receiver,
+            control: None,
+            rules: Rules::default(),
+            applied: 0,
+            authorizations: VecDeque::new(),
+            settled: VecDeque::new(),
+            archivable: VecDeque::new(),
+            archive: None,
+            audit: None,
+            note: None,
+            clock: None,
+            sequence: None
+        }
+    }
+
+    pub fn new_with_tenant(id: u16, tenant: Option<String>, receiver: Receiver<Transaction>) -> AccountAdmin {
+        let mut account_admin = AccountAdmin::new(id, receiver);
+        account_admin.account.set_tenant(tenant);
+        account_admin
+    }
+
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+    }
+
+    // Whether the policy may route transactions to the dead letters.
+    pub fn may_route(&self) -> bool {
+        self.rules.policy.as_ref().is_some_and(|policy| policy.routes())
+    }
+
+    // Resumes the account and the history saved to a state file. The rules and the archive must be
+    // set before, as the history is remembered with respect to them. The windows counted in
+    // transactions start over, while the account clock resumes from the latest time of the history.
+    pub fn restore(&mut self, account: Account, history: Vec<Transaction>) {
+        self.account = account;
+        for tx in history {
+            let at = tx.timestamp();
+            if tx.is_authorized() {
+                self.authorizations.push_back((self.applied, tx.tx_id()));
+            }
+            let charged_back = tx.is_charged_back() && tx.disputable_amount() == ZERO;
+            if tx.is_resolved() || charged_back || tx.is_represented() {
+                self.settle(tx.tx_id());
+            }
+            self.remember(tx);
+            self.clock = self.clock.max(at);
         }
+        let opened_at = self.account.disputes.iter().filter_map(|dispute| dispute.opened_at).max();
+        self.clock = self.clock.max(opened_at);
+    }
+
+    // What applying the transaction would do, as of the account and the history so far, returning
+    // the error it would be rejected with otherwise. Nothing is committed: the transaction is applied
+    // to a copy of the account admin, without its audit trail and archive.
+    pub fn evaluate(&self, tx: &Transaction) -> Result<ProjectedEffect> {
+        let (_, receiver) = tokio::sync::mpsc::channel(1);
+        let mut scratch = AccountAdmin {
+            account: self.account.clone(),
+            tx_history: self.tx_history.clone(),
+            receiver,
+            control: None,
+            rules: self.rules.clone(),
+            applied: self.applied,
+            authorizations: self.authorizations.clone(),
+            settled: self.settled.clone(),
+            archivable: VecDeque::new(),
+            archive: None,
+            audit: None,
+            note: None,
+            clock: self.clock,
+            sequence: self.sequence
+        };
+        let after = scratch.apply(tx.clone())?;
+        Ok(ProjectedEffect { before: self.account.balance(), after: after.balance(), from: self.account.status(),
+                             to: after.status() })
+    }
+
+    // Takes the history out of the account admin, in the order the transactions were read.
+    pub fn take_history(&mut self) -> Vec<Transaction> {
+        let mut history: Vec<Transaction> = self.tx_history.drain().map(|(_, tx)| tx).collect();
+        history.sort_by_key(|tx| (tx.record(), tx.tx_id()));
+        history
+    }
+
+    pub fn set_control(&mut self, control: Receiver<Transaction>) {
+        self.control = Some(control);
+    }
+
+    // Every transaction applied from now on is recorded on the audit trail.
+    pub fn set_audit(&mut self, audit: Sender<Entry>) {
+        self.audit = Some(audit);
+    }
+
+    // The transactions older than the archive age are moved from the history to the archive.
+    pub fn set_archive(&mut self, archive: std::sync::mpsc::Sender<Transaction>) {
+        self.archive = Some(archive);
     }
 
     pub fn id(&self) -> u16 { self.account.client_id() }
@@ -107,100 +628,691 @@ impl AccountAdmin {
         &self.account
     }
 
-    pub async fn  handle(&mut self) -> Result<&Account> {
-        match self.receiver.recv().await {
-            Some(tx) => {
-                let tx_type = tx.transaction_type().clone();
-                let tx_id = tx.tx_id();
-                match tx_type {
-                    Type::Deposit => {
-                        if self.account.is_locked() {
-                            return Err(Error::Handle(self.account().clone()));
+    // Waits for the next transaction, returning `None` once the channels are closed. The
+    // transactions of the priority lane come first.
+    pub async fn recv(&mut self) -> Option<Transaction> {
+        let control = match &mut self.control {
+            Some(control) => control,
+            None => return self.receiver.recv().await
+        };
+        tokio::select! {
+            biased;
+            Some(tx) = control.recv() => Some(tx),
+            tx = self.receiver.recv() => match tx {
+                Some(tx) => Some(tx),
+                None => control.recv().await
+            }
+        }
+    }
+
+    // Stops accepting transactions, returning those that were still queued, the ones of the
+    // priority lane first.
+    pub async fn close(&mut self) -> Vec<Transaction> {
+        let mut queued = Vec::new();
+        if let Some(control) = &mut self.control {
+            control.close();
+            while let Some(tx) = control.recv().await {
+                queued.push(tx);
+            }
+        }
+        self.receiver.close();
+        while let Some(tx) = self.receiver.recv().await {
+            queued.push(tx);
+        }
+        queued
+    }
+
+    pub async fn handle(&mut self) -> Result<&Account> {
+        match self.recv().await {
+            Some(tx) => self.apply(tx),
+            None => Err(Error::Handle(Box::new(self.account().clone())))
+        }
+    }
+
+    // Releases the held amount of the authorizations that were not captured in time.
+    fn expire_authorizations(&mut self) {
+        let window = match self.rules.authorization_window {
+            Some(window) => window,
+            None => return
+        };
+
+        while let Some(&(applied_at, tx_id)) = self.authorizations.front() {
+            if self.applied - applied_at <= window {
+                break;
+            }
+
+            self.authorizations.pop_front();
+            // Captured authorizations are plain deposits already.
+            if self.tx_history.get(&tx_id).is_some_and(|tx| tx.is_authorized()) {
+                let authorization = self.tx_history.remove(&tx_id).unwrap();
+                let _ = self.account.sub_held(authorization.amount().unwrap());
+            }
+        }
+    }
+
+    fn settle(&mut self, tx_id: u32) {
+        if self.rules.settled_window.is_some() {
+            self.settled.push_back((self.applied, tx_id));
+        }
+    }
+
+    // Drops the transactions settled for longer than the settled window from the history,
+    // returning how many were dropped. It runs before every transaction, but can be called
+    // explicitly too.
+    pub fn collect_settled(&mut self) -> usize {
+        let window = match self.rules.settled_window {
+            Some(window) => window,
+            None => return 0
+        };
+
+        let mut collected = 0;
+        while let Some(&(settled_at, tx_id)) = self.settled.front() {
+            if self.applied - settled_at <= window {
+                break;
+            }
+
+            self.settled.pop_front();
+            let settled = self.tx_history.get(&tx_id)
+                .is_some_and(|tx| tx.is_resolved() || tx.is_charged_back() || tx.is_represented());
+            if settled {
+                let tx = self.tx_history.remove(&tx_id).unwrap();
+                self.account.volumes.collected.add(tx.amount().unwrap_or(ZERO));
+                collected += 1;
+            }
+        }
+        collected
+    }
+
+    fn remember(&mut self, tx: Transaction) {
+        if let (Some(age), Some(_), Some(timestamp)) = (self.rules.archive_age, &self.archive, tx.timestamp()) {
+            self.archivable.push_back((timestamp.saturating_add(age), tx.tx_id()));
+        }
+        self.tx_history.insert(tx.tx_id(), tx);
+    }
+
+    // Moves the transactions older than the archive age, as of the account clock, from the
+    // history to the archive, returning how many were moved. It runs before every transaction,
+    // but can be called explicitly too.
+    pub fn archive_old(&mut self) -> usize {
+        let (archive, now) = match (&self.archive, self.clock) {
+            (Some(archive), Some(now)) => (archive, now),
+            _ => return 0
+        };
+
+        let mut archived = 0;
+        let mut busy = Vec::new();
+        while let Some(&(archive_at, tx_id)) = self.archivable.front() {
+            if archive_at >= now {
+                break;
+            }
+
+            self.archivable.pop_front();
+            let quiet = match self.tx_history.get(&tx_id) {
+                Some(tx) => !(tx.is_disputed() || tx.is_pending() || tx.is_authorized()),
+                // Reversed or collected already.
+                None => continue
+            };
+            if !quiet {
+                busy.push(tx_id);
+                continue;
+            }
+            let tx = self.tx_history.remove(&tx_id).unwrap();
+            // Without its archive, the run keeps the history it could not archive.
+            if let Err(err) = archive.send(tx) {
+                self.tx_history.insert(tx_id, err.0);
+                self.archive = None;
+                break;
+            }
+            archived += 1;
+        }
+        // Busy transactions are looked at again once another archive age went by.
+        if let Some(age) = self.rules.archive_age {
+            self.archivable.extend(busy.into_iter().map(|tx_id| (now.saturating_add(age), tx_id)));
+        }
+        archived
+    }
+
+    // Resolves the disputes that were open for longer than the dispute timeout, as of the account
+    // clock, releasing their held funds, or dropping the ones credited by the dispute of a
+    // withdrawal. The entries are attributed to the record being handled.
+    fn expire_disputes(&mut self, record: Option<u64>, source: Option<&str>) {
+        let (timeout, now) = match (self.rules.dispute_timeout, self.clock) {
+            (Some(timeout), Some(now)) => (timeout, now),
+            _ => return
+        };
+
+        let stale: Vec<Dispute> = self.account.disputes.iter()
+            .filter(|dispute| dispute.opened_at.is_some_and(|opened_at| now - opened_at > timeout))
+            .cloned()
+            .collect();
+        for dispute in stale {
+            let before = self.account.balance();
+            if self.account.sub_held(dispute.amount).is_err() {
+                continue;
+            }
+            let withdrawal = self.tx_history.get(&dispute.tx_id)
+                .is_some_and(|disputed_tx| disputed_tx.transaction_type() == Type::Withdrawal);
+            if !withdrawal {
+                let _ = self.account.add_available(dispute.amount);
+            }
+            self.account.close_dispute(dispute.tx_id);
+            self.account.volumes.disputes_resolved.add(dispute.amount);
+            if let Some(disputed_tx) = self.tx_history.get_mut(&dispute.tx_id) {
+                disputed_tx.mark_resolved();
+            }
+            self.settle(dispute.tx_id);
+
+            let _span = logger::enter_tx(dispute.tx_id, Type::Resolve.as_str());
+            log::debug!("Auto-resolved the dispute, open for {} seconds.", now - dispute.opened_at.unwrap());
+            if let Some(audit) = &self.audit {
+                let _ = audit.send(Entry {
+                    tenant: self.account.tenant.clone(),
+                    client_id: self.account.client_id,
+                    tx_id: dispute.tx_id,
+                    ttype: Type::Resolve,
+                    amount: Some(dispute.amount),
+                    outcome: Outcome::Applied,
+                    note: Some(String::from("auto_resolved")),
+                    before,
+                    after: self.account.balance(),
+                    timestamp: self.clock,
+                    locked: false,
+                    previous_status: self.account.status,
+                    status: self.account.status,
+                    record,
+                    source: source.map(String::from)
+                });
+            }
+        }
+    }
+
+    // Accounts an applied transaction in the volumes. The dispute lifecycle amounts are the
+    // moves of held funds.
+    fn record_volume(&mut self, ttype: &Type, amount: Option<Amount>, before: &Balance) {
+        let held = self.account.held - before.held;
+        let volumes = &mut self.account.volumes;
+        match ttype {
+            Type::Deposit => volumes.deposits.add(amount.unwrap_or(ZERO)),
+            Type::Withdrawal => volumes.withdrawals.add(amount.unwrap_or(ZERO)),
+            Type::Dispute => volumes.disputes_opened.add(held),
+            Type::Resolve => volumes.disputes_resolved.add(-held),
+            Type::Chargeback => volumes.chargebacks.add(-held),
+            Type::Representment => volumes.representments.add(self.account.available - before.available),
+            Type::Fee => volumes.fees.add(amount.unwrap_or(ZERO)),
+            Type::Credit | Type::Debit => volumes.adjustments.add(self.account.available - before.available),
+            _ => {}
+        }
+    }
+
+    // Applies a transaction on top of the account, recording the outcome on the audit trail.
+    pub fn apply(&mut self, tx: Transaction) -> Result<&Account> {
+        self.applied += 1;
+        self.expire_authorizations();
+        self.collect_settled();
+        if let Some(timestamp) = tx.timestamp() {
+            self.clock = Some(self.clock.map_or(timestamp, |clock| clock.max(timestamp)));
+        }
+        self.expire_disputes(tx.record(), tx.source());
+        self.archive_old();
+
+        let (ttype, tx_id, amount, record) = (tx.transaction_type(), tx.tx_id(), tx.amount(), tx.record());
+        let source = tx.source().map(String::from);
+        let before = self.account.balance();
+        let (was_locked, previous_status) = (self.account.is_locked(), self.account.status);
+        let balances = (self.account.available, self.account.held, self.account.pending);
+        self.account.transactions += 1;
+        // The transactions of a client are handled in the order they are numbered in, ties
+        // included, so applying one that comes after a higher number would reorder them.
+        let result = match (tx.sequence(), self.sequence) {
+            (Some(sequence), Some(latest)) if sequence < latest => Err(Error::OutOfSequence),
+            (sequence, latest) => {
+                self.sequence = sequence.max(latest);
+                let screened = if tx.is_replayed() { Err(Error::TxDuplicated) } else { self.screen(&tx) };
+                screened.and_then(|flagged| {
+                    let result = self.execute(tx);
+                    if let Some(flagged) = flagged {
+                        self.note = Some(match self.note.take() {
+                            Some(note) => format!("{}; {}", flagged, note),
+                            None => flagged
+                        });
+                    }
+                    result
+                })
+            }
+        };
+        // A transaction moving funds in several steps may overflow after the first ones.
+        if let Err(Error::Overflow) = result {
+            (self.account.available, self.account.held, self.account.pending) = balances;
+        }
+        match &result {
+            Ok(()) => self.record_volume(&ttype, amount, &before),
+            Err(_) => self.account.rejected += 1
+        }
+        let note = self.note.take();
+        if let Some(audit) = &self.audit {
+            let outcome = match &result {
+                Ok(()) => Outcome::Applied,
+                Err(err) => Outcome::Rejected(err.as_str())
+            };
+            // A failing audit trail is reported by the driver, once the run is over.
+            let _ = audit.send(Entry {
+                tenant: self.account.tenant.clone(),
+                client_id: self.account.client_id,
+                tx_id,
+                ttype,
+                amount,
+                outcome,
+                note,
+                before,
+                after: self.account.balance(),
+                timestamp: self.clock,
+                locked: !was_locked && self.account.is_locked(),
+                previous_status,
+                status: self.account.status,
+                record,
+                source
+            });
+        }
+
+        result.map(move |_| self.account())
+    }
+
+    // Evaluates the policy rules for the transaction, returning the note about the rules that
+    // flagged it, if any.
+    fn screen(&mut self, tx: &Transaction) -> Result<Option<String>> {
+        let policy = match &self.rules.policy {
+            Some(policy) => policy,
+            None => return Ok(None)
+        };
+        let volumes = &self.account.volumes;
+        let verdict = policy.evaluate(&Facts {
+            ttype: &tx.transaction_type(), tenant: self.account.tenant.as_deref(), client_id: self.account.client_id,
+            attributes: tx.attributes(), amount: tx.amount(), deposited: volumes.deposits.amount, withdrawn: volumes.withdrawals.amount,
+            transactions: self.account.transactions - 1
+        });
+        let flagged = (!verdict.flags.is_empty()).then(|| format!("flagged by {}", verdict.flags.join("+")));
+        match verdict.action {
+            Action::Reject | Action::Route => {
+                self.note = verdict.rule.map(|rule| format!("rule {}", rule));
+                Err(if verdict.action == Action::Reject { Error::PolicyRejected } else { Error::PolicyRouted })
+            }
+            Action::Accept | Action::Flag => Ok(flagged)
+        }
+    }
+
+    // Whether the transaction disputes what is left of a charged back transaction, which the lock
+    // of the chargeback does not prevent.
+    fn disputes_remainder(&self, tx_type: &Type, tx_id: u32) -> bool {
+        *tx_type == Type::Dispute && self.account.is_locked()
+            && self.tx_history.get(&tx_id).is_some_and(|disputed| disputed.is_charged_back()
+                                                                 && disputed.disputable_amount() > ZERO)
+    }
+
+    fn execute(&mut self, mut tx: Transaction) -> Result<()> {
+        let tx_type = tx.transaction_type().clone();
+        let tx_id = tx.tx_id();
+        if !self.account.status().allows(&tx_type) && !self.disputes_remainder(&tx_type, tx_id) {
+            // The account admin stops for accounts in a terminal status.
+            return match self.account.status() {
+                status if status.is_terminal() => Err(Error::Handle(Box::new(self.account().clone()))),
+                Status::Locked => Err(Error::AccountLocked),
+                Status::Active => Err(Error::AccountNotFrozen),
+                _ => Err(Error::AccountFrozen)
+            };
+        }
+
+        if self.rules.spec && self.account.is_locked() {
+            return Err(Error::AccountLocked);
+        }
+
+        // The records without an amount are invalid already, but not the transactions built at
+        // runtime, so the amounts unwrapped below are checked here.
+        if tx_type.requires_amount() && tx.amount().is_none() {
+            return Err(Error::MissingAmount);
+        }
+        // Likewise for the negative amounts, which the records can not have either.
+        if tx_type.requires_amount() && tx.amount().is_some_and(|amount| amount < ZERO) {
+            return Err(Error::NegativeAmount);
+        }
+
+        // A new transaction reusing the tx id of one still in the history would overwrite it, e.g.
+        // crediting a deposit twice, whether or not the run has a replay protection.
+        if tx_type.starts_new() && self.tx_history.contains_key(&tx_id) {
+            return Err(Error::TxDuplicated);
+        }
+
+        match tx_type {
+            Type::Deposit => {
+                // Safe to unwrap, since we are handling a deposit tx.
+                let amount = tx.amount().unwrap();
+                self.account.add_available(amount)?;
+                self.remember(tx);
+                Ok(())
+            },
+            Type::Withdrawal => {
+                // Safe to unwrap, since we are handling a withdrawal tx.
+                let amount = tx.amount().unwrap();
+                self.account.sub_available(amount)?;
+                if self.rules.two_step_withdrawals {
+                    self.account.add_pending(amount)?;
+                    tx.mark_pending();
+                }
+                self.remember(tx);
+                Ok(())
+            },
+            Type::Fee => {
+                // Safe to unwrap, since we are handling a fee tx. The fees are not disputable, so
+                // they are kept out of the history.
+                let amount = tx.amount().unwrap();
+                self.account.sub_available(amount)?;
+                Ok(())
+            },
+            Type::Credit | Type::Debit => {
+                // Safe to unwrap, since we are handling an adjustment tx. The adjustments are
+                // operator corrections, not disputable, so they are kept out of the history.
+                let amount = tx.amount().unwrap();
+                if tx_type == Type::Credit {
+                    self.account.add_available(amount)?;
+                } else {
+                    self.account.sub_available(amount)?;
+                }
+                Ok(())
+            },
+            Type::Authorize => {
+                // Safe to unwrap, since we are handling an authorization tx.
+                let amount = tx.amount().unwrap();
+                self.account.add_held(amount)?;
+                tx.mark_authorized();
+                self.remember(tx);
+                self.authorizations.push_back((self.applied, tx_id));
+                Ok(())
+            },
+            Type::Capture => {
+                match self.tx_history.get_mut(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(authorization) => {
+                        if !authorization.is_authorized() {
+                            return Err(Error::TxNotAuthorized);
                         }
 
-                        // Safe to unwrap, since we are handling a deposit tx.
-                        let amount = tx.amount().unwrap();
-                        self.tx_history.insert(tx_id, tx);
+                        // Once captured, an authorization is disputable like any deposit.
+                        let amount = authorization.amount().unwrap();
+                        self.account.sub_held(amount)?;
                         self.account.add_available(amount)?;
-                        Ok(self.account())
-                    },
-                    Type::Withdrawal => {
-                        if self.account.is_locked() {
-                            return Err(Error::Handle(self.account().clone()));
+                        authorization.clear_authorized();
+                        Ok(())
+                    }
+                }
+            },
+            Type::Refund => {
+                // Safe to unwrap, since we are handling a refund tx.
+                let amount = tx.amount().unwrap();
+                match self.tx_history.get_mut(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(deposit) => {
+                        // Only settled deposits, outside of any dispute, can be refunded.
+                        let refundable = matches!(deposit.transaction_type(), Type::Deposit | Type::Authorize)
+                            && !deposit.is_authorized() && deposit.is_emtpy_flags();
+                        if !refundable {
+                            return Err(Error::TxNotRefundable);
+                        }
+
+                        if amount > deposit.disputable_amount() {
+                            return Err(Error::RefundExceeded);
                         }
 
-                        // Safe to unwrap, since we are handling a withdrawal tx.
-                        let amount = tx.amount().unwrap();
-                        self.tx_history.insert(tx_id, tx);
                         self.account.sub_available(amount)?;
-                        Ok(self.account())
-                    },
-                    Type::Dispute => {
-                        match self.tx_history.get_mut(&tx_id) {
-                            None => Err(Error::TxNotFound),
-                            Some(to_be_disputed_tx) => {
-                                if !to_be_disputed_tx.is_emtpy_flags() {
-                                    return Err(Error::TxAlreadyDisputed);
-                                }
-
-                                if self.account.is_locked() {
-                                    return Err(Error::Handle(self.account().clone()));
-                                }
-
-                                let amount = to_be_disputed_tx.amount();
-                                self.account.sub_available(amount.unwrap())?;
-                                to_be_disputed_tx.mark_disputed();
-                                self.account.add_held(amount.unwrap())?;
-                                Ok(self.account())
+                        deposit.add_refunded(amount);
+                        Ok(())
+                    }
+                }
+            },
+            Type::Reversal => {
+                match self.tx_history.get(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(reversed) => {
+                        if reversed.is_authorized() || reversed.is_charged_back() {
+                            return Err(Error::TxNotReversible);
+                        }
+
+                        // Undo the effects of the transaction and of its dispute, if any.
+                        let amount = reversed.disputable_amount();
+                        let held = reversed.held_amount();
+                        let reversed_type = reversed.transaction_type();
+                        let disputed = reversed.is_disputed();
+                        let pending = reversed.is_pending();
+                        match (&reversed_type, disputed) {
+                            (Type::Deposit, false) | (Type::Authorize, false) => self.account.sub_available(amount)?,
+                            (Type::Deposit, true) | (Type::Authorize, true) => {
+                                // The part of the deposit that was not held comes out of the
+                                // available funds.
+                                self.account.sub_available(amount - held)?;
+                                self.account.sub_held(held)?;
+                            }
+                            (Type::Withdrawal, false) if pending => {
+                                self.account.sub_pending(amount)?;
+                                self.account.add_available(amount)?;
                             }
+                            (Type::Withdrawal, false) => self.account.add_available(amount)?,
+                            (Type::Withdrawal, true) => {
+                                // The funds credited by the dispute are the ones credited back.
+                                self.account.sub_held(held)?;
+                                self.account.add_available(amount)?;
+                            }
+                            _ => return Err(Error::TxNotReversible)
+                        };
+
+                        let reversed = self.tx_history.remove(&tx_id).unwrap();
+                        self.account.close_dispute(tx_id);
+                        self.note = Some(format!("reversed {}{} of {}", reversed_type.as_str(),
+                                                 if disputed { " under dispute" } else { "" }, amount::fmt(amount)));
+                        if reversed.refunded() > ZERO {
+                            self.note = self.note.take().map(|note| {
+                                format!("{}; {} already refunded", note, amount::fmt(reversed.refunded()))
+                            });
                         }
-                    },
-                    Type::Resolve => {
-                        match self.tx_history.get_mut(&tx_id) {
-                            None => Err(Error::TxNotFound),
-                            Some(disputed_tx) => {
-                                if disputed_tx.is_emtpy_flags() {
-                                    return Err(Error::TxNotDisputed);
-                                }
-
-                                if self.account.is_locked() {
-                                    return Err(Error::Handle(self.account().clone()));
-                                }
-
-                                let amount = disputed_tx.amount();
-                                self.account.sub_held(amount.unwrap())?;
-                                disputed_tx.mark_resolved();
-                                self.account.add_available(amount.unwrap())?;
-                                Ok(self.account())
+                        Ok(())
+                    }
+                }
+            },
+            Type::Settle | Type::Cancel if self.rules.two_step_withdrawals => {
+                match self.tx_history.get_mut(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(withdrawal) => {
+                        if !withdrawal.is_pending() {
+                            return Err(Error::TxNotPending);
+                        }
+
+                        // Safe to unwrap, since only withdrawals can be pending.
+                        let amount = withdrawal.amount().unwrap();
+                        self.account.sub_pending(amount)?;
+                        if tx_type == Type::Cancel {
+                            self.account.add_available(amount)?;
+                        }
+                        withdrawal.clear_pending();
+                        Ok(())
+                    }
+                }
+            },
+            Type::Dispute => {
+                match self.tx_history.get_mut(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(to_be_disputed_tx) => {
+                        if to_be_disputed_tx.is_authorized() {
+                            return Err(Error::TxNotCaptured);
+                        }
+
+                        if !to_be_disputed_tx.is_disputable(self.rules.redisputes) {
+                            return Err(Error::TxAlreadyDisputed);
+                        }
+
+                        if self.rules.spec && to_be_disputed_tx.transaction_type() != Type::Deposit {
+                            return Err(Error::TxNotDisputable);
+                        }
+
+                        // A dispute with an amount of its own only disputes that part of the
+                        // transaction, which the resolve or the chargeback moves in turn.
+                        let disputable = to_be_disputed_tx.disputable_amount();
+                        let amount = match tx.amount() {
+                            Some(partial) if !self.rules.spec && partial <= ZERO => {
+                                return Err(Error::DisputeNotPositive);
+                            }
+                            Some(partial) if !self.rules.spec && partial > disputable => {
+                                return Err(Error::DisputeExceeded);
                             }
+                            Some(partial) if !self.rules.spec => partial,
+                            _ => disputable
+                        };
+                        let available = self.account.available();
+                        let mut notes = Vec::new();
+                        if amount < disputable {
+                            notes.push(format!("partial dispute of {} out of {}", amount::fmt(amount),
+                                               amount::fmt(disputable)));
                         }
-                    },
-                    Type::Chargeback => {
-                        match self.tx_history.get_mut(&tx_id) {
-                            None => Err(Error::TxNotFound),
-                            Some(disputed_tx) => {
-                                if disputed_tx.is_emtpy_flags() {
-                                    return Err(Error::TxNotDisputed);
-                                }
-
-                                if self.account.is_locked() {
-                                    return Err(Error::Handle(self.account().clone()));
-                                }
-
-                                let amount = disputed_tx.amount();
-                                self.account.sub_held(amount.unwrap())?;
-                                self.account.set_locked(true);
-                                disputed_tx.mark_charged_back();
-                                Ok(self.account())
+                        let withdrawal = to_be_disputed_tx.transaction_type() == Type::Withdrawal;
+                        if withdrawal && to_be_disputed_tx.is_pending() {
+                            return Err(Error::TxNotDisputable);
+                        }
+
+                        // The funds of a disputed withdrawal already left the account, so they are
+                        // credited to the held funds rather than moved out of the available ones.
+                        let held = match self.rules.spent_dispute_policy() {
+                            _ if withdrawal => amount,
+                            _ if available >= amount => amount,
+                            SpentDisputePolicy::Reject => return Err(Error::DepositTooLow),
+                            SpentDisputePolicy::AllowNegative => {
+                                notes.push(String::from("available went negative"));
+                                amount
                             }
+                            SpentDisputePolicy::HoldRemaining => {
+                                let held = available.max(ZERO);
+                                notes.push(format!("held {} of {}", amount::fmt(held), amount::fmt(amount)));
+                                held
+                            }
+                        };
+                        if !withdrawal {
+                            self.account.debit_available(held)?;
+                        }
+                        to_be_disputed_tx.mark_disputed();
+                        to_be_disputed_tx.set_reason(tx.reason().map(String::from));
+                        to_be_disputed_tx.set_held_amount(held);
+                        self.account.add_held(held)?;
+                        self.account.disputes.push(Dispute { tx_id, amount: held, reason: tx.reason().map(String::from),
+                                                             opened_at: self.clock });
+
+                        if let Some(reason) = tx.reason() {
+                            notes.insert(0, format!("reason {}", reason));
+                            if self.rules.lock_reasons.iter().any(|code| code == reason) {
+                                self.account.set_status(Status::Locked);
+                                notes.push(String::from("account locked"));
+                            }
+                        }
+                        if !notes.is_empty() {
+                            self.note = Some(notes.join("; "));
                         }
+                        Ok(())
+                    }
+                }
+            },
+            Type::Resolve => {
+                match self.tx_history.get_mut(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(disputed_tx) => {
+                        if !disputed_tx.dispute_state().allows(DisputeState::Resolved, self.rules.redisputes) {
+                            return Err(Error::TxNotDisputed);
+                        }
+
+                        // The dispute of a withdrawal is resolved in favour of the withdrawal, so the
+                        // credited funds are dropped.
+                        let amount = disputed_tx.held_amount();
+                        self.account.sub_held(amount)?;
+                        // Resolving the dispute of what is left after a partial chargeback leaves
+                        // the part charged back before as it was, to be represented.
+                        if disputed_tx.charged_back() > ZERO {
+                            disputed_tx.mark_charged_back();
+                        } else {
+                            disputed_tx.mark_resolved();
+                        }
+                        if disputed_tx.transaction_type() != Type::Withdrawal {
+                            self.account.add_available(amount)?;
+                        }
+                        self.account.close_dispute(tx_id);
+                        self.settle(tx_id);
+                        Ok(())
+                    }
+                }
+            },
+            Type::Chargeback => {
+                match self.tx_history.get_mut(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(disputed_tx) => {
+                        if !disputed_tx.dispute_state().allows(DisputeState::ChargedBack, self.rules.redisputes) {
+                            return Err(Error::TxNotDisputed);
+                        }
+
+                        // Charging back a withdrawal returns its funds to the account.
+                        let amount = disputed_tx.held_amount();
+                        self.account.sub_held(amount)?;
+                        if disputed_tx.transaction_type() == Type::Withdrawal {
+                            self.account.add_available(amount)?;
+                        }
+                        self.account.set_status(Status::Locked);
+                        disputed_tx.mark_charged_back();
+                        disputed_tx.add_charged_back(amount);
+                        self.account.close_dispute(tx_id);
+                        // What is left of a partial chargeback can still be disputed.
+                        if disputed_tx.disputable_amount() == ZERO {
+                            self.settle(tx_id);
+                        }
+                        Ok(())
                     }
-                    _ => Err(Error::OperationNotSupported)
                 }
             }
-            None => {
-                Err(Error::Handle(self.account().clone()))
+            Type::Representment => {
+                match self.tx_history.get_mut(&tx_id) {
+                    None => Err(Error::TxNotFound),
+                    Some(charged_back_tx) => {
+                        if !charged_back_tx.is_charged_back() {
+                            return Err(Error::TxNotChargedBack);
+                        }
+
+                        // The merchant won the challenge, so the charged back funds come back, or
+                        // leave the account again for a withdrawal, all of the partial chargebacks
+                        // of the transaction included.
+                        let amount = charged_back_tx.charged_back();
+                        if charged_back_tx.transaction_type() == Type::Withdrawal {
+                            self.account.sub_available(amount)?;
+                        } else {
+                            self.account.add_available(amount)?;
+                        }
+                        charged_back_tx.mark_represented();
+                        charged_back_tx.set_charged_back(ZERO);
+                        if self.rules.unlock_on_representment && self.account.is_locked() {
+                            self.account.set_status(Status::Active);
+                            self.note = Some(String::from("account unlocked"));
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            Type::Freeze => {
+                self.account.set_status(Status::Frozen(self.rules.freeze_mode));
+                Ok(())
+            }
+            Type::Unfreeze => {
+                self.account.set_status(Status::Active);
+                Ok(())
+            }
+            Type::Unlock if self.rules.admin_unlock => {
+                if !self.account.is_locked() {
+                    return Err(Error::AccountNotLocked);
+                }
+
+                self.account.set_status(Status::Active);
+                self.note = Some(String::from("account unlocked"));
+                Ok(())
             }
+            _ => Err(Error::OperationNotSupported)
         }
     }
 }
@@ -208,64 +1320,818 @@ impl AccountAdmin {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::builder::Tx;
+    use crate::report::Volume;
+    use crate::input::Schema;
+    use crate::amount::{from_f64, ZERO};
 
     #[test]
     fn test_account_new_unlocked() {
-        let account = Account::new_unlocked(0,0.0, 0.0);
-        assert_eq!(account.is_locked(), false);
+        let account = Account::new_unlocked(0,ZERO, ZERO);
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_account_display_from_str() {
+        let account = Account::new(3, from_f64(1.5), from_f64(0.5), Status::Active);
+        assert_eq!(account.to_string(), "3,1.5000,0.5000,2.0000,false,active");
+        assert_eq!(account.to_string().parse::<Account>().unwrap(), account);
+        let frozen = Account::new(3, from_f64(1.5), from_f64(0.5), Status::Frozen(FreezeMode::Withdrawals));
+        assert_eq!(frozen.to_string().parse::<Account>().unwrap(), frozen);
+        assert_eq!("3,0.0000,1.0000,1.0000,true".parse::<Account>().unwrap().status(), Status::Locked);
+        assert!(matches!("3,one,0.0,0.0,false".parse::<Account>(), Err(Error::InvalidRow)));
+        assert!(matches!("3,1.0,0.0,1.0,false,gone".parse::<Account>(), Err(Error::InvalidRow)));
     }
 
     #[test]
     fn test_account_new() {
-        let account = Account::new(0,1.0, 2.0, true);
-        assert_eq!(account.available, 1.0);
-        assert_eq!(account.held, 2.0);
-        assert_eq!(account.locked, true);
+        let account = Account::new(0,from_f64(1.0), from_f64(2.0), Status::Locked);
+        assert_eq!(account.available, from_f64(1.0));
+        assert_eq!(account.held, from_f64(2.0));
+        assert_eq!(account.status, Status::Locked);
     }
 
     #[test]
     fn test_account_getters() {
-        let account = Account::new_unlocked(0,1.5, 2.0);
-        assert_eq!(account.available(), 1.5);
-        assert_eq!(account.held(), 2.0);
-        assert_eq!(account.total(), 3.5);
+        let account = Account::new_unlocked(0,from_f64(1.5), from_f64(2.0));
+        assert_eq!(account.available(), from_f64(1.5));
+        assert_eq!(account.held(), from_f64(2.0));
+        assert_eq!(account.total(), from_f64(3.5));
     }
 
     #[test]
     fn test_account_setters() {
-        let mut account = Account::new(0,1.0, 2.0, true);
+        let mut account = Account::new(0,from_f64(1.0), from_f64(2.0), Status::Locked);
         account.set_locked(false);
-        assert_eq!(account.is_locked(), false);
+        assert!(!account.is_locked());
     }
 
     #[test]
     fn test_account_add_available() {
-        let mut account = Account::new(0,1.0, 2.0, false);
-        assert!(account.add_available(1.1).is_ok());
-        assert_eq!(account.available(), 2.1);
+        let mut account = Account::new(0,from_f64(1.0), from_f64(2.0), Status::Active);
+        assert!(account.add_available(from_f64(1.1)).is_ok());
+        assert_eq!(account.available(), from_f64(2.1));
     }
 
     #[test]
     fn test_account_sub_available() {
-        let mut account = Account::new(0,1.0, 2.0, false);
-        assert!(account.sub_available(1.1).is_err());
-        assert!(account.sub_available(0.5).is_ok());
-        assert_eq!(account.available(), 0.5);
+        let mut account = Account::new(0,from_f64(1.0), from_f64(2.0), Status::Active);
+        assert!(account.sub_available(from_f64(1.1)).is_err());
+        assert!(account.sub_available(from_f64(0.5)).is_ok());
+        assert_eq!(account.available(), from_f64(0.5));
     }
 
     #[test]
     fn test_account_add_held() {
-        let mut account = Account::new(0,1.0, 2.0, false);
-        assert!(account.add_held(1.1).is_ok());
-        assert_eq!(account.held(), 3.1);
+        let mut account = Account::new(0,from_f64(1.0), from_f64(2.0), Status::Active);
+        assert!(account.add_held(from_f64(1.1)).is_ok());
+        assert_eq!(account.held(), from_f64(3.1));
     }
 
     #[test]
     fn test_account_sub_held() {
-        let mut account = Account::new(0,1.0, 2.0, false);
-        assert!(account.sub_held(2.1).is_err());
-        assert!(account.sub_held(0.5).is_ok());
-        assert_eq!(account.held(), 1.5);
+        let mut account = Account::new(0,from_f64(1.0), from_f64(2.0), Status::Active);
+        assert!(account.sub_held(from_f64(2.1)).is_err());
+        assert!(account.sub_held(from_f64(0.5)).is_ok());
+        assert_eq!(account.held(), from_f64(1.5));
+    }
+
+    #[test]
+    fn test_status_allowed_operations() {
+        for ttype in [Type::Deposit, Type::Withdrawal, Type::Dispute, Type::Resolve, Type::Chargeback].iter() {
+            assert!(Status::Active.allows(ttype));
+            assert_eq!(Status::Frozen(FreezeMode::Withdrawals).allows(ttype), *ttype != Type::Withdrawal);
+            assert!(!Status::Frozen(FreezeMode::Full).allows(ttype));
+            assert_eq!(Status::Locked.allows(ttype), matches!(ttype, Type::Resolve | Type::Chargeback));
+            assert!(!Status::Closed.allows(ttype));
+        }
+        assert!(!Status::Active.allows(&Type::Unfreeze));
+        assert!(Status::Frozen(FreezeMode::Full).allows(&Type::Unfreeze));
+        assert!(!Status::Locked.allows(&Type::Unfreeze));
+        assert!(Status::Locked.allows(&Type::Representment));
+        assert!(!Status::Closed.allows(&Type::Representment));
+        assert!(!Status::Frozen(FreezeMode::Full).is_terminal());
+        assert!(!Status::Locked.is_terminal());
+        assert!(Status::Closed.is_terminal());
+    }
+
+    #[test]
+    fn test_client_apply_frozen_and_closed() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.account.set_status(Status::Frozen(FreezeMode::Withdrawals));
+        assert!(account_admin.apply(Tx::deposit(1, 0).amount(2.0).build()).is_ok());
+        assert!(matches!(account_admin.apply(Tx::withdrawal(1, 1).amount(1.0).build()),
+                         Err(Error::AccountFrozen)));
+        assert_eq!(account_admin.account().available(), from_f64(2.0));
+        account_admin.account.set_status(Status::Closed);
+        assert!(matches!(account_admin.apply(Tx::deposit(1, 2).amount(1.0).build()),
+                         Err(Error::Handle(_))));
+    }
+
+    #[test]
+    fn test_client_apply_freeze_unfreeze() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 0).amount(2.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::unfreeze(1, 1).build()),
+                         Err(Error::AccountNotFrozen)));
+        account_admin.apply(Tx::freeze(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().status(), Status::Frozen(FreezeMode::Withdrawals));
+        assert!(account_admin.apply(Tx::withdrawal(1, 2).amount(1.0).build()).is_err());
+        account_admin.apply(Tx::unfreeze(1, 3).build()).unwrap();
+        assert_eq!(account_admin.account().status(), Status::Active);
+        account_admin.apply(Tx::withdrawal(1, 4).amount(1.0).build()).unwrap();
+
+        account_admin.set_rules(Rules { freeze_mode: FreezeMode::Full, ..Rules::default() });
+        account_admin.apply(Tx::freeze(1, 5).build()).unwrap();
+        assert!(account_admin.apply(Tx::deposit(1, 6).amount(1.0).build()).is_err());
+        assert_eq!(account_admin.account().available(), from_f64(1.0));
+        account_admin.apply(Tx::unfreeze(1, 7).build()).unwrap();
+        assert_eq!(account_admin.account().status(), Status::Active);
+    }
+
+    #[test]
+    fn test_client_apply_two_step_withdrawal() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        assert!(matches!(account_admin.apply(Tx::settle(1, 1).build()),
+                         Err(Error::OperationNotSupported)));
+        account_admin.set_rules(Rules { two_step_withdrawals: true, ..Rules::default() });
+        account_admin.apply(Tx::deposit(1, 0).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::withdrawal(1, 1).amount(2.0).build()).unwrap();
+        account_admin.apply(Tx::withdrawal(1, 2).amount(1.0).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(2.0));
+        assert_eq!(account_admin.account().pending(), from_f64(3.0));
+        assert_eq!(account_admin.account().total(), from_f64(5.0));
+
+        account_admin.apply(Tx::settle(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().pending(), from_f64(1.0));
+        assert_eq!(account_admin.account().total(), from_f64(3.0));
+        assert!(matches!(account_admin.apply(Tx::settle(1, 1).build()),
+                         Err(Error::TxNotPending)));
+
+        account_admin.apply(Tx::cancel(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().pending(), ZERO);
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+        assert!(matches!(account_admin.apply(Tx::cancel(1, 2).build()),
+                         Err(Error::TxNotPending)));
+        assert!(matches!(account_admin.apply(Tx::settle(1, 0).build()),
+                         Err(Error::TxNotPending)));
+        assert!(matches!(account_admin.apply(Tx::settle(1, 9).build()),
+                         Err(Error::TxNotFound)));
+    }
+
+    #[test]
+    fn test_client_apply_authorize_capture() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::authorize(1, 1).amount(5.0).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(5.0));
+        assert_eq!(account_admin.account().available(), ZERO);
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 1).build()),
+                         Err(Error::TxNotCaptured)));
+
+        account_admin.apply(Tx::capture(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().held(), ZERO);
+        assert_eq!(account_admin.account().available(), from_f64(5.0));
+        assert!(matches!(account_admin.apply(Tx::capture(1, 1).build()),
+                         Err(Error::TxNotAuthorized)));
+
+        // Captured authorizations can be disputed and charged back.
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(5.0));
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        assert!(account_admin.account().is_locked());
+    }
+
+    #[test]
+    fn test_client_apply_authorization_expiry() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { authorization_window: Some(2), ..Rules::default() });
+        account_admin.apply(Tx::authorize(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::authorize(1, 2).amount(1.0).build()).unwrap();
+        account_admin.apply(Tx::capture(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(5.0));
+        // The fourth transaction is out of the window of the first authorization.
+        account_admin.apply(Tx::deposit(1, 3).amount(1.0).build()).unwrap();
+        assert_eq!(account_admin.account().held(), ZERO);
+        assert_eq!(account_admin.account().available(), from_f64(2.0));
+        assert!(matches!(account_admin.apply(Tx::capture(1, 1).build()),
+                         Err(Error::TxNotFound)));
+        // The captured authorization is still around.
+        account_admin.apply(Tx::dispute(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(1.0));
+    }
+
+    #[test]
+    fn test_client_collect_settled() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { settled_window: Some(1), ..Rules::default() });
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::deposit(1, 2).amount(2.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        account_admin.apply(Tx::resolve(1, 1).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 2).build()).unwrap();
+        assert!(account_admin.tx_history.contains_key(&1));
+        // The sixth transaction is out of the window of the resolved dispute.
+        account_admin.apply(Tx::chargeback(1, 2).build()).unwrap();
+        assert!(!account_admin.tx_history.contains_key(&1));
+        // The charged back transaction can still be represented, within the window.
+        account_admin.apply(Tx::representment(1, 2).build()).unwrap();
+        assert_eq!(account_admin.collect_settled(), 0);
+        account_admin.account.set_status(Status::Active);
+        account_admin.apply(Tx::deposit(1, 3).amount(1.0).build()).unwrap();
+        assert!(!account_admin.tx_history.contains_key(&2));
+        assert_eq!(account_admin.tx_history.len(), 1);
+        assert_eq!(account_admin.account().volumes().collected, Volume { count: 2, amount: from_f64(7.0) });
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 1).build()), Err(Error::TxNotFound)));
+    }
+
+    #[test]
+    fn test_client_archive_old() {
+        let at = |mut tx: Transaction, timestamp: u64| {
+            tx.set_timestamp(Some(timestamp));
+            tx
+        };
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let (archive, archived) = std::sync::mpsc::channel();
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { archive_age: Some(10), ..Rules::default() });
+        account_admin.set_archive(archive);
+        account_admin.apply(at(Tx::deposit(1, 1).amount(5.0).build(), 100)).unwrap();
+        account_admin.apply(at(Tx::deposit(1, 2).amount(2.0).build(), 101)).unwrap();
+        account_admin.apply(at(Tx::dispute(1, 2).build(), 105)).unwrap();
+        // Not older than the archive age yet.
+        account_admin.apply(at(Tx::withdrawal(1, 3).amount(1.0).build(), 110)).unwrap();
+        assert_eq!(account_admin.tx_history.len(), 3);
+
+        // The disputed transaction is kept around until its dispute is settled.
+        account_admin.apply(at(Tx::deposit(1, 4).amount(1.0).build(), 112)).unwrap();
+        assert_eq!(archived.try_iter().map(|tx: Transaction| tx.tx_id()).collect::<Vec<_>>(), vec![1]);
+        assert!(account_admin.tx_history.contains_key(&2));
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 1).build()), Err(Error::TxNotFound)));
+        account_admin.apply(at(Tx::resolve(1, 2).build(), 113)).unwrap();
+        account_admin.apply(at(Tx::deposit(1, 5).amount(1.0).build(), 121)).unwrap();
+        assert_eq!(archived.try_iter().map(|tx| tx.tx_id()).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(account_admin.archive_old(), 0);
+        account_admin.apply(at(Tx::deposit(1, 6).amount(1.0).build(), 123)).unwrap();
+        let archived: Vec<Transaction> = archived.try_iter().collect();
+        assert_eq!(archived.iter().map(|tx| tx.tx_id()).collect::<Vec<_>>(), vec![2, 4]);
+        assert!(archived[0].is_resolved());
+        assert_eq!(account_admin.tx_history.len(), 2);
+        assert_eq!(account_admin.account().total(), from_f64(9.0));
+    }
+
+    #[test]
+    fn test_client_apply_refund() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::deposit(1, 2).amount(1.0).build()).unwrap();
+        account_admin.apply(Tx::withdrawal(1, 3).amount(1.0).build()).unwrap();
+        account_admin.apply(Tx::refund(1, 1).amount(2.0).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+        assert!(matches!(account_admin.apply(Tx::refund(1, 1).amount(3.5).build()),
+                         Err(Error::RefundExceeded)));
+        assert!(matches!(account_admin.apply(Tx::refund(1, 3).amount(0.5).build()),
+                         Err(Error::TxNotRefundable)));
+        assert!(matches!(account_admin.apply(Tx::refund(1, 4).amount(0.5).build()),
+                         Err(Error::TxNotFound)));
+
+        // A dispute only holds what is left of the deposit after the refunds.
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(3.0));
+        assert_eq!(account_admin.account().available(), ZERO);
+        assert!(matches!(account_admin.apply(Tx::refund(1, 1).amount(0.5).build()),
+                         Err(Error::TxNotRefundable)));
+        account_admin.apply(Tx::resolve(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().held(), ZERO);
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+    }
+
+    #[test]
+    fn test_client_apply_reversal() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::deposit(1, 2).amount(3.0).build()).unwrap();
+        account_admin.apply(Tx::withdrawal(1, 3).amount(1.0).build()).unwrap();
+
+        // A withdrawal is credited back.
+        account_admin.apply(Tx::reversal(1, 3).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(8.0));
+        assert!(!account_admin.tx_history.contains_key(&3));
+        assert!(matches!(account_admin.apply(Tx::reversal(1, 3).build()),
+                         Err(Error::TxNotFound)));
+
+        // A deposit under dispute is taken out of the held funds.
+        account_admin.apply(Tx::dispute(1, 2).build()).unwrap();
+        account_admin.apply(Tx::reversal(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().held(), ZERO);
+        assert_eq!(account_admin.account().available(), from_f64(5.0));
+
+        // A deposit can not be reversed once its funds were spent.
+        account_admin.apply(Tx::withdrawal(1, 4).amount(4.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::reversal(1, 1).build()),
+                         Err(Error::DepositTooLow)));
+        assert!(account_admin.tx_history.contains_key(&1));
+    }
+
+    #[test]
+    fn test_client_apply_withdrawal_dispute() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::withdrawal(1, 2).amount(2.0).build()).unwrap();
+
+        // The withdrawn funds are credited to the held funds, and dropped by the resolve.
+        account_admin.apply(Tx::dispute(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+        assert_eq!(account_admin.account().held(), from_f64(2.0));
+        assert_eq!(account_admin.account().total(), from_f64(5.0));
+        account_admin.apply(Tx::resolve(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+        assert_eq!(account_admin.account().held(), ZERO);
+
+        // The chargeback returns them to the available funds, and the representment takes them
+        // out again.
+        account_admin.apply(Tx::withdrawal(1, 3).amount(1.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 3).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 3).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+        assert_eq!(account_admin.account().held(), ZERO);
+        assert!(account_admin.account().is_locked());
+        account_admin.apply(Tx::representment(1, 3).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(2.0));
+
+        // A reversal credits the withdrawal back once, whatever its dispute state.
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::withdrawal(1, 2).amount(2.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 2).build()).unwrap();
+        account_admin.apply(Tx::reversal(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(5.0));
+        assert_eq!(account_admin.account().held(), ZERO);
+
+        // A pending withdrawal is not disputable yet.
+        account_admin.set_rules(Rules { two_step_withdrawals: true, ..Rules::default() });
+        account_admin.apply(Tx::withdrawal(1, 3).amount(1.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 3).build()), Err(Error::TxNotDisputable)));
+        account_admin.apply(Tx::settle(1, 3).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 3).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(1.0));
+    }
+
+    #[test]
+    fn test_client_apply_partial_dispute() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).amount(4.0).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(6.0));
+        assert_eq!(account_admin.account().held(), from_f64(4.0));
+        assert_eq!(account_admin.tx_history.get(&1).unwrap().held_amount(), from_f64(4.0));
+        account_admin.apply(Tx::resolve(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(10.0));
+
+        // What was refunded can not be disputed anymore, and only the disputed part is charged back.
+        account_admin.apply(Tx::deposit(1, 2).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::refund(1, 2).amount(1.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 2).amount(4.5).build()), Err(Error::DisputeExceeded)));
+        account_admin.apply(Tx::dispute(1, 2).amount(3.0).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(11.0));
+        assert_eq!(account_admin.account().held(), ZERO);
+        assert!(account_admin.account().is_locked());
+
+        // Spec mode disputes the whole transaction, whatever the amount of the dispute.
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { spec: true, ..Rules::default() });
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).amount(2.0).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(5.0));
+    }
+
+    #[test]
+    fn test_client_apply_partial_chargebacks() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).amount(3.0).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        assert!(account_admin.account().is_locked());
+        assert_eq!(account_admin.account().available(), from_f64(7.0));
+
+        // The remainder can still be disputed and charged back on the locked account.
+        account_admin.apply(Tx::dispute(1, 1).amount(4.0).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(4.0));
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+        assert_eq!(account_admin.account().held(), ZERO);
+        let tx = account_admin.tx_history.get(&1).unwrap();
+        assert_eq!((tx.charged_back(), tx.disputable_amount()), (from_f64(7.0), from_f64(3.0)));
+
+        // Only what was not charged back yet, and nothing but the disputes get through the lock.
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 1).amount(4.0).build()), Err(Error::DisputeExceeded)));
+        assert!(matches!(account_admin.apply(Tx::withdrawal(1, 2).amount(1.0).build()), Err(Error::AccountLocked)));
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().total(), ZERO);
+        // Once all of it was charged back, the lock is back to refusing the disputes.
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 1).build()), Err(Error::AccountLocked)));
+    }
+
+    #[test]
+    fn test_client_apply_partial_dispute_not_positive() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 1).amount(-5.0).build()), Err(Error::DisputeNotPositive)));
+        assert_eq!((account_admin.account().available(), account_admin.account().held()), (from_f64(10.0), ZERO));
+
+        // A zero amount read from a record holds nothing, so there is nothing to charge back either.
+        let dispute = Transaction::parse("dispute,1,1,0", &Schema::default()).unwrap();
+        assert!(matches!(account_admin.apply(dispute), Err(Error::DisputeNotPositive)));
+        assert!(matches!(account_admin.apply(Tx::chargeback(1, 1).build()), Err(Error::TxNotDisputed)));
+        assert!(!account_admin.account().is_locked());
+    }
+
+    #[test]
+    fn test_client_apply_partial_chargebacks_representment() {
+        // Every partial chargeback is represented.
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).amount(3.0).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).amount(7.0).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().total(), ZERO);
+        account_admin.apply(Tx::representment(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(10.0));
+        assert_eq!(account_admin.tx_history.get(&1).unwrap().charged_back(), ZERO);
+
+        // Resolving the dispute of the remainder keeps the partial chargeback to represent.
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).amount(3.0).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(7.0));
+        account_admin.apply(Tx::resolve(1, 1).build()).unwrap();
+        assert_eq!((account_admin.account().available(), account_admin.account().held()), (from_f64(7.0), ZERO));
+        assert!(account_admin.tx_history.get(&1).unwrap().is_charged_back());
+        account_admin.apply(Tx::representment(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(10.0));
+        assert!(account_admin.tx_history.get(&1).unwrap().is_represented());
+    }
+
+    #[test]
+    fn test_client_apply_redispute() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        account_admin.apply(Tx::resolve(1, 1).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 1).build()), Err(Error::TxAlreadyDisputed)));
+        assert!(matches!(account_admin.apply(Tx::resolve(1, 1).build()), Err(Error::TxNotDisputed)));
+
+        account_admin.set_rules(Rules { redisputes: true, ..Rules::default() });
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(5.0));
+        assert_eq!(account_admin.account().available(), ZERO);
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().total(), ZERO);
+        assert!(account_admin.account().is_locked());
+        assert_eq!(account_admin.tx_history.get(&1).unwrap().dispute_state(), DisputeState::ChargedBack);
+    }
+
+    #[test]
+    fn test_client_apply_unlock() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::deposit(1, 2).amount(3.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+
+        // Unless the run allows it, the lock is for good.
+        assert!(matches!(account_admin.apply(Tx::unlock(1, 3).build()), Err(Error::OperationNotSupported)));
+        assert!(account_admin.account().is_locked());
+
+        account_admin.set_rules(Rules { admin_unlock: true, ..Rules::default() });
+        account_admin.apply(Tx::unlock(1, 3).build()).unwrap();
+        assert_eq!(account_admin.account().status(), Status::Active);
+        assert_eq!(account_admin.account().available(), from_f64(3.0));
+        assert!(matches!(account_admin.apply(Tx::unlock(1, 4).build()), Err(Error::AccountNotLocked)));
+        account_admin.apply(Tx::withdrawal(1, 5).amount(1.0).build()).unwrap();
+    }
+
+    #[test]
+    fn test_client_apply_overflow() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        let large = Amount::from_minor(9_000_000_000_000_000_000);
+        account_admin.apply(Tx::deposit(1, 1).exact_amount(large).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::deposit(1, 2).exact_amount(large).build()), Err(Error::Overflow)));
+        assert_eq!((account_admin.account().available(), account_admin.account().rejected()), (large, 1));
+        // The rejected deposit is not in the history, so it can not be disputed.
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 2).build()), Err(Error::TxNotFound)));
+
+        // The total of the balances is checked too.
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::deposit(1, 3).exact_amount(large).build()), Err(Error::Overflow)));
+        assert_eq!((account_admin.account().available(), account_admin.account().held()), (ZERO, large));
+        account_admin.apply(Tx::deposit(1, 4).amount(1.0).build()).unwrap();
+    }
+
+    #[test]
+    fn test_client_apply_representment() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::deposit(1, 2).amount(3.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::representment(1, 1).build()),
+                         Err(Error::TxNotChargedBack)));
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+
+        // A locked account does not accept new transactions.
+        assert!(matches!(account_admin.apply(Tx::deposit(1, 3).amount(1.0).build()),
+                         Err(Error::AccountLocked)));
+        account_admin.apply(Tx::representment(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(8.0));
+        assert!(account_admin.tx_history.get(&1).unwrap().is_represented());
+        assert!(account_admin.account().is_locked());
+        assert!(matches!(account_admin.apply(Tx::representment(1, 1).build()),
+                         Err(Error::TxNotChargedBack)));
+    }
+
+    #[test]
+    fn test_client_apply_representment_unlock() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { unlock_on_representment: true, ..Rules::default() });
+        for tx_id in 1..3 {
+            account_admin.apply(Tx::deposit(1, tx_id).amount(2.0).build()).unwrap();
+            account_admin.apply(Tx::dispute(1, tx_id).build()).unwrap();
+        }
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        // The second dispute can still be settled while the account is locked.
+        account_admin.apply(Tx::chargeback(1, 2).build()).unwrap();
+
+        account_admin.apply(Tx::representment(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().status(), Status::Active);
+        account_admin.apply(Tx::representment(1, 2).build()).unwrap();
+        assert_eq!(account_admin.account().status(), Status::Active);
+        assert_eq!(account_admin.account().available(), from_f64(4.0));
+        account_admin.apply(Tx::withdrawal(1, 3).amount(1.0).build()).unwrap();
+    }
+
+    #[test]
+    fn test_client_apply_dispute_reason() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { lock_reasons: vec![String::from("fraud")], ..Rules::default() });
+        for tx_id in 1..4 {
+            account_admin.apply(Tx::deposit(1, tx_id).amount(2.0).build()).unwrap();
+        }
+        let dispute = |tx_id, reason: Option<&str>| {
+            let mut tx = Tx::dispute(1, tx_id).build();
+            tx.set_reason(reason.map(String::from));
+            tx
+        };
+        account_admin.apply(dispute(1, None)).unwrap();
+        account_admin.apply(dispute(2, Some("not_received"))).unwrap();
+        assert_eq!(account_admin.tx_history.get(&2).unwrap().reason(), Some("not_received"));
+        assert_eq!(account_admin.account().open_disputes().len(), 2);
+        account_admin.apply(Tx::resolve(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().open_disputes(),
+                   &[Dispute { tx_id: 2, amount: from_f64(2.0), reason: Some(String::from("not_received")), opened_at: None }]);
+        assert!(!account_admin.account().is_locked());
+
+        // Fraud disputes lock the account before any chargeback.
+        account_admin.apply(dispute(3, Some("fraud"))).unwrap();
+        assert!(account_admin.account().is_locked());
+        assert_eq!(account_admin.account().open_disputes().len(), 2);
+        account_admin.apply(Tx::resolve(1, 3).build()).unwrap();
+        assert_eq!(account_admin.account().open_disputes().len(), 1);
+    }
+
+    #[test]
+    fn test_client_apply_dispute_timeout() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let (audit, entries) = std::sync::mpsc::channel();
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { dispute_timeout: Some(60), ..Rules::default() });
+        account_admin.set_audit(audit);
+        let at = |mut tx: Transaction, timestamp| {
+            tx.set_timestamp(Some(timestamp));
+            tx
+        };
+        account_admin.apply(at(Tx::deposit(1, 1).amount(2.0).build(), 100)).unwrap();
+        account_admin.apply(at(Tx::deposit(1, 2).amount(3.0).build(), 110)).unwrap();
+        account_admin.apply(at(Tx::dispute(1, 1).build(), 120)).unwrap();
+        account_admin.apply(at(Tx::dispute(1, 2).build(), 150)).unwrap();
+
+        // A late transaction does not take the clock back.
+        account_admin.apply(at(Tx::deposit(1, 3).amount(1.0).build(), 10)).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(5.0));
+        account_admin.apply(at(Tx::deposit(1, 4).amount(1.0).build(), 181)).unwrap();
+        assert_eq!(account_admin.account().held(), from_f64(3.0));
+        assert_eq!(account_admin.account().available(), from_f64(4.0));
+        assert!(account_admin.tx_history.get(&1).unwrap().is_resolved());
+        assert_eq!(account_admin.account().open_disputes().len(), 1);
+        drop(account_admin);
+
+        let entries: Vec<Entry> = entries.iter().collect();
+        assert_eq!(entries.len(), 7);
+        assert_eq!(entries[5].ttype, Type::Resolve);
+        assert_eq!(entries[5].tx_id, 1);
+        assert_eq!(entries[5].note.as_deref(), Some("auto_resolved"));
+        assert_eq!(entries[6].tx_id, 4);
+    }
+
+    #[test]
+    fn test_client_apply_out_of_sequence() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        let deposit = |tx_id: u32, sequence: Option<u64>| {
+            let mut tx = Tx::deposit(1, tx_id).amount(1.0).build();
+            tx.set_sequence(sequence);
+            tx
+        };
+        account_admin.apply(deposit(1, Some(1))).unwrap();
+        account_admin.apply(deposit(2, Some(3))).unwrap();
+        assert!(matches!(account_admin.apply(deposit(3, Some(2))), Err(Error::OutOfSequence)));
+        account_admin.apply(deposit(4, Some(3))).unwrap();
+        account_admin.apply(deposit(5, None)).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(4.0));
+        assert_eq!(account_admin.account().rejected(), 1);
+    }
+
+    #[test]
+    fn test_client_apply_replay() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(2.0).build()).unwrap();
+        let mut replayed = Tx::deposit(1, 1).amount(2.0).build();
+        replayed.mark_replayed();
+        assert!(matches!(account_admin.apply(replayed), Err(Error::TxDuplicated)));
+        // The replayed deposit does not overwrite the history, so the first one is disputed.
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        assert_eq!((account_admin.account().available(), account_admin.account().held()), (ZERO, from_f64(2.0)));
+        assert_eq!(account_admin.account().rejected(), 1);
+
+        // Without a replay protection, the tx ids still in the history are rejected all the same.
+        assert!(matches!(account_admin.apply(Tx::withdrawal(1, 1).amount(1.0).build()), Err(Error::TxDuplicated)));
+        assert!(matches!(account_admin.apply(Tx::deposit(1, 1).amount(2.0).build()), Err(Error::TxDuplicated)));
+        assert_eq!(account_admin.account().held(), from_f64(2.0));
+        assert_eq!(account_admin.tx_history.get(&1).unwrap().transaction_type(), Type::Deposit);
+    }
+
+    #[test]
+    fn test_client_apply_missing_amount() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        assert!(matches!(account_admin.apply(Tx::deposit(1, 1).build()), Err(Error::MissingAmount)));
+        assert!(matches!(account_admin.apply(Tx::withdrawal(1, 2).build()), Err(Error::MissingAmount)));
+        account_admin.apply(Tx::deposit(1, 3).amount(1.0).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(1.0));
+        assert_eq!(account_admin.account().rejected(), 2);
+    }
+
+    #[test]
+    fn test_client_apply_negative_amount() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::deposit(1, 2).amount(-1.0).build()), Err(Error::NegativeAmount)));
+        assert!(matches!(account_admin.apply(Tx::withdrawal(1, 3).amount(-100.0).build()), Err(Error::NegativeAmount)));
+        assert!(matches!(account_admin.apply(Tx::fee(1, 4).amount(-50.0).build()), Err(Error::NegativeAmount)));
+        assert!(matches!(account_admin.apply(Tx::debit(1, 5).amount(-7.0).build()), Err(Error::NegativeAmount)));
+        assert_eq!((account_admin.account().available(), account_admin.account().rejected()), (from_f64(10.0), 4));
+    }
+
+    #[test]
+    fn test_client_apply_policy() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let (audit, entries) = std::sync::mpsc::channel();
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        let policy = Policy::parse("big: flag amount>=100\n\
+                                    large: reject type=withdrawal amount>50\n\
+                                    heavy: route type=withdrawal withdrawn>=20\n").unwrap();
+        account_admin.set_rules(Rules { policy: Some(Arc::new(policy)), ..Rules::default() });
+        account_admin.set_audit(audit);
+        account_admin.apply(Tx::deposit(1, 1).amount(100.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::withdrawal(1, 2).amount(60.0).build()),
+                         Err(Error::PolicyRejected)));
+        account_admin.apply(Tx::withdrawal(1, 3).amount(20.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::withdrawal(1, 4).amount(1.0).build()),
+                         Err(Error::PolicyRouted)));
+        assert_eq!(account_admin.account().available(), from_f64(80.0));
+        assert!(account_admin.may_route());
+        drop(account_admin);
+
+        let entries: Vec<Entry> = entries.iter().collect();
+        assert_eq!(entries[0].note.as_deref(), Some("flagged by big"));
+        assert_eq!(entries[1].outcome, Outcome::Rejected("policy_rejected"));
+        assert_eq!(entries[1].note.as_deref(), Some("rule large"));
+        assert_eq!(entries[3].outcome, Outcome::Rejected("policy_routed"));
+    }
+
+    #[test]
+    fn test_client_apply_spent_dispute_policy() {
+        let spent_dispute = |policy| {
+            let (_, receiver) = tokio::sync::mpsc::channel(32);
+            let mut account_admin = AccountAdmin::new(1, receiver);
+            account_admin.set_rules(Rules { spent_dispute_policy: policy, ..Rules::default() });
+            account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+            account_admin.apply(Tx::withdrawal(1, 2).amount(3.0).build()).unwrap();
+            let result = account_admin.apply(Tx::dispute(1, 1).build()).map(|_| ());
+            (account_admin, result)
+        };
+
+        let (account_admin, result) = spent_dispute(SpentDisputePolicy::Reject);
+        assert!(matches!(result, Err(Error::DepositTooLow)));
+        assert!(account_admin.tx_history.get(&1).unwrap().is_emtpy_flags());
+
+        let (mut account_admin, result) = spent_dispute(SpentDisputePolicy::AllowNegative);
+        result.unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(-3.0));
+        assert_eq!(account_admin.account().held(), from_f64(5.0));
+        account_admin.apply(Tx::resolve(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(2.0));
+        assert_eq!(account_admin.account().max_exposure(), from_f64(3.0));
+        assert!(account_admin.account().is_exposed());
+
+        let (mut account_admin, result) = spent_dispute(SpentDisputePolicy::HoldRemaining);
+        result.unwrap();
+        assert!(!account_admin.account().is_exposed());
+        assert_eq!(account_admin.account().available(), ZERO);
+        assert_eq!(account_admin.account().held(), from_f64(2.0));
+        assert_eq!(account_admin.account().open_disputes()[0].amount, from_f64(2.0));
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().total(), ZERO);
+        account_admin.apply(Tx::representment(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(2.0));
+    }
+
+    #[test]
+    fn test_client_apply_spec() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_rules(Rules { spec: true, ..Rules::default() });
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        account_admin.apply(Tx::withdrawal(1, 2).amount(3.0).build()).unwrap();
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 2).build()),
+                         Err(Error::TxNotDisputable)));
+        assert!(matches!(account_admin.apply(Tx::dispute(1, 3).build()),
+                         Err(Error::TxNotFound)));
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().available(), from_f64(-3.0));
+        account_admin.apply(Tx::chargeback(1, 1).build()).unwrap();
+
+        // Locked accounts ignore everything, representments included.
+        assert!(matches!(account_admin.apply(Tx::representment(1, 1).build()),
+                         Err(Error::AccountLocked)));
+        assert_eq!(account_admin.account().total(), from_f64(-3.0));
+    }
+
+    #[test]
+    fn test_client_apply_audit() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let (audit, entries) = std::sync::mpsc::channel();
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.set_audit(audit);
+        account_admin.apply(Tx::deposit(1, 1).amount(5.0).build()).unwrap();
+        assert!(account_admin.apply(Tx::withdrawal(1, 2).amount(6.0).build()).is_err());
+        account_admin.apply(Tx::reversal(1, 1).build()).unwrap();
+        assert_eq!(account_admin.account().transactions(), 3);
+        account_admin.apply(Tx::deposit(1, 3).amount(2.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 3).build()).unwrap();
+        account_admin.apply(Tx::chargeback(1, 3).build()).unwrap();
+        drop(account_admin);
+
+        let entries: Vec<Entry> = entries.iter().collect();
+        assert_eq!(entries.len(), 6);
+        assert!(entries[..5].iter().all(|entry| !entry.locked));
+        assert!(entries[5].locked);
+        assert_eq!(entries[0].outcome, Outcome::Applied);
+        assert_eq!(entries[0].amount, Some(from_f64(5.0)));
+        assert_eq!(entries[1].outcome, Outcome::Rejected("insufficient_funds"));
+        assert_eq!(entries[2].ttype, Type::Reversal);
+        assert_eq!(entries[2].note.as_deref(), Some("reversed deposit of 5.0000"));
+        assert_eq!(entries[1].before, Balance { available: from_f64(5.0), held: ZERO, total: from_f64(5.0) });
+        assert_eq!(entries[1].after, entries[1].before);
+        assert_eq!(entries[2].before, Balance { available: from_f64(5.0), held: ZERO, total: from_f64(5.0) });
+        assert_eq!(entries[2].after, Balance::default());
     }
 
     #[test]
@@ -273,10 +2139,18 @@ mod tests {
         let (_, receiver) = tokio::sync::mpsc::channel(32);
         let client = AccountAdmin::new(1, receiver);
         assert_eq!(client.account.client_id, 1);
-        assert_eq!(client.account, Account::new(1,0.0, 0.0, false));
+        assert_eq!(client.account, Account::new(1,ZERO, ZERO, Status::Active));
         assert!(client.tx_history.is_empty());
     }
 
+    #[test]
+    fn test_client_new_with_tenant() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let client = AccountAdmin::new_with_tenant(1, Some(String::from("acme")), receiver);
+        assert_eq!(client.account().tenant(), Some("acme"));
+        assert_eq!(client.id(), 1);
+    }
+
     #[test]
     fn test_client_id() {
         let (_, receiver) = tokio::sync::mpsc::channel(32);
@@ -297,12 +2171,12 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut account_admin = AccountAdmin::new(1, receiver);
-            sender.send(Transaction::new_with_amount(Type::Deposit, 1, 0, 1.0)).await.unwrap();
+            sender.send(Tx::deposit(1, 0).amount(1.0).build()).await.unwrap();
             account_admin.handle().await.unwrap();
-            assert_eq!(account_admin.account().available(), 1.0);
-            assert_eq!(account_admin.account().held(), 0.0);
-            assert_eq!(account_admin.account().total(), 1.0);
-            assert_eq!(account_admin.account().is_locked(), false);
+            assert_eq!(account_admin.account().available(), from_f64(1.0));
+            assert_eq!(account_admin.account().held(), ZERO);
+            assert_eq!(account_admin.account().total(), from_f64(1.0));
+            assert!(!account_admin.account().is_locked());
             assert!(account_admin.tx_history.contains_key(&0));
         });
     }
@@ -313,13 +2187,13 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut account_admin = AccountAdmin::new(1, receiver);
-            account_admin.account.available = 2.0;
-            sender.send(Transaction::new_with_amount(Type::Withdrawal, 1, 0, 1.0)).await.unwrap();
+            account_admin.account.available = from_f64(2.0);
+            sender.send(Tx::withdrawal(1, 0).amount(1.0).build()).await.unwrap();
             account_admin.handle().await.unwrap();
-            assert_eq!(account_admin.account().available(), 1.0);
-            assert_eq!(account_admin.account().held(), 0.0);
-            assert_eq!(account_admin.account().total(), 1.0);
-            assert_eq!(account_admin.account().is_locked(), false);
+            assert_eq!(account_admin.account().available(), from_f64(1.0));
+            assert_eq!(account_admin.account().held(), ZERO);
+            assert_eq!(account_admin.account().total(), from_f64(1.0));
+            assert!(!account_admin.account().is_locked());
             assert!(account_admin.tx_history.contains_key(&0));
         });
     }
@@ -330,22 +2204,22 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut account_admin = AccountAdmin::new(1, receiver);
-            account_admin.account.available = 2.0;
-            account_admin.tx_history.insert(0, Transaction::new_with_amount(Type::Deposit, 1, 0, 2.0));
-            sender.send(Transaction::new(Type::Dispute, 1, 0)).await.unwrap();
+            account_admin.account.available = from_f64(2.0);
+            account_admin.tx_history.insert(0, Tx::deposit(1, 0).amount(2.0).build());
+            sender.send(Tx::dispute(1, 0).build()).await.unwrap();
             account_admin.handle().await.unwrap();
-            assert_eq!(account_admin.account().available(), 0.0);
-            assert_eq!(account_admin.account().held(), 2.0);
-            assert_eq!(account_admin.account().is_locked(), false);
-            assert_eq!(account_admin.account().total(), 2.0);
+            assert_eq!(account_admin.account().available(), ZERO);
+            assert_eq!(account_admin.account().held(), from_f64(2.0));
+            assert!(!account_admin.account().is_locked());
+            assert_eq!(account_admin.account().total(), from_f64(2.0));
             assert!(account_admin.tx_history.get(&0).unwrap().is_disputed());
             assert!(!account_admin.tx_history.get(&0).unwrap().is_resolved());
             assert!(!account_admin.tx_history.get(&0).unwrap().is_charged_back());
-            sender.send(Transaction::new(Type::Dispute, 1, 0)).await.unwrap();
+            sender.send(Tx::dispute(1, 0).build()).await.unwrap();
             assert!(account_admin.handle().await.is_err());
             account_admin.tx_history.get_mut(&0).unwrap().clear_flags();
             account_admin.account.set_locked(true);
-            sender.send(Transaction::new(Type::Dispute, 1, 0)).await.unwrap();
+            sender.send(Tx::dispute(1, 0).build()).await.unwrap();
             assert!(account_admin.handle().await.is_err());
         });
     }
@@ -356,23 +2230,23 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut account_admin = AccountAdmin::new(1, receiver);
-            account_admin.account.held = 2.0;
-            account_admin.tx_history.insert(0, Transaction::new_with_amount(Type::Deposit, 1, 0, 2.0));
+            account_admin.account.held = from_f64(2.0);
+            account_admin.tx_history.insert(0, Tx::deposit(1, 0).amount(2.0).build());
             account_admin.tx_history.get_mut(&0).unwrap().mark_disputed();
-            sender.send(Transaction::new(Type::Resolve, 1, 0)).await.unwrap();
+            sender.send(Tx::resolve(1, 0).build()).await.unwrap();
             account_admin.handle().await.unwrap();
-            assert_eq!(account_admin.account().held(), 0.0);
-            assert_eq!(account_admin.account().available(), 2.0);
-            assert_eq!(account_admin.account().total(), 2.0);
-            assert_eq!(account_admin.account().is_locked(), false);
+            assert_eq!(account_admin.account().held(), ZERO);
+            assert_eq!(account_admin.account().available(), from_f64(2.0));
+            assert_eq!(account_admin.account().total(), from_f64(2.0));
+            assert!(!account_admin.account().is_locked());
             assert!(!account_admin.tx_history.get(&0).unwrap().is_disputed());
             assert!(account_admin.tx_history.get(&0).unwrap().is_resolved());
             assert!(!account_admin.tx_history.get(&0).unwrap().is_charged_back());
-            sender.send(Transaction::new(Type::Resolve, 1, 0)).await.unwrap();
+            sender.send(Tx::resolve(1, 0).build()).await.unwrap();
             assert!(account_admin.handle().await.is_err());
             account_admin.tx_history.get_mut(&0).unwrap().clear_flags();
             account_admin.account.set_locked(true);
-            sender.send(Transaction::new(Type::Resolve, 1, 0)).await.unwrap();
+            sender.send(Tx::resolve(1, 0).build()).await.unwrap();
             assert!(account_admin.handle().await.is_err());
         });
     }
@@ -383,28 +2257,54 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut account_admin = AccountAdmin::new(1, receiver);
-            account_admin.account.held = 2.0;
-            account_admin.tx_history.insert(0, Transaction::new_with_amount(Type::Deposit, 1, 0, 2.0));
+            account_admin.account.held = from_f64(2.0);
+            account_admin.tx_history.insert(0, Tx::deposit(1, 0).amount(2.0).build());
             account_admin.tx_history.get_mut(&0).unwrap().mark_disputed();
-            sender.send(Transaction::new(Type::Chargeback, 1, 0)).await.unwrap();
+            sender.send(Tx::chargeback(1, 0).build()).await.unwrap();
             account_admin.handle().await.unwrap();
-            assert_eq!(account_admin.account().held(), 0.0);
-            assert_eq!(account_admin.account().available(), 0.0);
-            assert_eq!(account_admin.account().total(), 0.0);
-            assert_eq!(account_admin.account().is_locked(), true);
-            assert_eq!(account_admin.tx_history.get(&0).unwrap().is_disputed(), false);
-            assert_eq!(account_admin.tx_history.get(&0).unwrap().is_resolved(), false);
-            assert_eq!(account_admin.tx_history.get(&0).unwrap().is_charged_back(), true);
+            assert_eq!(account_admin.account().held(), ZERO);
+            assert_eq!(account_admin.account().available(), ZERO);
+            assert_eq!(account_admin.account().total(), ZERO);
+            assert!(account_admin.account().is_locked());
+            assert!(!account_admin.tx_history.get(&0).unwrap().is_disputed());
+            assert!(!account_admin.tx_history.get(&0).unwrap().is_resolved());
+            assert!(account_admin.tx_history.get(&0).unwrap().is_charged_back());
             // Try to charge back the same transaction again results in error, because it was already
             // disputed.
-            sender.send(Transaction::new(Type::Chargeback, 1, 0)).await.unwrap();
+            sender.send(Tx::chargeback(1, 0).build()).await.unwrap();
             assert!(account_admin.handle().await.is_err());
             account_admin.tx_history.get_mut(&0).unwrap().clear_flags();
             // Even if the transaction flags are cleared, the account is locked after a `chargeback`,
             // so retrying the operation again result in error.
-            sender.send(Transaction::new(Type::Chargeback, 1, 0)).await.unwrap();
+            sender.send(Tx::chargeback(1, 0).build()).await.unwrap();
             assert!(account_admin.handle().await.is_err());
         });
     }
 
+    #[test]
+    fn test_client_control_lane() {
+        let (sender, receiver) = tokio::sync::mpsc::channel(32);
+        let (lane, control) = tokio::sync::mpsc::channel(32);
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut account_admin = AccountAdmin::new(1, receiver);
+            account_admin.set_control(control);
+            for tx_id in 1..3 {
+                sender.send(Tx::withdrawal(1, tx_id).amount(1.0).build()).await.unwrap();
+            }
+            lane.send(Tx::freeze(1, 3).build()).await.unwrap();
+            // The freeze overtakes the queued withdrawals, which are rejected.
+            account_admin.handle().await.unwrap();
+            assert_eq!(account_admin.account().status(), Status::Frozen(FreezeMode::Withdrawals));
+            assert!(matches!(account_admin.handle().await, Err(Error::AccountFrozen)));
+
+            // Closing hands back the queued transactions, those of the lane first.
+            drop(sender);
+            lane.send(Tx::unfreeze(1, 4).build()).await.unwrap();
+            drop(lane);
+            let ttypes: Vec<Type> = account_admin.close().await.iter().map(Transaction::transaction_type).collect();
+            assert_eq!(ttypes, vec![Type::Unfreeze, Type::Withdrawal]);
+            assert!(account_admin.recv().await.is_none());
+        });
+    }
 }
\ No newline at end of file
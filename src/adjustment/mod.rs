@@ -0,0 +1,103 @@
+// Manual adjustments of the accounts, e.g. an operator crediting a goodwill gesture or debiting a
+// correction, made while a run goes on rather than through its input. They are sent as `credit`
+// and `debit` transactions into a `ChannelSource`, read along with the input by `Sources`, so they
+// go through the same tenant dispatcher and the same queue of the account admin as the
+// transactions of the client: an adjustment is applied between two transactions of the stream,
+// never concurrently with one, and the replay protection, the policy rules and the audit trail
+// apply to it like to any other transaction.
+
+use std::sync::mpsc::{channel, Sender};
+
+use crate::amount::{Amount, ZERO};
+use crate::transaction::{ChannelSource, Transaction, Type};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // The amount of an adjustment must be positive, a debit being the negative adjustment.
+    NotPositive,
+    // The run is over, so the adjustment can not be applied anymore.
+    Closed,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// Handle over the adjustments of a run, which can be cloned to adjust the accounts from several
+// threads. The run goes on until every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct Adjustments {
+    sender: Sender<Transaction>,
+}
+
+impl Adjustments {
+    // The handle, along with the source to read into the run.
+    pub fn channel() -> (Adjustments, ChannelSource) {
+        let (sender, receiver) = channel();
+        (Adjustments { sender }, ChannelSource::new(receiver))
+    }
+
+    // Same as `channel`, for the runs over the accounts of tenants.
+    pub fn channel_with_tenant() -> (Adjustments, ChannelSource) {
+        let (sender, receiver) = channel();
+        (Adjustments { sender }, ChannelSource::new_with_tenant(receiver))
+    }
+
+    // Adds `amount` to the available funds of the client, under a tx id of its own.
+    pub fn credit(&self, tenant: Option<&str>, client_id: u16, tx_id: u32, amount: Amount) -> Result<()> {
+        self.send(Type::Credit, tenant, client_id, tx_id, amount)
+    }
+
+    // Takes `amount` out of the available funds of the client, which is rejected with
+    // `insufficient_funds` when they are not enough.
+    pub fn debit(&self, tenant: Option<&str>, client_id: u16, tx_id: u32, amount: Amount) -> Result<()> {
+        self.send(Type::Debit, tenant, client_id, tx_id, amount)
+    }
+
+    fn send(&self, ttype: Type, tenant: Option<&str>, client_id: u16, tx_id: u32, amount: Amount) -> Result<()> {
+        if amount <= ZERO {
+            return Err(Error::NotPositive);
+        }
+        let mut tx = Transaction::new_with_amount(ttype, client_id, tx_id, amount);
+        tx.set_tenant(tenant.map(String::from));
+        self.sender.send(tx).map_err(|_| Error::Closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::from_f64;
+    use crate::config::Config;
+    use crate::input::Input;
+    use crate::output::Collector;
+    use crate::sources::Sources;
+    use crate::state::State;
+    use crate::transaction::{input_source, resume_source};
+
+    #[test]
+    fn test_adjustments() {
+        let (adjustments, source) = Adjustments::channel();
+        assert_eq!(adjustments.credit(None, 1, 10, ZERO), Err(Error::NotPositive));
+        adjustments.credit(None, 1, 10, from_f64(2.5)).unwrap();
+        adjustments.debit(None, 1, 11, from_f64(1.0)).unwrap();
+        adjustments.debit(None, 1, 12, from_f64(9.0)).unwrap();
+        drop(adjustments);
+
+        let collector = Collector::new();
+        let config = Config { account_sinks: vec![collector.factory()], ..Config::default() };
+        let input = Input::from(&b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,3.0\n"[..]);
+        let mut sources = Sources::new(config.tenant_buffer);
+        sources.add("input", input_source(input, &config).unwrap());
+        sources.add("adjustments", source);
+        let run_report = resume_source(State::default(), sources, &config).unwrap();
+        assert_eq!(run_report.rejected, 1);
+        assert_eq!(run_report.volumes.adjustments.count, 2);
+        assert_eq!(run_report.volumes.adjustments.amount, from_f64(1.5));
+        let mut accounts: Vec<String> = collector.take().iter().map(|account| account.to_string()).collect();
+        accounts.sort();
+        assert_eq!(accounts, vec!["1,2.5000,0.0000,2.5000,false,active", "2,3.0000,0.0000,3.0000,false,active"]);
+
+        let (adjustments, source) = Adjustments::channel();
+        drop(source);
+        assert_eq!(adjustments.credit(None, 1, 1, from_f64(1.0)), Err(Error::Closed));
+    }
+}
@@ -0,0 +1,146 @@
+// Time bucketed aggregates of the applied transactions, derived from the audit trail.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::amount::{self, Amount, ZERO};
+use crate::audit::{Entry, Outcome, Sink};
+use crate::config::Labels;
+use crate::transaction::Type;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// Length of the aggregation periods, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Bucket {
+    #[default]
+    Day,
+    Month
+}
+
+impl Bucket {
+    // Formats the period a timestamp falls in as `YYYY-MM-DD` or `YYYY-MM`.
+    pub fn period(&self, timestamp: u64) -> String {
+        let (year, month, day) = civil_date(timestamp / SECONDS_PER_DAY);
+        match self {
+            Bucket::Day => format!("{:04}-{:02}-{:02}", year, month, day),
+            Bucket::Month => format!("{:04}-{:02}", year, month)
+        }
+    }
+}
+
+// Converts a number of days since the epoch to a (year, month, day) date of the proleptic
+// Gregorian calendar, following http://howardhinnant.github.io/date_algorithms.html.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Totals {
+    deposits: Amount,
+    withdrawals: Amount,
+    chargebacks: u64,
+}
+
+// Writes the totals of every client and period with respect to the
+// `tenant,client,period,deposits,withdrawals,net,chargebacks` schema. Transactions without a
+// timestamp do not fall in any period, so they are left out.
+pub struct Aggregates {
+    path: PathBuf,
+    bucket: Bucket,
+    labels: Labels,
+    totals: BTreeMap<(Option<String>, u16, String), Totals>,
+}
+
+impl Aggregates {
+    pub fn new(path: PathBuf, bucket: Bucket, labels: Labels) -> Self {
+        Aggregates { path, bucket, labels, totals: BTreeMap::new() }
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "tenant,client,period,deposits,withdrawals,net,chargebacks")?;
+        for ((tenant, client_id, period), totals) in self.totals.iter() {
+            let label = self.labels.label(*client_id);
+            writeln!(writer, "{},{},{},{},{},{},{}", tenant.as_deref().unwrap_or(""), label, period,
+                     amount::fmt(totals.deposits), amount::fmt(totals.withdrawals),
+                     amount::fmt(totals.deposits - totals.withdrawals), totals.chargebacks)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Aggregates {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        let timestamp = match entry.timestamp {
+            Some(timestamp) if entry.outcome == Outcome::Applied => timestamp,
+            _ => return Ok(())
+        };
+
+        let totals = self.totals.entry((entry.tenant.clone(), entry.client_id, self.bucket.period(timestamp)))
+            .or_default();
+        match entry.ttype {
+            Type::Deposit => totals.deposits += entry.amount.unwrap_or(ZERO),
+            Type::Withdrawal => totals.withdrawals += entry.amount.unwrap_or(ZERO),
+            Type::Chargeback => totals.chargebacks += 1,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use crate::audit::Balance;
+    use crate::amount::from_f64;
+
+    #[test]
+    fn test_bucket_period() {
+        assert_eq!(Bucket::Day.period(0), "1970-01-01");
+        assert_eq!(Bucket::Day.period(951_782_400), "2000-02-29");
+        assert_eq!(Bucket::Day.period(1_633_046_399), "2021-09-30");
+        assert_eq!(Bucket::Month.period(1_633_046_400), "2021-10");
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let mut aggregates = Aggregates::new(PathBuf::new(), Bucket::Month, Labels::default());
+        let entry = |ttype, amount, outcome, timestamp| Entry {
+            tenant: None, client_id: 1, tx_id: 1, ttype, amount, outcome, note: None,
+            before: Balance::default(), after: Balance::default(), timestamp, locked: false,
+            previous_status: Status::Active, status: Status::Active, record: None, source: None
+        };
+        aggregates.record(&entry(Type::Deposit, Some(from_f64(5.0)), Outcome::Applied, Some(1_633_046_400))).unwrap();
+        aggregates.record(&entry(Type::Withdrawal, Some(from_f64(2.0)), Outcome::Applied, Some(1_633_132_800))).unwrap();
+        aggregates.record(&entry(Type::Withdrawal, Some(from_f64(9.0)), Outcome::Rejected("insufficient_funds"),
+                                 Some(1_633_132_800))).unwrap();
+        aggregates.record(&entry(Type::Chargeback, None, Outcome::Applied, Some(1_633_132_800))).unwrap();
+        aggregates.record(&entry(Type::Deposit, Some(from_f64(1.0)), Outcome::Applied, Some(1_633_046_399))).unwrap();
+        aggregates.record(&entry(Type::Deposit, Some(from_f64(1.0)), Outcome::Applied, None)).unwrap();
+
+        let mut out = Vec::new();
+        aggregates.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,period,deposits,withdrawals,net,chargebacks\n\
+                   ,1,2021-09,1.0000,0.0000,1.0000,0\n\
+                   ,1,2021-10,5.0000,2.0000,3.0000,1\n");
+    }
+}
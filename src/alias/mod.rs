@@ -0,0 +1,85 @@
+// Client id aliasing, for inputs merging the feeds of systems with colliding client id spaces.
+// A mapping file gives the internal id of every external client identifier: the records are
+// parsed into the internal ids, and the outputs are written with the external identifiers back.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The row on this line, counting from 1, is not a valid `external,client` pair.
+    InvalidRow(usize),
+    // The external identifier or the internal id on this line was mapped before.
+    Duplicate(usize),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+const HEADER: &str = "external,client";
+
+#[derive(Debug, Default)]
+pub struct Aliases {
+    internal: HashMap<String, u16>,
+    external: HashMap<u16, String>,
+}
+
+impl Aliases {
+    // Reads a mapping with respect to the `external,client` schema. Every external identifier
+    // maps to a single internal id and the other way around, so that the outputs can be mapped
+    // back. External identifiers end up in the outputs, so they obey the rules of tenant names.
+    pub fn parse<R: BufRead>(reader: R) -> Result<Aliases> {
+        let mut aliases = Aliases::default();
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line.map_err(Error::Io)?;
+            let line = line.trim();
+            if idx == 0 && line == HEADER || line.is_empty() {
+                continue;
+            }
+            let (external, client_id) = line.split_once(',')
+                .and_then(|(external, client_id)| Some((external.trim(), client_id.trim().parse::<u16>().ok()?)))
+                .filter(|(external, _)| !external.is_empty() && external.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .ok_or(Error::InvalidRow(idx + 1))?;
+            if aliases.internal.contains_key(external) || aliases.external.contains_key(&client_id) {
+                return Err(Error::Duplicate(idx + 1));
+            }
+            aliases.internal.insert(external.to_string(), client_id);
+            aliases.external.insert(client_id, external.to_string());
+        }
+        Ok(aliases)
+    }
+
+    pub fn read_file(path: &Path) -> Result<Aliases> {
+        Aliases::parse(BufReader::new(File::open(path).map_err(Error::Io)?))
+    }
+
+    pub fn internal(&self, external: &str) -> Option<u16> {
+        self.internal.get(external).copied()
+    }
+
+    pub fn external(&self, client_id: u16) -> Option<&str> {
+        self.external.get(&client_id).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases() {
+        let aliases = Aliases::parse(&b"external,client\nbank-a-17,1\nbank-b-17,2\n\n"[..]).unwrap();
+        assert_eq!(aliases.internal("bank-b-17"), Some(2));
+        assert_eq!(aliases.internal("17"), None);
+        assert_eq!(aliases.external(1), Some("bank-a-17"));
+        assert_eq!(aliases.external(3), None);
+
+        assert!(matches!(Aliases::parse(&b"external,client\na,1\nb\n"[..]), Err(Error::InvalidRow(3))));
+        assert!(matches!(Aliases::parse(&b"a b,1\n"[..]), Err(Error::InvalidRow(1))));
+        assert!(matches!(Aliases::parse(&b"a,1\na,2\n"[..]), Err(Error::Duplicate(2))));
+        assert!(matches!(Aliases::parse(&b"a,1\nb,1\n"[..]), Err(Error::Duplicate(2))));
+    }
+}
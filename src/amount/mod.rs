@@ -0,0 +1,246 @@
+// Representation of the monetary amounts. An `Amount` is a fixed-point number with exactly four
+// decimal places, stored as an `i64` count of 1/10000 units, so that all the arithmetic is exact
+// and done in integers.
+//
+// Rounding rules: the amounts parsed from the input and converted from floating point numbers are
+// rounded to the nearest 1/10000, half away from zero. Adding, subtracting and negating amounts is
+// exact, and panics on an overflow instead of wrapping, in every build profile. The balances of the
+// accounts are moved with the checked operations, so that an overflow rejects a transaction.
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+// Number of decimal places the amounts are written with, and the precision of the minor units.
+pub const DECIMALS: usize = 4;
+const MINOR_PER_UNIT: i64 = 10_000;
+
+pub const ZERO: Amount = Amount(0);
+
+impl Amount {
+    // The amount of `minor` 1/10000 units.
+    pub const fn from_minor(minor: i64) -> Self {
+        Amount(minor)
+    }
+
+    pub fn minor(self) -> i64 {
+        self.0
+    }
+
+    pub fn abs(self) -> Self {
+        Amount(self.0.checked_abs().expect("amount overflow"))
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    // For the statistics over many accounts, e.g. the volumes of a run, which may not fit
+    // although the balance of every account does.
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        self.checked_add(other).expect("amount overflow")
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        self.checked_sub(other).expect("amount overflow")
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(self.0.checked_neg().expect("amount overflow"))
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Amount {
+        iter.copied().sum()
+    }
+}
+
+// Parses a decimal amount, e.g. `1.5` or `-0.0001`. Only plain decimal notation is accepted and the
+// amount is rounded to the nearest minor unit, half away from zero.
+pub fn parse(value: &str) -> Option<Amount> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value.strip_prefix('+').unwrap_or(value))
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && fraction.is_empty()
+        || !whole.bytes().chain(fraction.bytes()).all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut minor: i64 = 0;
+    for byte in whole.bytes() {
+        minor = minor.checked_mul(10)?.checked_add((byte - b'0') as i64)?;
+    }
+    minor = minor.checked_mul(MINOR_PER_UNIT)?;
+    let mut unit = MINOR_PER_UNIT;
+    for byte in fraction.bytes().take(DECIMALS) {
+        unit /= 10;
+        minor = minor.checked_add((byte - b'0') as i64 * unit)?;
+    }
+    if fraction.as_bytes().get(DECIMALS).is_some_and(|byte| *byte >= b'5') {
+        minor = minor.checked_add(1)?;
+    }
+    Some(Amount(if negative { -minor } else { minor }))
+}
+
+// Why the amount of a transaction can not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Invalid {
+    // Not a plain decimal number, e.g. `inf`, `NaN` or `1e3`, or out of range.
+    Malformed,
+    Negative,
+    // The amount has more decimal places than the scale allows.
+    Scale { decimals: usize },
+}
+
+// Parses the amount of a transaction: a plain decimal number, not negative, with at most `scale`
+// decimal places, trailing zeros aside. The transactions of the inputs get their amount from it,
+// so that an account admin never moves a negative amount, e.g. a withdrawal crediting funds.
+pub fn parse_transaction(value: &str, scale: usize) -> Result<Amount, Invalid> {
+    let decimals = decimals(value);
+    if decimals > scale {
+        return Err(Invalid::Scale { decimals });
+    }
+    match parse(value) {
+        Some(amount) if amount < ZERO => Err(Invalid::Negative),
+        Some(amount) => Ok(amount),
+        None => Err(Invalid::Malformed)
+    }
+}
+
+// Number of decimal places of a decimal amount, as written, trailing zeros aside.
+pub fn decimals(value: &str) -> usize {
+    value.split_once('.').map_or(0, |(_, fraction)| fraction.trim_end_matches('0').len())
+}
+
+// Converts a floating point amount, e.g. a threshold, rounding it to the nearest minor unit, half
+// away from zero. Out of range values saturate.
+pub fn from_f64(value: f64) -> Amount {
+    Amount((value * MINOR_PER_UNIT as f64).round() as i64)
+}
+
+// Converts an amount to a floating point number, for ratios and rankings.
+pub fn to_f64(amount: Amount) -> f64 {
+    amount.0 as f64 / MINOR_PER_UNIT as f64
+}
+
+// Formats an amount with four decimal places, e.g. `format!("{}", amount::fmt(amount))`.
+pub fn fmt(amount: Amount) -> Formatted {
+    Formatted(amount)
+}
+
+pub struct Formatted(Amount);
+
+impl fmt::Display for Formatted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < ZERO { "-" } else { "" };
+        let minor = self.0.0.unsigned_abs();
+        let per_unit = MINOR_PER_UNIT as u64;
+        write!(f, "{}{}.{:0width$}", sign, minor / per_unit, minor % per_unit, width = DECIMALS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_parse() {
+        assert_eq!(parse("1.5"), Some(Amount(15_000)));
+        assert_eq!(parse("-0.0001"), Some(Amount(-1)));
+        assert_eq!(parse("12"), Some(Amount(120_000)));
+        assert_eq!(parse("+2"), Some(Amount(20_000)));
+        assert_eq!(parse(".25"), Some(Amount(2_500)));
+        assert_eq!(parse("0.00005"), Some(Amount(1)));
+        assert_eq!(parse("-0.00005"), Some(Amount(-1)));
+        assert_eq!(parse("0.00004"), Some(ZERO));
+        for invalid in ["", "-", ".", "one", "1e3", "inf", "1.2.3", "1,5", "922337203685477.5808"].iter() {
+            assert_eq!(parse(invalid), None);
+        }
+    }
+
+    #[test]
+    fn test_amount_parse_transaction() {
+        assert_eq!(parse_transaction("1.50", 1), Ok(Amount(15_000)));
+        assert_eq!(parse_transaction("0", 4), Ok(ZERO));
+        assert_eq!(parse_transaction("-0.5", 4), Err(Invalid::Negative));
+        assert_eq!(parse_transaction("1.25", 1), Err(Invalid::Scale { decimals: 2 }));
+        for malformed in ["inf", "NaN", "1e3", "1.2.3", "922337203685477.5808"].iter() {
+            assert_eq!(parse_transaction(malformed, 4), Err(Invalid::Malformed));
+        }
+    }
+
+    #[test]
+    fn test_amount_decimals() {
+        assert_eq!(decimals("1"), 0);
+        assert_eq!(decimals("1.5"), 1);
+        assert_eq!(decimals("-0.0001"), 4);
+        assert_eq!(decimals("1.50000"), 1);
+        assert_eq!(decimals("0.00001"), 5);
+    }
+
+    #[test]
+    fn test_amount_fmt() {
+        assert_eq!(fmt(from_f64(1.5)).to_string(), "1.5000");
+        assert_eq!(fmt(from_f64(-2.25)).to_string(), "-2.2500");
+        assert_eq!(fmt(from_f64(0.12345)).to_string(), "0.1235");
+        assert_eq!(fmt(Amount(-15_001)).to_string(), "-1.5001");
+        assert_eq!(fmt(Amount(7)).to_string(), "0.0007");
+        assert_eq!(to_f64(ZERO), 0.0);
+        assert_eq!(to_f64(Amount(12_500)), 1.25);
+    }
+
+    #[test]
+    fn test_amount_arithmetic() {
+        let (a, b) = (from_f64(0.1), from_f64(0.2));
+        assert_eq!(a + b, from_f64(0.3));
+        assert_eq!(a - b, -a);
+        assert_eq!([a, b, -a].iter().sum::<Amount>(), b);
+        assert_eq!((-b).abs(), b);
+        assert_eq!(Amount(i64::MAX).checked_add(Amount(1)), None);
+        assert!(std::panic::catch_unwind(|| Amount(i64::MIN) - Amount(1)).is_err());
+    }
+}
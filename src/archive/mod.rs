@@ -0,0 +1,179 @@
+// Cold archive of the transaction history. The account admins move their old transactions out of
+// the history to a gzip compressed archive file, which the `inspect` tooling can still query.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::amount;
+use crate::gzip::GzEncoder;
+use crate::inspect::AsOf;
+use crate::transaction::Transaction;
+
+const HEADER: &str = "tenant,client,tx,type,amount,refunded,state,record,timestamp";
+
+// How the dispute lifecycle of an archived transaction ended, if it was ever disputed.
+fn state(tx: &Transaction) -> &'static str {
+    if tx.is_represented() {
+        "represented"
+    } else if tx.is_charged_back() {
+        "charged_back"
+    } else if tx.is_resolved() {
+        "resolved"
+    } else {
+        ""
+    }
+}
+
+fn optional(value: Option<u64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Writes the transactions with respect to the
+// `tenant,client,tx,type,amount,refunded,state,record,timestamp` schema.
+pub fn write_record<W: Write>(writer: &mut W, tx: &Transaction) -> io::Result<()> {
+    let amount = tx.amount().map(|amount| amount::fmt(amount).to_string()).unwrap_or_default();
+    writeln!(writer, "{},{},{},{},{},{},{},{},{}", tx.tenant().unwrap_or(""), tx.client_id(), tx.tx_id(),
+             tx.transaction_type().as_str(), amount, amount::fmt(tx.refunded()), state(tx), optional(tx.record()),
+             optional(tx.timestamp()))
+}
+
+// The account admins send the transactions they archive to a dedicated thread that owns the
+// archive file, so the compression stays off the async runtime.
+pub struct Archiver {
+    sender: Sender<Transaction>,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl Archiver {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut encoder = GzEncoder::new(BufWriter::new(File::create(path)?))?;
+        writeln!(encoder, "{}", HEADER)?;
+        let (sender, receiver) = channel::<Transaction>();
+        let handle = thread::spawn(move || {
+            let mut result = Ok(());
+            for tx in receiver {
+                result = result.and(write_record(&mut encoder, &tx));
+            }
+            result.and(encoder.finish().map(|_| ()))
+        });
+        Ok(Archiver { sender, handle })
+    }
+
+    pub fn sender(&self) -> Sender<Transaction> {
+        self.sender.clone()
+    }
+
+    // Waits for the archived transactions of all the senders to be written. All the other
+    // senders must be dropped before.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.sender);
+        self.handle.join().unwrap_or_else(|_| Err(io::Error::other("archiver panicked")))
+    }
+}
+
+// Reads a file, decompressing it if it is gzip compressed.
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if data.starts_with(&[0x1f, 0x8b]) {
+        crate::gzip::decode(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+pub fn is_archive(header: &str) -> bool {
+    header.trim_end() == HEADER
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+// Returns the archived transactions of a client up to `as_of`, optionally only those of a
+// transaction id, as they were written.
+pub fn archived_as_of<R: BufRead>(reader: R, tenant: Option<&str>, client_id: u16, tx_id: Option<u32>, as_of: AsOf)
+                                  -> io::Result<Vec<String>> {
+    let mut lines = reader.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    if !is_archive(&header) {
+        return Err(invalid_data("not a history archive"));
+    }
+
+    let mut archived = Vec::new();
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        let parse = |idx: usize| -> io::Result<Option<u64>> {
+            match fields.get(idx) {
+                Some(&"") => Ok(None),
+                Some(value) => value.parse().map(Some).map_err(|_| invalid_data("invalid archive record")),
+                None => Err(invalid_data("invalid archive record"))
+            }
+        };
+        if fields[0] != tenant.unwrap_or("") || parse(1)? != Some(client_id as u64) {
+            continue;
+        }
+        if tx_id.is_some() && parse(2)? != tx_id.map(u64::from) {
+            continue;
+        }
+        let included = match as_of {
+            AsOf::Record(record) => parse(7)?.map_or(true, |at| at <= record),
+            AsOf::Timestamp(timestamp) => parse(8)?.map_or(true, |at| at <= timestamp)
+        };
+        if included {
+            archived.push(line);
+        }
+    }
+    Ok(archived)
+}
+
+pub fn write_archived<W: Write>(writer: &mut W, archived: &[String]) -> io::Result<()> {
+    writeln!(writer, "{}", HEADER)?;
+    for line in archived {
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+
+    fn tx(client_id: u16, tx_id: u32, record: u64) -> Transaction {
+        let mut tx = Tx::deposit(client_id, tx_id).amount(1.5).build();
+        tx.set_record(Some(record));
+        tx.set_timestamp(Some(100 + record));
+        tx
+    }
+
+    #[test]
+    fn test_archiver() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.csv.gz");
+        let archiver = Archiver::create(&path).unwrap();
+        let sender = archiver.sender();
+        let mut charged_back = tx(1, 3, 3);
+        charged_back.mark_disputed();
+        charged_back.mark_charged_back();
+        for tx in [tx(1, 1, 1), tx(2, 2, 2), charged_back].iter() {
+            sender.send(tx.clone()).unwrap();
+        }
+        drop(sender);
+        archiver.finish().unwrap();
+
+        let data = read(&path).unwrap();
+        let archived = archived_as_of(&data[..], None, 1, None, AsOf::Record(u64::MAX)).unwrap();
+        assert_eq!(archived, vec![",1,1,deposit,1.5000,0.0000,,1,101", ",1,3,deposit,1.5000,0.0000,charged_back,3,103"]);
+        assert_eq!(archived_as_of(&data[..], None, 1, Some(3), AsOf::Timestamp(102)).unwrap().len(), 0);
+        assert_eq!(archived_as_of(&data[..], None, 1, Some(3), AsOf::Timestamp(103)).unwrap().len(), 1);
+        assert!(archived_as_of(&b"tenant,client,tx\n"[..], None, 1, None, AsOf::Record(1)).is_err());
+
+        let mut out = Vec::new();
+        write_archived(&mut out, &archived[..1]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{}\n,1,1,deposit,1.5000,0.0000,,1,101\n", HEADER));
+    }
+}
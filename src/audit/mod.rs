@@ -0,0 +1,261 @@
+// Audit trail of every transaction handled by the account admins, with its outcome.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::account::Status;
+use crate::amount::{self, Amount};
+use crate::digest::to_hex;
+use crate::merkle::Chain;
+use crate::transaction::Type;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Applied,
+    Rejected(&'static str),
+}
+
+// Funds of an account at some point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub tenant: Option<String>,
+    pub client_id: u16,
+    pub tx_id: u32,
+    pub ttype: Type,
+    pub amount: Option<Amount>,
+    pub outcome: Outcome,
+    // Free form details about what the transaction did, e.g. which transaction a reversal undid.
+    pub note: Option<String>,
+    // Funds of the account right before and right after the transaction was handled.
+    pub before: Balance,
+    pub after: Balance,
+    // Time of the account clock when the transaction was handled, if the inputs have timestamps.
+    pub timestamp: Option<u64>,
+    // The transaction locked the account.
+    pub locked: bool,
+    // Status of the account right before and right after the transaction.
+    pub previous_status: Status,
+    pub status: Status,
+    // Position of the input record that triggered the entry, among all the records of the input.
+    pub record: Option<u64>,
+    // Label of the source the record was read from, when the run has several.
+    pub source: Option<String>,
+}
+
+// Writes the entries with respect to the `tenant,client,tx,type,amount,outcome,detail` schema,
+// where the detail is the rejection reason or the note of an applied transaction, followed by the
+// balances before and after the transaction, the input record, the account clock and the source
+// of the record.
+pub fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "tenant,client,tx,type,amount,outcome,detail,available_before,held_before,total_before,\
+                      available_after,held_after,total_after,record,timestamp,source")
+}
+
+pub fn write_entry<W: Write>(writer: &mut W, entry: &Entry) -> io::Result<()> {
+    let amount = entry.amount.map(|amount| amount::fmt(amount).to_string()).unwrap_or_default();
+    let (outcome, detail) = match &entry.outcome {
+        Outcome::Applied => ("applied", entry.note.as_deref().unwrap_or("")),
+        Outcome::Rejected(reason) => ("rejected", *reason)
+    };
+    writeln!(writer, "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}", entry.tenant.as_deref().unwrap_or(""),
+             entry.client_id, entry.tx_id, entry.ttype.as_str(), amount, outcome, detail,
+             amount::fmt(entry.before.available), amount::fmt(entry.before.held), amount::fmt(entry.before.total),
+             amount::fmt(entry.after.available), amount::fmt(entry.after.held), amount::fmt(entry.after.total),
+             optional(entry.record), optional(entry.timestamp), entry.source.as_deref().unwrap_or(""))
+}
+
+impl Entry {
+    // How the transaction moved the funds of the account, the pending funds being the part of the
+    // total that is neither available nor held.
+    pub fn delta(&self) -> Balance {
+        Balance { available: self.after.available - self.before.available, held: self.after.held - self.before.held,
+                  total: self.after.total - self.before.total }
+    }
+
+    // The transaction moved the account to another status.
+    pub fn transitioned(&self) -> bool {
+        self.previous_status != self.status
+    }
+}
+
+fn optional(value: Option<u64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Consumer of the audit entries, called from the audit writer thread. Every entry carries the
+// funds and the status of the account before and after its transaction, so that an observer of the
+// run can follow the changes of the accounts without keeping copies of them.
+pub trait Sink: Send {
+    fn record(&mut self, entry: &Entry) -> io::Result<()>;
+
+    // Called once, after every entry was recorded.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Builds an observer of the run, once the run starts.
+pub type SinkFactory = Arc<dyn Fn() -> io::Result<Box<dyn Sink>> + Send + Sync>;
+
+// The audit trail file.
+pub struct AuditFile {
+    writer: BufWriter<File>,
+    // Set for a chained audit trail, along with the file its Merkle root is written to.
+    chain: Option<(Chain, PathBuf)>,
+}
+
+impl AuditFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer)?;
+        Ok(AuditFile { writer, chain: None })
+    }
+
+    // Every entry gets an extra `hash` column, chaining it to the previous one, while the Merkle
+    // root of the entries is written to `<path>.root` once the trail is finished.
+    pub fn create_chained(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut header = Vec::new();
+        write_header(&mut header)?;
+        writeln!(writer, "{},hash", String::from_utf8_lossy(&header).trim_end())?;
+        let mut root_path = path.as_os_str().to_owned();
+        root_path.push(".root");
+        Ok(AuditFile { writer, chain: Some((Chain::default(), PathBuf::from(root_path))) })
+    }
+}
+
+impl Sink for AuditFile {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        match &mut self.chain {
+            Some((chain, _)) => {
+                let mut line = Vec::new();
+                write_entry(&mut line, entry)?;
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end();
+                writeln!(self.writer, "{},{}", line, to_hex(&chain.push(line)))
+            }
+            None => write_entry(&mut self.writer, entry)
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        match &self.chain {
+            Some((chain, root_path)) => fs::write(root_path, format!("{}\n", to_hex(&chain.root()))),
+            None => Ok(())
+        }
+    }
+}
+
+// The account admins send their entries to a dedicated thread that owns the sinks, so the file
+// writes stay off the async runtime.
+pub struct AuditWriter {
+    sender: Sender<Entry>,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl AuditWriter {
+    // A failing sink does not stop the others, the first error being reported by `finish`.
+    pub fn spawn(mut sinks: Vec<Box<dyn Sink>>) -> Self {
+        let (sender, receiver) = channel::<Entry>();
+        let handle = thread::spawn(move || {
+            let mut result = Ok(());
+            for entry in receiver {
+                for sink in sinks.iter_mut() {
+                    result = result.and(sink.record(&entry));
+                }
+            }
+            for sink in sinks.iter_mut() {
+                result = result.and(sink.finish());
+            }
+            result
+        });
+        AuditWriter { sender, handle }
+    }
+
+    pub fn sender(&self) -> Sender<Entry> {
+        self.sender.clone()
+    }
+
+    // Waits for the entries of all the senders to be written. All the other senders must be
+    // dropped before.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.sender);
+        self.handle.join().unwrap_or_else(|_| Err(io::Error::other("audit writer panicked")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::{from_f64, ZERO};
+
+    fn entry(outcome: Outcome, note: Option<String>) -> Entry {
+        Entry { tenant: None, client_id: 1, tx_id: 2, ttype: Type::Deposit, amount: Some(from_f64(1.5)), outcome, note,
+                before: Balance::default(), after: Balance { available: from_f64(1.5), held: ZERO, total: from_f64(1.5) },
+                timestamp: None, locked: false,
+                previous_status: Status::Active, status: Status::Active, record: Some(3), source: None }
+    }
+
+    #[test]
+    fn test_write_entry() {
+        let mut out = Vec::new();
+        write_header(&mut out).unwrap();
+        write_entry(&mut out, &entry(Outcome::Applied, None)).unwrap();
+        write_entry(&mut out, &entry(Outcome::Rejected("account_frozen"), None)).unwrap();
+        let mut reversal = entry(Outcome::Applied, Some(String::from("reversed deposit")));
+        reversal.ttype = Type::Reversal;
+        reversal.tenant = Some(String::from("acme"));
+        reversal.source = Some(String::from("batch"));
+        write_entry(&mut out, &reversal).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,tx,type,amount,outcome,detail,available_before,held_before,total_before,\
+                   available_after,held_after,total_after,record,timestamp,source\n\
+                   ,1,2,deposit,1.5000,applied,,0.0000,0.0000,0.0000,1.5000,0.0000,1.5000,3,,\n\
+                   ,1,2,deposit,1.5000,rejected,account_frozen,0.0000,0.0000,0.0000,1.5000,0.0000,1.5000,3,,\n\
+                   acme,1,2,reversal,1.5000,applied,reversed deposit,0.0000,0.0000,0.0000,1.5000,0.0000,1.5000,3,,batch\n");
+    }
+
+    #[test]
+    fn test_chained_audit_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.csv");
+        let mut file = AuditFile::create_chained(&path).unwrap();
+        file.record(&entry(Outcome::Applied, None)).unwrap();
+        file.record(&entry(Outcome::Rejected("account_locked"), None)).unwrap();
+        file.finish().unwrap();
+
+        let trail = std::fs::read_to_string(&path).unwrap();
+        assert!(trail.starts_with("tenant,client,tx,type,amount,outcome,detail,available_before,held_before,\
+                                   total_before,available_after,held_after,total_after,record,timestamp,source,hash\n"));
+        let (root, inclusions) = crate::merkle::prove(trail.as_bytes(), None, 1, 2).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("audit.csv.root")).unwrap(), format!("{}\n", to_hex(&root)));
+        assert_eq!(inclusions.len(), 2);
+        assert!(crate::merkle::verify(&inclusions[1].entry, &inclusions[1].proof, &root));
+    }
+
+    #[test]
+    fn test_audit_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.csv");
+        let writer = AuditWriter::spawn(vec![Box::new(AuditFile::create(&path).unwrap())]);
+        let sender = writer.sender();
+        thread::spawn(move || sender.send(entry(Outcome::Applied, None)).unwrap()).join().unwrap();
+        writer.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+                   "tenant,client,tx,type,amount,outcome,detail,available_before,held_before,total_before,\
+                   available_after,held_after,total_after,record,timestamp,source\n\
+                   ,1,2,deposit,1.5000,applied,,0.0000,0.0000,0.0000,1.5000,0.0000,1.5000,3,,\n");
+    }
+}
@@ -0,0 +1,132 @@
+// Backfills of a corrected input over the state of an earlier run: the transactions the resumed
+// history already records, by tx id, are skipped, so that replaying the whole corrected input only
+// applies the transactions that were missing, and the corrections they made are reported.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::amount::{self, Amount};
+use crate::audit::{Entry, Outcome, Sink};
+use crate::config::Labels;
+use crate::report;
+use crate::transaction::{Transaction, Type};
+
+// The transactions of the resumed history, which are not applied again.
+pub struct Recorded {
+    ids: HashSet<(Option<String>, u16, u32)>,
+}
+
+impl Recorded {
+    pub fn new(history: &[Transaction]) -> Self {
+        let ids = history.iter().filter(|tx| tx.transaction_type().starts_new())
+            .map(|tx| (tx.tenant().map(String::from), tx.client_id(), tx.tx_id()))
+            .collect();
+        Recorded { ids }
+    }
+
+    // The transactions referring to an earlier one are never recorded as such: replaying them is
+    // left to the rules of the accounts, e.g. a dispute of a disputed deposit is rejected.
+    pub fn contains(&self, tx: &Transaction) -> bool {
+        tx.transaction_type().starts_new()
+            && self.ids.contains(&(tx.tenant().map(String::from), tx.client_id(), tx.tx_id()))
+    }
+}
+
+struct Correction {
+    tenant: Option<String>,
+    client_id: u16,
+    tx_id: u32,
+    ttype: Type,
+    amount: Option<Amount>,
+    before: Amount,
+    after: Amount,
+}
+
+// Report of the transactions a backfill applied, along with the total funds of their account
+// right before and right after.
+pub struct Corrections {
+    path: PathBuf,
+    labels: Labels,
+    corrections: Vec<Correction>,
+}
+
+impl Corrections {
+    pub fn new(path: PathBuf, labels: Labels) -> Self {
+        Corrections { path, labels, corrections: Vec::new() }
+    }
+
+    // Writes the corrections with respect to the `tenant,client,tx,type,amount,before,after` schema.
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "tenant,client,tx,type,amount,before,after")?;
+        for correction in &self.corrections {
+            let amount = correction.amount.map(|amount| amount::fmt(amount).to_string()).unwrap_or_default();
+            writeln!(writer, "{},{},{},{},{},{},{}", correction.tenant.as_deref().unwrap_or(""),
+                     self.labels.label(correction.client_id), correction.tx_id, correction.ttype.as_str(), amount,
+                     amount::fmt(correction.before), amount::fmt(correction.after))?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Corrections {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        if entry.outcome == Outcome::Applied {
+            self.corrections.push(Correction {
+                tenant: entry.tenant.clone(),
+                client_id: entry.client_id,
+                tx_id: entry.tx_id,
+                ttype: entry.ttype.clone(),
+                amount: entry.amount,
+                before: entry.before.total,
+                after: entry.after.total,
+            });
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        report::write_file(&self.path, |writer| self.write(writer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::account::Status;
+    use crate::audit::Balance;
+    use crate::amount::{from_f64, ZERO};
+
+    #[test]
+    fn test_backfill_recorded() {
+        let mut deposit = Tx::deposit(1, 1).amount(1.0).build();
+        deposit.mark_disputed();
+        let recorded = Recorded::new(&[deposit]);
+        assert!(recorded.contains(&Tx::deposit(1, 1).amount(1.5).build()));
+        assert!(!recorded.contains(&Tx::deposit(2, 1).amount(1.0).build()));
+        assert!(!recorded.contains(&Tx::dispute(1, 1).build()));
+        let mut tenant_deposit = Tx::deposit(1, 1).amount(1.0).build();
+        tenant_deposit.set_tenant(Some(String::from("acme")));
+        assert!(!recorded.contains(&tenant_deposit));
+    }
+
+    #[test]
+    fn test_backfill_corrections() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corrections.csv");
+        let mut corrections = Corrections::new(path.clone(), Labels::default());
+        let entry = |tx_id: u32, outcome: Outcome| Entry {
+            tenant: None, client_id: 1, tx_id, ttype: Type::Deposit, amount: Some(from_f64(2.0)), outcome, note: None,
+            before: Balance { available: from_f64(1.0), held: ZERO, total: from_f64(1.0) },
+            after: Balance { available: from_f64(3.0), held: ZERO, total: from_f64(3.0) },
+            timestamp: None, locked: false,
+            previous_status: Status::Active, status: Status::Active, record: None, source: None
+        };
+        corrections.record(&entry(2, Outcome::Applied)).unwrap();
+        corrections.record(&entry(3, Outcome::Rejected("account_locked"))).unwrap();
+        corrections.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+                   "tenant,client,tx,type,amount,before,after\n,1,2,deposit,2.0000,1.0000,3.0000\n");
+    }
+}
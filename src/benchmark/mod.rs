@@ -0,0 +1,179 @@
+// Throughput baselines of the built-in generated workloads. A baseline recorded on some hardware
+// is compared against the later runs on the same hardware, to catch performance regressions.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::generate::{self, Workload};
+use crate::input::Input;
+use crate::report;
+use crate::transaction::drill;
+
+// The built-in workloads: a single busy client, many busy clients, and many mostly idle clients
+// with disputes.
+pub fn workloads() -> Vec<(&'static str, Workload)> {
+    vec![
+        ("1-client-10k", Workload { clients: 1, transactions: 10_000, dispute_rate: 0.0, chargeback_rate: 0.0,
+                                    seed: 1 }),
+        ("100-clients-100k", Workload { clients: 100, transactions: 100_000, dispute_rate: 0.0, chargeback_rate: 0.0,
+                                        seed: 2 }),
+        ("10k-clients-100k-disputes", Workload { clients: 10_000, transactions: 100_000, dispute_rate: 5.0,
+                                                 chargeback_rate: 0.0, seed: 3 }),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub workload: String,
+    pub records: usize,
+    // Records applied per second.
+    pub throughput: f64,
+}
+
+// Runs the workload `iterations` times, keeping the best throughput, as the slower runs are
+// mostly the noise of the rest of the machine.
+pub fn measure(name: &str, workload: &Workload, iterations: usize) -> io::Result<Measurement> {
+    let mut csv = Vec::new();
+    let records = generate::write(&mut csv, workload)?;
+    let config = Config::default();
+    let mut best = Duration::MAX;
+    for _ in 0..iterations.max(1) {
+        let started = Instant::now();
+        drill(Input::from(&csv[..]), &config).map_err(|_| io::Error::other("the workload was aborted"))?;
+        best = best.min(started.elapsed());
+    }
+    Ok(Measurement { workload: String::from(name), records, throughput: records as f64 / best.as_secs_f64().max(1e-9) })
+}
+
+// Writes the measurements with respect to the `workload,records,throughput` schema.
+pub fn write_baseline<W: Write>(writer: &mut W, measurements: &[Measurement]) -> io::Result<()> {
+    writeln!(writer, "workload,records,throughput")?;
+    for measurement in measurements {
+        writeln!(writer, "{},{},{:.1}", measurement.workload, measurement.records, measurement.throughput)?;
+    }
+    Ok(())
+}
+
+pub fn write_baseline_file(path: &Path, measurements: &[Measurement]) -> io::Result<()> {
+    report::write_file(path, |writer| write_baseline(writer, measurements))
+}
+
+pub fn read_baseline<R: BufRead>(reader: R) -> io::Result<Vec<Measurement>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid baseline");
+    let mut lines = reader.lines();
+    if lines.next().transpose()?.as_deref() != Some("workload,records,throughput") {
+        return Err(invalid());
+    }
+    let mut measurements = Vec::new();
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        match fields[..] {
+            [workload, records, throughput] => measurements.push(Measurement {
+                workload: String::from(workload),
+                records: records.parse().map_err(|_| invalid())?,
+                throughput: throughput.parse().map_err(|_| invalid())?,
+            }),
+            _ => return Err(invalid())
+        }
+    }
+    Ok(measurements)
+}
+
+pub fn read_baseline_file(path: &Path) -> io::Result<Vec<Measurement>> {
+    read_baseline(BufReader::new(File::open(path)?))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub current: Measurement,
+    // Throughput of the baseline, unless the workload is not part of it.
+    pub baseline: Option<f64>,
+}
+
+impl Comparison {
+    // Change of the throughput against the baseline, in percent.
+    pub fn change(&self) -> Option<f64> {
+        self.baseline.map(|baseline| (self.current.throughput - baseline) / baseline * 100.0)
+    }
+
+    // Whether the throughput dropped by more than `threshold` percent. Workloads that are not part
+    // of the baseline never regress.
+    pub fn regressed(&self, threshold: f64) -> bool {
+        self.change().is_some_and(|change| -change > threshold)
+    }
+}
+
+pub fn compare(baseline: &[Measurement], current: Vec<Measurement>) -> Vec<Comparison> {
+    current.into_iter().map(|current| {
+        // A baseline of a workload with another size does not compare.
+        let baseline = baseline.iter()
+            .find(|measured| measured.workload == current.workload && measured.records == current.records)
+            .map(|measured| measured.throughput);
+        Comparison { current, baseline }
+    }).collect()
+}
+
+// Writes one `PASS` or `FAIL` line per workload.
+pub fn write_comparisons<W: Write>(writer: &mut W, comparisons: &[Comparison], threshold: f64) -> io::Result<()> {
+    for comparison in comparisons {
+        let current = &comparison.current;
+        let verdict = if comparison.regressed(threshold) { "FAIL" } else { "PASS" };
+        match (comparison.baseline, comparison.change()) {
+            (Some(baseline), Some(change)) => {
+                writeln!(writer, "{} {}: {:.0} records/s, baseline {:.0} records/s ({:+.1}%)", verdict, current.workload,
+                         current.throughput, baseline, change)?
+            }
+            _ => writeln!(writer, "{} {}: {:.0} records/s, no baseline", verdict, current.workload, current.throughput)?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(workload: &str, throughput: f64) -> Measurement {
+        Measurement { workload: String::from(workload), records: 100, throughput }
+    }
+
+    #[test]
+    fn test_benchmark_measure() {
+        let workload = Workload { clients: 3, transactions: 100, dispute_rate: 10.0, chargeback_rate: 0.0, seed: 1 };
+        let measured = measure("tiny", &workload, 2).unwrap();
+        assert_eq!((measured.workload.as_str(), measured.records), ("tiny", 100));
+        assert!(measured.throughput > 0.0);
+    }
+
+    #[test]
+    fn test_benchmark_baseline() {
+        let measurements = vec![measurement("a", 1000.0), measurement("b", 2.5)];
+        let mut out = Vec::new();
+        write_baseline(&mut out, &measurements).unwrap();
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), "workload,records,throughput\na,100,1000.0\nb,100,2.5\n");
+        assert_eq!(read_baseline(&out[..]).unwrap(), measurements);
+        assert!(read_baseline(&b"workload,records\na,1\n"[..]).is_err());
+        assert!(read_baseline(&b"workload,records,throughput\na,1\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_benchmark_compare() {
+        let baseline = vec![measurement("a", 1000.0), measurement("b", 1000.0),
+                            Measurement { records: 50, ..measurement("c", 1000.0) }];
+        let comparisons = compare(&baseline, vec![measurement("a", 950.0), measurement("b", 800.0), measurement("c", 10.0)]);
+        assert_eq!(comparisons[0].change(), Some(-5.0));
+        assert_eq!(comparisons.iter().map(|comparison| comparison.regressed(10.0)).collect::<Vec<bool>>(),
+                   vec![false, true, false]);
+        assert!(!comparisons[1].regressed(25.0));
+
+        let mut out = Vec::new();
+        write_comparisons(&mut out, &comparisons, 10.0).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "PASS a: 950 records/s, baseline 1000 records/s (-5.0%)\n\
+                                                     FAIL b: 800 records/s, baseline 1000 records/s (-20.0%)\n\
+                                                     PASS c: 10 records/s, no baseline\n");
+    }
+}
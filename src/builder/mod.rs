@@ -0,0 +1,307 @@
+// Fluent construction of the transactions built in memory, by the tests and the embedding
+// applications, e.g. `Tx::deposit(1, 1).amount(5).build()`, and of whole sequences of them with
+// `Script`, which numbers the transactions of a client on its own, e.g.
+// `Script::client(1).deposit(5).withdrawal(2).dispute(1).build()`.
+
+use crate::amount::{self, Amount};
+use crate::transaction::{Transaction, Type};
+
+// Builder of a single transaction. The amount is left out for the transactions that refer to a
+// previous one.
+#[derive(Debug, Clone)]
+pub struct Tx {
+    ttype: Type,
+    client_id: u16,
+    tx_id: u32,
+    amount: Option<Amount>,
+    tenant: Option<String>,
+    reason: Option<String>,
+    timestamp: Option<u64>,
+    sequence: Option<u64>,
+    source: Option<String>,
+}
+
+impl Tx {
+    pub fn new(ttype: Type, client_id: u16, tx_id: u32) -> Self {
+        Tx { ttype, client_id, tx_id, amount: None, tenant: None, reason: None, timestamp: None, sequence: None,
+             source: None }
+    }
+
+    pub fn deposit(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Deposit, client_id, tx_id)
+    }
+
+    pub fn withdrawal(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Withdrawal, client_id, tx_id)
+    }
+
+    pub fn dispute(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Dispute, client_id, tx_id)
+    }
+
+    pub fn resolve(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Resolve, client_id, tx_id)
+    }
+
+    pub fn chargeback(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Chargeback, client_id, tx_id)
+    }
+
+    pub fn representment(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Representment, client_id, tx_id)
+    }
+
+    pub fn freeze(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Freeze, client_id, tx_id)
+    }
+
+    pub fn unfreeze(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Unfreeze, client_id, tx_id)
+    }
+
+    pub fn unlock(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Unlock, client_id, tx_id)
+    }
+
+    pub fn settle(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Settle, client_id, tx_id)
+    }
+
+    pub fn cancel(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Cancel, client_id, tx_id)
+    }
+
+    pub fn authorize(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Authorize, client_id, tx_id)
+    }
+
+    pub fn capture(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Capture, client_id, tx_id)
+    }
+
+    pub fn refund(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Refund, client_id, tx_id)
+    }
+
+    pub fn reversal(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Reversal, client_id, tx_id)
+    }
+
+    pub fn fee(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Fee, client_id, tx_id)
+    }
+
+    pub fn credit(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Credit, client_id, tx_id)
+    }
+
+    pub fn debit(client_id: u16, tx_id: u32) -> Self {
+        Tx::new(Type::Debit, client_id, tx_id)
+    }
+
+    // The amount in units, rounded to the precision of the amounts, e.g. `amount(5)` or
+    // `amount(1.5)`.
+    pub fn amount<A: Into<f64>>(self, amount: A) -> Self {
+        self.exact_amount(amount::from_f64(amount.into()))
+    }
+
+    pub fn exact_amount(mut self, amount: Amount) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn sequence(mut self, sequence: u64) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    pub fn source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    pub fn build(self) -> Transaction {
+        let mut tx = match self.amount {
+            Some(amount) => Transaction::new_with_amount(self.ttype, self.client_id, self.tx_id, amount),
+            None => Transaction::new(self.ttype, self.client_id, self.tx_id)
+        };
+        tx.set_tenant(self.tenant);
+        tx.set_reason(self.reason);
+        tx.set_timestamp(self.timestamp);
+        tx.set_sequence(self.sequence);
+        tx.set_source(self.source);
+        tx
+    }
+}
+
+impl From<Tx> for Transaction {
+    fn from(tx: Tx) -> Self {
+        tx.build()
+    }
+}
+
+// Sequence of transactions, in the order they are applied. The transactions starting a new one
+// get the next tx id of the script, from 1 on, while the others name the tx id they refer to.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    client_id: u16,
+    tenant: Option<String>,
+    next_tx_id: u32,
+    txs: Vec<Transaction>,
+}
+
+impl Script {
+    pub fn client(client_id: u16) -> Self {
+        Script { client_id, tenant: None, next_tx_id: 1, txs: Vec::new() }
+    }
+
+    // The transactions that follow are of this client.
+    pub fn then_client(mut self, client_id: u16) -> Self {
+        self.client_id = client_id;
+        self
+    }
+
+    // The transactions that follow are of this tenant.
+    pub fn tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    pub fn deposit<A: Into<f64>>(self, amount: A) -> Self {
+        self.start(Type::Deposit, amount)
+    }
+
+    pub fn withdrawal<A: Into<f64>>(self, amount: A) -> Self {
+        self.start(Type::Withdrawal, amount)
+    }
+
+    pub fn authorize<A: Into<f64>>(self, amount: A) -> Self {
+        self.start(Type::Authorize, amount)
+    }
+
+    pub fn fee<A: Into<f64>>(self, amount: A) -> Self {
+        self.start(Type::Fee, amount)
+    }
+
+    pub fn credit<A: Into<f64>>(self, amount: A) -> Self {
+        self.start(Type::Credit, amount)
+    }
+
+    pub fn debit<A: Into<f64>>(self, amount: A) -> Self {
+        self.start(Type::Debit, amount)
+    }
+
+    pub fn dispute(self, tx_id: u32) -> Self {
+        self.refer(Type::Dispute, tx_id)
+    }
+
+    pub fn resolve(self, tx_id: u32) -> Self {
+        self.refer(Type::Resolve, tx_id)
+    }
+
+    pub fn chargeback(self, tx_id: u32) -> Self {
+        self.refer(Type::Chargeback, tx_id)
+    }
+
+    pub fn representment(self, tx_id: u32) -> Self {
+        self.refer(Type::Representment, tx_id)
+    }
+
+    pub fn settle(self, tx_id: u32) -> Self {
+        self.refer(Type::Settle, tx_id)
+    }
+
+    pub fn cancel(self, tx_id: u32) -> Self {
+        self.refer(Type::Cancel, tx_id)
+    }
+
+    pub fn capture(self, tx_id: u32) -> Self {
+        self.refer(Type::Capture, tx_id)
+    }
+
+    pub fn reversal(self, tx_id: u32) -> Self {
+        self.refer(Type::Reversal, tx_id)
+    }
+
+    pub fn refund<A: Into<f64>>(self, tx_id: u32, amount: A) -> Self {
+        let tx = Tx::refund(self.client_id, tx_id).amount(amount);
+        self.push(tx)
+    }
+
+    // Appends a transaction built on its own, e.g. with a timestamp. The tx ids it uses are
+    // skipped by the transactions started afterwards.
+    pub fn push<T: Into<Transaction>>(mut self, tx: T) -> Self {
+        let mut tx = tx.into();
+        if tx.tenant().is_none() {
+            tx.set_tenant(self.tenant.clone());
+        }
+        self.next_tx_id = self.next_tx_id.max(tx.tx_id().saturating_add(1));
+        self.txs.push(tx);
+        self
+    }
+
+    pub fn build(self) -> Vec<Transaction> {
+        self.txs
+    }
+
+    fn start<A: Into<f64>>(self, ttype: Type, amount: A) -> Self {
+        let tx = Tx::new(ttype, self.client_id, self.next_tx_id).amount(amount);
+        self.push(tx)
+    }
+
+    fn refer(self, ttype: Type, tx_id: u32) -> Self {
+        let tx = Tx::new(ttype, self.client_id, tx_id);
+        self.push(tx)
+    }
+}
+
+impl IntoIterator for Script {
+    type Item = Transaction;
+    type IntoIter = std::vec::IntoIter<Transaction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.txs.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::from_f64;
+
+    #[test]
+    fn test_tx() {
+        assert_eq!(Tx::deposit(1, 2).amount(5).build(), Transaction::new_with_amount(Type::Deposit, 1, 2, from_f64(5.0)));
+        let tx: Transaction = Tx::dispute(1, 2).into();
+        assert_eq!(tx, Transaction::new(Type::Dispute, 1, 2));
+        let tx = Tx::withdrawal(3, 4).amount(1.25).tenant("acme").reason("r1").timestamp(10).sequence(2).source("api")
+            .build();
+        assert_eq!((tx.amount(), tx.tenant(), tx.reason()), (Some(from_f64(1.25)), Some("acme"), Some("r1")));
+        assert_eq!((tx.timestamp(), tx.sequence(), tx.source()), (Some(10), Some(2), Some("api")));
+    }
+
+    #[test]
+    fn test_script() {
+        let txs = Script::client(1).deposit(5).withdrawal(2).dispute(1).push(Tx::deposit(1, 7).amount(1)).deposit(3)
+            .then_client(2).tenant("acme").fee(0.5).refund(1, 1).build();
+        assert_eq!(txs, vec![Tx::deposit(1, 1).amount(5).build(), Tx::withdrawal(1, 2).amount(2).build(),
+                             Tx::dispute(1, 1).build(), Tx::deposit(1, 7).amount(1).build(),
+                             Tx::deposit(1, 8).amount(3).build(), Tx::fee(2, 9).amount(0.5).tenant("acme").build(),
+                             Tx::refund(2, 1).amount(1).tenant("acme").build()]);
+    }
+}
@@ -0,0 +1,98 @@
+// Source of the wall clock time, for the features that run on the time of the machine rather than
+// on the timestamps of the input, e.g. the snapshot intervals or the webhook retries. The runs take
+// their clock from the config, so tests and replays can drive the time with a mock clock instead.
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Debug + Send + Sync {
+    // Seconds since the epoch.
+    fn unix_seconds(&self) -> u64;
+
+    // Monotonic time elapsed since the clock was created.
+    fn elapsed(&self) -> Duration;
+
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Debug)]
+pub struct SystemClock {
+    created: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock { created: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn unix_seconds(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+// A clock that only moves when it is told to.
+#[derive(Debug)]
+pub struct MockClock {
+    // Seconds since the epoch at creation.
+    start: u64,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn new(start: u64) -> Self {
+        MockClock { start, elapsed: Mutex::new(Duration::ZERO) }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn unix_seconds(&self) -> u64 {
+        self.start + self.elapsed().as_secs()
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Sleeping moves the clock forward, right away.
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1_000);
+        assert_eq!((clock.unix_seconds(), clock.elapsed()), (1_000, Duration::ZERO));
+        clock.advance(Duration::from_millis(1_500));
+        assert_eq!((clock.unix_seconds(), clock.elapsed()), (1_001, Duration::from_millis(1_500)));
+        clock.sleep(Duration::from_secs(2));
+        assert_eq!(clock.unix_seconds(), 1_003);
+        assert!(SystemClock::new().unix_seconds() > 1_000);
+    }
+}
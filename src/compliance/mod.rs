@@ -0,0 +1,172 @@
+// Suspicious activity report, derived from the audit trail.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::amount::{self, Amount};
+use crate::audit::{Entry, Outcome, Sink};
+use crate::config::Labels;
+use crate::transaction::Type;
+
+// Signals a client is flagged for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flag {
+    // A single deposit or withdrawal reached the AML threshold.
+    AmlThreshold,
+    // Too many deposits and withdrawals in a short time.
+    Velocity,
+    // A chargeback was applied, the strongest fraud signal of the inputs.
+    Chargeback,
+}
+
+impl Flag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Flag::AmlThreshold => "aml_threshold",
+            Flag::Velocity => "velocity",
+            Flag::Chargeback => "chargeback"
+        }
+    }
+}
+
+// Thresholds the signals are raised with.
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    pub aml_amount: Option<Amount>,
+    // At most this many deposits and withdrawals of a client are expected within the window, in
+    // seconds.
+    pub velocity_count: Option<usize>,
+    pub velocity_window: u64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds { aml_amount: None, velocity_count: None, velocity_window: 60 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Finding {
+    flag: Flag,
+    tx_id: u32,
+    amount: Option<Amount>,
+}
+
+// Writes the findings of every flagged client, with respect to the
+// `tenant,client,flag,tx,amount` schema, one line per triggering transaction.
+pub struct SuspiciousActivity {
+    path: PathBuf,
+    thresholds: Thresholds,
+    labels: Labels,
+    // Timestamps of the latest deposits and withdrawals of every client, within the window.
+    recent: HashMap<(Option<String>, u16), VecDeque<u64>>,
+    findings: BTreeMap<(Option<String>, u16), Vec<Finding>>,
+}
+
+impl SuspiciousActivity {
+    pub fn new(path: PathBuf, thresholds: Thresholds, labels: Labels) -> Self {
+        SuspiciousActivity { path, thresholds, labels, recent: HashMap::new(), findings: BTreeMap::new() }
+    }
+
+    fn is_velocity_breach(&mut self, entry: &Entry) -> bool {
+        let (limit, timestamp) = match (self.thresholds.velocity_count, entry.timestamp) {
+            (Some(limit), Some(timestamp)) => (limit, timestamp),
+            _ => return false
+        };
+
+        let window = self.thresholds.velocity_window;
+        let recent = self.recent.entry((entry.tenant.clone(), entry.client_id)).or_default();
+        while recent.front().is_some_and(|&oldest| timestamp - oldest >= window) {
+            recent.pop_front();
+        }
+        recent.push_back(timestamp);
+        recent.len() > limit
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "tenant,client,flag,tx,amount")?;
+        for ((tenant, client_id), findings) in self.findings.iter() {
+            let label = self.labels.label(*client_id);
+            for finding in findings {
+                let amount = finding.amount.map(|amount| amount::fmt(amount).to_string()).unwrap_or_default();
+                writeln!(writer, "{},{},{},{},{}", tenant.as_deref().unwrap_or(""), label, finding.flag.as_str(),
+                         finding.tx_id, amount)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for SuspiciousActivity {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        if entry.outcome != Outcome::Applied {
+            return Ok(());
+        }
+
+        let mut flags = Vec::new();
+        match entry.ttype {
+            Type::Deposit | Type::Withdrawal => {
+                if matches!((entry.amount, self.thresholds.aml_amount), (Some(amount), Some(threshold)) if amount >= threshold) {
+                    flags.push(Flag::AmlThreshold);
+                }
+                if self.is_velocity_breach(entry) {
+                    flags.push(Flag::Velocity);
+                }
+            }
+            Type::Chargeback => flags.push(Flag::Chargeback),
+            _ => {}
+        }
+
+        for flag in flags {
+            self.findings.entry((entry.tenant.clone(), entry.client_id)).or_default()
+                .push(Finding { flag, tx_id: entry.tx_id, amount: entry.amount });
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use crate::audit::Balance;
+    use crate::amount::from_f64;
+
+    fn entry(client_id: u16, tx_id: u32, ttype: Type, amount: Option<Amount>, timestamp: u64) -> Entry {
+        Entry { tenant: None, client_id, tx_id, ttype, amount, outcome: Outcome::Applied, note: None,
+                before: Balance::default(), after: Balance::default(), timestamp: Some(timestamp),
+                locked: false, previous_status: Status::Active, status: Status::Active, record: None, source: None }
+    }
+
+    #[test]
+    fn test_suspicious_activity() {
+        let thresholds = Thresholds { aml_amount: Some(from_f64(10_000.0)), velocity_count: Some(2), velocity_window: 60 };
+        let mut report = SuspiciousActivity::new(PathBuf::new(), thresholds, Labels::default());
+        report.record(&entry(1, 1, Type::Deposit, Some(from_f64(10_000.0)), 0)).unwrap();
+        report.record(&entry(1, 2, Type::Withdrawal, Some(from_f64(1.0)), 30)).unwrap();
+        report.record(&entry(1, 3, Type::Withdrawal, Some(from_f64(1.0)), 59)).unwrap();
+        // The first two transactions are out of the window by now.
+        report.record(&entry(1, 4, Type::Withdrawal, Some(from_f64(1.0)), 90)).unwrap();
+        report.record(&entry(2, 5, Type::Deposit, Some(from_f64(5.0)), 0)).unwrap();
+        report.record(&entry(2, 5, Type::Chargeback, None, 10)).unwrap();
+        let mut rejected = entry(3, 6, Type::Deposit, Some(from_f64(50_000.0)), 0);
+        rejected.outcome = Outcome::Rejected("account_locked");
+        report.record(&rejected).unwrap();
+
+        let mut out = Vec::new();
+        report.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,flag,tx,amount\n\
+                   ,1,aml_threshold,1,10000.0000\n\
+                   ,1,velocity,3,1.0000\n\
+                   ,2,chargeback,5,\n");
+    }
+}
@@ -0,0 +1,271 @@
+// Knobs that drive a transactions execution run.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::account::Rules;
+use crate::alias::Aliases;
+use crate::amount;
+use crate::audit::SinkFactory;
+use crate::aggregate::Bucket;
+use crate::compliance::Thresholds;
+use crate::house::Routes;
+use crate::notify::Route;
+use crate::output::AccountSinkFactory;
+use crate::retry::Policy;
+use crate::partition::Partitioning;
+use crate::clock::{Clock, SystemClock};
+use crate::pseudonym::Pseudonymizer;
+use crate::reference::Reference;
+use crate::replay::Protection;
+use crate::sample::Sample;
+use crate::snapshot::Schedule;
+use crate::transaction::{InvalidRateGuard, Mode, UnknownAccountPolicy};
+use crate::watchdog::Limits;
+
+#[non_exhaustive]
+pub struct Config {
+    // Schedule the account admins on a multi-threaded runtime instead of a single threaded one.
+    pub multi_threaded_runtime: bool,
+    // Artificial delay applied before handling every transaction, used by the benchmarks.
+    pub tx_delay: Option<Duration>,
+    // Rules the account admins apply the transactions with.
+    pub rules: Rules,
+    // When set, every handled transaction is recorded, with its outcome, in this file.
+    pub audit_path: Option<PathBuf>,
+    // Chain the audit trail entries with hashes, writing the Merkle root of the trail next to it.
+    pub audit_chain: bool,
+    // Observers of the run, each of them getting the audit entry of every handled transaction,
+    // whether or not the audit trail is written.
+    pub observers: Vec<SinkFactory>,
+    // Measure the time spent in every stage of the pipeline, for the run summary.
+    pub stage_timings: bool,
+    // When set, the changes of the accounts are streamed into this file as they are applied.
+    pub account_events_path: Option<PathBuf>,
+    // When set, a statement of every client is written under this directory.
+    pub statements_dir: Option<PathBuf>,
+    // When set, the totals of every client and period are written to this file.
+    pub aggregates_path: Option<PathBuf>,
+    pub aggregate_bucket: Bucket,
+    // When set, the top `top_count` accounts by total, held funds and number of transactions
+    // are written to this file.
+    pub top_accounts_path: Option<PathBuf>,
+    pub top_count: usize,
+    // When set, the per type volumes of the run are written to this file.
+    pub summary_path: Option<PathBuf>,
+    // When set, the suspicious activity of the clients is written to this file.
+    pub suspicious_activity_path: Option<PathBuf>,
+    pub suspicious_activity_thresholds: Thresholds,
+    // Sinks the alerts are routed to, and how the webhook sinks retry failed deliveries.
+    pub alerts: Vec<Route>,
+    pub webhook_retry: Policy,
+    // Send the administrative operations to the account admins over a priority lane, so that they
+    // are not queued behind the customer transactions.
+    pub admin_lane: bool,
+    // The final accounts are streamed into a sink of every factory at the end of the run.
+    pub account_sinks: Vec<AccountSinkFactory>,
+    // When set, the transactions moved out of the history once older than the archive age of the
+    // rules are written to this gzip compressed file.
+    pub archive_path: Option<PathBuf>,
+    // When set, the accounts and their history are written to this state file at the end of the
+    // run, for a later run to resume from.
+    pub state_path: Option<PathBuf>,
+    // When set, snapshots of the accounts are written to this file while the run goes on, with
+    // respect to the schedule.
+    pub snapshot_path: Option<PathBuf>,
+    pub snapshot_schedule: Schedule,
+    // When set, the transactions never applied because their account was locked or closed are
+    // written to this file.
+    pub dead_letters_path: Option<PathBuf>,
+    // When set, the run is a backfill over the resumed state: the transactions the history
+    // already records are skipped, and the corrections made are written to this file.
+    pub backfill_path: Option<PathBuf>,
+    // When set, the house accounts the charged back funds and the fees are credited to are written
+    // to this file.
+    pub house_accounts_path: Option<PathBuf>,
+    pub house_routes: Routes,
+    // When set, the disputes still open at the end of the run are written to this file.
+    pub open_disputes_path: Option<PathBuf>,
+    // When set, the accounts that ever had negative available funds are written to this file.
+    pub exposure_path: Option<PathBuf>,
+    // When set, client ids are replaced by their pseudonyms in every output.
+    pub pseudonymizer: Option<Arc<Pseudonymizer>>,
+    // When set, the client ids of the input are external identifiers, parsed into the internal
+    // ids of this mapping, and the outputs are written with the external identifiers back.
+    pub aliases: Option<Arc<Aliases>>,
+    // When set, the transactions are joined to the attributes of their client, for the policy
+    // rules, and the reports about the clients list them.
+    pub reference: Option<Arc<Reference>>,
+    // When set, the requests to the HTTP services, `serve` and `query`, must carry it as a bearer
+    // token.
+    pub api_token: Option<String>,
+    // When set, the final accounts are also written as one `<tenant>.csv` file per tenant under
+    // this directory.
+    pub tenant_output_dir: Option<PathBuf>,
+    // When set, the final accounts, and optionally the audit trail, are also written as one file
+    // per partition of the clients under this directory.
+    pub partition_dir: Option<PathBuf>,
+    pub partitioning: Partitioning,
+    // Maximum number of transactions accepted per tenant, the rest being dropped.
+    pub tenant_quota: Option<usize>,
+    // Maximum number of transactions of a tenant applied at the same time.
+    pub tenant_workers: Option<usize>,
+    // What happens to the transactions referring to a previous one of clients without an account.
+    pub unknown_account: UnknownAccountPolicy,
+    // When set, up to this many transactions of a client with a sequence number are held back, to
+    // be applied by ascending sequence numbers.
+    pub sequence_window: Option<usize>,
+    // When set, only the transactions of a sample of the clients of the input are applied.
+    pub sample: Option<Sample>,
+    // When set, the transactions reusing a tx id still remembered are rejected.
+    pub replay: Option<Protection>,
+    // Whether the deposits, withdrawals, authorizations and fees reusing the tx id of an earlier
+    // one of any client of their tenant are rejected, whatever the replay protection.
+    pub reject_duplicates: bool,
+    // Number of transactions buffered for a tenant before reading the input blocks.
+    pub tenant_buffer: usize,
+    // Maximum number of decimal places of the input amounts, the records with more being invalid.
+    pub amount_scale: usize,
+    // When set, the run is aborted if too many of the first records of the input are invalid.
+    pub invalid_rate_guard: Option<InvalidRateGuard>,
+    // Resources the run may use before it is aborted.
+    pub limits: Limits,
+    // Whether the run skips the invalid records and the rejected transactions, or fails at the first.
+    pub mode: Mode,
+    // Wall clock of the run. The features driven by the timestamps of the input do not use it.
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            multi_threaded_runtime: true,
+            tx_delay: None,
+            rules: Rules::default(),
+            audit_path: None,
+            audit_chain: false,
+            observers: Vec::new(),
+            account_events_path: None,
+            stage_timings: false,
+            statements_dir: None,
+            aggregates_path: None,
+            aggregate_bucket: Bucket::Day,
+            top_accounts_path: None,
+            top_count: 10,
+            summary_path: None,
+            suspicious_activity_path: None,
+            suspicious_activity_thresholds: Thresholds::default(),
+            alerts: Vec::new(),
+            webhook_retry: Policy::default(),
+            admin_lane: false,
+            account_sinks: Vec::new(),
+            archive_path: None,
+            state_path: None,
+            snapshot_path: None,
+            dead_letters_path: None,
+            backfill_path: None,
+            house_accounts_path: None,
+            house_routes: Routes::default(),
+            snapshot_schedule: Schedule::default(),
+            open_disputes_path: None,
+            exposure_path: None,
+            pseudonymizer: None,
+            aliases: None,
+            reference: None,
+            api_token: None,
+            tenant_output_dir: None,
+            partition_dir: None,
+            partitioning: Partitioning::default(),
+            tenant_quota: None,
+            tenant_workers: None,
+            unknown_account: UnknownAccountPolicy::default(),
+            sequence_window: None,
+            sample: None,
+            replay: None,
+            reject_duplicates: true,
+            tenant_buffer: 1024,
+            amount_scale: amount::DECIMALS,
+            invalid_rate_guard: None,
+            limits: Limits::default(),
+            mode: Mode::default(),
+            clock: Arc::new(SystemClock::new()),
+        }
+    }
+}
+
+// How the clients appear in the outputs, for the outputs written away from the config, e.g. by
+// the audit sinks.
+#[derive(Clone, Default)]
+pub struct Labels {
+    pseudonymizer: Option<Arc<Pseudonymizer>>,
+    aliases: Option<Arc<Aliases>>,
+}
+
+impl Labels {
+    pub fn label(&self, client_id: u16) -> String {
+        label(self.pseudonymizer.as_deref(), self.aliases.as_deref(), client_id)
+    }
+}
+
+// Pseudonyms take precedence over the external identifiers, which would reveal the clients as
+// much as their ids.
+fn label(pseudonymizer: Option<&Pseudonymizer>, aliases: Option<&Aliases>, client_id: u16) -> String {
+    match (pseudonymizer, aliases.and_then(|aliases| aliases.external(client_id))) {
+        (Some(pseudonymizer), _) => pseudonymizer.pseudonym(client_id),
+        (None, Some(external)) => external.to_string(),
+        (None, None) => client_id.to_string()
+    }
+}
+
+impl Config {
+    // The knobs that drive how the transactions are applied, without any output.
+    pub fn engine(&self) -> Config {
+        Config {
+            multi_threaded_runtime: self.multi_threaded_runtime,
+            rules: self.rules.clone(),
+            amount_scale: self.amount_scale,
+            tenant_quota: self.tenant_quota,
+            tenant_workers: self.tenant_workers,
+            tenant_buffer: self.tenant_buffer,
+            unknown_account: self.unknown_account,
+            sequence_window: self.sequence_window,
+            admin_lane: self.admin_lane,
+            sample: self.sample,
+            replay: self.replay,
+            reject_duplicates: self.reject_duplicates,
+            aliases: self.aliases.clone(),
+            reference: self.reference.clone(),
+            invalid_rate_guard: self.invalid_rate_guard.clone(),
+            limits: self.limits,
+            mode: self.mode,
+            clock: self.clock.clone(),
+            ..Config::default()
+        }
+    }
+
+    // Formats a client id the way it should appear in outputs.
+    pub fn client_label(&self, client_id: u16) -> String {
+        label(self.pseudonymizer.as_deref(), self.aliases.as_deref(), client_id)
+    }
+
+    pub fn labels(&self) -> Labels {
+        Labels { pseudonymizer: self.pseudonymizer.clone(), aliases: self.aliases.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_client_label() {
+        let mut config = Config::default();
+        assert_eq!(config.client_label(7), "7");
+        config.aliases = Some(Arc::new(Aliases::parse(&b"external,client\nbank-a-7,7\n"[..]).unwrap()));
+        assert_eq!(config.client_label(7), "bank-a-7");
+        assert_eq!(config.client_label(8), "8");
+        config.pseudonymizer = Some(Arc::new(Pseudonymizer::new(b"key")));
+        assert_eq!(config.client_label(7), Pseudonymizer::new(b"key").pseudonym(7));
+    }
+}
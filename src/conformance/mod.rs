@@ -0,0 +1,154 @@
+// Conformance of the runs against a reference implementation, e.g. the processor a team migrates
+// away from. The reference is another executable, run over the same input, whose accounts are
+// read from its stdout, with respect to the `client,available,held,total,locked` schema of the
+// payments spec, and compared to the accounts of the run, client by client.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::account::Account;
+use crate::amount;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The reference exited with this code, or was killed by a signal.
+    Failed(Option<i32>),
+    // The line of the output of the reference, counting from 1, is not an account.
+    InvalidOutput(usize),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// Runs `program` with `args` followed by the path of the input, returning the accounts it prints.
+pub fn run_reference(program: &Path, args: &[String], input: &Path) -> Result<Vec<Account>> {
+    let output = Command::new(program).args(args).arg(input).stdin(Stdio::null()).stderr(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(Error::Failed(output.status.code()));
+    }
+    parse_accounts(&String::from_utf8_lossy(&output.stdout))
+}
+
+// Parses the accounts printed by a reference, after their header line. The status column is
+// optional, and the empty lines are skipped.
+pub fn parse_accounts(output: &str) -> Result<Vec<Account>> {
+    output.lines().enumerate().skip(1)
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| line.trim().parse::<Account>().map_err(|_| Error::InvalidOutput(idx + 1)))
+        .collect()
+}
+
+// A client whose account differs, or that only one of the implementations has an account for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub client_id: u16,
+    pub ours: Option<Account>,
+    pub theirs: Option<Account>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Comparison {
+    // Number of clients with an account in either implementation.
+    pub clients: usize,
+    pub differences: Vec<Difference>,
+}
+
+impl Comparison {
+    pub fn passed(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    // Writes one line per differing client, with both accounts as rows of the spec, followed by
+    // the overall verdict.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for difference in &self.differences {
+            let describe = |account: &Option<Account>| account.as_ref().map(row).unwrap_or_else(|| "none".to_string());
+            writeln!(writer, "Client {}: ours {}, reference {}", difference.client_id, describe(&difference.ours),
+                     describe(&difference.theirs))?;
+        }
+        if self.passed() {
+            writeln!(writer, "All {} accounts match the reference.", self.clients)
+        } else {
+            writeln!(writer, "{} of {} accounts differ from the reference.", self.differences.len(), self.clients)
+        }
+    }
+}
+
+// Compares the funds and the lock of the accounts. The statuses beyond the lock, e.g. frozen, are
+// unknown to the payments spec, so they are not compared.
+pub fn compare(ours: &[Account], theirs: &[Account]) -> Comparison {
+    let mut clients: BTreeMap<u16, (Option<Account>, Option<Account>)> = BTreeMap::new();
+    for account in ours {
+        clients.entry(account.client_id()).or_default().0 = Some(account.clone());
+    }
+    for account in theirs {
+        clients.entry(account.client_id()).or_default().1 = Some(account.clone());
+    }
+    let same = |ours: &Account, theirs: &Account| {
+        (ours.available(), ours.held(), ours.total(), ours.is_locked())
+            == (theirs.available(), theirs.held(), theirs.total(), theirs.is_locked())
+    };
+    let count = clients.len();
+    let differences = clients.into_iter()
+        .filter(|(_, accounts)| !matches!(accounts, (Some(ours), Some(theirs)) if same(ours, theirs)))
+        .map(|(client_id, (ours, theirs))| Difference { client_id, ours, theirs })
+        .collect();
+    Comparison { clients: count, differences }
+}
+
+fn row(account: &Account) -> String {
+    format!("{},{},{},{},{}", account.client_id(), amount::fmt(account.available()), amount::fmt(account.held()),
+            amount::fmt(account.total()), account.is_locked())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{FreezeMode, Status};
+    use crate::amount::from_f64;
+
+    #[test]
+    fn test_compare() {
+        let theirs = parse_accounts("client,available,held,total,locked\n\
+                                     1,1.5,0,1.5,false\n\
+                                     \n\
+                                     2,0.0,0.0,0.0,true\n\
+                                     4,1.0,0.0,1.0,false\n").unwrap();
+        let ours = vec![Account::new(1, from_f64(1.5), from_f64(0.0), Status::Frozen(FreezeMode::Withdrawals)),
+                        Account::new(2, from_f64(0.0), from_f64(0.0), Status::Active),
+                        Account::new(3, from_f64(2.0), from_f64(0.0), Status::Active)];
+        let comparison = compare(&ours, &theirs);
+        assert!(!comparison.passed());
+        let mut out = Vec::new();
+        comparison.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "Client 2: ours 2,0.0000,0.0000,0.0000,false, reference 2,0.0000,0.0000,0.0000,true\n\
+                    Client 3: ours 3,2.0000,0.0000,2.0000,false, reference none\n\
+                    Client 4: ours none, reference 4,1.0000,0.0000,1.0000,false\n\
+                    3 of 4 accounts differ from the reference.\n");
+        assert!(compare(&ours[..1], &theirs[..1]).passed());
+
+        assert!(matches!(parse_accounts("client,available,held,total,locked\n1,one,0,1,false\n"),
+                         Err(Error::InvalidOutput(2))));
+    }
+
+    #[test]
+    fn test_run_reference() {
+        if cfg!(unix) {
+            let script = "echo client,available,held,total,locked; echo 1,2.0,0.0,2.0,false";
+            let args = vec![String::from("-c"), String::from(script), String::from("sh")];
+            let accounts = run_reference(Path::new("sh"), &args, Path::new("input.csv")).unwrap();
+            assert_eq!(accounts, vec![Account::new(1, from_f64(2.0), from_f64(0.0), Status::Active)]);
+            let args = vec![String::from("-c"), String::from("exit 3"), String::from("sh")];
+            assert!(matches!(run_reference(Path::new("sh"), &args, Path::new("input.csv")), Err(Error::Failed(Some(3)))));
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Dead letters of a run: the transactions that were never applied because their account was
+// locked or closed, instead of being dropped. They are written in the input format, so that they
+// can be reviewed, and replayed once their accounts are unlocked.
+
+use std::io::{self, Write};
+
+use crate::alias::Aliases;
+use crate::amount::{self, ZERO};
+use crate::report::Volume;
+use crate::transaction::Transaction;
+
+// Writes the transactions with respect to the `type,client,tx,amount,reason,timestamp` schema,
+// followed by a `tenant` column for multi-tenant runs, and a `source` column when the transactions
+// were read from labelled sources. The clients of aliased inputs are written with their external
+// identifiers, as in the input.
+pub fn write<W: Write>(writer: &mut W, dead_letters: &[Transaction], with_tenant: bool, aliases: Option<&Aliases>)
+                       -> io::Result<()> {
+    let with_source = dead_letters.iter().any(|tx| tx.source().is_some());
+    write!(writer, "type,client,tx,amount,reason,timestamp")?;
+    if with_tenant {
+        write!(writer, ",tenant")?;
+    }
+    if with_source {
+        write!(writer, ",source")?;
+    }
+    writeln!(writer)?;
+    for tx in dead_letters {
+        let client = aliases.and_then(|aliases| aliases.external(tx.client_id()))
+            .map_or_else(|| tx.client_id().to_string(), String::from);
+        let amount = tx.amount().map(|amount| amount::fmt(amount).to_string()).unwrap_or_default();
+        write!(writer, "{},{},{},{},{},{}", tx.transaction_type().as_str(), client, tx.tx_id(), amount,
+               tx.reason().unwrap_or(""), tx.timestamp().map(|timestamp| timestamp.to_string()).unwrap_or_default())?;
+        if with_tenant {
+            write!(writer, ",{}", tx.tenant().unwrap_or(""))?;
+        }
+        if with_source {
+            write!(writer, ",{}", tx.source().unwrap_or(""))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+pub fn volume(dead_letters: &[Transaction]) -> Volume {
+    let mut volume = Volume::default();
+    for tx in dead_letters {
+        volume.add(tx.amount().unwrap_or(ZERO));
+    }
+    volume
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::input::Schema;
+    use crate::amount::from_f64;
+
+    #[test]
+    fn test_dead_letters() {
+        let mut deposit = Tx::deposit(1, 3).amount(2.5).build();
+        deposit.set_tenant(Some(String::from("acme")));
+        let mut dispute = Tx::dispute(1, 2).build();
+        dispute.set_tenant(Some(String::from("acme")));
+        dispute.set_reason(Some(String::from("fraud")));
+        dispute.set_timestamp(Some(100));
+        let dead_letters = [deposit, dispute];
+        assert_eq!(volume(&dead_letters), Volume { count: 2, amount: from_f64(2.5) });
+
+        let mut out = Vec::new();
+        write(&mut out, &dead_letters, true, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "type,client,tx,amount,reason,timestamp,tenant\n\
+                         deposit,1,3,2.5000,,,acme\n\
+                         dispute,1,2,,fraud,100,acme\n");
+
+        // The dead letters can be fed back as an input.
+        let mut lines = out.lines();
+        let schema = Schema::from_header(lines.next().unwrap()).unwrap();
+        let replayed: Vec<Transaction> = lines.map(|line| Transaction::parse(line, &schema).unwrap()).collect();
+        assert_eq!(replayed, dead_letters);
+
+        let aliases = Aliases::parse(&b"bank-1,1\n"[..]).unwrap();
+        let mut out = Vec::new();
+        write(&mut out, &dead_letters[..1], false, Some(&aliases)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "type,client,tx,amount,reason,timestamp\ndeposit,bank-1,3,2.5000,,\n");
+
+        // The source labels round-trip as well.
+        let mut labelled = dead_letters[0].clone();
+        labelled.set_source(Some(String::from("batch")));
+        let mut out = Vec::new();
+        write(&mut out, &[labelled.clone()], false, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "type,client,tx,amount,reason,timestamp,source\ndeposit,1,3,2.5000,,,batch\n");
+        let schema = Schema::from_header(out.lines().next().unwrap()).unwrap();
+        let mut replayed = Transaction::parse(out.lines().nth(1).unwrap(), &schema).unwrap();
+        replayed.set_tenant(Some(String::from("acme")));
+        assert_eq!(replayed, labelled);
+    }
+}
@@ -0,0 +1,201 @@
+// Self-test of an installation, for support tickets. It runs a built-in sample workload through
+// the configured engine, verifies the balances it ends with and checks that the outputs of the
+// configuration can be written, without touching any of them.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::account::Account;
+use crate::amount::{self, Amount};
+use crate::config::Config;
+use crate::input::Input;
+use crate::state;
+use crate::transaction::drill;
+
+// Every client ends in a different state: one with a withdrawal, one charged back, the later
+// deposit being rejected, and one with a resolved dispute.
+const SAMPLE: &str = "type,client,tx,amount\n\
+                      deposit,1,1,10.0\n\
+                      deposit,2,2,5.0\n\
+                      deposit,3,3,2.0\n\
+                      withdrawal,1,4,4.0\n\
+                      dispute,2,2,\n\
+                      chargeback,2,2,\n\
+                      dispute,3,3,\n\
+                      resolve,3,3,\n\
+                      deposit,2,5,1.0\n";
+
+// Name of a scratch file that no other check, of this process or of another one, uses.
+fn scratch_name(suffix: &str) -> String {
+    static SCRATCH: AtomicUsize = AtomicUsize::new(0);
+    format!(".transaction-processor-doctor-{}-{}{}", process::id(), SCRATCH.fetch_add(1, Ordering::Relaxed), suffix)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: String, result: Result<String, String>) -> Self {
+        match result {
+            Ok(detail) => Check { name, passed: true, detail },
+            Err(detail) => Check { name, passed: false, detail }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diagnosis {
+    pub checks: Vec<Check>,
+}
+
+impl Diagnosis {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    // Writes one `PASS` or `FAIL` line per check, followed by the overall diagnosis.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for check in &self.checks {
+            writeln!(writer, "{} {}: {}", if check.passed { "PASS" } else { "FAIL" }, check.name, check.detail)?;
+        }
+        let failed = self.checks.iter().filter(|check| !check.passed).count();
+        if failed == 0 {
+            writeln!(writer, "All {} checks passed.", self.checks.len())
+        } else {
+            writeln!(writer, "{} of {} checks failed.", failed, self.checks.len())
+        }
+    }
+}
+
+// Runs every check against `config`. The state file to resume from, if any, and the outputs the
+// configuration is not aware of, e.g. the run manifest, are checked too.
+pub fn diagnose(config: &Config, resume: Option<&Path>, outputs: &[(&str, PathBuf)]) -> Diagnosis {
+    let mut checks = vec![Check::new(String::from("sample workload"), sample_workload(config))];
+    if let Some(path) = resume {
+        let result = state::read_file(path)
+            .map(|state| format!("{} accounts and {} transactions", state.accounts.len(), state.history.len()))
+            .map_err(|err| err.describe(path));
+        checks.push(Check::new(format!("state file {}", path.display()), result));
+    }
+    for (name, path, dir) in configured_outputs(config).into_iter()
+        .chain(outputs.iter().map(|(name, path)| (name.to_string(), path.clone(), false))) {
+        checks.push(Check::new(format!("{} {}", name, path.display()), writable(&path, dir)));
+    }
+    Diagnosis { checks }
+}
+
+// Outputs of the configuration, along with whether they are directories.
+fn configured_outputs(config: &Config) -> Vec<(String, PathBuf, bool)> {
+    let files = [("audit trail", &config.audit_path), ("aggregates", &config.aggregates_path),
+                 ("top accounts", &config.top_accounts_path), ("run summary", &config.summary_path),
+                 ("suspicious activity", &config.suspicious_activity_path), ("history archive", &config.archive_path),
+                 ("state file", &config.state_path), ("open disputes", &config.open_disputes_path),
+                 ("exposure report", &config.exposure_path), ("account events", &config.account_events_path)];
+    let dirs = [("statements", &config.statements_dir), ("tenant outputs", &config.tenant_output_dir)];
+    files.iter().map(|(name, path)| (name, path, false))
+        .chain(dirs.iter().map(|(name, path)| (name, path, true)))
+        .filter_map(|(name, path, dir)| path.as_ref().map(|path| (name.to_string(), path.clone(), dir)))
+        .collect()
+}
+
+// Checks that a file can be written at `path`, or that files can be written under the directory
+// at `path`, by writing a probe file in the directory it would be created in. Missing directories
+// are created by the run, so the nearest existing one is probed instead.
+fn writable(path: &Path, dir: bool) -> Result<String, String> {
+    if path.exists() && dir != path.is_dir() {
+        return Err(String::from(if dir { "not a directory" } else { "is a directory" }));
+    }
+    let parent = if dir { path } else { path.parent().unwrap_or(Path::new("")) };
+    let mut existing = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+    while !existing.exists() {
+        existing = match existing.parent() {
+            Some(ancestor) if !ancestor.as_os_str().is_empty() => ancestor,
+            _ => Path::new(".")
+        };
+    }
+    let probe = existing.join(scratch_name(""));
+    OpenOptions::new().write(true).create_new(true).open(&probe).map_err(|err| format!("{} is not writable, {}",
+                                                                                         existing.display(), err))?;
+    let _ = fs::remove_file(&probe);
+    Ok(String::from("writable"))
+}
+
+// The rules of `config`, without the limits that would get in the way of the sample workload.
+fn engine_config(config: &Config) -> Config {
+    Config { tenant_quota: None, invalid_rate_guard: None, ..config.engine() }
+}
+
+fn sample_workload(config: &Config) -> Result<String, String> {
+    let run_report = drill(Input::from(SAMPLE.as_bytes()), &engine_config(config))
+        .map_err(|err| format!("the run failed, {:?}", err))?;
+    let mut accounts = run_report.final_accounts.clone();
+    accounts.sort_by_key(Account::client_id);
+
+    // Withdrawals settled in two steps are still pending.
+    let pending = if config.rules.two_step_withdrawals { amount::from_f64(4.0) } else { amount::ZERO };
+    let expected: [(u16, f64, Amount, bool); 3] = [(1, 6.0, pending, false), (2, 0.0, amount::ZERO, true),
+                                                   (3, 2.0, amount::ZERO, false)];
+    if accounts.len() != expected.len() {
+        return Err(format!("expected {} accounts, got {}", expected.len(), accounts.len()));
+    }
+    for (account, (client_id, available, pending, locked)) in accounts.iter().zip(expected.iter()) {
+        let balances = (account.client_id(), account.available(), account.held(), account.pending(), account.is_locked());
+        if balances != (*client_id, amount::from_f64(*available), amount::ZERO, *pending, *locked) {
+            return Err(format!("client {} ended with available {}, held {}, pending {} and locked {}, expected \
+                                available {}, held {}, pending {} and locked {}", client_id,
+                               amount::fmt(account.available()), amount::fmt(account.held()),
+                               amount::fmt(account.pending()), account.is_locked(), amount::fmt(amount::from_f64(*available)),
+                               amount::fmt(amount::ZERO), amount::fmt(*pending), locked));
+        }
+    }
+    if run_report.rejected != 1 {
+        return Err(format!("expected 1 rejected transaction, got {}", run_report.rejected));
+    }
+    Ok(format!("{} records applied, balances as expected", run_report.records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Rules;
+
+    #[test]
+    fn test_doctor_sample_workload() {
+        assert!(sample_workload(&Config::default()).is_ok());
+        let config = Config { multi_threaded_runtime: false, ..Config::default() };
+        assert!(sample_workload(&config).is_ok());
+        let config = Config { rules: Rules { two_step_withdrawals: true, spec: true, ..Rules::default() }, ..Config::default() };
+        assert!(sample_workload(&config).is_ok());
+    }
+
+    #[test]
+    fn test_doctor_diagnose() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.csv");
+        fs::write(&file, "").unwrap();
+        let config = Config {
+            audit_path: Some(dir.path().join("missing/dir/audit.csv")),
+            statements_dir: Some(file.clone()),
+            ..Config::default()
+        };
+        let diagnosis = diagnose(&config, Some(&dir.path().join("missing.state")), &[("manifest", file.clone())]);
+        let passed: Vec<(&str, bool)> = diagnosis.checks.iter().map(|check| (check.name.split(' ').next().unwrap(), check.passed))
+            .collect();
+        assert_eq!(passed, vec![("sample", true), ("state", false), ("audit", true), ("statements", false), ("manifest", true)]);
+        assert!(!diagnosis.passed());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let mut out = Vec::new();
+        diagnosis.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("PASS sample workload: 9 records applied, balances as expected\n"));
+        assert!(out.ends_with("2 of 5 checks failed.\n"));
+    }
+}
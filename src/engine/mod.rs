@@ -0,0 +1,138 @@
+// A synchronous engine over accounts held in memory, for the front-ends that apply or pre-validate
+// the operations of their users one at a time, rather than running a batch of them through `drill`.
+// The accounts are applied the transactions with the rules of the config, clients without an
+// account getting one on their first transaction. As in a run, the first of the transactions
+// sharing a tx id within a tenant is the one applied, unless the config allows duplicate tx ids.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::account::{Account, AccountAdmin, ProjectedEffect, Result, Rules};
+use crate::config::Config;
+use crate::state::State;
+use crate::transaction::Transaction;
+
+pub struct Engine {
+    rules: Rules,
+    // The account admins, keyed by tenant and client.
+    accounts: BTreeMap<(Option<String>, u16), AccountAdmin>,
+    // The tx ids of the transactions starting a new one applied so far, keyed by tenant.
+    tx_ids: Option<HashSet<(Option<String>, u32)>>,
+}
+
+impl Engine {
+    pub fn new(config: &Config) -> Self {
+        Engine { rules: config.rules.clone(), accounts: BTreeMap::new(),
+                 tx_ids: config.reject_duplicates.then(HashSet::new) }
+    }
+
+    // Resumes the accounts and their history from the state of an earlier run.
+    pub fn resume(state: State, config: &Config) -> Self {
+        let mut engine = Engine::new(config);
+        for (tenant, restored) in state.into_tenants() {
+            for (client_id, (account, history)) in restored {
+                if let Some(tx_ids) = &mut engine.tx_ids {
+                    tx_ids.extend(history.iter().filter(|tx| tx.transaction_type().starts_new())
+                                      .map(|tx| (tenant.clone(), tx.tx_id())));
+                }
+                engine.admin(tenant.clone(), client_id).restore(account, history);
+            }
+        }
+        engine
+    }
+
+    fn admin(&mut self, tenant: Option<String>, client_id: u16) -> &mut AccountAdmin {
+        let rules = &self.rules;
+        self.accounts.entry((tenant.clone(), client_id)).or_insert_with(|| {
+            // The account admins are driven by the engine, their channel is never used.
+            let (_, receiver) = tokio::sync::mpsc::channel(1);
+            let mut account_admin = AccountAdmin::new_with_tenant(client_id, tenant, receiver);
+            account_admin.set_rules(rules.clone());
+            account_admin
+        })
+    }
+
+    pub fn apply(&mut self, mut tx: Transaction) -> Result<&Account> {
+        if let Some(tx_ids) = self.tx_ids.as_mut().filter(|_| tx.transaction_type().starts_new()) {
+            if !tx_ids.insert((tx.tenant().map(String::from), tx.tx_id())) {
+                tx.mark_replayed();
+            }
+        }
+        self.admin(tx.tenant().map(String::from), tx.client_id()).apply(tx)
+    }
+
+    // What applying the transaction would do, without committing it. A client without an account
+    // is evaluated against the account it would be opened.
+    pub fn evaluate(&self, tx: &Transaction) -> Result<ProjectedEffect> {
+        match self.accounts.get(&(tx.tenant().map(String::from), tx.client_id())) {
+            Some(account_admin) => account_admin.evaluate(tx),
+            None => {
+                let (_, receiver) = tokio::sync::mpsc::channel(1);
+                let mut account_admin = AccountAdmin::new_with_tenant(tx.client_id(), tx.tenant().map(String::from),
+                                                                      receiver);
+                account_admin.set_rules(self.rules.clone());
+                account_admin.evaluate(tx)
+            }
+        }
+    }
+
+    pub fn account(&self, tenant: Option<&str>, client_id: u16) -> Option<&Account> {
+        self.accounts.get(&(tenant.map(String::from), client_id)).map(AccountAdmin::account)
+    }
+
+    // The accounts, in tenant and client order.
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values().map(AccountAdmin::account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::account::{Error, Status};
+    use crate::amount::{from_f64, ZERO};
+
+    #[test]
+    fn test_engine_evaluate() {
+        let mut engine = Engine::new(&Config::default());
+        let withdrawal = Tx::withdrawal(1, 2).amount(4.0).build();
+        assert!(matches!(engine.evaluate(&withdrawal), Err(Error::DepositTooLow)));
+        let effect = engine.evaluate(&Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        assert_eq!((effect.before.total, effect.after.total), (ZERO, from_f64(10.0)));
+        assert!(engine.account(None, 1).is_none());
+
+        engine.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        let effect = engine.evaluate(&withdrawal).unwrap();
+        assert_eq!((effect.after.available, effect.transitions()), (from_f64(6.0), false));
+        assert_eq!(engine.account(None, 1).unwrap().available(), from_f64(10.0));
+
+        // The chargeback of the deposit would lock the account.
+        engine.apply(Tx::dispute(1, 1).build()).unwrap();
+        let effect = engine.evaluate(&Tx::chargeback(1, 1).build()).unwrap();
+        assert_eq!((effect.from, effect.to, effect.after.total), (Status::Active, Status::Locked, ZERO));
+        assert_eq!(engine.account(None, 1).unwrap().held(), from_f64(10.0));
+        // Evaluating it again projects the same effect, the dispute still being open.
+        assert_eq!(engine.evaluate(&Tx::chargeback(1, 1).build()).unwrap(), effect);
+    }
+
+    #[test]
+    fn test_engine_negative_amount() {
+        let mut engine = Engine::new(&Config::default());
+        engine.apply(Tx::deposit(1, 1).amount(10.0).build()).unwrap();
+        let withdrawal = Tx::withdrawal(1, 2).amount(-100.0).build();
+        assert!(matches!(engine.evaluate(&withdrawal), Err(Error::NegativeAmount)));
+        assert!(matches!(engine.apply(withdrawal), Err(Error::NegativeAmount)));
+        assert_eq!(engine.account(None, 1).unwrap().available(), from_f64(10.0));
+    }
+
+    #[test]
+    fn test_engine_resume() {
+        let mut engine = Engine::new(&Config::default());
+        engine.apply(Tx::deposit(1, 1).amount(2.0).build()).unwrap();
+        let accounts: Vec<Account> = engine.accounts().cloned().collect();
+        let history = vec![Tx::deposit(1, 1).amount(2.0).build()];
+        let engine = Engine::resume(State { accounts, history, checkpoint: None }, &Config::default());
+        let effect = engine.evaluate(&Tx::dispute(1, 1).build()).unwrap();
+        assert_eq!((effect.after.available, effect.after.held), (ZERO, from_f64(2.0)));
+    }
+}
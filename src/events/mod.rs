@@ -0,0 +1,122 @@
+// Stream of the changes of the accounts, for the downstream systems that need to follow the
+// accounts while the run goes on rather than read the final accounts, e.g. a risk system fed by a
+// message broker producer tailing the stream. Every applied transaction that moves the funds of
+// an account, locks or unlocks it, is written as a JSON line as soon as its audit entry is
+// recorded. The events are not published to Kafka: the crate has no Kafka client.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::account::Status;
+use crate::amount::{self, ZERO};
+use crate::audit::{Entry, Outcome, Sink};
+use crate::config::Labels;
+use crate::notify::json_string;
+
+// What happened to the account.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    BalanceChanged,
+    AccountLocked,
+    AccountUnlocked,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::BalanceChanged => "balance_changed",
+            Kind::AccountLocked => "account_locked",
+            Kind::AccountUnlocked => "account_unlocked"
+        }
+    }
+
+    // The event raised by an audit entry, if any. Locking or unlocking wins over the funds it
+    // moved, e.g. with a chargeback or a representment.
+    pub fn of(entry: &Entry) -> Option<Kind> {
+        let delta = entry.delta();
+        match entry.outcome {
+            Outcome::Rejected(_) => None,
+            Outcome::Applied if entry.locked => Some(Kind::AccountLocked),
+            Outcome::Applied if entry.previous_status == Status::Locked && entry.status != Status::Locked => {
+                Some(Kind::AccountUnlocked)
+            }
+            Outcome::Applied if (delta.available, delta.held, delta.total) != (ZERO, ZERO, ZERO) => {
+                Some(Kind::BalanceChanged)
+            }
+            Outcome::Applied => None
+        }
+    }
+}
+
+// Writes the events with respect to the
+// `{"event","tenant","client","tx","type","available","held","total","locked","status"}` schema,
+// the funds being the ones right after the transaction, and the client being labelled as it
+// appears in the outputs.
+pub fn write_event<W: Write>(writer: &mut W, kind: Kind, entry: &Entry, labels: &Labels) -> io::Result<()> {
+    writeln!(writer, "{{\"event\":\"{}\",\"tenant\":{},\"client\":{},\"tx\":{},\"type\":\"{}\",\"available\":{},\
+                      \"held\":{},\"total\":{},\"locked\":{},\"status\":\"{}\"}}",
+             kind.as_str(), json_string(entry.tenant.as_deref()), json_string(Some(&labels.label(entry.client_id))),
+             entry.tx_id, entry.ttype.as_str(), amount::fmt(entry.after.available), amount::fmt(entry.after.held),
+             amount::fmt(entry.after.total), entry.status == Status::Locked, entry.status.as_str())
+}
+
+// Event stream written into a file, e.g. a named pipe read by a producer. Every event is flushed
+// as it is written, so that it is not held back until the end of the run.
+pub struct EventFile {
+    writer: BufWriter<File>,
+    labels: Labels,
+}
+
+impl EventFile {
+    pub fn create(path: &Path, labels: Labels) -> io::Result<Self> {
+        Ok(EventFile { writer: BufWriter::new(File::create(path)?), labels })
+    }
+}
+
+impl Sink for EventFile {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        match Kind::of(entry) {
+            Some(kind) => {
+                write_event(&mut self.writer, kind, entry, &self.labels)?;
+                self.writer.flush()
+            }
+            None => Ok(())
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::input::Input;
+    use crate::transaction::drill;
+
+    #[test]
+    fn test_event_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.ndjson");
+        let mut config = Config { account_events_path: Some(path.clone()), ..Config::default() };
+        config.rules.admin_unlock = true;
+        let input = Input::from(&b"type,client,tx,amount\n\
+                                   deposit,1,1,2.0\n\
+                                   withdrawal,1,2,5.0\n\
+                                   dispute,1,1,\n\
+                                   chargeback,1,1,\n\
+                                   unlock,1,3,\n"[..]);
+        drill(input, &config).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+                   "{\"event\":\"balance_changed\",\"tenant\":null,\"client\":\"1\",\"tx\":1,\"type\":\"deposit\",\
+                    \"available\":2.0000,\"held\":0.0000,\"total\":2.0000,\"locked\":false,\"status\":\"active\"}\n\
+                    {\"event\":\"balance_changed\",\"tenant\":null,\"client\":\"1\",\"tx\":1,\"type\":\"dispute\",\
+                    \"available\":0.0000,\"held\":2.0000,\"total\":2.0000,\"locked\":false,\"status\":\"active\"}\n\
+                    {\"event\":\"account_locked\",\"tenant\":null,\"client\":\"1\",\"tx\":1,\"type\":\"chargeback\",\
+                    \"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\"locked\":true,\"status\":\"locked\"}\n\
+                    {\"event\":\"account_unlocked\",\"tenant\":null,\"client\":\"1\",\"tx\":3,\"type\":\"unlock\",\
+                    \"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\"locked\":false,\"status\":\"active\"}\n");
+    }
+}
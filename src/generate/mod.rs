@@ -0,0 +1,116 @@
+// Generator of synthetic transaction workloads. The workloads are pseudo-random, but fully
+// determined by their seed, so that the same workload can be generated again on any machine.
+
+use std::io::{self, Write};
+
+// Shape of a generated workload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    pub clients: u16,
+    // Number of records, of all types.
+    pub transactions: usize,
+    // Percentage of the records that dispute an earlier deposit of the client. Every dispute is
+    // settled later on.
+    pub dispute_rate: f64,
+    // Percentage of the settled disputes that are charged back instead of resolved, which locks
+    // the account of the client.
+    pub chargeback_rate: f64,
+    pub seed: u64,
+}
+
+// SplitMix64, good enough for workloads and stable across releases, unlike the generators of
+// external crates.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    // Uniform in `[0, 100)`.
+    fn percent(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64 * 100.0
+    }
+}
+
+// Writes the workload as a CSV input, with respect to the `type,client,tx,amount` schema,
+// returning the number of records written.
+pub fn write<W: Write>(writer: &mut W, workload: &Workload) -> io::Result<usize> {
+    writeln!(writer, "type,client,tx,amount")?;
+    let mut rng = Rng(workload.seed);
+    let clients = workload.clients.max(1) as usize;
+    // Latest undisputed deposit of every client, and the disputes not resolved yet.
+    let mut deposits: Vec<Option<u32>> = vec![None; clients];
+    let mut disputes: Vec<(usize, u32)> = Vec::new();
+    let mut tx_id: u32 = 0;
+    for _ in 0..workload.transactions {
+        let client = rng.below(clients as u64) as usize;
+        let roll = rng.percent();
+        if roll < workload.dispute_rate && deposits[client].is_some() {
+            let disputed = deposits[client].take().unwrap();
+            writeln!(writer, "dispute,{},{},", client, disputed)?;
+            disputes.push((client, disputed));
+        } else if roll < 2.0 * workload.dispute_rate && !disputes.is_empty() {
+            let (client, disputed) = disputes.swap_remove(rng.below(disputes.len() as u64) as usize);
+            // Without chargebacks, no number is drawn, so that the workloads stay the same.
+            let charged_back = workload.chargeback_rate > 0.0 && rng.percent() < workload.chargeback_rate;
+            writeln!(writer, "{},{},{},", if charged_back { "chargeback" } else { "resolve" }, client, disputed)?;
+        } else {
+            tx_id = tx_id.wrapping_add(1);
+            let (units, fraction) = (rng.below(1000), rng.below(10_000));
+            // One in five records takes out a small amount of what came in.
+            if roll >= 80.0 {
+                writeln!(writer, "withdrawal,{},{},{}.{:04}", client, tx_id, units / 10, fraction)?;
+            } else {
+                writeln!(writer, "deposit,{},{},{}.{:04}", client, tx_id, units, fraction)?;
+                deposits[client] = Some(tx_id);
+            }
+        }
+    }
+    Ok(workload.transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(workload: &Workload) -> String {
+        let mut out = Vec::new();
+        assert_eq!(write(&mut out, workload).unwrap(), workload.transactions);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_generate() {
+        let workload = Workload { clients: 10, transactions: 1000, dispute_rate: 5.0, chargeback_rate: 0.0, seed: 7 };
+        let generated = generate(&workload);
+        assert_eq!(generated, generate(&workload));
+        assert_ne!(generated, generate(&Workload { seed: 8, ..workload.clone() }));
+
+        let lines: Vec<&str> = generated.lines().collect();
+        assert_eq!(lines.len(), 1001);
+        assert_eq!(lines[0], "type,client,tx,amount");
+        let count = |ttype: &str| lines.iter().filter(|line| line.starts_with(ttype)).count();
+        assert!(count("dispute,") > 0);
+        assert!(count("resolve,") <= count("dispute,"));
+        assert!(count("withdrawal,") > 0);
+        assert!(lines[1..].iter().all(|line| line.split(',').nth(1).unwrap().parse::<u16>().unwrap() < 10));
+
+        let charged_back = generate(&Workload { chargeback_rate: 50.0, ..workload.clone() });
+        let charged_back: Vec<&str> = charged_back.lines().collect();
+        let count = |ttype: &str| charged_back.iter().filter(|line| line.starts_with(ttype)).count();
+        assert!(count("chargeback,") > 0);
+        assert!(count("resolve,") + count("chargeback,") <= count("dispute,"));
+
+        let deposits = generate(&Workload { clients: 1, transactions: 100, dispute_rate: 0.0, chargeback_rate: 0.0, seed: 1 });
+        assert!(deposits.lines().skip(1).all(|line| line.starts_with("deposit,0,") || line.starts_with("withdrawal,0,")));
+    }
+}
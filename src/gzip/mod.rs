@@ -0,0 +1,480 @@
+// Gzip (RFC 1952) encoding and decoding primitives, used to compress the files the processor
+// archives. The encoder emits DEFLATE (RFC 1951) blocks with the fixed Huffman codes, while the
+// decoder handles every block type, so files compressed by other tools can be read too.
+
+use std::io::{self, Write};
+
+const MAX_DISTANCE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// Input compressed at once, as a single block.
+const CHUNK_LEN: usize = 64 * 1024;
+// Number of earlier positions looked at when searching for a match.
+const MAX_PROBES: usize = 32;
+const HASH_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order the code length code lengths of a dynamic block are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *entry = c;
+    }
+    table
+}
+
+// Incremental CRC-32, as used by the gzip trailer.
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32 { table: crc32_table(), crc: 0xffffffff }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc = self.table[((self.crc ^ *byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn value(&self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
+
+// Packs bits starting with the least significant bit of every byte.
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { bytes: Vec::new(), bits: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting with their most significant bit.
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = (0..length).fold(0, |reversed, bit| (reversed << 1) | ((code >> bit) & 1));
+        self.write(reversed, length);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+            self.bits = 0;
+            self.count = 0;
+        }
+    }
+}
+
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + symbol - 280, 8)
+    }
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap();
+    write_literal(bits, 257 + code as u16);
+    bits.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+    let code = DISTANCE_BASE.iter().rposition(|base| *base as usize <= distance).unwrap();
+    bits.write_code(code as u32, 5);
+    bits.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+// Compresses a chunk as a fixed Huffman block, looking for the longest earlier match of every
+// position through hash chains.
+fn write_block(bits: &mut BitWriter, data: &[u8], last: bool) {
+    bits.write(last as u32, 1);
+    bits.write(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; data.len()];
+    let insert = |pos: usize, head: &mut Vec<usize>, previous: &mut Vec<usize>| {
+        if pos + MIN_MATCH <= data.len() {
+            let slot = hash(&data[pos..]);
+            previous[pos] = head[slot];
+            head[slot] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(&data[pos..])];
+            let mut probes = 0;
+            while candidate != usize::MAX && pos - candidate <= MAX_DISTANCE && probes < MAX_PROBES {
+                let length = data[candidate..].iter().zip(data[pos..].iter()).take(MAX_MATCH)
+                    .take_while(|(a, b)| a == b).count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = pos - candidate;
+                }
+                candidate = previous[candidate];
+                probes += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_match(bits, best_length, best_distance);
+            for idx in pos..pos + best_length {
+                insert(idx, &mut head, &mut previous);
+            }
+            pos += best_length;
+        } else {
+            write_literal(bits, data[pos] as u16);
+            insert(pos, &mut head, &mut previous);
+            pos += 1;
+        }
+    }
+    write_literal(bits, 256);
+}
+
+// Streams a gzip member into the inner writer, which gets the compressed bytes a chunk at a time.
+pub struct GzEncoder<W: Write> {
+    inner: W,
+    chunk: Vec<u8>,
+    bits: BitWriter,
+    crc: Crc32,
+    size: u32,
+}
+
+impl<W: Write> GzEncoder<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        // No file name, no modification time and an unknown operating system.
+        inner.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255])?;
+        Ok(GzEncoder { inner, chunk: Vec::with_capacity(CHUNK_LEN), bits: BitWriter::new(), crc: Crc32::new(), size: 0 })
+    }
+
+    fn compress_chunk(&mut self, last: bool) -> io::Result<()> {
+        write_block(&mut self.bits, &self.chunk, last);
+        self.chunk.clear();
+        self.inner.write_all(&self.bits.bytes)?;
+        self.bits.bytes.clear();
+        Ok(())
+    }
+
+    // Writes the last block and the trailer, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress_chunk(true)?;
+        self.bits.align();
+        self.inner.write_all(&self.bits.bytes)?;
+        self.inner.write_all(&self.crc.value().to_le_bytes())?;
+        self.inner.write_all(&self.size.to_le_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_LEN - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        self.crc.update(&buf[..len]);
+        self.size = self.size.wrapping_add(len as u32);
+        if self.chunk.len() == CHUNK_LEN {
+            self.compress_chunk(false)?;
+        }
+        Ok(len)
+    }
+
+    // The pending chunk is only compressed once full, or finished.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        let mut value = 0;
+        for idx in 0..count {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid_data("truncated deflate stream"))?;
+            value |= (((byte >> self.bit) & 1) as u32) << idx;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// Canonical Huffman code, as the number of codes of every length and the symbols sorted by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16).filter(|symbol| lengths[*symbol as usize] > 0).collect();
+        symbols.sort_by_key(|symbol| lengths[*symbol as usize]);
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literals = bits.read(5)? as usize + 257;
+    let distances = bits.read(5)? as usize + 1;
+    let code_lengths = bits.read(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for idx in CODE_LENGTH_ORDER.iter().take(code_lengths) {
+        lengths[*idx] = bits.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid_data("repeat without a length"))?, 3 + bits.read(2)?),
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?)
+        };
+        lengths.extend(std::iter::repeat(length).take(repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err(invalid_data("too many code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn inflate(bits: &mut BitReader, out: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.align();
+                let header = bits.data.get(bits.pos..bits.pos + 4).ok_or_else(|| invalid_data("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                bits.pos += 4;
+                let stored = bits.data.get(bits.pos..bits.pos + len).ok_or_else(|| invalid_data("truncated stored block"))?;
+                out.extend_from_slice(stored);
+                bits.pos += len;
+            }
+            block_type @ 1..=2 => {
+                let (literals, distances) = if block_type == 1 { fixed_codes() } else { dynamic_codes(bits)? };
+                loop {
+                    let symbol = literals.decode(bits)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let code = symbol - 257;
+                    if code >= LENGTH_BASE.len() {
+                        return Err(invalid_data("invalid length code"));
+                    }
+                    let length = LENGTH_BASE[code] as usize + bits.read(LENGTH_EXTRA[code] as u32)? as usize;
+                    let code = distances.decode(bits)? as usize;
+                    if code >= DISTANCE_BASE.len() {
+                        return Err(invalid_data("invalid distance code"));
+                    }
+                    let distance = DISTANCE_BASE[code] as usize + bits.read(DISTANCE_EXTRA[code] as u32)? as usize;
+                    if distance > out.len() {
+                        return Err(invalid_data("distance too far back"));
+                    }
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+            _ => return Err(invalid_data("invalid block type"))
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+// Decodes the members of a gzip file, checking their trailers.
+pub fn decode(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 10).ok_or_else(|| invalid_data("truncated gzip header"))?;
+        if header[..3] != [0x1f, 0x8b, 8] {
+            return Err(invalid_data("not a gzip file"));
+        }
+        let flags = header[3];
+        pos += 10;
+        if flags & 4 != 0 {
+            let extra = data.get(pos..pos + 2).ok_or_else(|| invalid_data("truncated gzip header"))?;
+            pos += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        for flag in [8, 16].iter() {
+            if flags & flag != 0 {
+                pos += data.get(pos..).and_then(|rest| rest.iter().position(|byte| *byte == 0))
+                    .ok_or_else(|| invalid_data("truncated gzip header"))? + 1;
+            }
+        }
+        if flags & 2 != 0 {
+            pos += 2;
+        }
+
+        let start = out.len();
+        let mut bits = BitReader { data, pos, bit: 0 };
+        inflate(&mut bits, &mut out)?;
+        bits.align();
+        let trailer = data.get(bits.pos..bits.pos + 8).ok_or_else(|| invalid_data("truncated gzip trailer"))?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err(invalid_data("corrupted gzip member"));
+        }
+        pos = bits.pos + 8;
+    }
+    Ok(out)
+}
+
+// Compresses a whole buffer at once, for the tests of the readers of gzip files.
+#[cfg(test)]
+pub fn encode(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new())?;
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_gzip_roundtrip() {
+        let mut records = String::new();
+        for idx in 0..20000 {
+            records.push_str(&format!(",{},{},deposit,{}.0000,0.0000,{}\n", idx % 7, idx, idx % 13, 1600000000 + idx));
+        }
+        for data in [&b""[..], b"a", b"abcabcabcabcabc", records.as_bytes()].iter() {
+            let encoded = encode(data).unwrap();
+            assert_eq!(decode(&encoded).unwrap(), *data);
+        }
+        assert!(encode(records.as_bytes()).unwrap().len() < records.len() / 3);
+    }
+
+    #[test]
+    fn test_gzip_decode_dynamic() {
+        // Python's `gzip.compress` output of the lines `<idx % 5>,tx <idx>,deposit` for the first 40
+        // indexes, compressed with a dynamic block.
+        let encoded = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x5d, 0xd1, 0x3b, 0x0e, 0xc2, 0x40, 0x10,
+            0x04, 0xd1, 0x9c, 0x53, 0x70, 0x00, 0x07, 0xdb, 0xdd, 0xe6, 0x77, 0x1f, 0x08, 0x88, 0xb0, 0x64, 0x07,
+            0x1c, 0x1f, 0x4d, 0xb2, 0x68, 0x2a, 0xac, 0xa4, 0xf5, 0x34, 0x33, 0x96, 0xe3, 0x7b, 0x1e, 0xcb, 0xf3,
+            0xb5, 0x7d, 0xf6, 0xf7, 0x71, 0x52, 0xa5, 0x66, 0xba, 0xd2, 0x33, 0x53, 0x99, 0x99, 0x6b, 0xe5, 0x3a,
+            0x73, 0x54, 0x5e, 0xfa, 0xd4, 0xb5, 0x4f, 0xdd, 0xfa, 0xd4, 0xbd, 0x4f, 0x3d, 0xfa, 0x94, 0xc8, 0x82,
+            0x4b, 0x80, 0x09, 0x32, 0x81, 0x26, 0xd8, 0x04, 0x9c, 0xa0, 0x13, 0x78, 0x82, 0xcf, 0xf0, 0x99, 0x77,
+            0x83, 0xcf, 0xf0, 0x19, 0x3e, 0xc3, 0x67, 0xf8, 0x0c, 0x9f, 0xe1, 0x33, 0x7c, 0x81, 0x2f, 0xf0, 0x85,
+            0x8f, 0x85, 0x2f, 0xf0, 0x05, 0xbe, 0xc0, 0x17, 0xf8, 0x02, 0x5f, 0xfe, 0xbe, 0x1f, 0x9d, 0xae, 0xe3,
+            0x1e, 0x76, 0x02, 0x00, 0x00,
+        ];
+        let lines: String = (0..40).map(|idx| format!("{},tx {},deposit\n", idx % 5, idx)).collect();
+        assert_eq!(decode(&encoded).unwrap(), lines.as_bytes());
+        assert!(decode(&encoded[..20]).is_err());
+        assert!(decode(b"not gzip at all").is_err());
+    }
+}
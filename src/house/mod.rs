@@ -0,0 +1,111 @@
+// Internal house accounts of a run. The funds that leave the client accounts other than by
+// withdrawals do not simply vanish: the charged back funds are credited to a chargeback house
+// account, until a representment takes them back, and the fees to a fee house account, so that
+// the books of every tenant stay balanced.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::account::Account;
+use crate::amount::{self, Amount, ZERO};
+
+// Names of the house accounts the funds are credited to. Both may name the same account, e.g. a
+// single suspense account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routes {
+    pub chargebacks: String,
+    pub fees: String,
+}
+
+impl Default for Routes {
+    fn default() -> Self {
+        Routes { chargebacks: String::from("chargebacks"), fees: String::from("fees") }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HouseAccount {
+    pub tenant: Option<String>,
+    pub name: String,
+    // Number of transactions that moved funds in or out of the account.
+    pub transactions: u64,
+    pub balance: Amount,
+}
+
+// Accumulates the house accounts of every tenant from the volumes of the client accounts of the
+// run, ordered by tenant and name.
+pub fn balances(accounts: &[Account], routes: &Routes) -> Vec<HouseAccount> {
+    let mut balances: BTreeMap<(Option<String>, String), (u64, Amount)> = BTreeMap::new();
+    for account in accounts {
+        let volumes = account.volumes();
+        let tenant = account.tenant().map(String::from);
+        let chargebacks = balances.entry((tenant.clone(), routes.chargebacks.clone())).or_insert((0, ZERO));
+        chargebacks.0 += volumes.chargebacks.count + volumes.representments.count;
+        chargebacks.1 += volumes.chargebacks.amount - volumes.representments.amount;
+        let fees = balances.entry((tenant, routes.fees.clone())).or_insert((0, ZERO));
+        fees.0 += volumes.fees.count;
+        fees.1 += volumes.fees.amount;
+    }
+    balances.into_iter()
+        .map(|((tenant, name), (transactions, balance))| HouseAccount { tenant, name, transactions, balance })
+        .collect()
+}
+
+// Writes the house accounts with respect to the `tenant,account,transactions,balance` schema.
+pub fn write<W: Write>(writer: &mut W, house_accounts: &[HouseAccount]) -> io::Result<()> {
+    writeln!(writer, "tenant,account,transactions,balance")?;
+    for house_account in house_accounts {
+        writeln!(writer, "{},{},{},{}", house_account.tenant.as_deref().unwrap_or(""), house_account.name,
+                 house_account.transactions, amount::fmt(house_account.balance))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::account::AccountAdmin;
+    use crate::transaction::Transaction;
+    use crate::amount::from_f64;
+
+    fn account(tenant: Option<&str>, client_id: u16, txs: Vec<Transaction>) -> Account {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(client_id, receiver);
+        for tx in txs {
+            account_admin.apply(tx).unwrap();
+        }
+        let mut account = account_admin.account().clone();
+        account.set_tenant(tenant.map(String::from));
+        account
+    }
+
+    #[test]
+    fn test_house_balances() {
+        let accounts = vec![
+            account(None, 1, vec![Tx::deposit(1, 1).amount(5.0).build(),
+                                  Tx::deposit(1, 2).amount(3.0).build(),
+                                  Tx::dispute(1, 1).build(), Tx::dispute(1, 2).build(),
+                                  Tx::chargeback(1, 1).build(), Tx::chargeback(1, 2).build(),
+                                  Tx::representment(1, 2).build()]),
+            account(None, 2, vec![Tx::deposit(2, 3).amount(5.0).build(),
+                                  Tx::fee(2, 4).amount(0.5).build()]),
+            account(Some("acme"), 1, vec![Tx::deposit(1, 1).amount(2.0).build(),
+                                          Tx::fee(1, 2).amount(1.5).build()]),
+        ];
+        let house_accounts = balances(&accounts, &Routes::default());
+        let mut out = Vec::new();
+        write(&mut out, &house_accounts).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "tenant,account,transactions,balance\n\
+                                                     ,chargebacks,3,5.0000\n\
+                                                     ,fees,1,0.5000\n\
+                                                     acme,chargebacks,0,0.0000\n\
+                                                     acme,fees,1,1.5000\n");
+
+        // Funds routed to the same account add up.
+        let routes = Routes { chargebacks: String::from("suspense"), fees: String::from("suspense") };
+        let house_accounts = balances(&accounts[..2], &routes);
+        assert_eq!(house_accounts, vec![HouseAccount { tenant: None, name: String::from("suspense"), transactions: 4,
+                                                       balance: from_f64(5.5) }]);
+    }
+}
@@ -1,42 +1,278 @@
 // Offers primitives for parsing the transaction processor input.
 
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
 
-// A file wrapper that provides primitives for iterating through a specifc CSV file line by line.
-// This also takes into account the header line.
+use crate::gzip;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    // The input is empty, without even a header line.
+    MissingHeader,
+    // The header line, which does not describe the known columns.
+    BadHeader(String),
+    Io(io::Error),
+}
+
+impl Error {
+    // Tells what is wrong with the input at `path`, for the operators.
+    pub fn describe(&self, path: &Path) -> String {
+        match self {
+            Error::MissingHeader => format!("{} is empty, while it is mandatory for a CSV file to begin with the header \
+                                             line.", path.display()),
+            Error::BadHeader(header) => {
+                format!("The header line `{}` of {} is not as expected. Please stick to the following header line \
+                         `type,client,tx,amount`, optionally extended with the `tenant`, `reason`, `timestamp`, \
+                         `sequence` and `source` columns.", header, path.display())
+            }
+            Error::Io(_) => format!("Could not read {}. Please provide the path to a correctly formatted csv file.",
+                                    path.display())
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+// Positions of the known columns of a CSV file, derived from its header line. The `type`,
+// `client`, `tx` and `amount` columns are mandatory, while `tenant`, `reason`, `timestamp`,
+// `sequence` and `source` are optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    ttype: usize,
+    client: usize,
+    tx: usize,
+    amount: usize,
+    tenant: Option<usize>,
+    // Reason code of the disputes.
+    reason: Option<usize>,
+    timestamp: Option<usize>,
+    // Position of the record among the records of its client, as numbered by the producer.
+    sequence: Option<usize>,
+    // Label of the source of the record, e.g. for the dead letters of a run with several sources.
+    source: Option<usize>,
+}
+
+impl Default for Schema {
+    // The `type,client,tx,amount` layout.
+    fn default() -> Self {
+        Schema { ttype: 0, client: 1, tx: 2, amount: 3, tenant: None, reason: None, timestamp: None, sequence: None,
+                 source: None }
+    }
+}
+
+impl Schema {
+    // Builds the schema out of a header line, returning `None` for unknown, duplicated or
+    // missing columns.
+    pub fn from_header(header: &str) -> Option<Schema> {
+        let (mut ttype, mut client, mut tx, mut amount) = (None, None, None, None);
+        let (mut tenant, mut reason, mut timestamp, mut sequence, mut source) = (None, None, None, None, None);
+        for (idx, column) in header.trim_end_matches(&['\r', '\n'][..]).split(',').enumerate() {
+            let slot = match column {
+                "type" => &mut ttype,
+                "client" => &mut client,
+                "tx" => &mut tx,
+                "amount" => &mut amount,
+                "tenant" => &mut tenant,
+                "reason" => &mut reason,
+                "timestamp" => &mut timestamp,
+                "sequence" => &mut sequence,
+                "source" => &mut source,
+                _ => return None
+            };
+            if slot.replace(idx).is_some() {
+                return None;
+            }
+        }
+
+        Some(Schema { ttype: ttype?, client: client?, tx: tx?, amount: amount?, tenant, reason, timestamp, sequence,
+                      source })
+    }
+
+    pub fn ttype(&self) -> usize {
+        self.ttype
+    }
+
+    pub fn client(&self) -> usize {
+        self.client
+    }
+
+    pub fn tx(&self) -> usize {
+        self.tx
+    }
+
+    pub fn amount(&self) -> usize {
+        self.amount
+    }
+
+    pub fn tenant(&self) -> Option<usize> {
+        self.tenant
+    }
+
+    pub fn has_tenant(&self) -> bool {
+        self.tenant.is_some()
+    }
+
+    pub fn reason(&self) -> Option<usize> {
+        self.reason
+    }
+
+    pub fn timestamp(&self) -> Option<usize> {
+        self.timestamp
+    }
+
+    pub fn sequence(&self) -> Option<usize> {
+        self.sequence
+    }
+
+    pub fn source(&self) -> Option<usize> {
+        self.source
+    }
+}
+
+// Where the reading of an input is at: the byte offset the next record starts at, and the number
+// of records before it, the header left out. A run over an input that grew since an earlier run
+// can carry on from the checkpoint of the earlier run, instead of reading the input all over.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Checkpoint {
+    pub offset: u64,
+    pub records: u64,
+}
+
+// A reader wrapper that provides primitives for iterating through CSV contents line by line, be
+// it a file, bytes in memory or any other reader. This also takes into account the header line.
 pub struct Input {
-    reader: BufReader<File>,
+    reader: Box<dyn BufRead + Send>,
+    schema: Schema,
+    checkpoint: Checkpoint,
 }
 
 impl From<File> for Input {
     fn from(file: File) -> Self {
-        let mut buf_reader = BufReader::new(file);
-        let mut line = String::new();
-        let bytes_read = buf_reader.read_line(&mut line);
-        match bytes_read {
-            Ok(_) => if line != "type,client,tx,amount\n" {
-                panic!("The CSV file format is not as expected.\n\
-                Please stick to the following header line `type,client,tx,amount`.\n\
-                If still in doubt, consult the documentation.");
-            }
-            Err(_) => panic!("Error while reading the header line of the CSV file.\n\
-            It is mandatory that the CSV file to began with the header line.")
+        Input::new(BufReader::new(file))
+    }
+}
+
+impl From<&[u8]> for Input {
+    fn from(bytes: &[u8]) -> Self {
+        Input::new(Cursor::new(bytes.to_vec()))
+    }
+}
+
+impl Input {
+    // Panics on a missing or unexpected header line, see `try_new` for the fallible version.
+    pub fn new<R: BufRead + Send + 'static>(reader: R) -> Self {
+        match Input::try_new(reader) {
+            Ok(input) => input,
+            Err(Error::Io(_)) => panic!("Error while reading the header line of the CSV file.\n\
+            It is mandatory that the CSV file to began with the header line."),
+            Err(_) => panic!("The CSV file format is not as expected.\n\
+            Please stick to the following header line `type,client,tx,amount`, optionally \
+            extended with the `tenant`, `reason`, `timestamp`, `sequence` and `source` columns.\n\
+            If still in doubt, consult the documentation.")
+        }
+    }
+
+    // Reads the header line of the reader, failing if it is missing or does not describe the
+    // known columns.
+    pub fn try_new<R: BufRead + Send + 'static>(mut reader: R) -> Result<Self> {
+        let (schema, offset) = read_header(&mut reader)?;
+        Ok(Input { reader: Box::new(reader), schema, checkpoint: Checkpoint { offset, records: 0 } })
+    }
+
+    // Same as `try_new`, the records being read from the checkpoint on, e.g. the one an earlier
+    // run over the same input stopped at. The header is still read for the schema.
+    pub fn try_new_at<R: BufRead + Seek + Send + 'static>(mut reader: R, checkpoint: Checkpoint) -> Result<Self> {
+        let (schema, offset) = read_header(&mut reader)?;
+        let checkpoint = if checkpoint.offset > offset {
+            reader.seek(SeekFrom::Start(checkpoint.offset))?;
+            checkpoint
+        } else {
+            Checkpoint { offset, records: 0 }
         };
+        Ok(Input { reader: Box::new(reader), schema, checkpoint })
+    }
+
+    // Opens the CSV file at `path`, decompressing it first if it is gzip compressed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if is_gzip(&mut reader)? {
+            Input::try_new(decompress(reader)?)
+        } else {
+            Input::try_new(reader)
+        }
+    }
+
+    // Opens the CSV file at `path`, reading it from the checkpoint on. The checkpoint of a gzip
+    // compressed file is an offset into its decompressed records.
+    pub fn open_at(path: &Path, checkpoint: Checkpoint) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        if is_gzip(&mut reader)? {
+            Input::try_new_at(decompress(reader)?, checkpoint)
+        } else {
+            Input::try_new_at(reader, checkpoint)
+        }
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
 
-        Input { reader: buf_reader }
+    // Where the next record starts.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.checkpoint
+    }
+
+    // Number of the line read last, the header being the first line of the input.
+    pub fn line(&self) -> u64 {
+        self.checkpoint.records + 1
     }
 }
 
-// We want to take on the input line by line.
+fn is_gzip<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(&[0x1f, 0x8b]))
+}
+
+// The compressed files are decompressed in memory, the decoder working on whole files.
+fn decompress<R: Read>(mut reader: R) -> io::Result<Cursor<Vec<u8>>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(Cursor::new(gzip::decode(&data)?))
+}
+
+// Reads the schema out of the header line, along with the length of the line.
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(Schema, u64)> {
+    let mut line = String::new();
+    let bytes_read = reader.read_line(&mut line)?;
+    if bytes_read == 0 {
+        return Err(Error::MissingHeader);
+    }
+    match Schema::from_header(&line) {
+        Some(schema) => Ok((schema, bytes_read as u64)),
+        None => Err(Error::BadHeader(line.trim_end().to_string()))
+    }
+}
+
+// We want to take on the input line by line. A line that is not valid UTF-8 is handed out with
+// the invalid bytes replaced, so that it is rejected as a record rather than ending the input.
 impl Iterator for Input {
     type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut line: String = String::new();
-        let line_wrapper = self.reader.read_line(&mut line);
+        let mut line = Vec::new();
+        let line_wrapper = self.reader.read_until(b'\n', &mut line);
         match line_wrapper {
-            Ok(bytes_read) if bytes_read > 0 => Some(line),
+            Ok(bytes_read) if bytes_read > 0 => {
+                self.checkpoint.offset += bytes_read as u64;
+                self.checkpoint.records += 1;
+                Some(String::from_utf8_lossy(&line).into_owned())
+            }
             Ok(_) | Err(_) => None
         }
     }
@@ -44,9 +280,10 @@ impl Iterator for Input {
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Seek, SeekFrom, Write};
+    use std::io::{BufReader, Cursor, Seek, SeekFrom, Write};
+    use std::path::Path;
     use tempfile::tempfile;
-    use crate::input::Input;
+    use crate::input::{Checkpoint, Error, Input, Schema};
 
     #[test]
     #[should_panic]
@@ -65,6 +302,102 @@ mod tests {
         let _ = Input::from(tmp_file);
     }
 
+    #[test]
+    fn test_input_from_bytes() {
+        let mut input = Input::from(&b"type,client,tx,amount,timestamp\ndeposit,0,0,1.0,5\n"[..]);
+        assert_eq!(input.schema().timestamp(), Some(4));
+        assert_eq!(input.next().unwrap(), "deposit,0,0,1.0,5\n");
+        assert!(input.next().is_none());
+
+        let mut input = Input::new(BufReader::new(&b"tenant,type,client,tx,amount\nacme,deposit,0,0,1.0"[..]));
+        assert!(input.schema().has_tenant());
+        assert_eq!(input.next().unwrap(), "acme,deposit,0,0,1.0");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_input_from_bytes_missing_header_line() {
+        let _ = Input::from(&b""[..]);
+    }
+
+    #[test]
+    fn test_input_try_new() {
+        assert!(Input::try_new(Cursor::new(b"type,client,tx,amount\n".to_vec())).is_ok());
+        assert!(matches!(Input::try_new(Cursor::new(Vec::new())), Err(Error::MissingHeader)));
+        assert!(matches!(Input::try_new(Cursor::new(b"1,2,3,4\r\n".to_vec())), Err(Error::BadHeader(header)) if header == "1,2,3,4"));
+        let dir = tempfile::tempdir().unwrap();
+        let err = Input::open(&dir.path().join("missing.csv")).err().unwrap();
+        assert!(matches!(err, Error::Io(_)));
+        assert!(err.describe(Path::new("missing.csv")).starts_with("Could not read missing.csv."));
+    }
+
+    #[test]
+    fn test_input_checkpoint() {
+        let csv = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n".to_vec();
+        let mut input = Input::try_new(Cursor::new(csv.clone())).unwrap();
+        assert_eq!(input.checkpoint(), Checkpoint { offset: 22, records: 0 });
+        input.next().unwrap();
+        let checkpoint = input.checkpoint();
+        assert_eq!(checkpoint, Checkpoint { offset: 38, records: 1 });
+
+        let mut input = Input::try_new_at(Cursor::new(csv.clone()), checkpoint).unwrap();
+        assert_eq!(input.next().unwrap(), "deposit,1,2,2.0\n");
+        assert!(input.next().is_none());
+        assert_eq!(input.checkpoint(), Checkpoint { offset: csv.len() as u64, records: 2 });
+        // A checkpoint within the header reads the input from its first record.
+        let mut input = Input::try_new_at(Cursor::new(csv), Checkpoint::default()).unwrap();
+        assert_eq!(input.next().unwrap(), "deposit,1,1,1.0\n");
+    }
+
+    #[test]
+    fn test_input_line() {
+        let mut input = Input::from(&b"type,client,tx,amount\ndeposit,1,\xff,1.0\ndeposit,1,2,2.0\n"[..]);
+        assert_eq!(input.line(), 1);
+        assert_eq!(input.next().unwrap(), "deposit,1,\u{fffd},1.0\n");
+        assert_eq!(input.line(), 2);
+        assert_eq!(input.next().unwrap(), "deposit,1,2,2.0\n");
+        assert_eq!(input.line(), 3);
+    }
+
+    #[test]
+    fn test_input_open_gzip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.csv.gz");
+        std::fs::write(&path, crate::gzip::encode(b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n").unwrap()).unwrap();
+        let mut input = Input::open(&path).unwrap();
+        assert_eq!(input.next().unwrap(), "deposit,1,1,1.0\n");
+        let mut input = Input::open_at(&path, input.checkpoint()).unwrap();
+        assert_eq!(input.next().unwrap(), "deposit,1,2,2.0\n");
+        assert!(input.next().is_none());
+    }
+
+    #[test]
+    fn test_schema_from_header() {
+        assert_eq!(Schema::from_header("type,client,tx,amount\n").unwrap(), Schema::default());
+        assert_eq!(Schema::from_header("type,client,tx,amount").unwrap(), Schema::default());
+        let schema = Schema::from_header("tenant,type,client,tx,amount\r\n").unwrap();
+        assert_eq!(schema.tenant(), Some(0));
+        assert_eq!(schema.ttype(), 1);
+        assert_eq!(schema.amount(), 4);
+        assert_eq!(schema.reason(), None);
+        assert_eq!(Schema::from_header("type,client,tx,amount,reason").unwrap().reason(), Some(4));
+        assert!(Schema::from_header("type,client,tx,amount,reason,reason").is_none());
+        assert_eq!(Schema::from_header("timestamp,type,client,tx,amount").unwrap().timestamp(), Some(0));
+        assert_eq!(Schema::from_header("type,client,tx,amount,sequence").unwrap().sequence(), Some(4));
+        assert!(Schema::from_header("type,client,tx").is_none());
+        assert!(Schema::from_header("type,client,tx,amount,amount").is_none());
+        assert!(Schema::from_header("type,client,tx,amount,extra").is_none());
+    }
+
+    #[test]
+    fn test_input_from_file_tenant_header_line() {
+        let mut tmp_file = tempfile().unwrap();
+        writeln!(tmp_file, "type,client,tx,amount,tenant").unwrap();
+        tmp_file.seek(SeekFrom::Start(0)).unwrap();
+        let input = Input::from(tmp_file);
+        assert!(input.schema().has_tenant());
+    }
+
     #[test]
     fn test_input_from_file_iterator() {
         let mut tmp_file = tempfile().unwrap();
@@ -0,0 +1,192 @@
+// Reconstructs the state an account had at some point in the past, out of the audit trail.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::amount;
+use crate::audit::Balance;
+
+// Point the state is reconstructed at, both bounds being inclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    // Right after the given input record was handled.
+    Record(u64),
+    // Right after the last transaction handled with the account clock at or before the given
+    // timestamp. The entries handled before the account clock was set are always included.
+    Timestamp(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct State {
+    pub balance: Balance,
+    pub applied: usize,
+    pub rejected: usize,
+    // Transactions whose dispute is still open.
+    pub open_disputes: BTreeSet<u32>,
+}
+
+// Positions of the audit trail columns the state is derived from.
+struct Columns {
+    tenant: usize,
+    client: usize,
+    tx: usize,
+    ttype: usize,
+    outcome: usize,
+    available: usize,
+    held: usize,
+    total: usize,
+    record: usize,
+    timestamp: usize,
+}
+
+impl Columns {
+    fn from_header(header: &str) -> Option<Columns> {
+        let columns: Vec<&str> = header.trim_end().split(',').collect();
+        let position = |name: &str| columns.iter().position(|column| *column == name);
+        Some(Columns {
+            tenant: position("tenant")?,
+            client: position("client")?,
+            tx: position("tx")?,
+            ttype: position("type")?,
+            outcome: position("outcome")?,
+            available: position("available_after")?,
+            held: position("held_after")?,
+            total: position("total_after")?,
+            record: position("record")?,
+            timestamp: position("timestamp")?,
+        })
+    }
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| invalid_data("invalid audit trail entry"))
+}
+
+// Replays the entries of a client up to `as_of`, returning `None` when the client has none.
+pub fn state_as_of<R: BufRead>(reader: R, tenant: Option<&str>, client_id: u16, as_of: AsOf)
+                               -> io::Result<Option<State>> {
+    let mut lines = reader.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns = Columns::from_header(&header).ok_or_else(|| invalid_data("not an audit trail"))?;
+
+    let mut state: Option<State> = None;
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() <= columns.timestamp {
+            return Err(invalid_data("invalid audit trail entry"));
+        }
+        if fields[columns.tenant] != tenant.unwrap_or("") || parse::<u16>(fields[columns.client])? != client_id {
+            continue;
+        }
+        let included = match as_of {
+            AsOf::Record(record) => fields[columns.record].is_empty() || parse::<u64>(fields[columns.record])? <= record,
+            AsOf::Timestamp(timestamp) => {
+                fields[columns.timestamp].is_empty() || parse::<u64>(fields[columns.timestamp])? <= timestamp
+            }
+        };
+        if !included {
+            // The entries of an account are recorded in the order they were handled.
+            break;
+        }
+
+        let state = state.get_or_insert_with(State::default);
+        let parse_amount = |value: &str| amount::parse(value).ok_or_else(|| invalid_data("invalid audit trail entry"));
+        state.balance = Balance {
+            available: parse_amount(fields[columns.available])?,
+            held: parse_amount(fields[columns.held])?,
+            total: parse_amount(fields[columns.total])?,
+        };
+        if fields[columns.outcome] != "applied" {
+            state.rejected += 1;
+            continue;
+        }
+        state.applied += 1;
+        let tx_id = parse::<u32>(fields[columns.tx])?;
+        match fields[columns.ttype] {
+            "dispute" => {
+                state.open_disputes.insert(tx_id);
+            }
+            "resolve" | "chargeback" | "reversal" => {
+                state.open_disputes.remove(&tx_id);
+            }
+            _ => {}
+        }
+    }
+    Ok(state)
+}
+
+// Writes the state with respect to the
+// `tenant,client,available,held,total,applied,rejected,open_disputes` schema, the open disputes
+// being separated by spaces.
+pub fn write_state<W: Write>(writer: &mut W, tenant: Option<&str>, client_id: u16, state: &State) -> io::Result<()> {
+    writeln!(writer, "tenant,client,available,held,total,applied,rejected,open_disputes")?;
+    let open_disputes: Vec<String> = state.open_disputes.iter().map(u32::to_string).collect();
+    writeln!(writer, "{},{},{},{},{},{},{},{}", tenant.unwrap_or(""), client_id, amount::fmt(state.balance.available),
+             amount::fmt(state.balance.held), amount::fmt(state.balance.total), state.applied, state.rejected,
+             open_disputes.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use crate::audit::{write_entry, write_header, Entry, Outcome};
+    use crate::transaction::Type;
+    use crate::amount::{from_f64, Amount, ZERO};
+
+    fn entry(client_id: u16, tx_id: u32, ttype: Type, outcome: Outcome, total: Amount, held: Amount, record: u64)
+             -> Entry {
+        Entry { tenant: None, client_id, tx_id, ttype, amount: None, outcome, note: None, before: Balance::default(),
+                after: Balance { available: total - held, held, total }, timestamp: Some(100 + record),
+                locked: false,
+                previous_status: Status::Active, status: Status::Active, record: Some(record), source: None }
+    }
+
+    fn audit_trail() -> Vec<u8> {
+        let mut trail = Vec::new();
+        write_header(&mut trail).unwrap();
+        for entry in [
+            entry(1, 1, Type::Deposit, Outcome::Applied, from_f64(5.0), ZERO, 1),
+            entry(2, 2, Type::Deposit, Outcome::Applied, from_f64(3.0), ZERO, 2),
+            entry(1, 1, Type::Dispute, Outcome::Applied, from_f64(5.0), from_f64(5.0), 3),
+            entry(1, 3, Type::Withdrawal, Outcome::Rejected("insufficient_funds"), from_f64(5.0), from_f64(5.0), 4),
+            entry(1, 1, Type::Chargeback, Outcome::Applied, ZERO, ZERO, 5),
+        ].iter() {
+            write_entry(&mut trail, entry).unwrap();
+        }
+        trail
+    }
+
+    #[test]
+    fn test_state_as_of() {
+        let trail = audit_trail();
+        let state = state_as_of(&trail[..], None, 1, AsOf::Record(4)).unwrap().unwrap();
+        assert_eq!(state.balance, Balance { available: ZERO, held: from_f64(5.0), total: from_f64(5.0) });
+        assert_eq!((state.applied, state.rejected), (2, 1));
+        assert_eq!(state.open_disputes, [1].iter().copied().collect());
+
+        let state = state_as_of(&trail[..], None, 1, AsOf::Timestamp(105)).unwrap().unwrap();
+        assert_eq!(state.balance, Balance::default());
+        assert!(state.open_disputes.is_empty());
+
+        assert_eq!(state_as_of(&trail[..], None, 2, AsOf::Record(1)).unwrap(), None);
+        assert_eq!(state_as_of(&trail[..], Some("acme"), 1, AsOf::Record(5)).unwrap(), None);
+        assert!(state_as_of(&b"type,client,tx\n"[..], None, 1, AsOf::Record(5)).is_err());
+    }
+
+    #[test]
+    fn test_write_state() {
+        let trail = audit_trail();
+        let state = state_as_of(&trail[..], None, 1, AsOf::Record(3)).unwrap().unwrap();
+        let mut out = Vec::new();
+        write_state(&mut out, None, 1, &state).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,available,held,total,applied,rejected,open_disputes\n\
+                   ,1,0.0000,5.0000,5.0000,2,0,1\n");
+    }
+}
@@ -1,4 +1,62 @@
+pub mod amount;
 pub mod input;
 pub mod transaction;
 pub mod account;
+#[doc(hidden)]
 pub mod logger;
+pub mod config;
+#[doc(hidden)]
+pub mod digest;
+pub mod pseudonym;
+pub mod report;
+pub mod audit;
+pub mod statement;
+pub mod aggregate;
+pub mod compliance;
+pub mod notify;
+pub mod inspect;
+pub mod merkle;
+pub(crate) mod gzip;
+pub mod archive;
+pub mod manifest;
+pub mod state;
+#[doc(hidden)]
+pub mod doctor;
+#[doc(hidden)]
+pub mod generate;
+#[doc(hidden)]
+pub mod benchmark;
+pub mod plan;
+pub mod snapshot;
+pub mod deadletter;
+pub mod house;
+pub mod sample;
+pub mod partition;
+pub mod policy;
+pub mod clock;
+pub mod backfill;
+pub mod alias;
+pub mod merge;
+#[doc(hidden)]
+pub mod shard;
+pub mod replay;
+pub mod retry;
+pub mod engine;
+pub mod output;
+pub mod sources;
+pub mod watchdog;
+pub mod reference;
+pub mod adjustment;
+pub mod validate;
+#[doc(hidden)]
+pub mod conformance;
+#[doc(hidden)]
+pub mod wal;
+pub mod server;
+pub mod builder;
+pub mod processor;
+pub mod events;
+pub mod timing;
+#[doc(hidden)]
+pub mod query;
+pub mod prelude;
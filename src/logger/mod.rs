@@ -1,15 +1,20 @@
-use log::{Record, Level, Metadata, SetLoggerError, LevelFilter};
+use std::cell::RefCell;
+use std::fmt;
+
+use log::{Record, Metadata, SetLoggerError, LevelFilter};
 
 struct SimpleLogger;
 
 impl log::Log for SimpleLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            println!("{}: {}", record.level(), record.args());
+            let fields = FIELDS.with(|fields| fields.borrow().as_ref().map(Fields::to_string).unwrap_or_default());
+            // The events go to stderr, stdout being left to the accounts.
+            eprintln!("{}: {}{}", record.level(), record.args(), fields);
         }
     }
 
@@ -18,7 +23,100 @@ impl log::Log for SimpleLogger {
 
 static LOGGER: SimpleLogger = SimpleLogger;
 
+// The events are logged from the `info` level on, until another level is set.
 pub fn init_logger() -> Result<(), SetLoggerError> {
     log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(LevelFilter::Trace))
+        .map(|()| log::set_max_level(LevelFilter::Info))
+}
+
+pub fn set_level(level: LevelFilter) {
+    log::set_max_level(level)
+}
+
+// Structured fields appended to the events, as ` key=value` pairs, so that the events about a
+// client or a transaction can be filtered by log tooling, instead of parsing the messages.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields {
+    pub tenant: Option<String>,
+    pub client_id: Option<String>,
+    pub tx_id: Option<u32>,
+    pub tx_type: Option<&'static str>,
+}
+
+impl Fields {
+    pub fn new(tenant: Option<&str>, client_id: impl ToString, tx_id: u32, tx_type: &'static str) -> Self {
+        Fields { tenant: tenant.map(String::from), client_id: Some(client_id.to_string()), tx_id: Some(tx_id),
+                 tx_type: Some(tx_type) }
+    }
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(tenant) = &self.tenant {
+            write!(f, " tenant={}", tenant)?;
+        }
+        if let Some(client_id) = &self.client_id {
+            write!(f, " client_id={}", client_id)?;
+        }
+        if let Some(tx_id) = self.tx_id {
+            write!(f, " tx_id={}", tx_id)?;
+        }
+        if let Some(tx_type) = self.tx_type {
+            write!(f, " tx_type={}", tx_type)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static FIELDS: RefCell<Option<Fields>> = const { RefCell::new(None) };
+}
+
+// Every event logged by the current thread carries the fields of the span, until it is dropped.
+// The spans are thread local, so they must not be held across an `.await`.
+pub struct Span {
+    previous: Option<Fields>,
+}
+
+pub fn enter(fields: Fields) -> Span {
+    Span { previous: FIELDS.with(|current| current.replace(Some(fields))) }
+}
+
+// Enters a span about another transaction of the client of the current span.
+pub fn enter_tx(tx_id: u32, tx_type: &'static str) -> Span {
+    let current = FIELDS.with(|current| current.borrow().clone()).unwrap_or_default();
+    enter(Fields { tx_id: Some(tx_id), tx_type: Some(tx_type), ..current })
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        FIELDS.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current() -> String {
+        FIELDS.with(|fields| fields.borrow().as_ref().map(Fields::to_string).unwrap_or_default())
+    }
+
+    #[test]
+    fn test_logger_spans() {
+        assert_eq!(current(), "");
+        {
+            let _span = enter(Fields::new(None, 1, 2, "deposit"));
+            assert_eq!(current(), " client_id=1 tx_id=2 tx_type=deposit");
+            {
+                let _span = enter_tx(1, "dispute");
+                assert_eq!(current(), " client_id=1 tx_id=1 tx_type=dispute");
+            }
+            assert_eq!(current(), " client_id=1 tx_id=2 tx_type=deposit");
+        }
+        assert_eq!(current(), "");
+        let _span = enter(Fields { tenant: Some(String::from("acme")), ..Fields::default() });
+        assert_eq!(current(), " tenant=acme");
+    }
 }
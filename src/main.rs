@@ -1,46 +1,1476 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+use std::iter;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
+use transaction_processor::account::{FreezeMode, SpentDisputePolicy};
+use transaction_processor::aggregate::Bucket;
+use transaction_processor::alias::{self, Aliases};
+use transaction_processor::amount::{self, Amount};
+use transaction_processor::archive;
+use transaction_processor::benchmark;
+use transaction_processor::config::{Config, Labels};
+use transaction_processor::conformance;
+use transaction_processor::digest::to_hex;
+use transaction_processor::doctor;
+use transaction_processor::generate::{self, Workload};
 use transaction_processor::input::Input;
-use transaction_processor::logger::init_logger;
-use transaction_processor::transaction::drill;
+use transaction_processor::inspect::{state_as_of, write_state, AsOf};
+use transaction_processor::logger::{init_logger, set_level};
+use transaction_processor::manifest::{self, Manifest};
+use transaction_processor::merge::{self, Error as MergeError};
+use transaction_processor::merkle;
+use transaction_processor::notify::{Event, Filter, Route, Target, Url};
+use transaction_processor::output::{self, CsvSink, JsonFormat, JsonSink};
+use transaction_processor::partition;
+use transaction_processor::plan;
+use transaction_processor::policy::{self, Policy};
+use transaction_processor::pseudonym::Pseudonymizer;
+use transaction_processor::reference::{self, Reference};
+use transaction_processor::replay;
+use transaction_processor::report;
+use transaction_processor::sample::Sample;
+use transaction_processor::server;
+use transaction_processor::query;
+use transaction_processor::shard::{self, Error as ShardError};
+use transaction_processor::state::{self, State};
+use transaction_processor::sources::Sources;
+use transaction_processor::transaction::{input_source, is_safe_name, resume, resume_source, Error as TxError,
+                                         InvalidRateGuard, Mode, UnknownAccountPolicy, Violation};
+use transaction_processor::validate;
+use transaction_processor::wal::Wal;
 
-fn main() {
-    if init_logger().is_err() {
-        log::error!("Could not initialse the logger. Exiting...");
-        exit(1);
-    }
+// Environment variable holding the secret used to pseudonymize client ids. It is not accepted
+// as a command line argument, to keep it out of the shell history and the process list.
+const PSEUDONYM_KEY_VAR: &str = "TX_PROCESSOR_PSEUDONYM_KEY";
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        log::error!("Invalid arguments. Please provide a correctly formatted csv file.\n\
-        Example of csv file:
+// Environment variable holding the token the HTTP services require, kept out of the command line
+// for the same reason.
+const API_TOKEN_VAR: &str = "TX_PROCESSOR_API_TOKEN";
+
+// Label of the csv file among the sources of `--source`.
+const INPUT_LABEL: &str = "input";
+
+const CSV_EXAMPLE: &str = "Example of csv file:
         deposit,1,1,1.0
         withdrawal,1,2,0.5
         deposit,2,3,1.0
         dispute,2,3
         resolve,2,3,
         dispute,2,3
-        chargeback,2,3");
+        chargeback,2,3";
+
+// The commands other than `process`, which is the default one.
+const COMMANDS: [&str; 11] = ["inspect", "prove", "merge", "doctor", "validate", "generate", "compare", "serve", "query",
+                              "bench", "help"];
+
+// An option taking a value, named after its flag.
+fn option(name: &'static str, value: &'static str, help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name).long(name).takes_value(true).value_name(value).help(help)
+}
+
+// An option taking a value, which may be given more than once.
+fn repeated(name: &'static str, value: &'static str, help: &'static str) -> Arg<'static, 'static> {
+    option(name, value, help).multiple(true).number_of_values(1)
+}
+
+fn flag(name: &'static str, help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name).long(name).help(help)
+}
+
+// The options naming the clients in the outputs and the logs, which the commands reading the
+// outputs of a run take too.
+fn label_options() -> Vec<Arg<'static, 'static>> {
+    vec![
+        flag("pseudonymize", "Replaces the client ids by pseudonyms keyed by TX_PROCESSOR_PSEUDONYM_KEY"),
+        option("client-aliases", "file", "Maps the external identifiers of the clients to internal ids"),
+    ]
+}
+
+// The options of a run, which the commands checking or serving a run take too.
+fn run_options() -> Vec<Arg<'static, 'static>> {
+    let mut options = label_options();
+    options.extend(vec![
+        option("audit", "file", "Records every handled transaction, along with its outcome"),
+        flag("audit-chain", "Chains the entries of the audit trail with hashes"),
+        option("statements", "dir", "Writes a statement of every client under the directory"),
+        option("account-events", "file", "Writes the changes of the accounts as JSON lines, there is no Kafka sink"),
+        option("aggregates", "file", "Writes the totals of every client and period"),
+        option("aggregate-by", "period", "Period of the aggregates").possible_values(&["day", "month"]),
+        option("pseudonym-map", "file", "Writes the mapping of the pseudonyms to the client ids"),
+        option("tenant-output-dir", "dir", "Writes the accounts of every tenant under the directory"),
+        option("tenant-quota", "count", "Maximum number of transactions accepted per tenant"),
+        option("tenant-workers", "count", "Maximum number of transactions of a tenant applied at the same time"),
+        option("freeze-mode", "mode", "What a freeze rejects").possible_values(&["withdrawals", "full"]),
+        flag("two-step-withdrawals", "Holds the withdrawals until they are settled or cancelled"),
+        option("authorization-window", "count", "Number of transactions after which an authorization expires"),
+        flag("unlock-on-representment", "Unlocks the account a representment reverses the chargeback of"),
+        flag("allow-redispute", "Allows disputing a resolved transaction again"),
+        flag("admin-unlock", "Allows the unlock operations"),
+        repeated("lock-on-reason", "code", "Locks the accounts of the disputes with the reason code"),
+        option("open-disputes", "file", "Writes the disputes still open at the end of the run"),
+        option("settled-window", "count", "Number of transactions after which the settled ones leave the history"),
+        option("archive", "file", "Moves the transactions older than the archive age to the gzip file"),
+        option("archive-age", "seconds", "Age of the transactions moved to the archive"),
+        option("resume", "state file", "Resumes the accounts and their history from the state file"),
+        option("backfill", "file", "Backfills the resumed state, writing the corrections made"),
+        flag("from-checkpoint", "Reads the input from the checkpoint of the resumed state on"),
+        option("save-state", "file", "Writes the accounts and their history for a later run to resume from"),
+        option("dead-letters", "file", "Writes the transactions never applied because of their account"),
+        option("sequence-window", "count", "Number of transactions with a sequence number held back per client"),
+        option("replay-protection", "scope", "Rejects the transactions reusing a remembered tx id")
+            .possible_values(&["client", "global"]),
+        option("replay-window", "count", "Number of tx ids remembered").conflicts_with("replay-ttl"),
+        option("replay-ttl", "seconds", "Time the tx ids are remembered for"),
+        flag("allow-duplicate-ids", "Applies the transactions reusing the tx id of an earlier one"),
+        option("house-accounts", "file", "Writes the house accounts credited the charged back funds and the fees"),
+        option("chargeback-account", "name", "House account credited the charged back funds"),
+        option("fee-account", "name", "House account credited the fees"),
+        option("sample", "count", "Only applies the transactions of the first clients").conflicts_with("sample-rate"),
+        option("sample-rate", "percent", "Only applies the transactions of a share of the clients"),
+        option("policy", "file", "Applies the rules of the policy file"),
+        option("partition-dir", "dir", "Writes the accounts of every partition of the clients under the directory"),
+        option("partitions", "count", "Number of partitions"),
+        option("partition-by", "key", "How the clients are partitioned").possible_values(&["range", "hash"]),
+        flag("partition-audit", "Writes the audit trail of every partition too"),
+        option("dispute-timeout", "seconds", "Age after which the open disputes are resolved"),
+        option("spent-dispute-policy", "policy", "What happens to the disputes of spent deposits")
+            .possible_values(&["reject", "allow-negative", "hold-remaining"]),
+        option("exposure-report", "file", "Writes the accounts that ever had negative available funds"),
+        option("top-accounts", "file", "Writes the top accounts by total, held funds and transactions"),
+        option("top", "count", "Number of top accounts"),
+        option("summary", "file", "Writes the volumes of the run per transaction type"),
+        flag("stage-timings", "Measures the time spent in every stage of the pipeline"),
+        option("suspicious-activity", "file", "Writes the compliance report of the flagged clients"),
+        option("aml-threshold", "amount", "Flags the transactions of at least the amount"),
+        option("velocity-count", "count", "Flags the clients with more deposits and withdrawals in the window"),
+        option("velocity-window", "seconds", "Window of the velocity flag"),
+        repeated("webhook", "url", "Posts the chargebacks and the locked accounts to the URL"),
+        repeated("alert", "spec", "Routes the alerts to a log|file:<path>|webhook:<url> sink, with filters"),
+        option("webhook-attempts", "count", "Number of attempts of a webhook delivery"),
+        option("webhook-backoff", "millis", "Delay before the first retry of a webhook delivery"),
+        option("webhook-max-backoff", "millis", "Maximum delay between the retries of a webhook delivery"),
+        option("webhook-jitter", "percent", "Random share of the delays between the retries"),
+        option("webhook-retry-budget", "count", "Maximum number of retries of the run"),
+        option("max-invalid-rate", "percent", "Aborts the run when too many of the first records are invalid"),
+        option("invalid-sample", "count", "Number of the first records the invalid rate is measured over"),
+        option("amount-scale", "decimals", "Maximum number of decimal places of the input amounts"),
+        option("manifest", "file", "Writes the manifest of the run"),
+        option("unknown-accounts", "policy", "What happens to the transactions of clients without an account")
+            .possible_values(&["open", "reject", "buffer"]),
+        option("unknown-account-window", "count", "Number of transactions buffered for clients without an account"),
+        option("plan", "file", "Dry runs the input, writing how the accounts would change"),
+        option("shard-processes", "count", "Splits the run across the worker processes"),
+        repeated("source", "label=csv file", "Reads the labelled csv file along with the input"),
+        option("output-format", "format", "Format of the accounts").possible_values(&["csv", "json", "ndjson"]),
+        option("accounts-output", "file", "Writes the accounts to the file too"),
+        flag("admin-priority", "Sends the administrative operations over a priority lane"),
+        option("max-duration", "seconds", "Aborts the run after the duration"),
+        option("max-memory", "MiB", "Aborts the run once it uses more memory"),
+        option("mode", "mode", "Whether the run skips the invalid records or fails at the first")
+            .possible_values(&["lenient", "strict"]),
+        option("reference-data", "file", "Joins the transactions to the attributes of their client"),
+        option("runtime", "runtime", "Runtime the accounts are scheduled on")
+            .possible_values(&["multi-thread", "current-thread"]),
+        option("channel-size", "count", "Number of transactions buffered for a tenant"),
+        option("log-level", "level", "The least severe events logged")
+            .possible_values(&["off", "error", "warn", "info", "debug", "trace"]),
+        option("snapshot", "file", "Writes snapshots of the accounts while the run goes on"),
+        option("snapshot-every", "count", "Number of transactions between the snapshots"),
+        option("snapshot-interval", "seconds", "Time between the snapshots"),
+        flag("snapshot-timestamped", "Writes every snapshot to its own timestamped file"),
+        flag("spec", "Sticks to the rules of the classic toy payments engine"),
+    ]);
+    options
+}
+
+fn cli() -> App<'static, 'static> {
+    App::new("transaction-processor")
+        .about("Applies the transactions of a csv file to the accounts of their clients.")
+        .settings(&[AppSettings::SubcommandRequired, AppSettings::DisableVersion, AppSettings::VersionlessSubcommands])
+        .subcommand(SubCommand::with_name("process")
+            .about("Applies the transactions of the csv file and prints the accounts, the default command")
+            .args(&run_options())
+            .arg(Arg::with_name("csv file").required(true).help("Transactions to apply, gzip compressed or not"))
+            .after_help(CSV_EXAMPLE))
+        .subcommand(SubCommand::with_name("inspect")
+            .about("Prints the state of an account at some point of its audit trail, or its archived transactions")
+            .args(&label_options())
+            .arg(Arg::with_name("audit file").required(true).help("Audit trail or history archive"))
+            .arg(option("client", "id", "Client of the account").required(true))
+            .arg(option("tenant", "name", "Tenant of the client"))
+            .arg(option("tx", "id", "Archived transaction to look up"))
+            .arg(option("as-of", "timestamp", "Timestamp the state is printed as of").conflicts_with("as-of-record"))
+            .arg(option("as-of-record", "record", "Record the state is printed as of")))
+        .subcommand(SubCommand::with_name("prove")
+            .about("Checks the chain of an audit trail and prints the inclusion proofs of a transaction")
+            .args(&label_options())
+            .arg(Arg::with_name("chained audit file").required(true).help("Audit trail written with --audit-chain"))
+            .arg(option("client", "id", "Client of the transaction").required(true))
+            .arg(option("tx", "id", "Transaction to prove").required(true))
+            .arg(option("tenant", "name", "Tenant of the client")))
+        .subcommand(SubCommand::with_name("merge")
+            .about("Merges the account of a client into the account of another one")
+            .args(&label_options())
+            .arg(Arg::with_name("state file").required(true).help("State file holding both accounts"))
+            .arg(option("from", "client", "Client merged into the other one").required(true))
+            .arg(option("into", "client", "Client the other one is merged into").required(true))
+            .arg(option("output", "file", "State file written with the merged accounts").required(true))
+            .arg(option("tenant", "name", "Tenant of the clients")))
+        .subcommand(SubCommand::with_name("doctor")
+            .about("Runs a sample workload with the rules of the run options and checks that their outputs can be \
+                    written")
+            .args(&run_options()))
+        .subcommand(SubCommand::with_name("validate")
+            .about("Checks every record of the csv file, without applying any, with the amount scale and the client \
+                    aliases of the run options")
+            .args(&run_options())
+            .arg(Arg::with_name("csv file").required(true).help("Transactions to check")))
+        .subcommand(SubCommand::with_name("generate")
+            .about("Writes a synthetic workload")
+            .arg(option("transactions", "count", "Number of transactions").required(true))
+            .arg(option("clients", "count", "Number of clients, 100 by default"))
+            .arg(option("dispute-rate", "percent", "Share of the deposits disputed"))
+            .arg(option("chargeback-rate", "percent", "Share of the disputes charged back"))
+            .arg(option("seed", "number", "Seed of the workload"))
+            .arg(option("output", "file", "File the workload is written to, instead of stdout")))
+        .subcommand(SubCommand::with_name("compare")
+            .about("Runs the csv file through the reference program too, given the arguments and then the path of \
+                    the csv file, and compares the accounts it prints to ours")
+            .args(&run_options())
+            .arg(Arg::with_name("csv file").required(true).help("Transactions to apply"))
+            .arg(option("reference", "program", "Reference implementation").required(true))
+            .arg(repeated("reference-arg", "arg", "Argument of the reference program").allow_hyphen_values(true)))
+        .subcommand(SubCommand::with_name("serve")
+            .about("Applies the transactions posted to /transactions and /adjustments, serves the accounts at \
+                    /accounts and /accounts/<client>, and writes the outputs of the run once /shutdown is posted")
+            .args(&run_options())
+            .arg(option("listen", "address:port", "Address to listen on").required(true))
+            .arg(option("wal", "file", "Logs the batches before applying them, and applies the logged ones on start")))
+        .subcommand(SubCommand::with_name("query")
+            .about("Serves the accounts of the state file at /accounts/<client>, the locked ones at /locked and the \
+                    open disputes at /disputes, without applying any transaction")
+            .args(&run_options())
+            .arg(Arg::with_name("state file").required(true).help("State file saved by a completed run"))
+            .arg(option("listen", "address:port", "Address to listen on").required(true)))
+        .subcommand(SubCommand::with_name("bench")
+            .about("Measures the throughput of the built-in workloads, recording it or comparing it to a baseline")
+            .arg(Arg::with_name("command").required(true).possible_values(&["record", "compare"])
+                 .help("Whether the baseline is recorded or compared to"))
+            .arg(Arg::with_name("baseline file").required(true).help("Throughput of the workloads"))
+            .arg(option("iterations", "count", "Number of runs of every workload, 3 by default"))
+            .arg(option("threshold", "percent", "Drop of the throughput above which a workload regressed, 10 by \
+                                                 default")))
+}
+
+struct Args {
+    // Missing for the `doctor` command only.
+    file_path: Option<String>,
+    config: Config,
+    pseudonym_map: Option<String>,
+    manifest_path: Option<PathBuf>,
+    resume_path: Option<PathBuf>,
+    // Read the input from the checkpoint of the resumed state on.
+    from_checkpoint: bool,
+    // When set, the run is a dry run whose changes to the accounts are written to this file.
+    plan_path: Option<PathBuf>,
+    // When set, the run is split across this many worker processes.
+    shard_processes: Option<usize>,
+    // Labelled inputs read along with the csv file, which is labelled `input` then.
+    sources: Vec<(String, PathBuf)>,
+    // When set, the accounts are printed as JSON instead of CSV.
+    json_output: Option<JsonFormat>,
+    // When set, the accounts are also written to this file, in the same format.
+    accounts_output: Option<PathBuf>,
+}
+
+struct InspectArgs {
+    audit_path: String,
+    tenant: Option<String>,
+    client_id: u16,
+    // Only used to look up an archived transaction.
+    tx_id: Option<u32>,
+    as_of: AsOf,
+    labels: Labels,
+}
+
+fn parse_inspect_args(matches: &ArgMatches) -> Result<InspectArgs, String> {
+    let mut config = Config::default();
+    parse_labels(matches, &mut config)?;
+    let as_of = match (parse_id::<u64>(matches, "as-of", "a timestamp")?, parse_count(matches, "as-of-record")?) {
+        (Some(timestamp), _) => AsOf::Timestamp(timestamp),
+        (None, Some(record)) => AsOf::Record(record as u64),
+        (None, None) => return Err(String::from("Missing either the --as-of or the --as-of-record option."))
+    };
+
+    Ok(InspectArgs {
+        audit_path: String::from(matches.value_of("audit file").unwrap_or_default()),
+        tenant: matches.value_of("tenant").map(String::from),
+        client_id: parse_id(matches, "client", "a client id")?.unwrap_or_default(),
+        tx_id: parse_id(matches, "tx", "a transaction id")?,
+        as_of,
+        labels: config.labels(),
+    })
+}
+
+// Prints the state an account had at some point of a past run, out of its audit trail, or the
+// transactions of the account moved to a history archive. Both can be gzip compressed.
+fn inspect(matches: &ArgMatches) {
+    let args = match parse_inspect_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+
+    let data = match archive::read(Path::new(&args.audit_path)) {
+        Ok(data) => data,
+        Err(_) => {
+            log::error!("Invalid path. Please provide the path to an audit trail or a history archive.\n{}",
+                        matches.usage());
+            exit(1);
+        }
+    };
+
+    let header = data.split(|byte| *byte == b'\n').next().unwrap_or_default();
+    if archive::is_archive(&String::from_utf8_lossy(header)) {
+        let archived = archive::archived_as_of(&data[..], args.tenant.as_deref(), args.client_id, args.tx_id, args.as_of);
+        match archived {
+            Ok(archived) if archived.is_empty() => {
+                log::error!("The history archive has no transactions of client {} up to the given point.",
+                            args.labels.label(args.client_id));
+                exit(1);
+            }
+            Ok(archived) => {
+                let stdout = io::stdout();
+                if archive::write_archived(&mut stdout.lock(), &archived).is_err() {
+                    log::error!("Could not print the archived transactions.");
+                    exit(1);
+                }
+            }
+            Err(err) => {
+                log::error!("Could not read the history archive {}, {}.", args.audit_path, err);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    match state_as_of(&data[..], args.tenant.as_deref(), args.client_id, args.as_of) {
+        Ok(Some(state)) => {
+            let stdout = io::stdout();
+            if write_state(&mut stdout.lock(), args.tenant.as_deref(), args.client_id, &state).is_err() {
+                log::error!("Could not print the account state.");
+                exit(1);
+            }
+        }
+        Ok(None) => {
+            log::error!("The audit trail has no entries of client {} up to the given point.",
+                        args.labels.label(args.client_id));
+            exit(1);
+        }
+        Err(err) => {
+            log::error!("Could not read the audit trail {}, {}.", args.audit_path, err);
+            exit(1);
+        }
+    }
+}
+
+struct ProveArgs {
+    audit_path: String,
+    tenant: Option<String>,
+    client_id: u16,
+    tx_id: u32,
+    labels: Labels,
+}
+
+fn parse_prove_args(matches: &ArgMatches) -> Result<ProveArgs, String> {
+    let mut config = Config::default();
+    parse_labels(matches, &mut config)?;
+    Ok(ProveArgs {
+        audit_path: String::from(matches.value_of("chained audit file").unwrap_or_default()),
+        tenant: matches.value_of("tenant").map(String::from),
+        client_id: parse_id(matches, "client", "a client id")?.unwrap_or_default(),
+        tx_id: parse_id(matches, "tx", "a transaction id")?.unwrap_or_default(),
+        labels: config.labels(),
+    })
+}
+
+// Checks the chain of an audit trail and prints the inclusion proofs of the entries of a
+// transaction, against the root of the trail.
+fn prove(matches: &ArgMatches) {
+    let args = match parse_prove_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+
+    let file = match File::open(&args.audit_path) {
+        Ok(file) => file,
+        Err(_) => {
+            log::error!("Invalid path. Please provide the path to a chained audit trail.\n{}", matches.usage());
+            exit(1);
+        }
+    };
+
+    let (root, inclusions) = match merkle::prove(BufReader::new(file), args.tenant.as_deref(), args.client_id, args.tx_id) {
+        Ok(proofs) => proofs,
+        Err(err) => {
+            log::error!("Could not prove the inclusion of the transaction, {}.", err);
+            exit(1);
+        }
+    };
+    if let Ok(recorded) = fs::read_to_string(format!("{}.root", args.audit_path)) {
+        if recorded.trim_end() != to_hex(&root) {
+            log::error!("The audit trail does not match its recorded root.");
+            exit(1);
+        }
+    }
+    if inclusions.is_empty() {
+        log::error!("The audit trail has no entries of tx {} of client {}.", args.tx_id, args.labels.label(args.client_id));
         exit(1);
     }
 
-    let file_path = args.get(1).unwrap();
-    let result = File::open(file_path);
-    if result.is_err() {
-        log::error!("Invalid path. Please provide the path to a correctly formatted csv file.\n\
-        Example of csv file:
-        deposit,1,1,1.0
-        withdrawal,1,2,0.5
-        deposit,2,3,1.0
-        dispute,2,3
-        resolve,2,3,
-        dispute,2,3
-        chargeback,2,3");
+    println!("root {}", to_hex(&root));
+    for inclusion in inclusions {
+        println!("entry {} {}", inclusion.index, inclusion.entry);
+        let steps: Vec<String> = inclusion.proof.iter()
+            .map(|(side, sibling)| format!("{}:{}", side.as_str(), to_hex(sibling)))
+            .collect();
+        println!("proof {}", steps.join(" "));
+    }
+}
+
+struct MergeArgs {
+    state_path: PathBuf,
+    output_path: PathBuf,
+    tenant: Option<String>,
+    from: u16,
+    into: u16,
+    labels: Labels,
+}
+
+fn parse_merge_args(matches: &ArgMatches) -> Result<MergeArgs, String> {
+    let mut config = Config::default();
+    parse_labels(matches, &mut config)?;
+    Ok(MergeArgs {
+        state_path: path(matches, "state file").unwrap_or_default(),
+        output_path: path(matches, "output").unwrap_or_default(),
+        tenant: matches.value_of("tenant").map(String::from),
+        from: parse_id(matches, "from", "a client id")?.unwrap_or_default(),
+        into: parse_id(matches, "into", "a client id")?.unwrap_or_default(),
+        labels: config.labels(),
+    })
+}
+
+// Merges the account of a client into the account of another one, e.g. after the deduplication
+// of the customers, and writes the resulting state file.
+fn merge(matches: &ArgMatches) {
+    let args = match parse_merge_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+
+    let mut state = match state::read_file(&args.state_path) {
+        Ok(state) => state,
+        Err(err) => {
+            log::error!("{}", err.describe(&args.state_path));
+            exit(1);
+        }
+    };
+    if let Err(err) = merge::merge(&mut state, args.tenant.as_deref(), args.from, args.into) {
+        match err {
+            MergeError::AccountNotFound(client_id) => {
+                log::error!("Client {} has no account in the state file.", args.labels.label(client_id))
+            }
+            MergeError::SameAccount => log::error!("An account can not be merged into itself."),
+            MergeError::AccountClosed(client_id) => {
+                log::error!("The account of client {} is closed.", args.labels.label(client_id))
+            }
+            MergeError::TxIdCollision(tx_id) => {
+                log::error!("Both accounts have a tx {} in their history, the accounts can not be merged.", tx_id)
+            }
+            MergeError::Overflow => log::error!("The funds of both accounts would not fit in one account."),
+        }
+        exit(1);
+    }
+    if state::write_file(&args.output_path, &state.accounts, &state.history, state.checkpoint).is_err() {
+        log::error!("Could not write the state file {}.", args.output_path.display());
+        exit(1);
+    }
+    log::info!("Merged the account of client {} into the account of client {}.", args.labels.label(args.from),
+               args.labels.label(args.into));
+}
+
+fn path(matches: &ArgMatches, name: &str) -> Option<PathBuf> {
+    matches.value_of_os(name).map(PathBuf::from)
+}
+
+// The values of an option given any number of times.
+fn values<'a>(matches: &'a ArgMatches, name: &str) -> impl Iterator<Item = &'a str> {
+    matches.values_of(name).into_iter().flatten()
+}
+
+fn parse_count(matches: &ArgMatches, name: &str) -> Result<Option<usize>, String> {
+    matches.value_of(name).map(|value| match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("--{} requires a positive number.", name))
+    }).transpose()
+}
+
+fn parse_amount(matches: &ArgMatches, name: &str) -> Result<Option<Amount>, String> {
+    matches.value_of(name).map(|value| match amount::parse(value) {
+        Some(amount) if amount > amount::ZERO => Ok(amount),
+        _ => Err(format!("--{} requires a positive amount.", name))
+    }).transpose()
+}
+
+fn parse_rate(matches: &ArgMatches, name: &str) -> Result<Option<f64>, String> {
+    matches.value_of(name).map(|value| match value.parse::<f64>() {
+        Ok(rate) if (0.0..=100.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("--{} requires a percentage between 0 and 100.", name))
+    }).transpose()
+}
+
+// The value of an option identifying a client or a transaction, e.g. `--client`.
+fn parse_id<T: FromStr>(matches: &ArgMatches, name: &str, what: &str) -> Result<Option<T>, String> {
+    matches.value_of(name).map(|value| value.parse::<T>().map_err(|_| format!("--{} requires {}.", name, what)))
+        .transpose()
+}
+
+// Parses the options naming the clients in the outputs and the logs, which the commands reading
+// the outputs of a run take too.
+fn parse_labels(matches: &ArgMatches, config: &mut Config) -> Result<(), String> {
+    if matches.is_present("pseudonymize") {
+        let key = env::var(PSEUDONYM_KEY_VAR)
+            .map_err(|_| format!("--pseudonymize requires the {} environment variable.", PSEUDONYM_KEY_VAR))?;
+        config.pseudonymizer = Some(Arc::new(Pseudonymizer::new(key.as_bytes())));
+    }
+    if let Some(path) = matches.value_of("client-aliases") {
+        let aliases = Aliases::read_file(Path::new(path)).map_err(|err| match err {
+            alias::Error::Io(_) => format!("Could not read the client aliases {}.", path),
+            alias::Error::InvalidRow(line) => format!("Line {} of the client aliases {} is not a valid \
+                                                       `external,client` pair.", line, path),
+            alias::Error::Duplicate(line) => format!("Line {} of the client aliases {} maps a client \
+                                                      mapped before.", line, path)
+        })?;
+        config.aliases = Some(Arc::new(aliases));
+    }
+    Ok(())
+}
+
+fn parse_args(matches: &ArgMatches) -> Result<Args, String> {
+    // Missing for the commands that take no csv file.
+    let file_path = matches.value_of("csv file").map(String::from);
+    let mut config = Config::default();
+    parse_labels(matches, &mut config)?;
+    config.audit_path = path(matches, "audit");
+    config.audit_chain = matches.is_present("audit-chain");
+    config.stage_timings = matches.is_present("stage-timings");
+    config.account_events_path = path(matches, "account-events");
+    config.statements_dir = path(matches, "statements");
+    config.aggregates_path = path(matches, "aggregates");
+    if let Some(bucket) = matches.value_of("aggregate-by") {
+        config.aggregate_bucket = if bucket == "month" { Bucket::Month } else { Bucket::Day };
+    }
+    let json_output = match matches.value_of("output-format") {
+        Some("json") => Some(JsonFormat::Array),
+        Some("ndjson") => Some(JsonFormat::Lines),
+        _ => None
+    };
+
+    if let Some(path) = matches.value_of("policy") {
+        let policy = Policy::read_file(Path::new(path)).map_err(|err| match err {
+            policy::Error::Io(_) => format!("Could not read the policy file {}.", path),
+            policy::Error::InvalidRule(line) => format!("Line {} of the policy file {} is not a valid rule.", line, path)
+        })?;
+        config.rules.policy = Some(Arc::new(policy));
+    }
+
+    config.partition_dir = path(matches, "partition-dir");
+    if let Some(count) = parse_count(matches, "partitions")? {
+        config.partitioning.count = count;
+    }
+    if let Some(key) = matches.value_of("partition-by") {
+        config.partitioning.key = if key == "hash" { partition::Key::Hash } else { partition::Key::Range };
+    }
+    config.partitioning.audit = matches.is_present("partition-audit");
+    let partitioned = ["partitions", "partition-by", "partition-audit"].iter().any(|name| matches.is_present(name));
+
+    config.tenant_output_dir = path(matches, "tenant-output-dir");
+    config.tenant_quota = parse_count(matches, "tenant-quota")?;
+    config.tenant_workers = parse_count(matches, "tenant-workers")?;
+    if let Some(mode) = matches.value_of("freeze-mode") {
+        config.rules.freeze_mode = if mode == "full" { FreezeMode::Full } else { FreezeMode::Withdrawals };
+    }
+    config.rules.spec = matches.is_present("spec");
+    config.admin_lane = matches.is_present("admin-priority");
+    config.rules.two_step_withdrawals = matches.is_present("two-step-withdrawals");
+    config.rules.unlock_on_representment = matches.is_present("unlock-on-representment");
+    config.rules.redisputes = matches.is_present("allow-redispute");
+    config.rules.admin_unlock = matches.is_present("admin-unlock");
+    config.rules.lock_reasons.extend(values(matches, "lock-on-reason").map(String::from));
+    if let Some(timeout) = parse_count(matches, "dispute-timeout")? {
+        config.rules.dispute_timeout = Some(timeout as u64);
+    }
+    if let Some(policy) = matches.value_of("spent-dispute-policy") {
+        config.rules.spent_dispute_policy = match policy {
+            "allow-negative" => SpentDisputePolicy::AllowNegative,
+            "hold-remaining" => SpentDisputePolicy::HoldRemaining,
+            _ => SpentDisputePolicy::Reject
+        };
+    }
+
+    config.summary_path = path(matches, "summary");
+    config.suspicious_activity_path = path(matches, "suspicious-activity");
+    if let Some(amount) = parse_amount(matches, "aml-threshold")? {
+        config.suspicious_activity_thresholds.aml_amount = Some(amount);
+    }
+    if let Some(count) = parse_count(matches, "velocity-count")? {
+        config.suspicious_activity_thresholds.velocity_count = Some(count);
+    }
+    if let Some(window) = parse_count(matches, "velocity-window")? {
+        config.suspicious_activity_thresholds.velocity_window = window as u64;
+    }
+
+    for url in values(matches, "webhook") {
+        let url = Url::parse(url).ok_or("--webhook requires an `http://host[:port]/path` URL.")?;
+        let filter = Filter { events: vec![Event::Chargeback, Event::AccountLocked], ..Filter::default() };
+        config.alerts.push(Route { target: Target::Webhook(url), filter });
+    }
+    for spec in values(matches, "alert") {
+        let route = Route::parse(spec).ok_or("--alert requires a `log|file:<path>|webhook:<url>[,severity=<severity>]\
+                                              [,events=<event>+<event>...]` spec.")?;
+        config.alerts.push(route);
+    }
+    if let Some(attempts) = parse_count(matches, "webhook-attempts")? {
+        config.webhook_retry.attempts = attempts as u32;
+    }
+    if let Some(backoff) = parse_count(matches, "webhook-backoff")? {
+        config.webhook_retry.backoff = Duration::from_millis(backoff as u64);
+    }
+    if let Some(max_backoff) = parse_count(matches, "webhook-max-backoff")? {
+        config.webhook_retry.max_backoff = Duration::from_millis(max_backoff as u64);
+    }
+    if let Some(jitter) = parse_rate(matches, "webhook-jitter")? {
+        config.webhook_retry.jitter = jitter / 100.0;
+    }
+    if let Some(budget) = parse_count(matches, "webhook-retry-budget")? {
+        config.webhook_retry.budget = Some(budget as u64);
+    }
+
+    config.top_accounts_path = path(matches, "top-accounts");
+    if let Some(count) = parse_count(matches, "top")? {
+        config.top_count = count;
+    }
+    config.exposure_path = path(matches, "exposure-report");
+    let resume_path = path(matches, "resume");
+    let from_checkpoint = matches.is_present("from-checkpoint");
+    config.backfill_path = path(matches, "backfill");
+    config.state_path = path(matches, "save-state");
+    config.open_disputes_path = path(matches, "open-disputes");
+    if let Some(window) = parse_count(matches, "settled-window")? {
+        config.rules.settled_window = Some(window as u64);
+    }
+    config.archive_path = path(matches, "archive");
+    if let Some(age) = parse_count(matches, "archive-age")? {
+        config.rules.archive_age = Some(age as u64);
+    }
+    if let Some(window) = parse_count(matches, "authorization-window")? {
+        config.rules.authorization_window = Some(window as u64);
+    }
+    if let Some(decimals) = matches.value_of("amount-scale") {
+        config.amount_scale = decimals.parse::<usize>().map_err(|_| "--amount-scale requires a number of decimal places.")?;
+    }
+    let max_invalid_rate = parse_rate(matches, "max-invalid-rate")?;
+    let invalid_sample = parse_count(matches, "invalid-sample")?;
+    let pseudonym_map = matches.value_of("pseudonym-map").map(String::from);
+
+    if let Some(policy) = matches.value_of("unknown-accounts") {
+        config.unknown_account = match policy {
+            "reject" => UnknownAccountPolicy::Reject,
+            "buffer" => UnknownAccountPolicy::Buffer { window: 1000 },
+            _ => UnknownAccountPolicy::Open
+        };
+    }
+    let unknown_account_window = parse_count(matches, "unknown-account-window")?;
+
+    config.snapshot_path = path(matches, "snapshot");
+    config.snapshot_schedule.every = parse_count(matches, "snapshot-every")?;
+    if let Some(interval) = parse_count(matches, "snapshot-interval")? {
+        config.snapshot_schedule.interval = Some(Duration::from_secs(interval as u64));
+    }
+    config.snapshot_schedule.timestamped = matches.is_present("snapshot-timestamped");
+
+    if let Some(mode) = matches.value_of("mode") {
+        config.mode = if mode == "strict" { Mode::Strict } else { Mode::Lenient };
+    }
+    if let Some(duration) = parse_count(matches, "max-duration")? {
+        config.limits.wall_clock = Some(Duration::from_secs(duration as u64));
+    }
+    if let Some(memory) = parse_count(matches, "max-memory")? {
+        config.limits.memory = Some((memory as u64) << 20);
+    }
+    if let Some(runtime) = matches.value_of("runtime") {
+        config.multi_threaded_runtime = runtime == "multi-thread";
+    }
+    if let Some(size) = parse_count(matches, "channel-size")? {
+        config.tenant_buffer = size;
+    }
+    if let Some(level) = matches.value_of("log-level").and_then(|level| level.parse::<log::LevelFilter>().ok()) {
+        set_level(level);
+    }
+
+    config.sequence_window = parse_count(matches, "sequence-window")?;
+    let replay_scope = matches.value_of("replay-protection")
+        .map(|scope| if scope == "global" { replay::Scope::Global } else { replay::Scope::Client });
+    let replay_window = match (parse_count(matches, "replay-window")?, parse_count(matches, "replay-ttl")?) {
+        (Some(ids), _) => Some(replay::Window::Ids(ids)),
+        (None, Some(seconds)) => Some(replay::Window::Seconds(seconds as u64)),
+        (None, None) => None
+    };
+    config.reject_duplicates = !matches.is_present("allow-duplicate-ids");
+    config.sample = match (parse_count(matches, "sample")?, parse_rate(matches, "sample-rate")?) {
+        (Some(clients), _) => Some(Sample::Clients(clients)),
+        (None, Some(rate)) => Some(Sample::Rate(rate)),
+        (None, None) => None
+    };
+
+    config.dead_letters_path = path(matches, "dead-letters");
+    config.house_accounts_path = path(matches, "house-accounts");
+    if let Some(name) = matches.value_of("chargeback-account") {
+        config.house_routes.chargebacks = String::from(name);
+    }
+    if let Some(name) = matches.value_of("fee-account") {
+        config.house_routes.fees = String::from(name);
+    }
+    let plan_path = path(matches, "plan");
+    let shard_processes = parse_count(matches, "shard-processes")?;
+    let manifest_path = path(matches, "manifest");
+    let accounts_output = path(matches, "accounts-output");
+
+    let mut sources: Vec<(String, PathBuf)> = Vec::new();
+    for source in values(matches, "source") {
+        let (label, path) = source.split_once('=').ok_or("--source requires a <label>=<path> pair.")?;
+        if label.is_empty() || !is_safe_name(label) {
+            return Err(format!("Invalid source label `{}`, only letters, digits, `-` and `_` are allowed.", label));
+        }
+        if label == INPUT_LABEL {
+            return Err(format!("The source label `{}` is reserved for the csv file.", INPUT_LABEL));
+        }
+        if sources.iter().any(|(other, _)| other == label) {
+            return Err(format!("The source label `{}` is used more than once.", label));
+        }
+        sources.push((String::from(label), PathBuf::from(path)));
+    }
+
+    // The clients of the reference data are given as in the input, so the aliases are read first.
+    if let Some(path) = matches.value_of("reference-data") {
+        let reference = Reference::read_file(Path::new(&path), config.aliases.as_deref()).map_err(|err| match err {
+            reference::Error::Io(_) => format!("Could not read the reference data {}.", path),
+            reference::Error::InvalidHeader => format!("The header line of the reference data {} is not a `client` \
+                                                        column followed by uniquely named attributes.", path),
+            reference::Error::InvalidRow(line) => format!("Line {} of the reference data {} does not describe a known \
+                                                           client.", line, path),
+            reference::Error::Duplicate(line) => format!("Line {} of the reference data {} describes a client \
+                                                          described before.", line, path)
+        })?;
+        config.reference = Some(Arc::new(reference));
+    }
+
+    if config.audit_chain && config.audit_path.is_none() && !config.partitioning.audit {
+        return Err(String::from("--audit-chain can only be used together with --audit or --partition-audit."));
+    }
+
+    if partitioned && config.partition_dir.is_none() {
+        return Err(String::from("--partitions, --partition-by and --partition-audit can only be used together with \
+                                 --partition-dir."));
+    }
+
+    let scheduled = config.snapshot_schedule.every.is_some() || config.snapshot_schedule.interval.is_some();
+    if config.snapshot_path.is_some() != scheduled {
+        return Err(String::from("--snapshot requires one of --snapshot-every or --snapshot-interval, and the other way \
+                                 around."));
+    }
+    if config.snapshot_schedule.timestamped && config.snapshot_path.is_none() {
+        return Err(String::from("--snapshot-timestamped can only be used together with --snapshot."));
+    }
+
+    if config.archive_path.is_some() != config.rules.archive_age.is_some() {
+        return Err(String::from("--archive and --archive-age can only be used together."));
+    }
+
+    if config.backfill_path.is_some() && resume_path.is_none() {
+        return Err(String::from("--backfill can only be used together with --resume."));
+    }
+
+    if from_checkpoint && resume_path.is_none() {
+        return Err(String::from("--from-checkpoint can only be used together with --resume."));
+    }
+
+    if pseudonym_map.is_some() && config.pseudonymizer.is_none() {
+        return Err(String::from("--pseudonym-map can only be used together with --pseudonymize."));
+    }
+
+    match (max_invalid_rate, invalid_sample) {
+        (Some(max_rate), sample) => {
+            config.invalid_rate_guard = Some(InvalidRateGuard { sample: sample.unwrap_or(1000), max_rate });
+        }
+        (None, Some(_)) => {
+            return Err(String::from("--invalid-sample can only be used together with --max-invalid-rate."));
+        }
+        (None, None) => ()
+    }
+
+    if let Some(window) = unknown_account_window {
+        match &mut config.unknown_account {
+            UnknownAccountPolicy::Buffer { window: buffer_window } => *buffer_window = window,
+            _ => return Err(String::from("--unknown-account-window can only be used together with \
+                                          --unknown-accounts buffer."))
+        }
+    }
+
+    match (replay_scope, replay_window) {
+        (Some(scope), window) => {
+            config.replay = Some(replay::Protection { scope, window: window.unwrap_or(replay::Window::Unlimited) });
+        }
+        (None, Some(_)) => {
+            return Err(String::from("--replay-window and --replay-ttl can only be used together with --replay-protection."));
+        }
+        (None, None) => ()
+    }
+
+    if shard_processes.is_some() {
+        let sharded_outputs = manifest::outputs(&config).into_iter().all(|path| Some(&path) == config.state_path.as_ref());
+        if !sharded_outputs || config.tenant_output_dir.is_some() || config.statements_dir.is_some() {
+            return Err(String::from("--shard-processes only writes the accounts and --save-state."));
+        }
+        if resume_path.is_some() || plan_path.is_some() || manifest_path.is_some() || pseudonym_map.is_some() {
+            return Err(String::from("--shard-processes can not be used together with --resume, --plan, --manifest or \
+                                     --pseudonym-map."));
+        }
+        if matches!(config.sample, Some(Sample::Clients(_))) {
+            return Err(String::from("--shard-processes can not be used together with --sample."));
+        }
+        if config.replay.is_some_and(|replay| replay.scope == replay::Scope::Global) {
+            return Err(String::from("--shard-processes can not be used together with --replay-protection global."));
+        }
+    }
+
+    if !sources.is_empty() && (shard_processes.is_some() || plan_path.is_some() || from_checkpoint) {
+        return Err(String::from("--source can not be used together with --shard-processes, --plan or --from-checkpoint."));
+    }
+
+    Ok(Args { file_path, config, pseudonym_map, manifest_path, resume_path, from_checkpoint, plan_path, shard_processes,
+              sources, json_output, accounts_output })
+}
+
+struct GenerateArgs {
+    workload: Workload,
+    // The workload is printed to stdout, unless it is written to this file.
+    output_path: Option<PathBuf>,
+}
+
+fn parse_generate_args(matches: &ArgMatches) -> Result<GenerateArgs, String> {
+    let mut workload = Workload { clients: 100, transactions: 0, dispute_rate: 0.0, chargeback_rate: 0.0, seed: 1 };
+    workload.transactions = parse_count(matches, "transactions")?.unwrap_or_default();
+    if let Some(clients) = matches.value_of("clients") {
+        workload.clients = clients.parse::<u16>().ok().filter(|count| *count > 0)
+            .ok_or("--clients requires a number of clients between 1 and 65535.")?;
+    }
+    if let Some(rate) = parse_rate(matches, "dispute-rate")? {
+        workload.dispute_rate = rate;
+    }
+    if let Some(rate) = parse_rate(matches, "chargeback-rate")? {
+        workload.chargeback_rate = rate;
+    }
+    if let Some(seed) = parse_id(matches, "seed", "a number")? {
+        workload.seed = seed;
+    }
+    Ok(GenerateArgs { workload, output_path: path(matches, "output") })
+}
+
+// Writes a synthetic workload, e.g. a benchmark input larger than the built-in ones.
+fn generate(matches: &ArgMatches) {
+    let args = match parse_generate_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+
+    let written = match &args.output_path {
+        Some(path) => File::create(path).map(BufWriter::new)
+            .and_then(|mut file| generate::write(&mut file, &args.workload).and_then(|_| file.flush())),
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = BufWriter::new(stdout.lock());
+            generate::write(&mut stdout, &args.workload).and_then(|_| stdout.flush())
+        }
+    };
+    if written.is_err() {
+        match &args.output_path {
+            Some(path) => log::error!("Could not write the workload {}.", path.display()),
+            None => log::error!("Could not print the workload.")
+        }
+        exit(1);
+    }
+}
+
+// Runs the input through both the processor and a reference implementation, printing the clients
+// whose accounts differ.
+fn compare(matches: &ArgMatches) {
+    let args = match parse_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+    let file_path = args.file_path.clone().unwrap_or_default();
+    let program = path(matches, "reference").unwrap_or_default();
+    let program_args: Vec<String> = values(matches, "reference-arg").map(String::from).collect();
+
+    let input = match Input::open(Path::new(&file_path)) {
+        Ok(input) => input,
+        Err(err) => {
+            log::error!("{}", err.describe(Path::new(&file_path)));
+            exit(1);
+        }
+    };
+    let ours = match resume(State::default(), input, &args.config) {
+        Ok(run_report) => run_report.final_accounts,
+        Err(TxError::TooManyInvalidRecords { invalid, records }) => abort_invalid(invalid, records),
+        Err(TxError::Strict(violation)) => abort_strict(&violation),
+        Err(_) => Vec::new()
+    };
+    let theirs = match conformance::run_reference(&program, &program_args, Path::new(&file_path)) {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            match err {
+                conformance::Error::Io(_) => log::error!("Could not run the reference {}.", program.display()),
+                conformance::Error::Failed(code) => {
+                    log::error!("The reference {} failed with exit code {:?}.", program.display(), code)
+                }
+                conformance::Error::InvalidOutput(line) => {
+                    log::error!("Line {} of the output of the reference {} is not an account.", line, program.display())
+                }
+            }
+            exit(1);
+        }
+    };
+
+    let comparison = conformance::compare(&ours, &theirs);
+    let stdout = io::stdout();
+    if comparison.write(&mut stdout.lock()).is_err() {
+        log::error!("Could not print the comparison.");
+        exit(1);
+    }
+    if !comparison.passed() {
+        exit(1);
+    }
+}
+
+// The final accounts are printed to stdout, and written to the accounts file, if any.
+fn add_account_sinks(args: &mut Args) {
+    let stdout = match args.json_output {
+        Some(format) => JsonSink::factory(&args.config, format, || Ok(io::stdout())),
+        None => CsvSink::factory(&args.config, || Ok(io::stdout()))
+    };
+    args.config.account_sinks.push(stdout);
+    if let Some(path) = args.accounts_output.clone() {
+        let create = move || File::create(&path).map(BufWriter::new);
+        let file = match args.json_output {
+            Some(format) => JsonSink::factory(&args.config, format, create),
+            None => CsvSink::factory(&args.config, create)
+        };
+        args.config.account_sinks.push(file);
+    }
+}
+
+// The state the run resumes from, if any.
+fn read_state(path: Option<&Path>) -> State {
+    match path {
+        Some(path) => match state::read_file(path) {
+            Ok(state) => state,
+            Err(err) => {
+                log::error!("{}", err.describe(path));
+                exit(1);
+            }
+        },
+        None => State::default()
+    }
+}
+
+// Runs the processor as a service, applying the transactions posted over HTTP until it is shut
+// down, at which point the outputs of the run are written.
+fn serve(matches: &ArgMatches) {
+    let parsed = parse_args(matches).and_then(|args| {
+        if !args.sources.is_empty() || args.plan_path.is_some() || args.shard_processes.is_some() || args.from_checkpoint {
+            return Err(String::from("--source, --plan, --shard-processes and --from-checkpoint need a csv file."));
+        }
+        // The log is cleared once its batches are part of a saved state.
+        if matches.is_present("wal") && args.config.state_path.is_none() {
+            return Err(String::from("--wal requires --save-state."));
+        }
+        Ok(args)
+    });
+    let mut args = match parsed {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+    let address = matches.value_of("listen").unwrap_or_default();
+
+    add_account_sinks(&mut args);
+    let state = read_state(args.resume_path.as_deref());
+    let wal = path(matches, "wal").map(|path| match Wal::open(&path) {
+        Ok(wal) => wal,
+        Err(err) => {
+            log::error!("{}", err.describe(&path));
+            exit(1);
+        }
+    });
+    let listener = listen(address, &mut args.config);
+    let run_report = match server::serve(listener, state, args.config, wal) {
+        Ok(run_report) => run_report,
+        Err(TxError::Strict(violation)) => abort_strict(&violation),
+        Err(_) => Default::default()
+    };
+    log::info!("Applied {} transactions.", run_report.records);
+    if run_report.aborted.is_some() {
+        exit(1);
+    }
+}
+
+// Listens on the address for one of the HTTP services, which then require the API token of the
+// environment, if it has one. Whoever reaches the server can move funds and shut it down, and
+// whoever reaches either service can read the accounts, so they only listen beyond the loopback
+// interface with a token.
+fn listen(address: &str, config: &mut Config) -> TcpListener {
+    config.api_token = env::var(API_TOKEN_VAR).ok().filter(|token| !token.is_empty());
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(_) => {
+            log::error!("Could not listen on {}.", address);
+            exit(1);
+        }
+    };
+    let loopback = listener.local_addr().is_ok_and(|address| address.ip().is_loopback());
+    if !loopback && config.api_token.is_none() {
+        log::error!("Listening on {}, beyond the loopback interface, requires the {} environment variable.", address,
+                    API_TOKEN_VAR);
+        exit(1);
+    }
+    log::info!("Listening on {}.", address);
+    listener
+}
+
+// Serves the accounts of a state file, saved by a completed run, over HTTP, for looking into them
+// after the run.
+fn query(matches: &ArgMatches) {
+    let mut args = match parse_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+    let (address, state_path) = (matches.value_of("listen").unwrap_or_default(),
+                                 matches.value_of("state file").unwrap_or_default());
+
+    let state = read_state(Some(Path::new(&state_path)));
+    let listener = listen(address, &mut args.config);
+    if let Err(err) = query::serve(listener, &state, &args.config) {
+        log::error!("Could not accept the queries: {}.", err);
+        exit(1);
+    }
+}
+
+struct BenchArgs {
+    // Record a new baseline, instead of comparing against it.
+    record: bool,
+    baseline_path: PathBuf,
+    iterations: usize,
+    // Drop of the throughput, in percent, above which a workload regressed.
+    threshold: f64,
+}
+
+fn parse_bench_args(matches: &ArgMatches) -> Result<BenchArgs, String> {
+    Ok(BenchArgs {
+        record: matches.value_of("command") == Some("record"),
+        baseline_path: path(matches, "baseline file").unwrap_or_default(),
+        iterations: parse_count(matches, "iterations")?.unwrap_or(3),
+        threshold: parse_rate(matches, "threshold")?.unwrap_or(10.0),
+    })
+}
+
+// Measures the throughput of the built-in workloads, recording it as a baseline or comparing it
+// against the recorded one.
+fn bench(matches: &ArgMatches) {
+    let args = match parse_bench_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+
+    let baseline = if args.record {
+        Vec::new()
+    } else {
+        match benchmark::read_baseline_file(&args.baseline_path) {
+            Ok(baseline) => baseline,
+            Err(_) => {
+                log::error!("Could not read the baseline {}.", args.baseline_path.display());
+                exit(1);
+            }
+        }
+    };
+
+    let mut measurements = Vec::new();
+    for (name, workload) in benchmark::workloads() {
+        match benchmark::measure(name, &workload, args.iterations) {
+            Ok(measurement) => measurements.push(measurement),
+            Err(_) => {
+                log::error!("Could not run the {} workload.", name);
+                exit(1);
+            }
+        }
+    }
+
+    if args.record {
+        if benchmark::write_baseline_file(&args.baseline_path, &measurements).is_err() {
+            log::error!("Could not write the baseline {}.", args.baseline_path.display());
+            exit(1);
+        }
+        return;
+    }
+    let comparisons = benchmark::compare(&baseline, measurements);
+    let stdout = io::stdout();
+    if benchmark::write_comparisons(&mut stdout.lock(), &comparisons, args.threshold).is_err() {
+        log::error!("Could not print the comparison.");
+        exit(1);
+    }
+    if comparisons.iter().any(|comparison| comparison.regressed(args.threshold)) {
+        exit(1);
+    }
+}
+
+// Runs the self-test against the configuration of a run, printing a pass or fail diagnosis.
+fn doctor(matches: &ArgMatches) {
+    let args = match parse_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+
+    let mut outputs: Vec<(&str, PathBuf)> = Vec::new();
+    outputs.extend(args.pseudonym_map.iter().map(|path| ("pseudonym map", PathBuf::from(path))));
+    outputs.extend(args.manifest_path.iter().map(|path| ("run manifest", path.clone())));
+    let diagnosis = doctor::diagnose(&args.config, args.resume_path.as_deref(), &outputs);
+    let stdout = io::stdout();
+    if diagnosis.write(&mut stdout.lock()).is_err() {
+        log::error!("Could not print the diagnosis.");
+        exit(1);
+    }
+    if !diagnosis.passed() {
+        exit(1);
+    }
+}
+
+fn validate(matches: &ArgMatches) {
+    let args = match parse_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+    let file_path = args.file_path.clone().unwrap_or_default();
+
+    let input = match Input::open(Path::new(&file_path)) {
+        Ok(input) => input,
+        Err(err) => {
+            log::error!("{}", err.describe(Path::new(&file_path)));
+            exit(1);
+        }
+    };
+    let validation = validate::validate(input, args.config.amount_scale, args.config.aliases.as_deref());
+    let stdout = io::stdout();
+    if validation.write(&mut stdout.lock()).is_err() {
+        log::error!("Could not print the validation report.");
+        exit(1);
+    }
+    if !validation.passed() {
+        exit(1);
+    }
+}
+
+fn abort_invalid(invalid: usize, records: usize) -> ! {
+    log::error!("Aborted the run, {} of the first {} records are invalid. Please check the delimiter and the \
+                 encoding of the csv file.", invalid, records);
+    exit(1);
+}
+
+fn abort_strict(violation: &Violation) -> ! {
+    log::error!("Aborted the strict run, {}.", violation.describe());
+    exit(1);
+}
+
+// Reads the labelled sources concurrently with the input.
+fn read_sources(input: Input, paths: &[(String, PathBuf)], config: &Config) -> Result<Sources, TxError> {
+    let mut sources = Sources::new(config.tenant_buffer);
+    sources.add(INPUT_LABEL, input_source(input, config)?);
+    for (label, path) in paths {
+        let input = match Input::open(path) {
+            Ok(input) => input,
+            Err(err) => {
+                log::error!("The source {} can not be read. {}", label, err.describe(path));
+                exit(1);
+            }
+        };
+        sources.add(label, input_source(input, config)?);
+    }
+    Ok(sources)
+}
+
+// Dry runs the input against the resumed state, writing how the accounts would change instead of
+// any of the outputs of the run.
+fn run_plan(path: &Path, state: State, input: Input, config: &Config) {
+    let plan = match plan::plan(state, input, config) {
+        Ok(plan) => plan,
+        Err(plan::Error::Run(TxError::TooManyInvalidRecords { invalid, records })) => abort_invalid(invalid, records),
+        Err(plan::Error::Run(TxError::Strict(violation))) => abort_strict(&violation),
+        Err(err) => {
+            log::error!("The dry run failed, {:?}.", err);
+            exit(1);
+        }
+    };
+    if report::write_file(path, |writer| plan::write(writer, &plan.changes, config)).is_err() {
+        log::error!("Could not write the plan {}.", path.display());
+        exit(1);
+    }
+    log::info!("The batch would change {} accounts and lock {} of them.", plan.changes.len(), plan.locks());
+}
+
+// Splits the input into a shard per worker process, runs the workers with the same options and
+// writes the accounts and the state gathered from them.
+fn run_sharded(cli_args: &[String], file_path: &str, count: usize, config: &Config) {
+    let dir = env::temp_dir().join(format!("transaction-processor-shards-{}", std::process::id()));
+    let forwarded = worker_args(cli_args, file_path);
+    let result = fs::create_dir_all(&dir).map_err(ShardError::Io)
+        .and_then(|_| File::open(file_path).map_err(ShardError::Io))
+        .and_then(|file| shard::split(BufReader::new(file), &dir, count))
+        .and_then(|schema| {
+            let program = env::current_exe()?;
+            shard::run(&program, &forwarded, &dir, count)?;
+            shard::gather(&dir, count).map(|state| (schema, state))
+        });
+    let (schema, state) = match result {
+        Ok(gathered) => gathered,
+        Err(err) => {
+            match err {
+                ShardError::Io(_) => log::error!("Could not split the input across the worker processes."),
+                ShardError::InvalidHeader => log::error!("The CSV file format is not as expected.\n{}", CSV_EXAMPLE),
+                ShardError::WorkerFailed(shard, code) => {
+                    log::error!("The worker of shard {} failed with exit code {:?}, its shard is kept under {}.", shard,
+                                code, dir.display());
+                    exit(1);
+                }
+                ShardError::State(shard, err) => log::error!("{}", err.describe(&shard::state_path(&dir, shard)))
+            }
+            let _ = shard::clean(&dir);
+            exit(1);
+        }
+    };
+    let _ = shard::clean(&dir);
+
+    let with_tenant = schema.has_tenant() || state.accounts.iter().any(|account| account.tenant().is_some());
+    for (idx, result) in output::write_all(&config.account_sinks, &state.accounts, with_tenant).into_iter().enumerate() {
+        if result.is_err() {
+            log::error!("Could not write the accounts into account sink {}.", idx + 1);
+        }
+    }
+    if let Some(path) = &config.state_path {
+        if state::write_file(path, &state.accounts, &state.history, None).is_err() {
+            log::error!("Could not write the state file {}.", path.display());
+            exit(1);
+        }
+    }
+}
+
+// The options of the run, without the input path, the process count and the state file, which
+// the coordinator passes to every worker on its own.
+fn worker_args(cli_args: &[String], file_path: &str) -> Vec<String> {
+    let mut forwarded = Vec::new();
+    let mut input_seen = false;
+    let mut args = cli_args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shard-processes" | "--save-state" | "--accounts-output" => {
+                args.next();
+            }
+            _ if arg == file_path && !input_seen => input_seen = true,
+            _ => forwarded.push(arg.clone())
+        }
+    }
+    forwarded
+}
+
+fn main() {
+    if init_logger().is_err() {
+        log::error!("Could not initialse the logger. Exiting...");
         exit(1);
     }
 
+    let mut cli_args: Vec<String> = env::args().skip(1).collect();
+    // Processing a csv file is the default command, so its name may be left out.
+    let explicit = cli_args.first().map(String::as_str) == Some("process");
+    if explicit {
+        cli_args.remove(0);
+    }
+    let named = !explicit && cli_args.first()
+        .is_some_and(|arg| COMMANDS.contains(&arg.as_str()) || arg == "-h" || arg == "--help");
+    let command = if named { None } else { Some("process") };
+    let argv = iter::once("transaction-processor").chain(command).chain(cli_args.iter().map(String::as_str));
+    let matches = match cli().get_matches_from_safe(argv) {
+        Ok(matches) => matches,
+        // Unlike `Error::exit`, the help is printed without panicking when stdout is closed, e.g. by `head`.
+        Err(err) if err.kind == ErrorKind::HelpDisplayed => {
+            let _ = writeln!(io::stdout(), "{}", err.message);
+            return;
+        }
+        Err(err) => {
+            log::error!("Invalid arguments. {}", err.message.trim_start_matches("error: "));
+            exit(1);
+        }
+    };
+
+    match matches.subcommand() {
+        ("inspect", Some(matches)) => inspect(matches),
+        ("prove", Some(matches)) => prove(matches),
+        ("merge", Some(matches)) => merge(matches),
+        ("doctor", Some(matches)) => doctor(matches),
+        ("validate", Some(matches)) => validate(matches),
+        ("generate", Some(matches)) => generate(matches),
+        ("compare", Some(matches)) => compare(matches),
+        ("serve", Some(matches)) => serve(matches),
+        ("query", Some(matches)) => query(matches),
+        ("bench", Some(matches)) => bench(matches),
+        ("process", Some(matches)) => process(matches, cli_args),
+        _ => ()
+    }
+}
+
+// Runs the transactions of the csv file, printing the accounts and writing the outputs of the
+// options.
+fn process(matches: &ArgMatches, cli_args: Vec<String>) {
+    let mut args = match parse_args(matches) {
+        Ok(args) => args,
+        Err(reason) => {
+            log::error!("Invalid arguments. {}\n{}", reason, matches.usage());
+            exit(1);
+        }
+    };
+    let file_path = args.file_path.clone().unwrap_or_default();
+
+    let input = match Input::open(Path::new(&file_path)) {
+        Ok(input) => input,
+        Err(err) => {
+            log::error!("{}\n{}", err.describe(Path::new(&file_path)), CSV_EXAMPLE);
+            exit(1);
+        }
+    };
+
+    add_account_sinks(&mut args);
+    if let Some(count) = args.shard_processes {
+        run_sharded(&cli_args, &file_path, count, &args.config);
+        return;
+    }
+
+    let state = read_state(args.resume_path.as_deref());
+
+    // The records up to the checkpoint were applied by the run that wrote the state.
+    let input = match (args.from_checkpoint, state.checkpoint) {
+        (false, _) => input,
+        (true, Some(checkpoint)) => match Input::open_at(Path::new(&file_path), checkpoint) {
+            Ok(input) => input,
+            Err(err) => {
+                log::error!("{}", err.describe(Path::new(&file_path)));
+                exit(1);
+            }
+        },
+        (true, None) => {
+            log::error!("The state file {} has no checkpoint to read the input from.",
+                        args.resume_path.as_ref().unwrap().display());
+            exit(1);
+        }
+    };
+
+    if let Some(path) = &args.plan_path {
+        run_plan(path, state, input, &args.config);
+        return;
+    }
+
     // Process the tx from input.
-    drill(Input::from(result.unwrap()), true, None, true);
+    let started = Instant::now();
+    let run = if args.sources.is_empty() {
+        resume(state, input, &args.config)
+    } else {
+        read_sources(input, &args.sources, &args.config)
+            .and_then(|sources| resume_source(state, sources, &args.config))
+    };
+    let run_report = match run {
+        Ok(run_report) => run_report,
+        Err(TxError::TooManyInvalidRecords { invalid, records }) => abort_invalid(invalid, records),
+        Err(TxError::Strict(violation)) => abort_strict(&violation),
+        Err(_) => Default::default()
+    };
+    let duration = started.elapsed();
+    let aborted = run_report.aborted.is_some();
+
+    if let (Some(path), Some(pseudonymizer)) = (&args.pseudonym_map, &args.config.pseudonymizer) {
+        if pseudonymizer.write_mapping_file(path).is_err() {
+            log::error!("Could not write the pseudonym mapping file {}.", path);
+            exit(1);
+        }
+    }
 
+    // The manifest is written last, so that it covers every other output of the run.
+    if let Some(path) = &args.manifest_path {
+        let mut manifest = Manifest::new(&args.config, run_report, duration);
+        manifest.args = cli_args;
+        manifest.inputs.push(PathBuf::from(&file_path));
+        manifest.inputs.extend(args.sources.iter().map(|(_, path)| path.clone()));
+        manifest.inputs.extend(args.resume_path.iter().cloned());
+        manifest.outputs.extend(args.pseudonym_map.iter().map(PathBuf::from));
+        manifest.outputs.extend(args.accounts_output.iter().cloned());
+        if manifest.write_file(path).is_err() {
+            log::error!("Could not write the run manifest {}.", path.display());
+            exit(1);
+        }
+    }
+
+    // The outputs of an aborted run are written all the same, but they are partial.
+    if aborted {
+        exit(1);
+    }
 }
@@ -0,0 +1,191 @@
+// Machine-readable manifest of a run, for the audit trail of scheduled settlement jobs. It records
+// the engine version, the knobs the run was configured with, the digests of the files it read and
+// wrote, its record counts, how long it took and the limit it was aborted for, if any.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::digest::{to_hex, Digest, Sha256};
+use crate::notify::json_string;
+use crate::report::RunReport;
+use crate::watchdog::Limits;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub struct Manifest {
+    // Command line arguments the run was started with.
+    pub args: Vec<String>,
+    pub config: Vec<(&'static str, String)>,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+    pub report: RunReport,
+    pub duration: Duration,
+}
+
+impl Manifest {
+    // Describes a run of `config`, listing every output file it is configured to write.
+    pub fn new(config: &Config, report: RunReport, duration: Duration) -> Self {
+        let knobs = vec![
+            ("multi_threaded_runtime", config.multi_threaded_runtime.to_string()),
+            ("rules", format!("{:?}", config.rules)),
+            ("amount_scale", config.amount_scale.to_string()),
+            ("audit_chain", config.audit_chain.to_string()),
+            ("aggregate_bucket", format!("{:?}", config.aggregate_bucket)),
+            ("top_count", config.top_count.to_string()),
+            ("pseudonymized", config.pseudonymizer.is_some().to_string()),
+            ("aliased", config.aliases.is_some().to_string()),
+            ("reference_attributes", config.reference.as_ref().map(|reference| reference.names().join("|"))
+                .unwrap_or_default()),
+            ("tenant_quota", optional(config.tenant_quota)),
+            ("tenant_workers", optional(config.tenant_workers)),
+            ("tenant_buffer", config.tenant_buffer.to_string()),
+            ("unknown_account", format!("{:?}", config.unknown_account)),
+            ("sequence_window", optional(config.sequence_window)),
+            ("admin_lane", config.admin_lane.to_string()),
+            ("partitioning", config.partition_dir.as_ref().map(|_| format!("{:?}", config.partitioning))
+                .unwrap_or_default()),
+            ("sample", config.sample.map(|sample| format!("{:?}", sample)).unwrap_or_default()),
+            ("replay_protection", config.replay.map(|replay| format!("{:?}", replay)).unwrap_or_default()),
+            ("reject_duplicates", config.reject_duplicates.to_string()),
+            ("invalid_rate_guard", config.invalid_rate_guard.as_ref().map(|guard| format!("{:?}", guard))
+                .unwrap_or_default()),
+            ("mode", format!("{:?}", config.mode)),
+            ("limits", Some(config.limits).filter(Limits::is_set).map(|limits| format!("{:?}", limits))
+                .unwrap_or_default()),
+        ];
+        Manifest { args: Vec::new(), config: knobs, inputs: Vec::new(), outputs: outputs(config), report, duration }
+    }
+
+    // Writes the manifest as a single JSON object, digesting the input and output files. Files
+    // that are missing, e.g. because their output could not be written, have a `null` digest.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let args: Vec<String> = self.args.iter().map(|arg| json_string(Some(arg))).collect();
+        let config: Vec<String> = self.config.iter()
+            .map(|(knob, value)| format!("{}:{}", json_string(Some(knob)), json_string(Some(value))))
+            .collect();
+        let report = &self.report;
+        writeln!(writer, "{{\"version\":{},\"args\":[{}],\"config\":{{{}}},\"inputs\":[{}],\"records\":{},\
+                          \"invalid\":{},\"rejected\":{},\"dead_letters\":{},\"accounts\":{},\"duration_ms\":{},\
+                          \"aborted\":{},\"outputs\":[{}]}}",
+                 json_string(Some(VERSION)), args.join(","), config.join(","), files(&self.inputs), report.records,
+                 report.invalid, report.rejected, report.dead_letters.count, report.accounts, self.duration.as_millis(),
+                 json_string(report.aborted.map(|exceeded| exceeded.describe()).as_deref()), files(&self.outputs))
+    }
+
+    pub fn write_file(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+fn optional(value: Option<usize>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Files a run configured with `config` writes, the files of the output directories included.
+pub fn outputs(config: &Config) -> Vec<PathBuf> {
+    let mut outputs = Vec::new();
+    if let Some(path) = &config.audit_path {
+        outputs.push(path.clone());
+        if config.audit_chain {
+            let mut root_path = path.clone().into_os_string();
+            root_path.push(".root");
+            outputs.push(PathBuf::from(root_path));
+        }
+    }
+    for path in [&config.aggregates_path, &config.top_accounts_path, &config.summary_path,
+                 &config.suspicious_activity_path, &config.archive_path, &config.state_path, &config.dead_letters_path,
+                 &config.house_accounts_path, &config.open_disputes_path, &config.exposure_path,
+                 &config.backfill_path, &config.account_events_path].iter().filter_map(|path| path.as_ref()) {
+        outputs.push(path.clone());
+    }
+    if let Some(dir) = &config.partition_dir {
+        outputs.extend(config.partitioning.paths(dir, config.audit_chain));
+    }
+    // Timestamped snapshots get a file each, named after the time they were taken, so they are left out.
+    if let Some(path) = config.snapshot_path.as_ref().filter(|_| !config.snapshot_schedule.timestamped) {
+        outputs.push(path.clone());
+    }
+    for dir in [&config.statements_dir, &config.tenant_output_dir].iter().filter_map(|dir| dir.as_ref()) {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir).map(|entries| {
+            entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).filter(|path| path.is_file()).collect()
+        }).unwrap_or_default();
+        files.sort();
+        outputs.extend(files);
+    }
+    outputs
+}
+
+// Streams a file through SHA-256, so large inputs are not loaded in memory.
+pub fn sha256_file(path: &Path) -> io::Result<Digest> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+fn files(paths: &[PathBuf]) -> String {
+    let files: Vec<String> = paths.iter().map(|path| {
+        let sha256 = sha256_file(path).ok().map(|digest| to_hex(&digest));
+        format!("{{\"path\":{},\"sha256\":{}}}", json_string(Some(&path.to_string_lossy())), json_string(sha256.as_deref()))
+    }).collect();
+    files.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::sha256;
+
+    #[test]
+    fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        fs::write(&input, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        let statements = dir.path().join("statements");
+        fs::create_dir(&statements).unwrap();
+        fs::write(statements.join("2.csv"), "b").unwrap();
+        fs::write(statements.join("1.csv"), "a").unwrap();
+
+        let config = Config {
+            summary_path: Some(dir.path().join("missing.csv")),
+            statements_dir: Some(statements.clone()),
+            ..Default::default()
+        };
+        let report = RunReport { accounts: 1, records: 3, invalid: 1, rejected: 1, ..Default::default() };
+        let mut manifest = Manifest::new(&config, report, Duration::from_millis(42));
+        manifest.args = vec![String::from("input.csv"), String::from("--summary")];
+        manifest.inputs.push(input.clone());
+        assert_eq!(manifest.outputs, vec![dir.path().join("missing.csv"), statements.join("1.csv"), statements.join("2.csv")]);
+
+        let mut out = Vec::new();
+        manifest.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(&format!("{{\"version\":\"{}\",\"args\":[\"input.csv\",\"--summary\"],\"config\":{{", VERSION)));
+        assert!(out.contains(&format!("\"inputs\":[{{\"path\":\"{}\",\"sha256\":\"{}\"}}],\"records\":3,\"invalid\":1,\
+                                       \"rejected\":1,\"dead_letters\":0,\"accounts\":1,\"duration_ms\":42,\"aborted\":null,",
+                                      input.display(), to_hex(&sha256(&fs::read(&input).unwrap())))));
+        assert!(out.contains(&format!("{{\"path\":\"{}\",\"sha256\":null}}", dir.path().join("missing.csv").display())));
+        assert!(out.contains(&format!("\"sha256\":\"{}\"}}]}}\n", to_hex(&sha256(b"b")))));
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        let data: Vec<u8> = (0..200_000).map(|idx| (idx % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+        assert_eq!(sha256_file(&path).unwrap(), sha256(&data));
+        assert!(sha256_file(&dir.path().join("missing")).is_err());
+    }
+}
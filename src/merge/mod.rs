@@ -0,0 +1,124 @@
+// Merge of the account of a client into the account of another one, within a state file, e.g.
+// once two clients turned out to be the same customer. The surviving account gets the funds, the
+// open disputes and the history of the merged one, so the transactions of the merged client can
+// still be disputed, resolved or charged back, against the surviving client.
+
+use crate::state::State;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // The client has no account in the state.
+    AccountNotFound(u16),
+    SameAccount,
+    // Closed accounts can not take part in a merge.
+    AccountClosed(u16),
+    // Both accounts have a transaction with this id in their history.
+    TxIdCollision(u32),
+    // The funds of both accounts would not fit in one.
+    Overflow,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Merges the account of `from` into the one of `into`, both of `tenant`.
+pub fn merge(state: &mut State, tenant: Option<&str>, from: u16, into: u16) -> Result<()> {
+    if from == into {
+        return Err(Error::SameAccount);
+    }
+    let position = |client_id: u16| {
+        state.accounts.iter()
+            .position(|account| account.tenant() == tenant && account.client_id() == client_id)
+            .ok_or(Error::AccountNotFound(client_id))
+    };
+    let (from_idx, into_idx) = (position(from)?, position(into)?);
+    for idx in [from_idx, into_idx] {
+        if state.accounts[idx].status().is_terminal() {
+            return Err(Error::AccountClosed(state.accounts[idx].client_id()));
+        }
+    }
+
+    let of = |tx: &crate::transaction::Transaction, client_id: u16| {
+        tx.tenant() == tenant && tx.client_id() == client_id
+    };
+    let surviving: Vec<u32> = state.history.iter().filter(|tx| of(tx, into)).map(|tx| tx.tx_id()).collect();
+    if let Some(tx) = state.history.iter().find(|tx| of(tx, from) && surviving.contains(&tx.tx_id())) {
+        return Err(Error::TxIdCollision(tx.tx_id()));
+    }
+
+    let merged = state.accounts[from_idx].clone();
+    state.accounts[into_idx].merge(merged).map_err(|_| Error::Overflow)?;
+    state.accounts.remove(from_idx);
+    for tx in state.history.iter_mut().filter(|tx| of(tx, from)) {
+        tx.set_client_id(into);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::account::{Account, AccountAdmin, Status};
+    use crate::transaction::Transaction;
+    use crate::amount::{from_f64, Amount, ZERO};
+
+    fn restored(client_id: u16, txs: Vec<Transaction>) -> (Account, Vec<Transaction>) {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(client_id, receiver);
+        for tx in txs {
+            account_admin.apply(tx).unwrap();
+        }
+        (account_admin.account().clone(), account_admin.take_history())
+    }
+
+    #[test]
+    fn test_merge() {
+        let (first, first_history) = restored(1, vec![Tx::deposit(1, 1).amount(5.0).build(),
+                                                      Tx::dispute(1, 1).build()]);
+        let (second, second_history) = restored(2, vec![Tx::deposit(2, 2).amount(3.0).build()]);
+        let mut state = State { accounts: vec![first, second], history: [first_history, second_history].concat(),
+                                 checkpoint: None };
+        assert_eq!(merge(&mut state, None, 1, 1), Err(Error::SameAccount));
+        assert_eq!(merge(&mut state, None, 1, 3), Err(Error::AccountNotFound(3)));
+        assert_eq!(merge(&mut state, Some("acme"), 1, 2), Err(Error::AccountNotFound(1)));
+
+        merge(&mut state, None, 1, 2).unwrap();
+        assert_eq!(state.accounts.len(), 1);
+        let account = &state.accounts[0];
+        assert_eq!((account.client_id(), account.available(), account.held(), account.total()),
+                   (2, from_f64(3.0), from_f64(5.0), from_f64(8.0)));
+        assert_eq!(account.open_disputes().len(), 1);
+        assert!(state.history.iter().all(|tx| tx.client_id() == 2));
+
+        // The merged deposit can be charged back against the surviving account.
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(2, receiver);
+        let State { mut accounts, history, .. } = state;
+        account_admin.restore(accounts.remove(0), history);
+        account_admin.apply(Tx::chargeback(2, 1).build()).unwrap();
+        assert_eq!(account_admin.account().total(), from_f64(3.0));
+        assert_eq!(account_admin.account().status(), Status::Locked);
+    }
+
+    #[test]
+    fn test_merge_refused() {
+        let (first, first_history) = restored(1, vec![Tx::deposit(1, 1).amount(5.0).build()]);
+        let (second, second_history) = restored(2, vec![Tx::deposit(2, 1).amount(3.0).build()]);
+        let mut state = State { accounts: vec![first, second], history: [first_history, second_history].concat(),
+                                 checkpoint: None };
+        assert_eq!(merge(&mut state, None, 1, 2), Err(Error::TxIdCollision(1)));
+
+        state.accounts.push(Account::new(3, ZERO, ZERO, Status::Closed));
+        assert_eq!(merge(&mut state, None, 3, 2), Err(Error::AccountClosed(3)));
+        assert_eq!(state.accounts.len(), 3);
+
+        // Neither the accounts nor the history change when the funds would not fit in one account.
+        let half = Amount::from_minor(i64::MAX / 2 + 1);
+        let mut state = State { accounts: vec![Account::new(4, half, ZERO, Status::Active),
+                                               Account::new(5, half, ZERO, Status::Active)],
+                                history: vec![Tx::deposit(4, 4).amount(1.0).build()], checkpoint: None };
+        assert_eq!(merge(&mut state, None, 4, 5), Err(Error::Overflow));
+        assert_eq!((state.accounts.len(), state.accounts[1].available()), (2, half));
+        assert_eq!(state.history[0].client_id(), 4);
+    }
+}
@@ -0,0 +1,211 @@
+// Tamper evidence of the audit trail. Every entry is chained to the previous one with a hash, and
+// a Merkle tree over the entries yields a root plus per entry inclusion proofs, so that an auditor
+// only trusting the root can verify that a transaction was processed.
+
+use std::io::{self, BufRead};
+
+use crate::digest::{sha256, to_hex, Digest, Sha256};
+
+// Hash of the chain before its first entry.
+pub const CHAIN_START: Digest = [0; 32];
+
+// The leaves and the inner nodes are hashed with different prefixes, so that an inner node can
+// not be passed off as an entry.
+pub fn leaf_hash(entry: &str) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(&[0]);
+    hasher.update(entry.as_bytes());
+    hasher.finalize()
+}
+
+fn node_hash(left: &Digest, right: &Digest) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(&[1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize()
+}
+
+pub fn chain_hash(previous: &Digest, entry: &str) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(previous);
+    hasher.update(entry.as_bytes());
+    hasher.finalize()
+}
+
+// Side a sibling is hashed on, on the way from a leaf to the root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Left => "L",
+            Side::Right => "R"
+        }
+    }
+}
+
+pub type Proof = Vec<(Side, Digest)>;
+
+// The nodes are paired level by level, the last node of a level with an odd number of nodes
+// being carried up as it is.
+fn next_level(level: &[Digest]) -> Vec<Digest> {
+    level.chunks(2).map(|pair| match pair {
+        [left, right] => node_hash(left, right),
+        [node] => *node,
+        _ => unreachable!()
+    }).collect()
+}
+
+pub fn root(leaves: &[Digest]) -> Digest {
+    if leaves.is_empty() {
+        return sha256(b"");
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+pub fn proof(leaves: &[Digest], mut index: usize) -> Proof {
+    let mut proof = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        if index % 2 == 1 {
+            proof.push((Side::Left, level[index - 1]));
+        } else if index + 1 < level.len() {
+            proof.push((Side::Right, level[index + 1]));
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    proof
+}
+
+pub fn verify(entry: &str, proof: &[(Side, Digest)], root: &Digest) -> bool {
+    let node = proof.iter().fold(leaf_hash(entry), |node, (side, sibling)| match side {
+        Side::Left => node_hash(sibling, &node),
+        Side::Right => node_hash(&node, sibling)
+    });
+    node == *root
+}
+
+// Consumes the entries of a chained audit trail, the `hash` column being the last one.
+#[derive(Default)]
+pub struct Chain {
+    previous: Option<Digest>,
+    leaves: Vec<Digest>,
+}
+
+impl Chain {
+    // Returns the hash to record along with the entry.
+    pub fn push(&mut self, entry: &str) -> Digest {
+        let hash = chain_hash(&self.previous.unwrap_or(CHAIN_START), entry);
+        self.previous = Some(hash);
+        self.leaves.push(leaf_hash(entry));
+        hash
+    }
+
+    pub fn leaves(&self) -> &[Digest] {
+        &self.leaves
+    }
+
+    pub fn root(&self) -> Digest {
+        root(&self.leaves)
+    }
+}
+
+// Inclusion proof of an entry of the audit trail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inclusion {
+    pub index: usize,
+    pub entry: String,
+    pub proof: Proof,
+}
+
+fn invalid_data(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+// Checks the chain of a chained audit trail and proves the inclusion of the entries of a
+// transaction, returning the root of the trail too.
+pub fn prove<R: BufRead>(reader: R, tenant: Option<&str>, client_id: u16, tx_id: u32)
+                         -> io::Result<(Digest, Vec<Inclusion>)> {
+    let mut lines = reader.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns: Vec<&str> = header.split(',').collect();
+    let position = |name: &str| columns.iter().position(|column| *column == name);
+    let (tenant_column, client_column, tx_column) = match (position("tenant"), position("client"), position("tx")) {
+        (Some(tenant), Some(client), Some(tx)) if columns.last() == Some(&"hash") => (tenant, client, tx),
+        _ => return Err(invalid_data(String::from("not a chained audit trail")))
+    };
+
+    let mut chain = Chain::default();
+    let mut matching = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line?;
+        let (entry, hash) = line.rsplit_once(',').ok_or_else(|| invalid_data(format!("entry {} has no hash", index)))?;
+        if to_hex(&chain.push(entry)) != hash {
+            return Err(invalid_data(format!("the chain is broken at entry {}", index)));
+        }
+        let fields: Vec<&str> = entry.split(',').collect();
+        if fields.get(tenant_column) == Some(&tenant.unwrap_or("")) && fields.get(client_column) == Some(&&*client_id.to_string())
+            && fields.get(tx_column) == Some(&&*tx_id.to_string()) {
+            matching.push((index, entry.to_string()));
+        }
+    }
+
+    let inclusions = matching.into_iter()
+        .map(|(index, entry)| Inclusion { index, entry, proof: proof(chain.leaves(), index) })
+        .collect();
+    Ok((chain.root(), inclusions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<String> {
+        (0..count).map(|idx| format!("entry {}", idx)).collect()
+    }
+
+    #[test]
+    fn test_merkle_proofs() {
+        for count in 1..=7 {
+            let entries = leaves(count);
+            let hashes: Vec<Digest> = entries.iter().map(|entry| leaf_hash(entry)).collect();
+            let root = root(&hashes);
+            for (index, entry) in entries.iter().enumerate() {
+                let proof = proof(&hashes, index);
+                assert!(verify(entry, &proof, &root));
+                assert!(!verify("altered", &proof, &root));
+            }
+        }
+        let hashes: Vec<Digest> = leaves(3).iter().map(|entry| leaf_hash(entry)).collect();
+        assert_eq!(root(&hashes), node_hash(&node_hash(&hashes[0], &hashes[1]), &hashes[2]));
+        assert_eq!(proof(&hashes, 2), vec![(Side::Left, node_hash(&hashes[0], &hashes[1]))]);
+    }
+
+    #[test]
+    fn test_prove() {
+        let mut chain = Chain::default();
+        let mut trail = String::from("tenant,client,tx,type,hash\n");
+        for entry in [",1,1,deposit", ",2,2,deposit", ",1,1,dispute"].iter() {
+            trail.push_str(&format!("{},{}\n", entry, to_hex(&chain.push(entry))));
+        }
+
+        let (root, inclusions) = prove(trail.as_bytes(), None, 1, 1).unwrap();
+        assert_eq!(root, chain.root());
+        assert_eq!(inclusions.iter().map(|inclusion| inclusion.index).collect::<Vec<_>>(), vec![0, 2]);
+        assert!(inclusions.iter().all(|inclusion| verify(&inclusion.entry, &inclusion.proof, &root)));
+
+        let altered = trail.replacen(",2,2,deposit", ",2,2,withdrawal", 1);
+        assert!(prove(altered.as_bytes(), None, 1, 1).is_err());
+        assert!(prove(&b"tenant,client,tx,type\n"[..], None, 1, 1).is_err());
+    }
+}
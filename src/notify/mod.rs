@@ -0,0 +1,574 @@
+// Real time alerts about the events that need the attention of the operators, routed to sinks
+// that filter them by severity and event.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::amount;
+use crate::audit::{Balance, Entry, Outcome, Sink};
+use crate::logger::{self, Fields};
+use crate::config::Labels;
+use crate::retry::Retrier;
+use crate::transaction::Type;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Importance of an alert, used by the sinks to filter out the alerts they are not interested in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical"
+        }
+    }
+
+    pub fn parse(severity: &str) -> Option<Severity> {
+        match severity {
+            "info" => Some(Severity::Info),
+            "warning" => Some(Severity::Warning),
+            "critical" => Some(Severity::Critical),
+            _ => None
+        }
+    }
+}
+
+// Events the operators can be alerted about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Rejected,
+    AccountLocked,
+    Chargeback,
+    InvalidRecords,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Rejected => "rejected",
+            Event::AccountLocked => "account_locked",
+            Event::Chargeback => "chargeback",
+            Event::InvalidRecords => "invalid_records"
+        }
+    }
+
+    pub fn parse(event: &str) -> Option<Event> {
+        match event {
+            "rejected" => Some(Event::Rejected),
+            "account_locked" => Some(Event::AccountLocked),
+            "chargeback" => Some(Event::Chargeback),
+            "invalid_records" => Some(Event::InvalidRecords),
+            _ => None
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Event::Rejected => Severity::Info,
+            Event::Chargeback | Event::InvalidRecords => Severity::Warning,
+            Event::AccountLocked => Severity::Critical
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub event: Event,
+    pub severity: Severity,
+    pub tenant: Option<String>,
+    // The client, as it should appear in outputs.
+    pub client: Option<String>,
+    pub tx: Option<u32>,
+    pub tx_type: Option<Type>,
+    // Funds of the account right after the transaction that raised the alert.
+    pub balance: Option<Balance>,
+    pub detail: String,
+}
+
+impl Alert {
+    fn new(event: Event, detail: String) -> Self {
+        Alert { event, severity: event.severity(), tenant: None, client: None, tx: None, tx_type: None, balance: None,
+                detail }
+    }
+
+    // The alerts raised by an audit entry, if any.
+    pub fn from_entry(entry: &Entry, client: &str) -> Vec<Alert> {
+        let mut events = Vec::new();
+        match entry.outcome {
+            Outcome::Rejected(reason) => {
+                events.push((Event::Rejected, format!("{} rejected: {}", entry.ttype.as_str(), reason)));
+            }
+            Outcome::Applied => {
+                if entry.ttype == Type::Chargeback {
+                    events.push((Event::Chargeback, entry.note.clone().unwrap_or_default()));
+                }
+                if entry.locked {
+                    events.push((Event::AccountLocked, format!("locked by a {}", entry.ttype.as_str())));
+                }
+            }
+        }
+        events.into_iter().map(|(event, detail)| Alert {
+            tenant: entry.tenant.clone(),
+            client: Some(client.to_string()),
+            tx: Some(entry.tx_id),
+            tx_type: Some(entry.ttype.clone()),
+            balance: Some(entry.after),
+            ..Alert::new(event, detail)
+        }).collect()
+    }
+
+    pub fn invalid_records(count: usize) -> Alert {
+        Alert::new(Event::InvalidRecords, format!("{} invalid records", count))
+    }
+
+    pub fn to_json(&self) -> String {
+        let balance = match &self.balance {
+            Some(balance) => format!("\"available\":{},\"held\":{},\"total\":{}", amount::fmt(balance.available),
+                                     amount::fmt(balance.held), amount::fmt(balance.total)),
+            None => String::from("\"available\":null,\"held\":null,\"total\":null")
+        };
+        format!("{{\"event\":\"{}\",\"severity\":\"{}\",\"tenant\":{},\"client\":{},\"tx\":{},{},\"detail\":\"{}\"}}",
+                self.event.as_str(), self.severity.as_str(), json_string(self.tenant.as_deref()),
+                json_string(self.client.as_deref()), self.tx.map(|tx| tx.to_string()).unwrap_or_else(|| String::from("null")),
+                balance, escape(&self.detail))
+    }
+
+    // The event and its detail. The client and the transaction are logged as fields instead.
+    fn describe(&self) -> String {
+        let mut description = String::from(self.event.as_str());
+        if !self.detail.is_empty() {
+            description.push_str(&format!(", {}", self.detail));
+        }
+        description
+    }
+
+    fn fields(&self) -> Fields {
+        Fields { tenant: self.tenant.clone(), client_id: self.client.clone(), tx_id: self.tx,
+                 tx_type: self.tx_type.as_ref().map(Type::as_str) }
+    }
+}
+
+pub(crate) fn json_string(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", escape(value)),
+        None => String::from("null")
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+// An `http://host[:port]/path` URL. TLS is not supported, so the webhooks are expected to be
+// reachable on a trusted network, e.g. through a local relay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Option<Url> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/")
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().ok()?),
+            None => (authority, 80)
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Url { host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
+// POSTs a JSON body, succeeding on a 2xx status only.
+fn post(url: &Url, body: &str) -> io::Result<()> {
+    let addr = (url.host.as_str(), url.port).to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::other("could not resolve the webhook host"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+                           Content-Length: {}\r\nConnection: close\r\n\r\n{}", url.path, url.host, body.len(), body);
+    stream.write_all(request.as_bytes())?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("webhook answered `{}`", status_line.trim_end())))
+    }
+}
+
+// Consumer of the alerts, called from the alerter thread.
+pub trait AlertSink: Send {
+    fn alert(&mut self, alert: &Alert) -> io::Result<()>;
+
+    // Called once, after every alert was delivered.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Logs the alerts, with a level matching their severity.
+pub struct LogSink;
+
+impl AlertSink for LogSink {
+    fn alert(&mut self, alert: &Alert) -> io::Result<()> {
+        let _span = logger::enter(alert.fields());
+        match alert.severity {
+            Severity::Info => log::info!("Alert: {}.", alert.describe()),
+            Severity::Warning => log::warn!("Alert: {}.", alert.describe()),
+            Severity::Critical => log::error!("Alert: {}.", alert.describe())
+        }
+        Ok(())
+    }
+}
+
+// Writes the alerts with respect to the `severity,event,tenant,client,tx,detail` schema.
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "severity,event,tenant,client,tx,detail")?;
+        Ok(FileSink { writer })
+    }
+}
+
+impl AlertSink for FileSink {
+    fn alert(&mut self, alert: &Alert) -> io::Result<()> {
+        writeln!(self.writer, "{},{},{},{},{},{}", alert.severity.as_str(), alert.event.as_str(),
+                 alert.tenant.as_deref().unwrap_or(""), alert.client.as_deref().unwrap_or(""),
+                 alert.tx.map(|tx| tx.to_string()).unwrap_or_default(), alert.detail)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Posts the alerts as JSON, retrying failed deliveries.
+pub struct WebhookSink {
+    url: Url,
+    retrier: Retrier,
+}
+
+impl WebhookSink {
+    pub fn new(url: Url, retrier: Retrier) -> Self {
+        WebhookSink { url, retrier }
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn alert(&mut self, alert: &Alert) -> io::Result<()> {
+        let body = alert.to_json();
+        self.retrier.call(|| post(&self.url, &body))
+    }
+}
+
+// Which alerts a sink is interested in. An empty list of events stands for all the events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub severity: Severity,
+    pub events: Vec<Event>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter { severity: Severity::Info, events: Vec::new() }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, alert: &Alert) -> bool {
+        alert.severity >= self.severity && (self.events.is_empty() || self.events.contains(&alert.event))
+    }
+}
+
+// Builds a custom sink, once the run starts.
+pub type SinkFactory = Arc<dyn Fn() -> io::Result<Box<dyn AlertSink>> + Send + Sync>;
+
+#[derive(Clone)]
+pub enum Target {
+    Log,
+    File(PathBuf),
+    Webhook(Url),
+    Custom(SinkFactory),
+}
+
+// Where the alerts matching a filter are sent to.
+#[derive(Clone)]
+pub struct Route {
+    pub target: Target,
+    pub filter: Filter,
+}
+
+impl Route {
+    // Parses a `log|file:<path>|webhook:<url>[,severity=<severity>][,events=<event>+<event>...]` spec.
+    pub fn parse(spec: &str) -> Option<Route> {
+        let mut parts = spec.split(',');
+        let target = match parts.next()? {
+            "log" => Target::Log,
+            target => match target.split_once(':')? {
+                ("file", path) if !path.is_empty() => Target::File(PathBuf::from(path)),
+                ("webhook", url) => Target::Webhook(Url::parse(url)?),
+                _ => return None
+            }
+        };
+        let mut filter = Filter::default();
+        for part in parts {
+            match part.split_once('=')? {
+                ("severity", severity) => filter.severity = Severity::parse(severity)?,
+                ("events", events) => {
+                    filter.events = events.split('+').map(Event::parse).collect::<Option<Vec<Event>>>()?;
+                }
+                _ => return None
+            }
+        }
+        Some(Route { target, filter })
+    }
+
+    fn open(&self, retrier: &Retrier) -> io::Result<Box<dyn AlertSink>> {
+        Ok(match &self.target {
+            Target::Log => Box::new(LogSink),
+            Target::File(path) => Box::new(FileSink::create(path)?),
+            Target::Webhook(url) => Box::new(WebhookSink::new(url.clone(), retrier.clone())),
+            Target::Custom(factory) => factory()?
+        })
+    }
+}
+
+// Routes the alerts to the sinks from a dedicated thread, so slow deliveries, e.g. the webhook
+// retries, do not hold the other audit sinks back. It is itself an audit sink, raising the alerts
+// of the audit entries, while the alerts about the run are sent through `sender`.
+pub struct Alerter {
+    sender: Option<Sender<Alert>>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+    labels: Labels,
+}
+
+impl Alerter {
+    // An undelivered alert does not stop the others, the first error being reported by `finish`.
+    // The webhook sinks share the retrier, and so its budget.
+    pub fn spawn(routes: &[Route], retrier: &Retrier, labels: Labels) -> io::Result<Self> {
+        let mut sinks = Vec::with_capacity(routes.len());
+        for route in routes {
+            sinks.push((route.filter.clone(), route.open(retrier)?));
+        }
+        let (sender, receiver) = channel::<Alert>();
+        let handle = thread::spawn(move || {
+            let mut result = Ok(());
+            for alert in receiver {
+                let _span = logger::enter(alert.fields());
+                for (_, sink) in sinks.iter_mut().filter(|(filter, _)| filter.matches(&alert)) {
+                    if let Err(err) = sink.alert(&alert) {
+                        log::error!("Could not deliver the {} alert, {}.", alert.event.as_str(), err);
+                        result = result.and(Err(err));
+                    }
+                }
+            }
+            for (_, sink) in sinks.iter_mut() {
+                result = result.and(sink.finish());
+            }
+            result
+        });
+        Ok(Alerter { sender: Some(sender), handle: Some(handle), labels })
+    }
+
+    // All the senders must be dropped before the alerter is finished.
+    pub fn sender(&self) -> Option<Sender<Alert>> {
+        self.sender.clone()
+    }
+}
+
+impl Sink for Alerter {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        if let Some(sender) = &self.sender {
+            let client = self.labels.label(entry.client_id);
+            for alert in Alert::from_entry(entry, &client) {
+                let _ = sender.send(alert);
+            }
+        }
+        Ok(())
+    }
+
+    // Waits for the pending alerts to be delivered.
+    fn finish(&mut self) -> io::Result<()> {
+        drop(self.sender.take());
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("alerter panicked")),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use crate::audit::Balance;
+    use crate::clock::{Clock, MockClock};
+    use crate::retry::{Metrics, Policy};
+    use crate::amount::{from_f64, ZERO};
+
+    fn chargeback() -> Entry {
+        Entry { tenant: Some(String::from("acme")), client_id: 1, tx_id: 2, ttype: Type::Chargeback, amount: None,
+                outcome: Outcome::Applied, note: None, before: Balance::default(),
+                after: Balance { available: from_f64(1.0), held: ZERO, total: from_f64(1.0) }, timestamp: None, locked: true,
+                previous_status: Status::Active, status: Status::Locked, record: None, source: None }
+    }
+
+    // Reads a request up to the end of its body.
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            let read = stream.read(&mut chunk).unwrap();
+            request.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let length: usize = text.lines().find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap().parse().unwrap();
+                if request.len() >= header_end + 4 + length || read == 0 {
+                    return text;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_url_parse() {
+        assert_eq!(Url::parse("http://localhost:8080/hooks/ops"),
+                   Some(Url { host: String::from("localhost"), port: 8080, path: String::from("/hooks/ops") }));
+        assert_eq!(Url::parse("http://example.com"),
+                   Some(Url { host: String::from("example.com"), port: 80, path: String::from("/") }));
+        assert!(Url::parse("https://example.com").is_none());
+        assert!(Url::parse("http://example.com:port/").is_none());
+    }
+
+    #[test]
+    fn test_alerts_from_entry() {
+        let entry = chargeback();
+        let alerts = Alert::from_entry(&entry, "1");
+        assert_eq!(alerts.iter().map(|alert| (alert.event, alert.severity)).collect::<Vec<_>>(),
+                   vec![(Event::Chargeback, Severity::Warning), (Event::AccountLocked, Severity::Critical)]);
+        assert_eq!(alerts[0].to_json(),
+                   "{\"event\":\"chargeback\",\"severity\":\"warning\",\"tenant\":\"acme\",\"client\":\"1\",\"tx\":2,\
+                   \"available\":1.0000,\"held\":0.0000,\"total\":1.0000,\"detail\":\"\"}");
+        assert_eq!(Alert::invalid_records(3).to_json(),
+                   "{\"event\":\"invalid_records\",\"severity\":\"warning\",\"tenant\":null,\"client\":null,\
+                   \"tx\":null,\"available\":null,\"held\":null,\"total\":null,\"detail\":\"3 invalid records\"}");
+
+        let rejected = Entry { outcome: Outcome::Rejected("account_locked"), locked: false, ..chargeback() };
+        let alerts = Alert::from_entry(&rejected, "1");
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].event, Event::Rejected);
+        assert_eq!(alerts[0].detail, "chargeback rejected: account_locked");
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\u000a");
+    }
+
+    #[test]
+    fn test_route_parse() {
+        let route = Route::parse("file:alerts.csv,severity=warning,events=chargeback+invalid_records").unwrap();
+        assert!(matches!(&route.target, Target::File(path) if path == Path::new("alerts.csv")));
+        assert_eq!(route.filter, Filter { severity: Severity::Warning,
+                                          events: vec![Event::Chargeback, Event::InvalidRecords] });
+        let route = Route::parse("webhook:http://localhost:8080/hook").unwrap();
+        assert!(matches!(route.target, Target::Webhook(_)));
+        assert_eq!(route.filter, Filter::default());
+        assert!(matches!(Route::parse("log,severity=critical").unwrap().target, Target::Log));
+        assert!(Route::parse("file:").is_none());
+        assert!(Route::parse("log,severity=high").is_none());
+        assert!(Route::parse("log,events=chargeback+unknown").is_none());
+        assert!(Route::parse("pager:ops").is_none());
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let alerts = Alert::from_entry(&chargeback(), "1");
+        let filter = Filter { severity: Severity::Critical, events: Vec::new() };
+        assert!(!filter.matches(&alerts[0]));
+        assert!(filter.matches(&alerts[1]));
+        let filter = Filter { severity: Severity::Info, events: vec![Event::Chargeback] };
+        assert!(filter.matches(&alerts[0]));
+        assert!(!filter.matches(&alerts[1]));
+        assert!(!filter.matches(&Alert::invalid_records(1)));
+    }
+
+    #[test]
+    fn test_alerter_routes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/hook", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["503 Service Unavailable", "200 OK"].iter() {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&mut stream));
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+            requests
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("alerts.csv");
+        let routes = vec![
+            Route { target: Target::Webhook(url), filter: Filter { severity: Severity::Critical, events: Vec::new() } },
+            Route { target: Target::File(path.clone()), filter: Filter { severity: Severity::Warning, events: Vec::new() } },
+        ];
+        // The retry waits on the mock clock, so a long backoff does not slow the test down.
+        let clock = Arc::new(MockClock::new(0));
+        let policy = Policy { attempts: 2, backoff: Duration::from_secs(60), max_backoff: Duration::from_secs(60),
+                              ..Policy::default() };
+        let retrier = Retrier::new(policy, clock.clone(), Arc::new(Metrics::default()));
+        let mut alerter = Alerter::spawn(&routes, &retrier, Labels::default()).unwrap();
+        alerter.record(&chargeback()).unwrap();
+        let sender = alerter.sender().unwrap();
+        sender.send(Alert::invalid_records(2)).unwrap();
+        drop(sender);
+        alerter.finish().unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(requests[1].ends_with(&Alert::from_entry(&chargeback(), "1")[1].to_json()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+                   "severity,event,tenant,client,tx,detail\n\
+                   warning,chargeback,acme,1,2,\n\
+                   critical,account_locked,acme,1,2,locked by a chargeback\n\
+                   warning,invalid_records,,,,2 invalid records\n");
+        assert_eq!(clock.elapsed(), Duration::from_secs(60));
+        assert_eq!((retrier.stats().attempts, retrier.stats().retries), (2, 1));
+    }
+}
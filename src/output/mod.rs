@@ -0,0 +1,307 @@
+// Sinks the final accounts of a run are streamed into, once the run is over. The binary writes
+// them as CSV or JSON to the standard output, while embedders can write them into their own store,
+// or collect them, through the same trait.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::account::Account;
+use crate::amount;
+use crate::config::{Config, Labels};
+use crate::notify::json_string;
+use crate::report::NO_TENANT;
+
+// Consumer of the final accounts, in tenant and client order.
+pub trait AccountSink: Send {
+    // Called once, before the first account, telling whether the accounts carry a tenant.
+    fn start(&mut self, _with_tenant: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn account(&mut self, account: &Account) -> io::Result<()>;
+
+    // Called once, after the last account.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Builds the sink, once the run is over.
+pub type AccountSinkFactory = Arc<dyn Fn() -> io::Result<Box<dyn AccountSink>> + Send + Sync>;
+
+// Streams the accounts into a new sink of the factory.
+pub fn write(factory: &AccountSinkFactory, accounts: &[Account], with_tenant: bool) -> io::Result<()> {
+    let mut sink = factory()?;
+    sink.start(with_tenant)?;
+    for account in accounts {
+        sink.account(account)?;
+    }
+    sink.finish()
+}
+
+// Streams the accounts into a new sink of every factory, each sink on a thread of its own, so that
+// a slow sink does not hold back the others. The sinks fail independently of each other, the
+// results being in the order of the factories.
+pub fn write_all(factories: &[AccountSinkFactory], accounts: &[Account], with_tenant: bool) -> Vec<io::Result<()>> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = factories.iter()
+            .map(|factory| scope.spawn(move || write(factory, accounts, with_tenant)))
+            .collect();
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(io::Error::other("account sink panicked"))))
+            .collect()
+    })
+}
+
+// Writes the accounts with respect to the
+// `[tenant,]client,available,held[,pending],total,locked[,status]` schema, the pending funds
+// being written under two-step withdrawals and the status unless the spec is followed.
+pub struct CsvSink<W: Write> {
+    writer: W,
+    labels: Labels,
+    with_tenant: bool,
+    with_pending: bool,
+    with_status: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W, config: &Config) -> Self {
+        CsvSink { writer, labels: config.labels(), with_tenant: false,
+                  with_pending: config.rules.two_step_withdrawals, with_status: !config.rules.spec }
+    }
+
+    // A factory of sinks writing into the writers `open` opens, e.g. the standard output or files.
+    pub fn factory<F>(config: &Config, open: F) -> AccountSinkFactory
+        where F: Fn() -> io::Result<W> + Send + Sync + 'static, W: Send + 'static {
+        let (labels, with_pending, with_status) = (config.labels(), config.rules.two_step_withdrawals, !config.rules.spec);
+        Arc::new(move || {
+            Ok(Box::new(CsvSink { writer: open()?, labels: labels.clone(), with_tenant: false, with_pending, with_status }))
+        })
+    }
+
+    // The writes of the sink, for the writers that cannot be sent to another thread.
+    pub fn write_header(&mut self, with_tenant: bool) -> io::Result<()> {
+        self.with_tenant = with_tenant;
+        if with_tenant {
+            write!(self.writer, "tenant,")?;
+        }
+        if self.with_pending {
+            write!(self.writer, "client,available,held,pending,total,locked")?;
+        } else {
+            write!(self.writer, "client,available,held,total,locked")?;
+        }
+        if self.with_status {
+            write!(self.writer, ",status")?;
+        }
+        writeln!(self.writer)
+    }
+
+    pub fn write_account(&mut self, account: &Account) -> io::Result<()> {
+        if self.with_tenant {
+            write!(self.writer, "{},", account.tenant().unwrap_or(NO_TENANT))?;
+        }
+        write!(self.writer, "{},{},{},", self.labels.label(account.client_id()),
+               amount::fmt(account.available()), amount::fmt(account.held()))?;
+        if self.with_pending {
+            write!(self.writer, "{},", amount::fmt(account.pending()))?;
+        }
+        write!(self.writer, "{},{}", amount::fmt(account.total()), account.is_locked())?;
+        if self.with_status {
+            write!(self.writer, ",{}", account.status().as_str())?;
+        }
+        writeln!(self.writer)
+    }
+}
+
+impl<W: Write + Send> AccountSink for CsvSink<W> {
+    fn start(&mut self, with_tenant: bool) -> io::Result<()> {
+        self.write_header(with_tenant)
+    }
+
+    fn account(&mut self, account: &Account) -> io::Result<()> {
+        self.write_account(account)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonFormat {
+    // A single array of all the accounts.
+    Array,
+    // An object per line, i.e. NDJSON.
+    Lines,
+}
+
+// Writes the accounts as JSON objects with the fields of the CSV columns, the client being the
+// label it gets in the outputs and the amounts being numbers, e.g.
+// `{"client":"1","available":1.5000,"held":0.0000,"total":1.5000,"locked":false,"status":"active"}`.
+pub struct JsonSink<W: Write> {
+    writer: W,
+    format: JsonFormat,
+    labels: Labels,
+    with_tenant: bool,
+    with_pending: bool,
+    with_status: bool,
+    written: usize,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W, format: JsonFormat, config: &Config) -> Self {
+        JsonSink { writer, format, labels: config.labels(), with_tenant: false,
+                   with_pending: config.rules.two_step_withdrawals, with_status: !config.rules.spec, written: 0 }
+    }
+
+    // A factory of sinks writing into the writers `open` opens, e.g. the standard output or files.
+    pub fn factory<F>(config: &Config, format: JsonFormat, open: F) -> AccountSinkFactory
+        where F: Fn() -> io::Result<W> + Send + Sync + 'static, W: Send + 'static {
+        let (labels, with_pending, with_status) = (config.labels(), config.rules.two_step_withdrawals, !config.rules.spec);
+        Arc::new(move || {
+            Ok(Box::new(JsonSink { writer: open()?, format, labels: labels.clone(), with_tenant: false, with_pending,
+                                   with_status, written: 0 }))
+        })
+    }
+}
+
+impl<W: Write + Send> AccountSink for JsonSink<W> {
+    fn start(&mut self, with_tenant: bool) -> io::Result<()> {
+        self.with_tenant = with_tenant;
+        match self.format {
+            JsonFormat::Array => write!(self.writer, "["),
+            JsonFormat::Lines => Ok(())
+        }
+    }
+
+    fn account(&mut self, account: &Account) -> io::Result<()> {
+        match (self.format, self.written) {
+            (JsonFormat::Array, 0) => writeln!(self.writer)?,
+            (JsonFormat::Array, _) => writeln!(self.writer, ",")?,
+            (JsonFormat::Lines, _) => ()
+        }
+        write!(self.writer, "{{")?;
+        if self.with_tenant {
+            write!(self.writer, "\"tenant\":{},", json_string(Some(account.tenant().unwrap_or(NO_TENANT))))?;
+        }
+        write!(self.writer, "\"client\":{},\"available\":{},\"held\":{},",
+               json_string(Some(&self.labels.label(account.client_id()))), amount::fmt(account.available()),
+               amount::fmt(account.held()))?;
+        if self.with_pending {
+            write!(self.writer, "\"pending\":{},", amount::fmt(account.pending()))?;
+        }
+        write!(self.writer, "\"total\":{},\"locked\":{}", amount::fmt(account.total()), account.is_locked())?;
+        if self.with_status {
+            write!(self.writer, ",\"status\":\"{}\"", account.status().as_str())?;
+        }
+        write!(self.writer, "}}")?;
+        if self.format == JsonFormat::Lines {
+            writeln!(self.writer)?;
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match (self.format, self.written) {
+            (JsonFormat::Array, 0) => writeln!(self.writer, "]")?,
+            (JsonFormat::Array, _) => writeln!(self.writer, "\n]")?,
+            (JsonFormat::Lines, _) => ()
+        }
+        self.writer.flush()
+    }
+}
+
+// Collects the accounts in memory. The clones share the collected accounts, so that a clone can
+// be handed to the factory and the accounts read from another one after the run.
+#[derive(Debug, Clone, Default)]
+pub struct Collector {
+    accounts: Arc<Mutex<Vec<Account>>>,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Collector::default()
+    }
+
+    // A factory of sinks collecting into this collector.
+    pub fn factory(&self) -> AccountSinkFactory {
+        let collector = self.clone();
+        Arc::new(move || Ok(Box::new(collector.clone())))
+    }
+
+    // Takes the accounts collected so far.
+    pub fn take(&self) -> Vec<Account> {
+        std::mem::take(&mut self.accounts.lock().unwrap())
+    }
+}
+
+impl AccountSink for Collector {
+    fn account(&mut self, account: &Account) -> io::Result<()> {
+        self.accounts.lock().unwrap().push(account.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use crate::amount::{from_f64, ZERO};
+
+    #[test]
+    fn test_output_sinks() {
+        let accounts = vec![Account::new(1, from_f64(1.5), ZERO, Status::Active),
+                            Account::new(2, from_f64(1.0), from_f64(0.5), Status::Locked)];
+        let collector = Collector::new();
+        write(&collector.factory(), &accounts, false).unwrap();
+        assert_eq!(collector.take(), accounts);
+        assert!(collector.take().is_empty());
+
+        let mut out = Vec::new();
+        let mut sink = CsvSink::new(&mut out, &Config::default());
+        sink.start(true).unwrap();
+        sink.account(&accounts[0]).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,available,held,total,locked,status\ndefault,1,1.5000,0.0000,1.5000,false,active\n");
+    }
+
+    #[test]
+    fn test_write_all() {
+        let accounts = vec![Account::new(1, from_f64(1.5), ZERO, Status::Active)];
+        let (first, second) = (Collector::new(), Collector::new());
+        let broken: AccountSinkFactory = Arc::new(|| Err(io::Error::other("unreachable")));
+        let results = write_all(&[first.factory(), broken, second.factory()], &accounts, false);
+        assert_eq!(results.iter().map(Result::is_ok).collect::<Vec<bool>>(), vec![true, false, true]);
+        // The broken sink did not hold back the others.
+        assert_eq!((first.take(), second.take()), (accounts.clone(), accounts));
+    }
+
+    #[test]
+    fn test_json_sink() {
+        let accounts = vec![Account::new(1, from_f64(1.5), ZERO, Status::Active),
+                            Account::new(2, from_f64(1.0), from_f64(0.5), Status::Locked)];
+        let json = |format, config: &Config, accounts: &[Account], with_tenant| {
+            let mut out = Vec::new();
+            let mut sink = JsonSink::new(&mut out, format, config);
+            sink.start(with_tenant).unwrap();
+            for account in accounts {
+                sink.account(account).unwrap();
+            }
+            sink.finish().unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(json(JsonFormat::Array, &Config::default(), &accounts, false),
+                   "[\n{\"client\":\"1\",\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false,\"status\":\"active\"},\n\
+                    {\"client\":\"2\",\"available\":1.0000,\"held\":0.5000,\"total\":1.5000,\"locked\":true,\"status\":\"locked\"}\n]\n");
+        assert_eq!(json(JsonFormat::Array, &Config::default(), &[], false), "[]\n");
+
+        let mut config = Config::default();
+        config.rules.spec = true;
+        assert_eq!(json(JsonFormat::Lines, &config, &accounts, true),
+                   "{\"tenant\":\"default\",\"client\":\"1\",\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false}\n\
+                    {\"tenant\":\"default\",\"client\":\"2\",\"available\":1.0000,\"held\":0.5000,\"total\":1.5000,\"locked\":true}\n");
+    }
+}
@@ -0,0 +1,201 @@
+// Partitioned outputs, for very large client populations: the accounts and the audit trail are
+// split into one file per partition of the clients, written in parallel, so that they can be
+// loaded in parallel downstream.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::account::Account;
+use crate::audit::{AuditFile, Entry, Sink};
+use crate::config::Config;
+use crate::digest::hash64;
+use crate::report;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    // Contiguous ranges of client ids, the same for every tenant.
+    Range,
+    // A hash of the tenant and of the client id, spreading uneven client ids evenly.
+    Hash,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partitioning {
+    pub count: usize,
+    pub key: Key,
+    // Partition the audit trail too.
+    pub audit: bool,
+}
+
+impl Default for Partitioning {
+    fn default() -> Self {
+        Partitioning { count: 16, key: Key::Range, audit: false }
+    }
+}
+
+impl Partitioning {
+    pub fn partition(&self, tenant: Option<&str>, client_id: u16) -> usize {
+        match self.key {
+            Key::Range => client_id as usize * self.count / (u16::MAX as usize + 1),
+            Key::Hash => {
+                let mut data = tenant.unwrap_or("").as_bytes().to_vec();
+                data.push(b',');
+                data.extend_from_slice(&client_id.to_be_bytes());
+                (hash64(&data) % self.count as u64) as usize
+            }
+        }
+    }
+
+    // Path of a partition file, e.g. `accounts-03.csv`, numbered with as many digits as the last
+    // partition needs.
+    pub fn path(&self, dir: &Path, name: &str, partition: usize) -> PathBuf {
+        let width = (self.count - 1).to_string().len();
+        dir.join(format!("{}-{:0width$}.csv", name, partition, width = width))
+    }
+
+    // Every file of the partitioned outputs, the Merkle roots of a chained audit trail included.
+    pub fn paths(&self, dir: &Path, chained: bool) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = (0..self.count).map(|partition| self.path(dir, "accounts", partition)).collect();
+        if self.audit {
+            for partition in 0..self.count {
+                let path = self.path(dir, "audit", partition);
+                paths.push(path.clone());
+                if chained {
+                    let mut root_path = path.into_os_string();
+                    root_path.push(".root");
+                    paths.push(PathBuf::from(root_path));
+                }
+            }
+        }
+        paths
+    }
+}
+
+// Writes the accounts of every partition to its `accounts-<n>.csv` file, under `dir`, the
+// partitions being written in parallel. Empty partitions get a file with the header only.
+pub fn write_accounts(dir: &Path, accounts: &[Account], config: &Config, with_tenant: bool) -> io::Result<()> {
+    let partitioning = &config.partitioning;
+    let mut partitions = vec![Vec::new(); partitioning.count];
+    for account in accounts {
+        partitions[partitioning.partition(account.tenant(), account.client_id())].push(account.clone());
+    }
+
+    fs::create_dir_all(dir)?;
+    thread::scope(|scope| {
+        let handles: Vec<_> = partitions.iter().enumerate()
+            .map(|(partition, accounts)| {
+                let path = partitioning.path(dir, "accounts", partition);
+                scope.spawn(move || report::write_file(&path, |writer| {
+                    report::write_accounts(writer, accounts, config, with_tenant)
+                }))
+            })
+            .collect();
+        handles.into_iter()
+            .try_for_each(|handle| handle.join().unwrap_or_else(|_| Err(io::Error::other("partition writer panicked"))))
+    })
+}
+
+// Audit sink splitting the trail into one `audit-<n>.csv` file per partition, every one of them
+// written by its own thread. The partitions of a chained trail are chained on their own.
+pub struct PartitionedAudit {
+    partitioning: Partitioning,
+    senders: Vec<Sender<Entry>>,
+    handles: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl PartitionedAudit {
+    pub fn create(dir: &Path, partitioning: &Partitioning, chained: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let (mut senders, mut handles) = (Vec::new(), Vec::new());
+        for partition in 0..partitioning.count {
+            let path = partitioning.path(dir, "audit", partition);
+            let mut file = if chained { AuditFile::create_chained(&path)? } else { AuditFile::create(&path)? };
+            let (sender, receiver) = channel::<Entry>();
+            handles.push(thread::spawn(move || {
+                let mut result = Ok(());
+                for entry in receiver {
+                    result = result.and(file.record(&entry));
+                }
+                result.and(file.finish())
+            }));
+            senders.push(sender);
+        }
+        Ok(PartitionedAudit { partitioning: partitioning.clone(), senders, handles })
+    }
+}
+
+impl Sink for PartitionedAudit {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        let partition = self.partitioning.partition(entry.tenant.as_deref(), entry.client_id);
+        self.senders[partition].send(entry.clone()).map_err(|_| io::Error::other("partition writer stopped"))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.senders.clear();
+        let mut result = Ok(());
+        for handle in self.handles.drain(..) {
+            result = result.and(handle.join().unwrap_or_else(|_| Err(io::Error::other("partition writer panicked"))));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use crate::audit::{Balance, Outcome};
+    use crate::transaction::Type;
+    use crate::amount::{from_f64, ZERO};
+
+    #[test]
+    fn test_partition() {
+        let partitioning = Partitioning { count: 4, ..Partitioning::default() };
+        assert_eq!(partitioning.partition(None, 0), 0);
+        assert_eq!(partitioning.partition(Some("acme"), 16383), 0);
+        assert_eq!(partitioning.partition(None, 16384), 1);
+        assert_eq!(partitioning.partition(None, u16::MAX), 3);
+
+        let partitioning = Partitioning { key: Key::Hash, ..partitioning };
+        assert!((0..1000).all(|client_id| partitioning.partition(None, client_id) < 4));
+        assert_eq!(partitioning.partition(Some("acme"), 7), partitioning.partition(Some("acme"), 7));
+        assert_eq!(partitioning.path(Path::new("out"), "accounts", 3), Path::new("out/accounts-3.csv"));
+        assert_eq!(Partitioning::default().path(Path::new("out"), "audit", 3), Path::new("out/audit-03.csv"));
+    }
+
+    #[test]
+    fn test_partitioned_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let partitioning = Partitioning { count: 2, key: Key::Range, audit: true };
+        let config = Config { partitioning: partitioning.clone(), ..Config::default() };
+        let accounts = vec![Account::new(1, from_f64(1.0), ZERO, Status::Active),
+                            Account::new(40000, from_f64(2.0), ZERO, Status::Active)];
+        write_accounts(dir.path(), &accounts, &config, false).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("accounts-0.csv")).unwrap(),
+                   "client,available,held,total,locked,status\n1,1.0000,0.0000,1.0000,false,active\n");
+        assert_eq!(fs::read_to_string(dir.path().join("accounts-1.csv")).unwrap(),
+                   "client,available,held,total,locked,status\n40000,2.0000,0.0000,2.0000,false,active\n");
+
+        let mut audit = PartitionedAudit::create(dir.path(), &partitioning, false).unwrap();
+        for client_id in [1, 40000, 2] {
+            audit.record(&Entry { tenant: None, client_id, tx_id: 1, ttype: Type::Deposit, amount: Some(from_f64(1.0)),
+                                  outcome: Outcome::Applied, note: None, before: Balance::default(),
+                                  after: Balance { available: from_f64(1.0), held: ZERO, total: from_f64(1.0) }, timestamp: None,
+                                  locked: false,
+                                  previous_status: Status::Active, status: Status::Active, record: None, source: None }).unwrap();
+        }
+        audit.finish().unwrap();
+        let clients = |partition: usize| -> Vec<String> {
+            fs::read_to_string(partitioning.path(dir.path(), "audit", partition)).unwrap().lines().skip(1)
+                .map(|line| line.split(',').nth(1).unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(clients(0), vec!["1", "2"]);
+        assert_eq!(clients(1), vec!["40000"]);
+        assert_eq!(partitioning.paths(dir.path(), false).len(), 4);
+        assert_eq!(partitioning.paths(dir.path(), true).len(), 6);
+    }
+}
@@ -0,0 +1,138 @@
+// Dry runs against the state of an earlier run. A plan applies a new batch of transactions to the
+// resumed accounts without writing any output, and lists how every account would change, for the
+// operators to approve before the batch is applied for real.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::account::{Account, Status};
+use crate::amount::{self, Amount, ZERO};
+use crate::config::Config;
+use crate::input::Input;
+use crate::report::RunReport;
+use crate::state::State;
+use crate::transaction::{self, resume};
+
+#[derive(Debug)]
+pub enum Error {
+    Run(transaction::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// How an account would change, its funds being given as deltas. New accounts have no status
+// before the batch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub tenant: Option<String>,
+    pub client_id: u16,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub before: Option<Status>,
+    pub after: Status,
+}
+
+impl Change {
+    // Whether the batch would lock the account.
+    pub fn locks(&self) -> bool {
+        self.after == Status::Locked && self.before != Some(Status::Locked)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    pub report: RunReport,
+}
+
+impl Plan {
+    pub fn locks(&self) -> usize {
+        self.changes.iter().filter(|change| change.locks()).count()
+    }
+}
+
+// Applies `input` to the accounts of `state` with the rules of `config`, without writing any of
+// its outputs.
+pub fn plan(state: State, input: Input, config: &Config) -> Result<Plan> {
+    let before = state.accounts.clone();
+    let mut report = resume(state, input, &config.engine()).map_err(Error::Run)?;
+    let after = std::mem::take(&mut report.final_accounts);
+    Ok(Plan { changes: diff(&before, &after), report })
+}
+
+// The accounts that differ between `before` and `after`, ordered by tenant and client.
+pub fn diff(before: &[Account], after: &[Account]) -> Vec<Change> {
+    let before: HashMap<(Option<&str>, u16), &Account> = before.iter()
+        .map(|account| ((account.tenant(), account.client_id()), account))
+        .collect();
+    let mut changes: Vec<Change> = after.iter().filter_map(|account| {
+        let previous = before.get(&(account.tenant(), account.client_id()));
+        let funds = |account: Option<&&Account>| match account {
+            Some(account) => (account.available(), account.held(), account.total()),
+            None => (ZERO, ZERO, ZERO)
+        };
+        let (available, held, total) = funds(previous);
+        let change = Change {
+            tenant: account.tenant().map(String::from),
+            client_id: account.client_id(),
+            available: account.available() - available,
+            held: account.held() - held,
+            total: account.total() - total,
+            before: previous.map(|previous| previous.status()),
+            after: account.status(),
+        };
+        let unchanged = change.before == Some(change.after) && change.available == ZERO && change.held == ZERO
+            && change.total == ZERO;
+        if unchanged { None } else { Some(change) }
+    }).collect();
+    changes.sort_by(|a, b| (&a.tenant, a.client_id).cmp(&(&b.tenant, b.client_id)));
+    changes
+}
+
+// Writes the changes with respect to the `tenant,client,available,held,total,before,after`
+// schema, the funds being deltas and the statuses those before and after the batch.
+pub fn write<W: Write>(writer: &mut W, changes: &[Change], config: &Config) -> io::Result<()> {
+    writeln!(writer, "tenant,client,available,held,total,before,after")?;
+    for change in changes {
+        writeln!(writer, "{},{},{},{},{},{},{}", change.tenant.as_deref().unwrap_or(""),
+                 config.client_label(change.client_id), amount::fmt(change.available), amount::fmt(change.held),
+                 amount::fmt(change.total), change.before.map(|status| status.as_str()).unwrap_or(""),
+                 change.after.as_str())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::amount::{from_f64, ZERO};
+
+    #[test]
+    fn test_plan() {
+        let state = State {
+            accounts: vec![Account::new(1, from_f64(5.0), ZERO, Status::Active),
+                           Account::new(2, from_f64(3.0), ZERO, Status::Active), Account::new(3, from_f64(1.0), ZERO, Status::Active)],
+            history: vec![Tx::deposit(2, 1).amount(3.0).build()],
+            checkpoint: None,
+        };
+        let input = "type,client,tx,amount\n\
+                     deposit,1,2,1.0\n\
+                     dispute,2,1,\n\
+                     chargeback,2,1,\n\
+                     deposit,4,3,2.5\n";
+        let config = Config::default();
+        let plan = plan(state, Input::from(input.as_bytes()), &config).unwrap();
+        assert_eq!(plan.report.records, 4);
+        assert_eq!(plan.locks(), 1);
+
+        let mut out = Vec::new();
+        write(&mut out, &plan.changes, &config).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,available,held,total,before,after\n\
+                    ,1,1.0000,0.0000,1.0000,active,active\n\
+                    ,2,-3.0000,0.0000,-3.0000,active,locked\n\
+                    ,4,2.5000,0.0000,2.5000,,active\n");
+    }
+}
@@ -0,0 +1,294 @@
+// Declarative acceptance rules, read from a policy file, that the account admins evaluate before
+// applying every transaction. A rule is an action taken when all of its conditions hold, over the
+// transaction and the running totals of its client, e.g.
+// `large_withdrawal: reject type=withdrawal amount>10000 client=1|2|3`. The attributes the reference
+// data gives the clients are conditions too, e.g. `attr.tier=gold`.
+
+use std::fs;
+use std::path::Path;
+
+use crate::amount::{self, Amount};
+use crate::reference::Attributes;
+use crate::transaction::Type;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    // The rule on this line, counting from 1, could not be parsed.
+    InvalidRule(usize),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    // The transaction is applied, without evaluating the rules that follow.
+    Accept,
+    Reject,
+    // The transaction is applied, and noted as flagged by the rule in the audit trail. The rules
+    // that follow are still evaluated.
+    Flag,
+    // The transaction is not applied, but routed to the dead letters, for review.
+    Route,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+impl Op {
+    fn holds<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Ge => left >= right,
+            Op::Gt => left > right
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    // The transaction is, or with `false` is not, of one of the types.
+    Type(Vec<Type>, bool),
+    Client(Vec<u16>, bool),
+    Tenant(Vec<String>, bool),
+    // The named attribute of the client is one of the values.
+    Attribute(String, Vec<String>, bool),
+    Amount(Op, Amount),
+    // Running totals of the client: the deposits and the withdrawals applied, and the number of
+    // transactions handled, before this transaction.
+    Deposited(Op, Amount),
+    Withdrawn(Op, Amount),
+    Transactions(Op, u64),
+}
+
+// What the conditions are evaluated against.
+pub struct Facts<'a> {
+    pub ttype: &'a Type,
+    pub tenant: Option<&'a str>,
+    pub client_id: u16,
+    // Attributes of the client in the reference data, if any.
+    pub attributes: Option<&'a Attributes>,
+    pub amount: Option<Amount>,
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    pub transactions: u64,
+}
+
+impl Condition {
+    fn parse(spec: &str) -> Option<Condition> {
+        let idx = spec.find(['<', '>', '=', '!'])?;
+        let (field, rest) = spec.split_at(idx);
+        let (op, value) = match rest.as_bytes() {
+            [b'<', b'=', ..] => (Op::Le, &rest[2..]),
+            [b'>', b'=', ..] => (Op::Ge, &rest[2..]),
+            [b'!', b'=', ..] => (Op::Ne, &rest[2..]),
+            [b'<', ..] => (Op::Lt, &rest[1..]),
+            [b'>', ..] => (Op::Gt, &rest[1..]),
+            [b'=', ..] => (Op::Eq, &rest[1..]),
+            _ => return None
+        };
+        let amount = || amount::parse(value);
+        let set = |op: Op| match op {
+            Op::Eq => Some(true),
+            Op::Ne => Some(false),
+            _ => None
+        };
+        let condition = match field {
+            "type" => {
+                let types: Vec<Type> = value.split('|').map(Type::from).collect();
+                if types.contains(&Type::ERR) {
+                    return None;
+                }
+                Condition::Type(types, set(op)?)
+            }
+            "client" => {
+                let clients: Option<Vec<u16>> = value.split('|').map(|client| client.parse().ok()).collect();
+                Condition::Client(clients?, set(op)?)
+            }
+            "tenant" => Condition::Tenant(value.split('|').map(String::from).collect(), set(op)?),
+            "amount" => Condition::Amount(op, amount()?),
+            "deposited" => Condition::Deposited(op, amount()?),
+            "withdrawn" => Condition::Withdrawn(op, amount()?),
+            "transactions" => Condition::Transactions(op, value.parse().ok()?),
+            _ => {
+                let name = field.strip_prefix("attr.").filter(|name| !name.is_empty())?;
+                Condition::Attribute(name.to_string(), value.split('|').map(String::from).collect(), set(op)?)
+            }
+        };
+        Some(condition)
+    }
+
+    fn holds(&self, facts: &Facts) -> bool {
+        match self {
+            Condition::Type(types, is) => types.contains(facts.ttype) == *is,
+            Condition::Client(clients, is) => clients.contains(&facts.client_id) == *is,
+            Condition::Tenant(tenants, is) => tenants.iter().any(|tenant| Some(tenant.as_str()) == facts.tenant) == *is,
+            // Clients without the attribute never have one of the values.
+            Condition::Attribute(name, values, is) => {
+                let value = facts.attributes.and_then(|attributes| attributes.get(name));
+                values.iter().any(|expected| Some(expected.as_str()) == value) == *is
+            }
+            // Transactions without an amount never match the amount conditions.
+            Condition::Amount(op, amount) => facts.amount.is_some_and(|value| op.holds(value, *amount)),
+            Condition::Deposited(op, amount) => op.holds(facts.deposited, *amount),
+            Condition::Withdrawn(op, amount) => op.holds(facts.withdrawn, *amount),
+            Condition::Transactions(op, count) => op.holds(facts.transactions, *count)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    name: String,
+    action: Action,
+    conditions: Vec<Condition>,
+}
+
+impl Rule {
+    // Parses a `<name>: <action> <condition> <condition>...` rule, a rule without conditions
+    // matching every transaction.
+    fn parse(line: &str) -> Option<Rule> {
+        let (name, rest) = line.split_once(':')?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
+        let mut words = rest.split_whitespace();
+        let action = match words.next()? {
+            "accept" => Action::Accept,
+            "reject" => Action::Reject,
+            "flag" => Action::Flag,
+            "route" => Action::Route,
+            _ => return None
+        };
+        let conditions = words.map(Condition::parse).collect::<Option<Vec<_>>>()?;
+        Some(Rule { name: name.to_string(), action, conditions })
+    }
+}
+
+// Outcome of the evaluation of the rules for a transaction.
+#[derive(Debug, PartialEq)]
+pub struct Verdict<'a> {
+    // Action of the first accepting, rejecting or routing rule that matched, `Accept` if none did.
+    pub action: Action,
+    pub rule: Option<&'a str>,
+    // Flagging rules that matched before.
+    pub flags: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    // One rule per line, in evaluation order. Blank lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> Result<Policy> {
+        let mut rules = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            rules.push(Rule::parse(line).ok_or(Error::InvalidRule(idx + 1))?);
+        }
+        Ok(Policy { rules })
+    }
+
+    pub fn read_file(path: &Path) -> Result<Policy> {
+        Policy::parse(&fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    // Whether some rule routes transactions to the dead letters.
+    pub fn routes(&self) -> bool {
+        self.rules.iter().any(|rule| rule.action == Action::Route)
+    }
+
+    pub fn evaluate(&self, facts: &Facts) -> Verdict<'_> {
+        let mut flags = Vec::new();
+        for rule in self.rules.iter().filter(|rule| rule.conditions.iter().all(|condition| condition.holds(facts))) {
+            if rule.action == Action::Flag {
+                flags.push(rule.name.as_str());
+            } else {
+                return Verdict { action: rule.action, rule: Some(&rule.name), flags };
+            }
+        }
+        Verdict { action: Action::Accept, rule: None, flags }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::{from_f64, ZERO};
+
+    fn facts(ttype: &Type, client_id: u16, amount: Option<Amount>, withdrawn: Amount) -> Facts<'_> {
+        Facts { ttype, tenant: None, client_id, attributes: None, amount, deposited: ZERO, withdrawn, transactions: 3 }
+    }
+
+    #[test]
+    fn test_policy_parse() {
+        let policy = Policy::parse("# Acceptance rules\n\n\
+                                    vip: accept client=7\n\
+                                    large: reject type=withdrawal amount>10000 client=1|2  # listed clients\n").unwrap();
+        assert_eq!(policy.rules.len(), 2);
+        assert_eq!(policy.rules[1].conditions[0], Condition::Type(vec![Type::Withdrawal], true));
+        assert_eq!(policy.rules[1].conditions[2], Condition::Client(vec![1, 2], true));
+        assert!(!policy.routes());
+
+        for line in ["reject amount>1", "rule: drop", "rule: reject kind=deposit", "rule: reject type=gift",
+                     "rule: reject type>deposit", "rule: reject amount>ten", "two words: accept"].iter() {
+            assert!(matches!(Policy::parse(&format!("ok: accept\n{}", line)), Err(Error::InvalidRule(2))), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_policy_evaluate() {
+        let policy = Policy::parse("big: flag amount>=100\n\
+                                    vip: accept client=7\n\
+                                    large: reject type=withdrawal amount>1000\n\
+                                    heavy: route type=withdrawal withdrawn>5000\n\
+                                    second: flag transactions>2 type!=deposit\n").unwrap();
+        assert!(policy.routes());
+        let withdrawal = Type::Withdrawal;
+        let verdict = policy.evaluate(&facts(&withdrawal, 1, Some(from_f64(2000.0)), ZERO));
+        assert_eq!(verdict, Verdict { action: Action::Reject, rule: Some("large"), flags: vec!["big"] });
+        let verdict = policy.evaluate(&facts(&withdrawal, 7, Some(from_f64(2000.0)), ZERO));
+        assert_eq!(verdict, Verdict { action: Action::Accept, rule: Some("vip"), flags: vec!["big"] });
+        let verdict = policy.evaluate(&facts(&withdrawal, 1, Some(from_f64(10.0)), from_f64(6000.0)));
+        assert_eq!(verdict, Verdict { action: Action::Route, rule: Some("heavy"), flags: vec![] });
+        let verdict = policy.evaluate(&facts(&withdrawal, 1, Some(from_f64(10.0)), ZERO));
+        assert_eq!(verdict, Verdict { action: Action::Accept, rule: None, flags: vec!["second"] });
+        let dispute = Type::Dispute;
+        assert_eq!(policy.evaluate(&facts(&dispute, 1, None, ZERO)).flags, vec!["second"]);
+    }
+
+    #[test]
+    fn test_policy_attributes() {
+        let policy = Policy::parse("waived: reject type=fee attr.tier=gold|platinum
+                                    review: route attr.country!=RO
+").unwrap();
+        assert!(matches!(Policy::parse("rule: reject attr.=gold"), Err(Error::InvalidRule(1))));
+        assert!(matches!(Policy::parse("rule: reject attr.tier>gold"), Err(Error::InvalidRule(1))));
+        let fee = Type::Fee;
+        let gold = Attributes::new(vec![(String::from("tier"), String::from("gold")),
+                                        (String::from("country"), String::from("RO"))]);
+        let verdict = policy.evaluate(&Facts { attributes: Some(&gold), ..facts(&fee, 1, Some(from_f64(1.0)), ZERO) });
+        assert_eq!(verdict.rule, Some("waived"));
+        let deposit = Type::Deposit;
+        let verdict = policy.evaluate(&Facts { attributes: Some(&gold), ..facts(&deposit, 1, Some(from_f64(1.0)), ZERO) });
+        assert_eq!(verdict.action, Action::Accept);
+        // A client without attributes is not in RO.
+        assert_eq!(policy.evaluate(&facts(&fee, 1, Some(from_f64(1.0)), ZERO)).rule, Some("review"));
+    }
+}
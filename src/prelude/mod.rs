@@ -0,0 +1,48 @@
+// The supported public surface of the crate, for the applications embedding the processor, e.g.
+// `use transaction_processor::prelude::*;`. These items only break with a new major version, while
+// the other modules may change in any release, the hidden ones being the internals of the binary.
+// The errors, the transaction types, the account statuses, the `Config` and the `RunReport` are
+// non exhaustive, so that adding to them is not a breaking change: the matches on them need a
+// wildcard arm, and the configs are built with `ProcessorBuilder` or from `Config::default()`.
+
+pub use crate::account::{Account, Error as AccountError, ProjectedEffect, Rules, Status};
+pub use crate::adjustment::Adjustments;
+pub use crate::amount::Amount;
+pub use crate::audit::{Entry, Outcome, Sink};
+pub use crate::builder::{Script, Tx};
+pub use crate::clock::{Clock, MockClock, SystemClock};
+pub use crate::config::Config;
+pub use crate::engine::Engine;
+pub use crate::input::{Error as InputError, Input};
+pub use crate::output::{AccountSink, Collector, CsvSink, JsonFormat, JsonSink};
+pub use crate::processor::ProcessorBuilder;
+pub use crate::report::RunReport;
+pub use crate::sources::Sources;
+pub use crate::state::{Error as StateError, State};
+pub use crate::transaction::{drill, drill_source, input_source, resume, resume_source, ChannelSource,
+                             Error as TxError, Transaction, TransactionSource, Type};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_prelude() {
+        let config = Config::default();
+        let mut engine = Engine::new(&config);
+        for tx in Script::client(1).deposit(5).withdrawal(2) {
+            engine.apply(tx).unwrap();
+        }
+        assert!(matches!(engine.apply(Tx::withdrawal(1, 3).amount(4).build()), Err(AccountError::DepositTooLow)));
+
+        let (sender, receiver) = mpsc::channel();
+        for tx in Script::client(1).deposit(5).withdrawal(2) {
+            sender.send(tx).unwrap();
+        }
+        drop(sender);
+        let run_report: RunReport = drill_source(ChannelSource::new(receiver), &config).unwrap();
+        let account: &Account = &run_report.final_accounts[0];
+        assert_eq!(account.available(), engine.account(None, 1).unwrap().available());
+    }
+}
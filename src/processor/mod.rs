@@ -0,0 +1,117 @@
+// Fluent construction of the config of a run, for the applications embedding the processor, which
+// can not write a `Config` out field by field as the knobs keep being added to it, e.g.
+// `ProcessorBuilder::new().rules(rules).drill_source(source)`. The knobs it has no setter for are
+// set on the config of `build`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::account::Rules;
+use crate::audit::SinkFactory;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::output::AccountSinkFactory;
+use crate::report::RunReport;
+use crate::state::State;
+use crate::transaction::{self, Result, TransactionSource};
+
+#[derive(Default)]
+pub struct ProcessorBuilder {
+    config: Config,
+}
+
+impl ProcessorBuilder {
+    pub fn new() -> Self {
+        ProcessorBuilder::default()
+    }
+
+    pub fn multi_threaded_runtime(mut self, multi_threaded_runtime: bool) -> Self {
+        self.config.multi_threaded_runtime = multi_threaded_runtime;
+        self
+    }
+
+    pub fn tx_delay(mut self, tx_delay: Duration) -> Self {
+        self.config.tx_delay = Some(tx_delay);
+        self
+    }
+
+    pub fn rules(mut self, rules: Rules) -> Self {
+        self.config.rules = rules;
+        self
+    }
+
+    pub fn reject_duplicates(mut self, reject_duplicates: bool) -> Self {
+        self.config.reject_duplicates = reject_duplicates;
+        self
+    }
+
+    pub fn amount_scale(mut self, amount_scale: usize) -> Self {
+        self.config.amount_scale = amount_scale;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.config.clock = clock;
+        self
+    }
+
+    // Adds an observer of the run, getting the audit entry of every handled transaction.
+    pub fn observer(mut self, observer: SinkFactory) -> Self {
+        self.config.observers.push(observer);
+        self
+    }
+
+    // Adds a sink the final accounts are streamed into at the end of the run.
+    pub fn account_sink(mut self, account_sink: AccountSinkFactory) -> Self {
+        self.config.account_sinks.push(account_sink);
+        self
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
+
+    pub fn engine(self) -> Engine {
+        Engine::new(&self.config)
+    }
+
+    pub fn drill_source<S: TransactionSource>(self, source: S) -> Result<RunReport> {
+        transaction::drill_source(source, &self.config)
+    }
+
+    pub fn resume_source<S: TransactionSource>(self, state: State, source: S) -> Result<RunReport> {
+        transaction::resume_source(state, source, &self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::from_f64;
+    use crate::builder::Script;
+    use crate::transaction::ChannelSource;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_processor_builder() {
+        let config = ProcessorBuilder::new().multi_threaded_runtime(false).reject_duplicates(false).build();
+        assert!(!config.multi_threaded_runtime && !config.reject_duplicates);
+        assert!(config.observers.is_empty() && config.tx_delay.is_none());
+
+        let (sender, receiver) = mpsc::channel();
+        for tx in Script::client(1).deposit(5).withdrawal(2) {
+            sender.send(tx).unwrap();
+        }
+        drop(sender);
+        let run_report = ProcessorBuilder::new().multi_threaded_runtime(false)
+            .drill_source(ChannelSource::new(receiver)).unwrap();
+        assert_eq!(run_report.final_accounts[0].available(), from_f64(3.0));
+
+        let mut engine = ProcessorBuilder::new().rules(Rules { redisputes: true, ..Rules::default() }).engine();
+        for tx in Script::client(1).deposit(5).dispute(1).resolve(1).dispute(1) {
+            engine.apply(tx).unwrap();
+        }
+        assert_eq!(engine.account(None, 1).unwrap().held(), from_f64(5.0));
+    }
+}
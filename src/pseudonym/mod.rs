@@ -0,0 +1,71 @@
+// Client id pseudonymization for outputs that are shared outside of the settlement team.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::digest::{hmac_sha256, to_hex};
+
+// Number of digest bytes kept in a pseudonym. 8 bytes keep collisions negligible for the u16
+// client id space while keeping the report rows short.
+const PSEUDONYM_LEN: usize = 8;
+
+// Maps real client ids through HMAC-SHA256 keyed with a secret, so the same client always gets
+// the same pseudonym for a given key, but the real id can not be recovered without the key or
+// the separately kept mapping file.
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+    seen: Mutex<BTreeMap<u16, String>>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &[u8]) -> Self {
+        Pseudonymizer { key: key.to_vec(), seen: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn pseudonym(&self, client_id: u16) -> String {
+        let pseudonym = to_hex(&hmac_sha256(&self.key, &client_id.to_be_bytes())[..PSEUDONYM_LEN]);
+        self.seen.lock().unwrap().insert(client_id, pseudonym.clone());
+        pseudonym
+    }
+
+    // Writes the reversible `pseudonym,client` mapping for every client pseudonymized so far.
+    pub fn write_mapping<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "pseudonym,client")?;
+        for (client_id, pseudonym) in self.seen.lock().unwrap().iter() {
+            writeln!(writer, "{},{}", pseudonym, client_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_mapping_file(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        self.write_mapping(&mut file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudonym_is_stable_per_key() {
+        let pseudonymizer = Pseudonymizer::new(b"secret");
+        assert_eq!(pseudonymizer.pseudonym(1), pseudonymizer.pseudonym(1));
+        assert_ne!(pseudonymizer.pseudonym(1), pseudonymizer.pseudonym(2));
+        assert_ne!(pseudonymizer.pseudonym(1), Pseudonymizer::new(b"other").pseudonym(1));
+        assert_eq!(pseudonymizer.pseudonym(1).len(), PSEUDONYM_LEN * 2);
+    }
+
+    #[test]
+    fn test_pseudonym_mapping() {
+        let pseudonymizer = Pseudonymizer::new(b"secret");
+        let first = pseudonymizer.pseudonym(2);
+        let second = pseudonymizer.pseudonym(1);
+        let mut out = Vec::new();
+        pseudonymizer.write_mapping(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   format!("pseudonym,client\n{},1\n{},2\n", second, first));
+    }
+}
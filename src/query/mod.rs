@@ -0,0 +1,133 @@
+// Read-only queries over the state a completed run saved, e.g. for the support staff looking into
+// an account after the run, without any way of applying transactions. The requests are handled
+// one at a time, with the same JSON objects as the accounts output:
+// * `GET /accounts/<client>` returns the account of a client, `?tenant=<name>` naming the tenant
+//   of the multi-tenant states.
+// * `GET /locked` returns the locked accounts.
+// * `GET /disputes` returns the disputes still open, with the account they were opened against.
+// The requests must carry the API token of the config, if it has one, as the ones of the server.
+
+use std::io;
+use std::net::TcpListener;
+
+use crate::account::{Account, Status};
+use crate::amount;
+use crate::config::Config;
+use crate::notify::json_string;
+use crate::output::JsonFormat;
+use crate::server::{json, read_request, unauthorized, write_response, ReadError, Request, Response};
+use crate::state::State;
+
+// Answers the queries received by the listener over the accounts of `state`, until the listener
+// fails.
+pub fn serve(listener: TcpListener, state: &State, config: &Config) -> io::Result<()> {
+    loop {
+        let (mut stream, _) = listener.accept()?;
+        let response = match read_request(&mut stream) {
+            Ok(request) if !request.is_authorized(config) => unauthorized(),
+            Ok(request) => handle(&request, state, config),
+            Err(ReadError::Refused(response)) => response,
+            Err(ReadError::Io(err)) => {
+                log::debug!("Dropped a connection: {}.", err);
+                continue;
+            }
+        };
+        let _ = write_response(&mut stream, &response);
+    }
+}
+
+fn handle(request: &Request, state: &State, config: &Config) -> Response {
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    let tenant = query.split('&').find_map(|param| param.strip_prefix("tenant="));
+    let path: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let tenanted = state.accounts.iter().any(|account| account.tenant().is_some());
+    match (request.method.as_str(), path.as_slice()) {
+        ("GET", ["accounts", client]) => {
+            let found = state.accounts.iter()
+                .find(|account| account.tenant() == tenant && config.client_label(account.client_id()) == *client);
+            match found {
+                Some(account) => {
+                    Response::new("200 OK", json(config, JsonFormat::Lines, tenanted, Some(account)).trim_end().to_string())
+                }
+                None => Response::error("404 Not Found", "the client has no account")
+            }
+        }
+        ("GET", ["locked"]) => {
+            let locked = state.accounts.iter().filter(|account| account.status() == Status::Locked);
+            Response::new("200 OK", json(config, JsonFormat::Array, tenanted, locked))
+        }
+        ("GET", ["disputes"]) => Response::new("200 OK", disputes(&state.accounts, config)),
+        (_, ["accounts", _]) | (_, ["locked"]) | (_, ["disputes"]) => {
+            Response::error("405 Method Not Allowed", "the state can only be queried")
+        }
+        _ => Response::error("404 Not Found", "no such endpoint")
+    }
+}
+
+// The open disputes as a JSON array, with respect to the `tenant,client,tx,amount,reason` fields
+// of the open disputes report.
+fn disputes(accounts: &[Account], config: &Config) -> String {
+    let disputes: Vec<String> = accounts.iter()
+        .flat_map(|account| account.open_disputes().iter().map(move |dispute| (account, dispute)))
+        .map(|(account, dispute)| {
+            format!("{{\"tenant\":{},\"client\":{},\"tx\":{},\"amount\":{},\"reason\":{}}}", json_string(account.tenant()),
+                    json_string(Some(&config.client_label(account.client_id()))), dispute.tx_id,
+                    amount::fmt(dispute.amount), json_string(dispute.reason.as_deref()))
+        })
+        .collect();
+    format!("[{}]", disputes.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Input;
+    use crate::transaction::drill;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn request(port: u16, method: &str, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn test_query() {
+        let input = Input::from(&b"type,client,tx,amount,tenant\n\
+                                   deposit,1,1,2.0,acme\n\
+                                   dispute,1,1,,acme\n\
+                                   chargeback,1,1,,acme\n\
+                                   deposit,2,2,3.0,acme\n\
+                                   dispute,2,2,,acme\n\
+                                   deposit,1,3,1.0,globex\n"[..]);
+        let run_report = drill(input, &Config::default()).unwrap();
+        let state = State { accounts: run_report.final_accounts, ..State::default() };
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || serve(listener, &state, &Config::default()));
+
+        assert_eq!(request(port, "GET", "/accounts/1?tenant=globex"),
+                   (String::from("HTTP/1.1 200 OK"),
+                    String::from("{\"tenant\":\"globex\",\"client\":\"1\",\"available\":1.0000,\"held\":0.0000,\
+                                  \"total\":1.0000,\"locked\":false,\"status\":\"active\"}")));
+        assert_eq!(request(port, "GET", "/accounts/1").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(request(port, "GET", "/locked").1,
+                   "[\n{\"tenant\":\"acme\",\"client\":\"1\",\"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\
+                    \"locked\":true,\"status\":\"locked\"}\n]\n");
+        assert_eq!(request(port, "GET", "/disputes").1,
+                   "[{\"tenant\":\"acme\",\"client\":\"2\",\"tx\":2,\"amount\":3.0000,\"reason\":null}]");
+        assert_eq!(request(port, "POST", "/disputes").0, "HTTP/1.1 405 Method Not Allowed");
+        assert_eq!(request(port, "POST", "/transactions").0, "HTTP/1.1 404 Not Found");
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = Config { api_token: Some(String::from("secret")), ..Config::default() };
+        thread::spawn(move || serve(listener, &State::default(), &config));
+        assert_eq!(request(port, "GET", "/locked").0, "HTTP/1.1 401 Unauthorized");
+    }
+}
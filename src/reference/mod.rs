@@ -0,0 +1,156 @@
+// Reference data about the clients, e.g. their tier or their country, read from a lookup file.
+// The attributes of a client are joined to its transactions as they are parsed, so that the
+// policy rules can tell the clients apart by them, and they are appended to the reports about
+// the clients.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::alias::Aliases;
+use crate::transaction::is_safe_name;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The header line does not have a `client` column and uniquely named attributes.
+    InvalidHeader,
+    // The row on this line, counting from 1, does not have a known client and a value per column.
+    InvalidRow(usize),
+    // The client on this line was described before.
+    Duplicate(usize),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// Attributes of a client, shared by all of its transactions.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Attributes {
+    fields: Vec<(String, String)>,
+}
+
+impl Attributes {
+    pub fn new(fields: Vec<(String, String)>) -> Self {
+        Attributes { fields }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Reference {
+    names: Vec<String>,
+    clients: HashMap<(Option<String>, u16), Arc<Attributes>>,
+}
+
+impl Reference {
+    // Reads the reference data with respect to the `client,<attribute>...` schema, optionally
+    // with a `tenant` column, the rows without a tenant describing the client in every tenant.
+    // The clients are given as in the input, i.e. as external identifiers when the run has
+    // aliases. The values end up in the reports, so they obey the rules of tenant names, or are
+    // left empty.
+    pub fn parse<R: BufRead>(reader: R, aliases: Option<&Aliases>) -> Result<Reference> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or(Error::InvalidHeader)?.map_err(Error::Io)?;
+        let columns: Vec<&str> = header.trim().split(',').collect();
+        let position = |name: &str| columns.iter().position(|column| *column == name);
+        let (client, tenant) = (position("client").ok_or(Error::InvalidHeader)?, position("tenant"));
+        let is_attribute = |idx: usize| idx != client && Some(idx) != tenant;
+        let names: Vec<String> = columns.iter().enumerate()
+            .filter(|(idx, _)| is_attribute(*idx))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let invalid = |idx: usize, name: &String| name.is_empty() || !is_safe_name(name) || names[..idx].contains(name);
+        if names.iter().enumerate().any(|(idx, name)| invalid(idx, name)) {
+            return Err(Error::InvalidHeader);
+        }
+
+        let mut reference = Reference { names, clients: HashMap::new() };
+        for (idx, line) in lines.enumerate() {
+            // The header is the first line.
+            let number = idx + 2;
+            let line = line.map_err(Error::Io)?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values: Vec<&str> = line.split(',').map(str::trim).collect();
+            if values.len() != columns.len() || values.iter().any(|value| !is_safe_name(value)) {
+                return Err(Error::InvalidRow(number));
+            }
+            let client_id = match aliases {
+                Some(aliases) => aliases.internal(values[client]),
+                None => values[client].parse::<u16>().ok()
+            }.ok_or(Error::InvalidRow(number))?;
+            let fields = values.iter().enumerate()
+                .filter(|(idx, _)| is_attribute(*idx))
+                .map(|(idx, value)| (columns[idx].to_string(), value.to_string()))
+                .collect();
+            let tenant = tenant.map(|idx| values[idx]).filter(|tenant| !tenant.is_empty()).map(String::from);
+            if reference.clients.insert((tenant, client_id), Arc::new(Attributes { fields })).is_some() {
+                return Err(Error::Duplicate(number));
+            }
+        }
+        Ok(reference)
+    }
+
+    pub fn read_file(path: &Path, aliases: Option<&Aliases>) -> Result<Reference> {
+        Reference::parse(BufReader::new(File::open(path).map_err(Error::Io)?), aliases)
+    }
+
+    // Names of the attributes, in the order of the columns.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    // The attributes of the client of a tenant, or else those of the client in every tenant.
+    pub fn attributes(&self, tenant: Option<&str>, client_id: u16) -> Option<&Arc<Attributes>> {
+        tenant.and_then(|tenant| self.clients.get(&(Some(tenant.to_string()), client_id)))
+            .or_else(|| self.clients.get(&(None, client_id)))
+    }
+
+    // The values of the attributes of the client, in the order of the names, empty when the
+    // client is not described.
+    pub fn values(&self, tenant: Option<&str>, client_id: u16) -> Vec<&str> {
+        let attributes = self.attributes(tenant, client_id);
+        self.names.iter().map(|name| attributes.and_then(|attributes| attributes.get(name)).unwrap_or("")).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference() {
+        let reference = Reference::parse(&b"client,tier,country\n1,gold,RO\n2,,DE\n\n"[..], None).unwrap();
+        assert_eq!(reference.names(), ["tier", "country"]);
+        assert_eq!(reference.attributes(None, 1).unwrap().get("tier"), Some("gold"));
+        assert_eq!(reference.attributes(Some("acme"), 1).unwrap().get("country"), Some("RO"));
+        assert_eq!(reference.values(None, 2), vec!["", "DE"]);
+        assert_eq!(reference.values(None, 3), vec!["", ""]);
+        assert!(reference.attributes(None, 3).is_none());
+
+        assert!(matches!(Reference::parse(&b"tier\ngold\n"[..], None), Err(Error::InvalidHeader)));
+        assert!(matches!(Reference::parse(&b"client,tier,tier\n"[..], None), Err(Error::InvalidHeader)));
+        assert!(matches!(Reference::parse(&b"client,tier\n1,gold\nx,gold\n"[..], None), Err(Error::InvalidRow(3))));
+        assert!(matches!(Reference::parse(&b"client,tier\n1,gold,RO\n"[..], None), Err(Error::InvalidRow(2))));
+        assert!(matches!(Reference::parse(&b"client,tier\n1,a b\n"[..], None), Err(Error::InvalidRow(2))));
+        assert!(matches!(Reference::parse(&b"client,tier\n1,gold\n1,silver\n"[..], None), Err(Error::Duplicate(3))));
+    }
+
+    #[test]
+    fn test_reference_tenants_and_aliases() {
+        let aliases = Aliases::parse(&b"external,client\nbank-a-7,7\n"[..]).unwrap();
+        let reference = Reference::parse(&b"tenant,client,tier\nacme,bank-a-7,gold\n,bank-a-7,silver\n"[..],
+                                         Some(&aliases)).unwrap();
+        assert_eq!(reference.names(), ["tier"]);
+        assert_eq!(reference.values(Some("acme"), 7), vec!["gold"]);
+        assert_eq!(reference.values(Some("globex"), 7), vec!["silver"]);
+        assert_eq!(reference.values(None, 7), vec!["silver"]);
+    }
+}
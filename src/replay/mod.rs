@@ -0,0 +1,140 @@
+// Replay protection: the memory of the tx ids seen so far by the driver, so that a transaction
+// reusing the tx id of an earlier deposit, withdrawal, authorization or fee is rejected instead of
+// being applied twice. The memory trades its size against the strength of the protection: it remembers the ids
+// of every client apart or of all the clients of a tenant together, and forgets them never, past
+// a number of ids, or past a number of seconds of the timestamps of the input.
+
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    // The tx ids of every client are only compared to the earlier ones of the client.
+    Client,
+    // The tx ids are compared to those of every client of the tenant.
+    Global,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Unlimited,
+    // Only the latest tx ids are remembered.
+    Ids(usize),
+    // The tx ids are forgotten once the timestamps of the input moved this many seconds past
+    // theirs. The tx ids of transactions without a timestamp are never forgotten.
+    Seconds(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Protection {
+    pub scope: Scope,
+    pub window: Window,
+}
+
+impl Default for Protection {
+    fn default() -> Self {
+        Protection { scope: Scope::Client, window: Window::Unlimited }
+    }
+}
+
+type Key = (Option<String>, Option<u16>, u32);
+
+#[derive(Debug)]
+pub struct Memory {
+    protection: Protection,
+    seen: HashSet<Key>,
+    // The tx ids that can be forgotten, in the order they were remembered, with their timestamp.
+    order: VecDeque<(Option<u64>, Key)>,
+    // Latest timestamp seen so far.
+    clock: Option<u64>,
+}
+
+impl Memory {
+    pub fn new(protection: Protection) -> Self {
+        Memory { protection, seen: HashSet::new(), order: VecDeque::new(), clock: None }
+    }
+
+    // Remembers the tx id of a transaction, returning `false` if it was still remembered.
+    pub fn remember(&mut self, tenant: Option<&str>, client_id: u16, tx_id: u32, timestamp: Option<u64>) -> bool {
+        if let Some(timestamp) = timestamp {
+            self.clock = Some(self.clock.map_or(timestamp, |clock| clock.max(timestamp)));
+        }
+        self.forget();
+
+        let client_id = (self.protection.scope == Scope::Client).then_some(client_id);
+        let key = (tenant.map(String::from), client_id, tx_id);
+        if self.seen.contains(&key) {
+            return false;
+        }
+        self.seen.insert(key.clone());
+        match self.protection.window {
+            Window::Unlimited => (),
+            Window::Ids(count) => {
+                self.order.push_back((timestamp, key));
+                if self.order.len() > count {
+                    let (_, key) = self.order.pop_front().unwrap();
+                    self.seen.remove(&key);
+                }
+            }
+            Window::Seconds(_) => {
+                if timestamp.is_some() {
+                    self.order.push_back((timestamp, key));
+                }
+            }
+        }
+        true
+    }
+
+    // Forgets the tx ids whose time window ran out.
+    fn forget(&mut self) {
+        let (seconds, clock) = match (self.protection.window, self.clock) {
+            (Window::Seconds(seconds), Some(clock)) => (seconds, clock),
+            _ => return
+        };
+        while let Some((Some(timestamp), _)) = self.order.front() {
+            if timestamp.saturating_add(seconds) >= clock {
+                break;
+            }
+            let (_, key) = self.order.pop_front().unwrap();
+            self.seen.remove(&key);
+        }
+    }
+
+    // Number of tx ids remembered.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_memory() {
+        let mut memory = Memory::new(Protection::default());
+        assert!(memory.remember(None, 1, 1, None));
+        assert!(!memory.remember(None, 1, 1, None));
+        assert!(memory.remember(None, 2, 1, None));
+        assert!(memory.remember(Some("acme"), 1, 1, None));
+
+        let mut memory = Memory::new(Protection { scope: Scope::Global, window: Window::Ids(2) });
+        assert!(memory.remember(None, 1, 1, None));
+        assert!(!memory.remember(None, 2, 1, None));
+        assert!(memory.remember(None, 2, 2, None));
+        assert!(memory.remember(None, 2, 3, None));
+        assert_eq!(memory.len(), 2);
+        assert!(memory.remember(None, 1, 1, None));
+
+        let mut memory = Memory::new(Protection { scope: Scope::Client, window: Window::Seconds(10) });
+        assert!(memory.remember(None, 1, 1, Some(100)));
+        assert!(memory.remember(None, 1, 2, None));
+        assert!(!memory.remember(None, 1, 1, Some(110)));
+        assert!(memory.remember(None, 1, 3, Some(111)));
+        assert!(memory.remember(None, 1, 1, Some(111)));
+        assert!(!memory.remember(None, 1, 2, Some(500)));
+    }
+}
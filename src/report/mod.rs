@@ -0,0 +1,508 @@
+// Writers for the reports produced at the end of a run.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::account::Account;
+use crate::amount::{self, Amount};
+use crate::config::Config;
+use crate::house::HouseAccount;
+use crate::output::CsvSink;
+use crate::retry::Stats;
+use crate::timing::StageTimings;
+use crate::watchdog::Exceeded;
+
+// Number and summed amount of the applied transactions of some kind.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Volume {
+    pub count: u64,
+    pub amount: Amount,
+}
+
+impl Volume {
+    // The amounts saturate, the volumes of a run adding up the funds of every account.
+    pub fn add(&mut self, amount: Amount) {
+        self.count += 1;
+        self.amount = self.amount.saturating_add(amount);
+    }
+
+    fn merge(&mut self, other: &Volume) {
+        self.count += other.count;
+        self.amount = self.amount.saturating_add(other.amount);
+    }
+}
+
+// Volumes of the applied transactions, per kind. The amounts of the dispute lifecycle are the
+// funds that were held, released or charged back.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Volumes {
+    pub deposits: Volume,
+    pub withdrawals: Volume,
+    pub disputes_opened: Volume,
+    pub disputes_resolved: Volume,
+    pub chargebacks: Volume,
+    // Charged back funds credited back to the accounts.
+    pub representments: Volume,
+    pub fees: Volume,
+    // Net funds credited by the manual adjustments, the debits counting as negative amounts.
+    pub adjustments: Volume,
+    // Settled transactions dropped from the history of the accounts.
+    pub collected: Volume,
+}
+
+impl Volumes {
+    pub fn merge(&mut self, other: &Volumes) {
+        self.deposits.merge(&other.deposits);
+        self.withdrawals.merge(&other.withdrawals);
+        self.disputes_opened.merge(&other.disputes_opened);
+        self.disputes_resolved.merge(&other.disputes_resolved);
+        self.chargebacks.merge(&other.chargebacks);
+        self.representments.merge(&other.representments);
+        self.fees.merge(&other.fees);
+        self.adjustments.merge(&other.adjustments);
+        self.collected.merge(&other.collected);
+    }
+
+    fn by_type(&self) -> [(&'static str, &Volume); 9] {
+        [("deposit", &self.deposits), ("withdrawal", &self.withdrawals), ("dispute", &self.disputes_opened),
+         ("resolve", &self.disputes_resolved), ("chargeback", &self.chargebacks),
+         ("representment", &self.representments), ("fee", &self.fees), ("adjustment", &self.adjustments),
+         ("collected", &self.collected)]
+    }
+}
+
+// Summary of a transactions execution run.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[non_exhaustive]
+pub struct RunReport {
+    pub accounts: usize,
+    // Number of records read from the input, the invalid ones included.
+    pub records: usize,
+    pub invalid: usize,
+    // Number of transactions the account admins rejected, along with those of clients without an
+    // account rejected by the unknown account policy.
+    pub rejected: u64,
+    pub volumes: Volumes,
+    // Transactions never applied because their account was locked or closed.
+    pub dead_letters: Volume,
+    // Funds credited to the house accounts, per tenant.
+    pub house_accounts: Vec<HouseAccount>,
+    // Transactions of a backfill skipped for being recorded by the resumed state already.
+    pub backfilled: usize,
+    // Transactions rejected for reusing the tx id of an earlier one.
+    pub duplicates: usize,
+    // Clients, or whole tenants, whose task panicked and whose accounts are missing from the run.
+    pub failed: Vec<Failure>,
+    // Deliveries of the webhook alert sinks, and their retries.
+    pub webhook_retries: Stats,
+    // Records of every labelled source, for the runs reading several sources.
+    pub sources: Vec<SourceStats>,
+    // The limit the run was aborted for, the outputs only covering the records read until then.
+    pub aborted: Option<Exceeded>,
+    // Time spent in every stage of the pipeline, for the runs measuring them.
+    pub timings: Option<StageTimings>,
+    // Final accounts of the run, grouped by tenant, and whether they belong to tenants, in which
+    // case their outputs start with a tenant column.
+    pub final_accounts: Vec<Account>,
+    pub with_tenant: bool,
+}
+
+// A client whose account admin panicked, or a tenant whose dispatcher did, when the client is
+// missing. The task is left out of the outputs while the rest of the run completes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Failure {
+    pub tenant: Option<String>,
+    pub client_id: Option<u16>,
+}
+
+// Records read from a source of the run, the invalid ones included, and the dead letters among
+// its transactions.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceStats {
+    pub label: String,
+    pub records: usize,
+    pub invalid: usize,
+    pub dead_letters: Volume,
+}
+
+impl RunReport {
+    pub fn new(accounts: &[Account]) -> Self {
+        let mut volumes = Volumes::default();
+        let mut rejected = 0;
+        for account in accounts {
+            volumes.merge(account.volumes());
+            rejected += account.rejected();
+        }
+        RunReport { accounts: accounts.len(), rejected, volumes, ..Default::default() }
+    }
+
+    // Writes the volumes with respect to the `type,count,amount` schema.
+    pub fn write_summary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "type,count,amount")?;
+        for (ttype, volume) in self.volumes.by_type().iter() {
+            writeln!(writer, "{},{},{}", ttype, volume.count, amount::fmt(volume.amount))?;
+        }
+        writeln!(writer, "dead_letter,{},{}", self.dead_letters.count, amount::fmt(self.dead_letters.amount))?;
+        if self.duplicates > 0 {
+            writeln!(writer, "duplicate,{},", self.duplicates)?;
+        }
+        // The funds of the failed clients are unknown, so their amount is left empty.
+        if !self.failed.is_empty() {
+            writeln!(writer, "failed,{},", self.failed.len())?;
+        }
+        if self.webhook_retries.retries > 0 {
+            writeln!(writer, "webhook_retry,{},", self.webhook_retries.retries)?;
+        }
+        for source in &self.sources {
+            writeln!(writer, "source:{},{},", source.label, source.records)?;
+        }
+        // The count of the timing rows is in microseconds.
+        if let Some(timings) = &self.timings {
+            for (stage, time) in [("read", timings.read), ("dispatch", timings.dispatch), ("apply", timings.apply)].iter() {
+                writeln!(writer, "stage:{},{},", stage, time.as_micros())?;
+            }
+            for (name, percent) in [("p50", 50.0), ("p90", 90.0), ("p99", 99.0)].iter() {
+                writeln!(writer, "queue_wait:{},{},", name, timings.queue_wait.percentile(*percent).as_micros())?;
+            }
+            writeln!(writer, "queue_wait:max,{},", timings.queue_wait.max().as_micros())?;
+        }
+        Ok(())
+    }
+}
+
+// Tenant name used for the partition of accounts that do not belong to any tenant.
+pub const NO_TENANT: &str = "default";
+
+// Creates the file at `path` and writes a report into it.
+pub fn write_file<F>(path: &Path, write: F) -> io::Result<()>
+    where F: FnOnce(&mut BufWriter<File>) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer)?;
+    writer.flush()
+}
+
+// Writes the accounts with respect to the `client,available,held,total,locked,status` schema,
+// prefixed by a `tenant` column for multi-tenant inputs. A `pending` column follows `held` when
+// withdrawals are settled in two steps. The spec mode leaves the `status` column out, to match
+// the classic payments engine outputs.
+pub fn write_accounts<W: Write>(writer: &mut W, accounts: &[Account], config: &Config,
+                                with_tenant: bool) -> io::Result<()> {
+    let mut sink = CsvSink::new(writer, config);
+    sink.write_header(with_tenant)?;
+    for account in accounts {
+        sink.write_account(account)?;
+    }
+    Ok(())
+}
+
+// Writes the disputes that are still open at the end of the run, with respect to the
+// `tenant,client,tx,amount,reason` schema.
+pub fn write_open_disputes<W: Write>(writer: &mut W, accounts: &[Account], config: &Config) -> io::Result<()> {
+    writeln!(writer, "tenant,client,tx,amount,reason")?;
+    for account in accounts {
+        for dispute in account.open_disputes() {
+            writeln!(writer, "{},{},{},{},{}", account.tenant().unwrap_or(""),
+                     config.client_label(account.client_id()), dispute.tx_id, amount::fmt(dispute.amount),
+                     dispute.reason.as_deref().unwrap_or(""))?;
+        }
+    }
+    Ok(())
+}
+
+// The attribute columns appended to the reports about the clients, when the run has reference
+// data: the names for the header, or else the values of the client of the account.
+fn attributes(config: &Config, account: Option<&Account>) -> String {
+    let reference = match &config.reference {
+        Some(reference) => reference,
+        None => return String::new()
+    };
+    let columns = match account {
+        Some(account) => reference.values(account.tenant(), account.client_id()),
+        None => reference.names().iter().map(String::as_str).collect()
+    };
+    columns.iter().map(|column| format!(",{}", column)).collect()
+}
+
+// Writes the accounts whose available funds ended up negative or ever were, with respect to the
+// `tenant,client,available,max_exposure` schema, followed by the attributes of the clients, for
+// risk review.
+pub fn write_exposures<W: Write>(writer: &mut W, accounts: &[Account], config: &Config) -> io::Result<()> {
+    writeln!(writer, "tenant,client,available,max_exposure{}", attributes(config, None))?;
+    for account in accounts.iter().filter(|account| account.is_exposed()) {
+        writeln!(writer, "{},{},{},{}{}", account.tenant().unwrap_or(""), config.client_label(account.client_id()),
+                 amount::fmt(account.available()), amount::fmt(account.max_exposure()),
+                 attributes(config, Some(account)))?;
+    }
+    Ok(())
+}
+
+// An account ranked by some value. Ties are broken by the position of the account, the first
+// one ranking higher.
+struct Ranked {
+    value: f64,
+    idx: usize,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.total_cmp(&other.value).then_with(|| other.idx.cmp(&self.idx))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+// Picks the `count` accounts with the highest values, best first. Only `count` accounts are kept
+// in a min-heap while going through them, instead of sorting all of them.
+fn top<F: Fn(&Account) -> f64>(accounts: &[Account], count: usize, value: F) -> Vec<(usize, f64)> {
+    let mut heap: BinaryHeap<Reverse<Ranked>> = BinaryHeap::with_capacity(count + 1);
+    for (idx, account) in accounts.iter().enumerate() {
+        heap.push(Reverse(Ranked { value: value(account), idx }));
+        if heap.len() > count {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|Reverse(ranked)| (ranked.idx, ranked.value)).collect()
+}
+
+// Name of a ranking, along with the value the accounts are ranked by.
+type Ranking = (&'static str, fn(&Account) -> f64);
+
+// Writes the top `count` accounts by total balance, by held funds and by number of transactions,
+// with respect to the `ranking,rank,tenant,client,value` schema, followed by the attributes of the
+// clients.
+pub fn write_top_accounts<W: Write>(writer: &mut W, accounts: &[Account], count: usize,
+                                    config: &Config) -> io::Result<()> {
+    writeln!(writer, "ranking,rank,tenant,client,value{}", attributes(config, None))?;
+    let rankings: [Ranking; 3] = [
+        ("total", |account| amount::to_f64(account.total())),
+        ("held", |account| amount::to_f64(account.held())),
+        ("transactions", |account| account.transactions() as f64),
+    ];
+    for (ranking, value) in rankings.iter() {
+        for (rank, (idx, value)) in top(accounts, count, value).into_iter().enumerate() {
+            let account = &accounts[idx];
+            write!(writer, "{},{},{},{},", ranking, rank + 1, account.tenant().unwrap_or(""),
+                   config.client_label(account.client_id()))?;
+            if *ranking == "transactions" {
+                write!(writer, "{}", value)?;
+            } else {
+                write!(writer, "{:.4}", value)?;
+            }
+            writeln!(writer, "{}", attributes(config, Some(account)))?;
+        }
+    }
+    Ok(())
+}
+
+// Writes the accounts of each tenant in its own `<tenant>.csv` file under `dir`.
+pub fn write_accounts_per_tenant(dir: &Path, accounts: &[Account], config: &Config) -> io::Result<()> {
+    let mut partitions: BTreeMap<&str, Vec<Account>> = BTreeMap::new();
+    for account in accounts {
+        partitions.entry(account.tenant().unwrap_or(NO_TENANT)).or_default().push(account.clone());
+    }
+
+    fs::create_dir_all(dir)?;
+    for (tenant, accounts) in partitions {
+        let mut writer = BufWriter::new(File::create(dir.join(format!("{}.csv", tenant)))?);
+        write_accounts(&mut writer, &accounts, config, false)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::account::{AccountAdmin, FreezeMode, Status};
+    use crate::transaction::{Transaction, Type};
+    use crate::amount::{from_f64, ZERO};
+    use crate::reference::Reference;
+    use std::sync::Arc;
+
+    fn tenant_account(tenant: &str, client_id: u16, available: Amount) -> Account {
+        let mut account = Account::new_unlocked(client_id, available, ZERO);
+        account.set_tenant(Some(String::from(tenant)));
+        account
+    }
+
+    #[test]
+    fn test_write_accounts() {
+        let mut out = Vec::new();
+        let accounts = vec![Account::new(1, from_f64(1.5), from_f64(0.5), Status::Locked),
+                            Account::new(2, from_f64(1.0), ZERO, Status::Frozen(FreezeMode::Full))];
+        write_accounts(&mut out, &accounts, &Config::default(), false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "client,available,held,total,locked,status\n\
+                   1,1.5000,0.5000,2.0000,true,locked\n\
+                   2,1.0000,0.0000,1.0000,false,frozen\n");
+    }
+
+    #[test]
+    fn test_write_accounts_spec() {
+        let mut out = Vec::new();
+        let mut config = Config::default();
+        config.rules.spec = true;
+        write_accounts(&mut out, &[Account::new(1, from_f64(1.5), from_f64(0.5), Status::Locked)], &config, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "client,available,held,total,locked\n1,1.5000,0.5000,2.0000,true\n");
+    }
+
+    #[test]
+    fn test_write_accounts_with_pending() {
+        let mut out = Vec::new();
+        let mut config = Config::default();
+        config.rules.two_step_withdrawals = true;
+        let mut account = Account::new_unlocked(1, from_f64(1.0), ZERO);
+        account.add_pending(from_f64(2.0)).unwrap();
+        write_accounts(&mut out, &[account], &config, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "client,available,held,pending,total,locked,status\n\
+                   1,1.0000,0.0000,2.0000,3.0000,false,active\n");
+    }
+
+    #[test]
+    fn test_write_accounts_with_tenant() {
+        let mut out = Vec::new();
+        let accounts = vec![tenant_account("acme", 1, from_f64(1.0)), Account::new_unlocked(1, from_f64(2.0), ZERO)];
+        write_accounts(&mut out, &accounts, &Config::default(), true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,available,held,total,locked,status\n\
+                   acme,1,1.0000,0.0000,1.0000,false,active\n\
+                   default,1,2.0000,0.0000,2.0000,false,active\n");
+    }
+
+    #[test]
+    fn test_write_open_disputes() {
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(1, receiver);
+        account_admin.apply(Tx::deposit(1, 1).amount(2.0).build()).unwrap();
+        account_admin.apply(Tx::deposit(1, 2).amount(3.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(1, 1).build()).unwrap();
+        let mut dispute = Tx::dispute(1, 2).build();
+        dispute.set_reason(Some(String::from("fraud")));
+        account_admin.apply(dispute).unwrap();
+
+        let mut out = Vec::new();
+        write_open_disputes(&mut out, &[account_admin.account().clone()], &Config::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,tx,amount,reason\n,1,1,2.0000,\n,1,2,3.0000,fraud\n");
+    }
+
+    #[test]
+    fn test_write_exposures() {
+        let mut exposed = Account::new_unlocked(1, from_f64(1.0), ZERO);
+        exposed.debit_available(from_f64(3.0)).unwrap();
+        exposed.add_available(from_f64(4.0)).unwrap();
+        let mut negative = tenant_account("acme", 2, ZERO);
+        negative.debit_available(from_f64(1.5)).unwrap();
+        let accounts = vec![exposed, negative, Account::new_unlocked(3, from_f64(1.0), ZERO)];
+
+        let mut out = Vec::new();
+        write_exposures(&mut out, &accounts, &Config::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,available,max_exposure\n,1,2.0000,2.0000\nacme,2,-1.5000,1.5000\n");
+
+        // The attributes of the clients follow, when the run has reference data.
+        let reference = Reference::parse(&b"tenant,client,tier,country\n,1,gold,RO\n"[..], None).unwrap();
+        let config = Config { reference: Some(Arc::new(reference)), ..Config::default() };
+        let mut out = Vec::new();
+        write_exposures(&mut out, &accounts, &config).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "tenant,client,available,max_exposure,tier,country\n,1,2.0000,2.0000,gold,RO\n\
+                   acme,2,-1.5000,1.5000,,\n");
+    }
+
+    #[test]
+    fn test_write_top_accounts() {
+        let mut accounts = Vec::new();
+        for (client_id, deposits) in [(1, 1), (2, 3), (3, 2), (4, 3)].iter() {
+            let (_, receiver) = tokio::sync::mpsc::channel(32);
+            let mut account_admin = AccountAdmin::new(*client_id, receiver);
+            for tx_id in 0..*deposits {
+                let amount = from_f64(*client_id as f64);
+                account_admin.apply(Transaction::new_with_amount(Type::Deposit, *client_id, tx_id, amount)).unwrap();
+            }
+            account_admin.apply(Tx::dispute(*client_id, 0).build()).unwrap();
+            accounts.push(account_admin.account().clone());
+        }
+
+        let mut out = Vec::new();
+        write_top_accounts(&mut out, &accounts, 2, &Config::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "ranking,rank,tenant,client,value\n\
+                   total,1,,4,12.0000\n\
+                   total,2,,2,6.0000\n\
+                   held,1,,4,4.0000\n\
+                   held,2,,3,3.0000\n\
+                   transactions,1,,2,4\n\
+                   transactions,2,,4,4\n");
+    }
+
+    #[test]
+    fn test_run_report() {
+        let mut accounts = Vec::new();
+        for client_id in 1..3 {
+            let (_, receiver) = tokio::sync::mpsc::channel(32);
+            let mut account_admin = AccountAdmin::new(client_id, receiver);
+            account_admin.apply(Tx::deposit(client_id, 1).amount(5.0).build()).unwrap();
+            account_admin.apply(Tx::withdrawal(client_id, 2).amount(1.0).build()).unwrap();
+            assert!(account_admin.apply(Tx::withdrawal(client_id, 3).amount(9.0).build()).is_err());
+            accounts.push(account_admin.account().clone());
+        }
+        let (_, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new(3, receiver);
+        account_admin.apply(Tx::deposit(3, 1).amount(2.0).build()).unwrap();
+        account_admin.apply(Tx::dispute(3, 1).build()).unwrap();
+        account_admin.apply(Tx::chargeback(3, 1).build()).unwrap();
+        accounts.push(account_admin.account().clone());
+
+        let report = RunReport::new(&accounts);
+        assert_eq!(report.accounts, 3);
+        assert_eq!(report.volumes.deposits, Volume { count: 3, amount: from_f64(12.0) });
+        assert_eq!(report.volumes.withdrawals, Volume { count: 2, amount: from_f64(2.0) });
+        let mut out = Vec::new();
+        report.write_summary(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "type,count,amount\n\
+                   deposit,3,12.0000\n\
+                   withdrawal,2,2.0000\n\
+                   dispute,1,2.0000\n\
+                   resolve,0,0.0000\n\
+                   chargeback,1,2.0000\n\
+                   representment,0,0.0000\n\
+                   fee,0,0.0000\n\
+                   adjustment,0,0.0000\n\
+                   collected,0,0.0000\n\
+                   dead_letter,0,0.0000\n");
+    }
+
+    #[test]
+    fn test_write_accounts_per_tenant() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = vec![tenant_account("acme", 1, from_f64(1.0)), tenant_account("globex", 1, from_f64(2.0)),
+                            tenant_account("acme", 2, from_f64(3.0))];
+        write_accounts_per_tenant(dir.path(), &accounts, &Config::default()).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("acme.csv")).unwrap(),
+                   "client,available,held,total,locked,status\n\
+                   1,1.0000,0.0000,1.0000,false,active\n\
+                   2,3.0000,0.0000,3.0000,false,active\n");
+        assert_eq!(fs::read_to_string(dir.path().join("globex.csv")).unwrap(),
+                   "client,available,held,total,locked,status\n1,2.0000,0.0000,2.0000,false,active\n");
+    }
+}
@@ -0,0 +1,161 @@
+// Retries of the calls to the integrations of a run that fail transiently, e.g. the webhook
+// deliveries. Every integration gets its own policy and its own metrics, while the retries of all
+// its calls draw from the same budget, so an unreachable integration stops being retried instead
+// of slowing down the whole run.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::digest::hash64;
+
+// How the failed calls of an integration are retried. The delay doubles after every failed
+// attempt, up to `max_backoff`, and is shortened by up to `jitter` of itself, so that the retries
+// of concurrent calls spread out. The budget caps the retries of all the calls of the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    // Attempts of a single call, the first one included.
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    // Fraction of the delay, between 0 and 1.
+    pub jitter: f64,
+    pub budget: Option<u64>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy { attempts: 3, backoff: Duration::from_millis(100), max_backoff: Duration::from_secs(10), jitter: 0.0,
+                 budget: None }
+    }
+}
+
+impl Policy {
+    // Delay before the retry, numbered from 1, `draw` picking the jitter between 0 and 1.
+    fn delay(&self, retry: u32, draw: f64) -> Duration {
+        let backoff = self.backoff.saturating_mul(1 << (retry - 1).min(31)).min(self.max_backoff);
+        backoff.mul_f64(1.0 - self.jitter * draw)
+    }
+}
+
+// Counters of the calls of an integration, shared by its retriers.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    attempts: AtomicU64,
+    retries: AtomicU64,
+    failures: AtomicU64,
+    denied: AtomicU64,
+}
+
+impl Metrics {
+    pub fn stats(&self) -> Stats {
+        Stats {
+            attempts: self.attempts.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub attempts: u64,
+    pub retries: u64,
+    // Calls that still failed after their last attempt.
+    pub failures: u64,
+    // Retries refused because the budget ran out.
+    pub denied: u64,
+}
+
+// Runs the calls of an integration with respect to its policy. The clones share the metrics, and
+// so the budget.
+#[derive(Debug, Clone)]
+pub struct Retrier {
+    policy: Policy,
+    // The retries wait on the clock of the run.
+    clock: Arc<dyn Clock>,
+    metrics: Arc<Metrics>,
+}
+
+impl Retrier {
+    pub fn new(policy: Policy, clock: Arc<dyn Clock>, metrics: Arc<Metrics>) -> Self {
+        Retrier { policy, clock, metrics }
+    }
+
+    pub fn call<T, E, F: FnMut() -> Result<T, E>>(&self, mut call: F) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            self.metrics.attempts.fetch_add(1, Ordering::Relaxed);
+            let err = match call() {
+                Ok(value) => return Ok(value),
+                Err(err) => err
+            };
+            if attempt >= self.policy.attempts {
+                self.metrics.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(err);
+            }
+            let retries = match self.take_retry() {
+                Some(retries) => retries,
+                None => {
+                    self.metrics.denied.fetch_add(1, Ordering::Relaxed);
+                    self.metrics.failures.fetch_add(1, Ordering::Relaxed);
+                    return Err(err);
+                }
+            };
+            // The jitter is drawn from the number of retries so far, so the delays are the same
+            // from a run to another.
+            let draw = (hash64(&retries.to_le_bytes()) >> 11) as f64 / (1u64 << 53) as f64;
+            self.clock.sleep(self.policy.delay(attempt, draw));
+            attempt += 1;
+        }
+    }
+
+    // Counts a retry against the budget, returning the number of retries before it, unless the
+    // budget ran out.
+    fn take_retry(&self) -> Option<u64> {
+        let budget = self.policy.budget.unwrap_or(u64::MAX);
+        self.metrics.retries
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |retries| Some(retries + 1).filter(|_| retries < budget))
+            .ok()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.metrics.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = Policy { attempts: 10, backoff: Duration::from_secs(1), max_backoff: Duration::from_secs(5),
+                              ..Policy::default() };
+        let delays: Vec<u64> = (1..6).map(|retry| policy.delay(retry, 0.5).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+        let jittered = Policy { jitter: 0.5, ..policy };
+        assert_eq!(jittered.delay(3, 0.0), Duration::from_secs(4));
+        assert_eq!(jittered.delay(3, 0.5), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_retrier() {
+        let clock = Arc::new(MockClock::new(0));
+        let policy = Policy { attempts: 3, backoff: Duration::from_secs(1), budget: Some(3), ..Policy::default() };
+        let retrier = Retrier::new(policy, clock.clone(), Arc::new(Metrics::default()));
+
+        let mut failures = 2;
+        let result = retrier.call(|| if failures > 0 { failures -= 1; Err(()) } else { Ok(1) });
+        assert_eq!(result, Ok(1));
+        assert_eq!(clock.elapsed(), Duration::from_secs(3));
+
+        // The clone shares the budget, only one retry is left.
+        assert_eq!(retrier.clone().call(|| Err::<(), _>("down")), Err("down"));
+        assert_eq!(clock.elapsed(), Duration::from_secs(4));
+        assert_eq!(retrier.stats(), Stats { attempts: 5, retries: 3, failures: 1, denied: 1 });
+    }
+}
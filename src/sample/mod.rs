@@ -0,0 +1,104 @@
+// Sampling of the clients of an input, for quick validation runs over huge files. A sampled
+// client keeps all of its transactions, so the sampled accounts end up exactly as in a full run,
+// and only the run as a whole is approximate.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::digest::hash64;
+use crate::transaction::Transaction;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    // The first `count` clients met in the input, per tenant.
+    Clients(usize),
+    // A percentage of the clients, picked by a hash of their tenant and id, so that the same
+    // clients are sampled from one run to another.
+    Rate(f64),
+}
+
+pub struct Sampler {
+    sample: Sample,
+    kept: HashSet<(Option<String>, u16)>,
+    dropped: HashSet<(Option<String>, u16)>,
+    // Number of sampled clients per tenant.
+    tenants: HashMap<Option<String>, usize>,
+}
+
+impl Sampler {
+    pub fn new(sample: Sample) -> Self {
+        Sampler { sample, kept: HashSet::new(), dropped: HashSet::new(), tenants: HashMap::new() }
+    }
+
+    // Whether the transaction belongs to a sampled client.
+    pub fn keeps(&mut self, tx: &Transaction) -> bool {
+        let client = (tx.tenant().map(String::from), tx.client_id());
+        if self.kept.contains(&client) {
+            return true;
+        }
+        if self.dropped.contains(&client) {
+            return false;
+        }
+
+        let kept = match self.sample {
+            Sample::Clients(count) => self.tenants.get(&client.0).copied().unwrap_or(0) < count,
+            Sample::Rate(rate) => position(&client.0, client.1) < rate / 100.0
+        };
+        if kept {
+            *self.tenants.entry(client.0.clone()).or_insert(0) += 1;
+            self.kept.insert(client);
+        } else {
+            self.dropped.insert(client);
+        }
+        kept
+    }
+
+    // Number of sampled clients, and of clients met in the input.
+    pub fn clients(&self) -> (usize, usize) {
+        (self.kept.len(), self.kept.len() + self.dropped.len())
+    }
+}
+
+// Position of a client in [0, 1), uniformly spread by the hash of its tenant and id.
+fn position(tenant: &Option<String>, client_id: u16) -> f64 {
+    let mut data = tenant.as_deref().unwrap_or("").as_bytes().to_vec();
+    data.push(b',');
+    data.extend_from_slice(&client_id.to_be_bytes());
+    (hash64(&data) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+
+    fn tx(tenant: Option<&str>, client_id: u16) -> Transaction {
+        let mut tx = Tx::deposit(client_id, 1).amount(1.0).build();
+        tx.set_tenant(tenant.map(String::from));
+        tx
+    }
+
+    #[test]
+    fn test_sample_clients() {
+        let mut sampler = Sampler::new(Sample::Clients(2));
+        let kept: Vec<bool> = [(None, 3), (None, 1), (None, 3), (None, 2), (Some("acme"), 2), (None, 1)].iter()
+            .map(|(tenant, client_id)| sampler.keeps(&tx(*tenant, *client_id)))
+            .collect();
+        assert_eq!(kept, vec![true, true, true, false, true, true]);
+        assert_eq!(sampler.clients(), (3, 4));
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut sampler = Sampler::new(Sample::Rate(10.0));
+        let kept: Vec<u16> = (0..1000).filter(|client_id| sampler.keeps(&tx(None, *client_id))).collect();
+        assert!(kept.len() > 50 && kept.len() < 150);
+
+        // The same clients are sampled again, whatever the order they are met in.
+        let mut sampler = Sampler::new(Sample::Rate(10.0));
+        let mut again: Vec<u16> = (0..1000).rev().filter(|client_id| sampler.keeps(&tx(None, *client_id))).collect();
+        again.reverse();
+        assert_eq!(kept, again);
+        assert!((0..1000).all(|client_id| !Sampler::new(Sample::Rate(0.0)).keeps(&tx(None, client_id))));
+        assert!((0..1000).all(|client_id| Sampler::new(Sample::Rate(100.0)).keeps(&tx(None, client_id))));
+    }
+}
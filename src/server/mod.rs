@@ -0,0 +1,726 @@
+// Long running mode of the processor, receiving the transactions over HTTP instead of reading them
+// from a file. The transactions are sent into a run like any other source, so they are applied by
+// the account admins of their clients, in the order they are received, and the run ends, writing
+// its outputs, once the server is shut down. Every connection is served on a thread of its own,
+// so that a slow client only holds its own connection, and the endpoints are:
+// * `POST /transactions` takes a CSV body, a header line followed by one or more records, e.g.
+//   `type,client,tx,amount\ndeposit,1,1,1.5`, answering with the number of accepted records and
+//   the line and reason of every invalid one, along with the acknowledgement of every record.
+// * `POST /adjustments` takes the `credit` and `debit` records of the manual adjustments only.
+// * `GET /accounts` and `GET /accounts/<client>` return the accounts as of the transactions
+//   applied so far, as JSON objects of the accounts output.
+// * `POST /shutdown` stops receiving transactions and ends the run.
+//
+// When the config has an API token, every request must carry it in an `Authorization: Bearer`
+// header, the others being answered with a 401.
+//
+// A record is acknowledged with the outcome of its transaction, `applied`, or `accepted` when it
+// was not applied in time to be answered for, and not acknowledged with the reason it was rejected
+// for, or was invalid, e.g. `{"line":2,"tx":1,"ack":"applied"}` and
+// `{"line":3,"tx":2,"nack":"insufficient_funds"}`. The outcomes are the ones of the audit entries.
+//
+// With a write-ahead log, every batch posted is logged before it is applied, and the batches the
+// log records are applied again first when the server starts, so that none of the batches it
+// answered for is lost by a crash. The log is cleared once the run saved its state.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::account::Account;
+use crate::adjustment::Adjustments;
+use crate::audit::{self, Entry, Outcome};
+use crate::config::Config;
+use crate::input::Input;
+use crate::notify::json_string;
+use crate::output::{AccountSink, JsonFormat, JsonSink};
+use crate::report::RunReport;
+use crate::sources::Sources;
+use crate::state::{self, State};
+use crate::transaction::{self, resume_source, ChannelSource, Transaction, Type};
+use crate::wal::{Batch, Wal};
+
+// A client that does not send its request in time is dropped, so that it does not hold a thread
+// for good, whether it stops sending or sends a byte at a time.
+const TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_DEADLINE: Duration = Duration::from_secs(30);
+// Connections served at the same time, the others being answered with a 503 right away.
+const MAX_CONNECTIONS: usize = 64;
+// Largest body accepted, larger batches being split by the client.
+const MAX_BODY: usize = 16 << 20;
+// Largest request line and headers accepted, and the most headers.
+const MAX_HEAD: u64 = 16 << 10;
+const MAX_HEADERS: usize = 100;
+// The records not applied by then, e.g. held back by a sequence window, are answered as accepted.
+const OUTCOME_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Labels of the sources of the transactions and of the adjustments.
+const SOURCES: [&str; 2] = ["http", "adjustments"];
+
+// Accounts by tenant and client.
+type AccountMap = BTreeMap<(Option<String>, u16), Account>;
+
+// Latest account of every client, as of the audit entries of the run.
+#[derive(Clone, Default)]
+struct Accounts(Arc<Mutex<AccountMap>>);
+
+impl Accounts {
+    fn update(&self, account: Account) {
+        let key = (account.tenant().map(String::from), account.client_id());
+        self.0.lock().unwrap().insert(key, account);
+    }
+}
+
+impl audit::Sink for Accounts {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        let mut account = Account::new(entry.client_id, entry.after.available, entry.after.held, entry.status);
+        account.set_tenant(entry.tenant.clone());
+        // The pending funds are the part of the total that is neither available nor held.
+        let _ = account.add_pending(entry.after.total - entry.after.available - entry.after.held);
+        self.update(account);
+        Ok(())
+    }
+}
+
+// The records awaited by the batches being answered, by source and record number, along with their
+// tx id and type, and their outcome once their audit entry came.
+type Awaited = HashMap<(&'static str, u64), (u32, Type, Option<Outcome>)>;
+
+// Outcomes of the records of the batches being answered, as of the audit entries of the run.
+#[derive(Clone, Default)]
+struct Outcomes(Arc<(Mutex<Awaited>, Condvar)>);
+
+impl Outcomes {
+    fn expect(&self, source: &'static str, record: u64, tx_id: u32, ttype: Type) {
+        self.0.0.lock().unwrap().insert((source, record), (tx_id, ttype, None));
+    }
+
+    fn forget(&self, source: &'static str, record: u64) {
+        self.0.0.lock().unwrap().remove(&(source, record));
+    }
+
+    // Waits for the outcomes of the records up to the timeout, and forgets about them.
+    fn take(&self, records: &[(&'static str, u64)]) -> Vec<Option<Outcome>> {
+        let (awaited, arrived) = &*self.0;
+        let deadline = Instant::now() + OUTCOME_TIMEOUT;
+        let mut awaited = awaited.lock().unwrap();
+        while records.iter().any(|key| awaited.get(key).is_some_and(|(_, _, outcome)| outcome.is_none())) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            awaited = arrived.wait_timeout(awaited, left).unwrap().0;
+        }
+        records.iter().map(|key| awaited.remove(key).and_then(|(_, _, outcome)| outcome)).collect()
+    }
+}
+
+impl audit::Sink for Outcomes {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        let source = SOURCES.iter().find(|source| entry.source.as_deref() == Some(**source));
+        if let (Some(source), Some(record)) = (source, entry.record) {
+            let (awaited, arrived) = &*self.0;
+            let mut awaited = awaited.lock().unwrap();
+            // The entries a transaction triggers for others, e.g. an expired dispute, are not its own.
+            if let Some((tx_id, ttype, outcome)) = awaited.get_mut(&(*source, record)) {
+                if *tx_id == entry.tx_id && *ttype == entry.ttype && outcome.is_none() {
+                    *outcome = Some(entry.outcome.clone());
+                    arrived.notify_all();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The sources the batches are sent into, numbering the records they send the way the sources
+// number the records they receive.
+struct Channels {
+    transactions: Sender<Transaction>,
+    adjustments: Adjustments,
+    sent: [u64; 2],
+    outcomes: Outcomes,
+}
+
+impl Channels {
+    // Sends the transaction of a batch posted to the endpoint, returning its source and record
+    // number. The outcome of the transaction is awaited, unless the batch is not answered for.
+    fn send(&mut self, endpoint: &str, tx: Transaction, awaited: bool) -> Result<(&'static str, u64), String> {
+        let idx = if endpoint == "adjustments" { 1 } else { 0 };
+        let amount = match (idx, tx.transaction_type(), tx.amount()) {
+            (0, _, amount) => amount,
+            (_, Type::Credit, Some(amount)) | (_, Type::Debit, Some(amount)) => Some(amount),
+            (_, Type::Credit, None) | (_, Type::Debit, None) => return Err(String::from("the adjustment has no amount")),
+            _ => return Err(String::from("only the credit and debit records are adjustments"))
+        };
+        let record = self.sent[idx] + 1;
+        if awaited {
+            self.outcomes.expect(SOURCES[idx], record, tx.tx_id(), tx.transaction_type());
+        }
+        let sent = match (idx, amount) {
+            (1, Some(amount)) => {
+                let sent = if tx.transaction_type() == Type::Credit {
+                    self.adjustments.credit(tx.tenant(), tx.client_id(), tx.tx_id(), amount)
+                } else {
+                    self.adjustments.debit(tx.tenant(), tx.client_id(), tx.tx_id(), amount)
+                };
+                sent.map_err(|err| format!("the adjustment could not be sent, {:?}", err))
+            }
+            _ => self.transactions.send(tx).map_err(|_| String::from("the run is over"))
+        };
+        match sent {
+            Ok(()) => {
+                self.sent[idx] = record;
+                Ok((SOURCES[idx], record))
+            }
+            Err(reason) => {
+                self.outcomes.forget(SOURCES[idx], record);
+                Err(reason)
+            }
+        }
+    }
+}
+
+// A record of a batch, along with its tx id and the source and record number it was sent under,
+// unless it was refused for a reason.
+struct Posted {
+    line: u64,
+    tx_id: Option<u32>,
+    sent: Result<(&'static str, u64), String>,
+}
+
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    // Value of the `Authorization` header, if any.
+    pub(crate) authorization: Option<String>,
+    pub(crate) body: String,
+}
+
+impl Request {
+    // Whether the request carries the API token of the config, if it has one. The token is
+    // compared in constant time, so that the response times do not tell how much of it matched.
+    pub(crate) fn is_authorized(&self, config: &Config) -> bool {
+        let token = match &config.api_token {
+            Some(token) => token,
+            None => return true
+        };
+        match self.authorization.as_deref().and_then(|value| value.strip_prefix("Bearer ")) {
+            Some(given) if given.len() == token.len() => {
+                given.bytes().zip(token.bytes()).fold(0, |diff, (given, expected)| diff | (given ^ expected)) == 0
+            }
+            _ => false
+        }
+    }
+}
+
+pub(crate) struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub(crate) fn new(status: &'static str, body: String) -> Self {
+        Response { status, body }
+    }
+
+    pub(crate) fn error(status: &'static str, reason: &str) -> Self {
+        Response::new(status, format!("{{\"error\":{}}}", json_string(Some(reason))))
+    }
+}
+
+pub(crate) fn unauthorized() -> Response {
+    Response::error("401 Unauthorized", "the request does not carry the API token")
+}
+
+// What the connections of the server share. The batches are logged and sent under one lock, so
+// that the log records them in the order they are applied, while their outcomes are awaited
+// outside of it.
+struct Shared {
+    config: Arc<Config>,
+    accounts: Accounts,
+    outcomes: Outcomes,
+    ingest: Mutex<(Channels, Option<Wal>)>,
+    shutdown: AtomicBool,
+    // Address the accept loop is woken up at, once the server is shut down.
+    address: Option<SocketAddr>,
+}
+
+// Serves the requests received by the listener, until it is shut down, and returns the report of
+// the run the transactions were applied by, on top of `state`, and of the batches of the log.
+pub fn serve(listener: TcpListener, state: State, mut config: Config, wal: Option<(Wal, Vec<Batch>)>)
+             -> transaction::Result<RunReport> {
+    let accounts = Accounts::default();
+    for account in &state.accounts {
+        accounts.update(account.clone());
+    }
+    let observer = accounts.clone();
+    config.observers.push(Arc::new(move || Ok(Box::new(observer.clone()))));
+    let outcomes = Outcomes::default();
+    let observer = outcomes.clone();
+    config.observers.push(Arc::new(move || Ok(Box::new(observer.clone()))));
+    let config = Arc::new(config);
+
+    let (transactions, receiver) = channel();
+    let (adjustments, adjusted) = Adjustments::channel();
+    let mut sources = Sources::new(config.tenant_buffer);
+    sources.add(SOURCES[0], ChannelSource::new(receiver));
+    sources.add(SOURCES[1], adjusted);
+    let run_config = config.clone();
+    let run = thread::spawn(move || resume_source(state, sources, &run_config));
+    let mut channels = Channels { transactions, adjustments, sent: [0; 2], outcomes: outcomes.clone() };
+
+    let wal = wal.map(|(wal, batches)| {
+        // Their producers were answered for them already.
+        for batch in &batches {
+            let _ = apply(&batch.endpoint, &batch.body, &config, &mut channels, false);
+        }
+        if !batches.is_empty() {
+            log::info!("Applied the {} batches of the write-ahead log again.", batches.len());
+        }
+        wal
+    });
+
+    let address = listener.local_addr().ok().map(|mut address| {
+        if address.ip().is_unspecified() {
+            address.set_ip(if address.is_ipv4() { Ipv4Addr::LOCALHOST.into() } else { Ipv6Addr::LOCALHOST.into() });
+        }
+        address
+    });
+    let shared = Arc::new(Shared { config: config.clone(), accounts, outcomes, ingest: Mutex::new((channels, wal)),
+                                   shutdown: AtomicBool::new(false), address });
+    let mut connections: Vec<JoinHandle<()>> = Vec::new();
+    for stream in listener.incoming() {
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        connections.retain(|connection| !connection.is_finished());
+        if connections.len() >= MAX_CONNECTIONS {
+            let _ = stream.set_write_timeout(Some(TIMEOUT));
+            let _ = write_response(&mut stream, &Response::error("503 Service Unavailable", "too many connections"));
+            continue;
+        }
+        let shared = shared.clone();
+        connections.push(thread::spawn(move || serve_connection(stream, &shared)));
+    }
+
+    // The run ends with its last source, once the requests being served are answered.
+    for connection in connections {
+        let _ = connection.join();
+    }
+    let shared = Arc::try_unwrap(shared).unwrap_or_else(|_| panic!("A connection of the server outlived it."));
+    let (channels, mut wal) = shared.ingest.into_inner().unwrap();
+    drop(channels);
+    let run_report = run.join().unwrap_or_else(|_| panic!("The run of the server panicked."));
+
+    // The batches are part of the saved state from now on, unless it could not be written.
+    if let (Some(wal), Some(path), Ok(_)) = (&mut wal, &config.state_path, &run_report) {
+        match state::read_file(path) {
+            Ok(_) => {
+                if wal.clear().is_err() {
+                    log::error!("Could not clear the write-ahead log, its batches would be applied twice.");
+                }
+            }
+            Err(_) => log::error!("The state was not saved, the write-ahead log keeps its batches.")
+        }
+    }
+    run_report
+}
+
+// Answers the request of a connection, on a thread of its own, so that a slow client only holds
+// its own connection.
+fn serve_connection(mut stream: TcpStream, shared: &Shared) {
+    let response = match read_request(&mut stream) {
+        Ok(request) if !request.is_authorized(&shared.config) => unauthorized(),
+        Ok(request) if request.method == "POST" && request.path == "/shutdown" => {
+            let _ = write_response(&mut stream, &Response::new("200 OK", String::from("{}")));
+            shared.shutdown.store(true, Ordering::SeqCst);
+            if let Some(address) = shared.address {
+                let _ = TcpStream::connect(address);
+            }
+            return;
+        }
+        Ok(request) => handle(&request, shared),
+        Err(ReadError::Refused(response)) => response,
+        Err(ReadError::Io(err)) => {
+            log::debug!("Dropped a connection: {}.", err);
+            return;
+        }
+    };
+    if write_response(&mut stream, &response).is_err() {
+        log::warn!("Could not answer {} request.", response.status);
+    }
+}
+
+fn handle(request: &Request, shared: &Shared) -> Response {
+    let config = &shared.config;
+    let path: Vec<&str> = request.path.trim_start_matches('/').split('/').collect();
+    match (request.method.as_str(), path.as_slice()) {
+        ("POST", [endpoint @ "transactions"]) | ("POST", [endpoint @ "adjustments"]) => {
+            let posted = {
+                let mut ingest = shared.ingest.lock().unwrap();
+                let (channels, wal) = &mut *ingest;
+                // Logged before it is applied, so that it can be applied again after a crash.
+                if let Some(wal) = wal {
+                    if wal.append(endpoint, &request.body).is_err() {
+                        return Response::error("503 Service Unavailable", "the batch could not be logged");
+                    }
+                }
+                match apply(endpoint, &request.body, config, channels, true) {
+                    Ok(posted) => posted,
+                    Err(response) => return response
+                }
+            };
+            answer(&posted, &shared.outcomes)
+        }
+        ("GET", ["accounts"]) => {
+            let accounts = shared.accounts.0.lock().unwrap();
+            Response::new("200 OK", json(config, JsonFormat::Array, false, accounts.values()))
+        }
+        ("GET", ["accounts", client]) => {
+            let accounts = shared.accounts.0.lock().unwrap();
+            match accounts.values().find(|account| config.client_label(account.client_id()) == *client) {
+                Some(account) => {
+                    Response::new("200 OK", json(config, JsonFormat::Lines, false, Some(account)).trim_end().to_string())
+                }
+                None => Response::error("404 Not Found", "the client has no account")
+            }
+        }
+        (_, ["transactions"]) | (_, ["adjustments"]) | (_, ["shutdown"]) | (_, ["accounts"]) | (_, ["accounts", _]) => {
+            Response::error("405 Method Not Allowed", "the method is not allowed")
+        }
+        _ => Response::error("404 Not Found", "no such endpoint")
+    }
+}
+
+// Sends the records of a batch posted to the endpoint, their outcomes being awaited when they are
+// `answered`.
+fn apply(endpoint: &str, body: &str, config: &Config, channels: &mut Channels, answered: bool)
+         -> Result<Vec<Posted>, Response> {
+    if endpoint != "transactions" && endpoint != "adjustments" {
+        return Err(Response::error("404 Not Found", "no such endpoint"));
+    }
+    ingest(body, config, |tx| channels.send(endpoint, tx, answered))
+}
+
+// Answers for every record of a batch, once its outcome is known.
+fn answer(posted: &[Posted], outcomes: &Outcomes) -> Response {
+    let sent: Vec<(&'static str, u64)> = posted.iter().filter_map(|record| record.sent.clone().ok()).collect();
+    let mut outcomes = outcomes.take(&sent).into_iter();
+
+    let (mut accepted, mut invalid, mut records) = (0, Vec::new(), Vec::new());
+    for record in posted {
+        let tx = record.tx_id.map(|tx_id| format!(",\"tx\":{}", tx_id)).unwrap_or_default();
+        let answer = match &record.sent {
+            Ok(_) => {
+                accepted += 1;
+                match outcomes.next().flatten() {
+                    Some(Outcome::Applied) => String::from("\"ack\":\"applied\""),
+                    Some(Outcome::Rejected(reason)) => format!("\"nack\":{}", json_string(Some(reason))),
+                    None => String::from("\"ack\":\"accepted\"")
+                }
+            }
+            Err(reason) => {
+                invalid.push(format!("{{\"line\":{},\"reason\":{}}}", record.line, json_string(Some(reason))));
+                format!("\"nack\":{}", json_string(Some(reason)))
+            }
+        };
+        records.push(format!("{{\"line\":{}{},{}}}", record.line, tx, answer));
+    }
+    Response::new("202 Accepted", format!("{{\"accepted\":{},\"invalid\":[{}],\"records\":[{}]}}", accepted,
+                                          invalid.join(","), records.join(",")))
+}
+
+// Parses the CSV body, with the amount scale, the client aliases and the reference data of the
+// run, and hands out every valid transaction.
+fn ingest<F>(body: &str, config: &Config, mut send: F) -> Result<Vec<Posted>, Response>
+    where F: FnMut(Transaction) -> Result<(&'static str, u64), String> {
+    let mut input = match Input::try_new(io::Cursor::new(body.as_bytes().to_vec())) {
+        Ok(input) => input,
+        Err(_) => return Err(Response::error("400 Bad Request", "the body does not start with a valid header line"))
+    };
+    if input.schema().has_tenant() {
+        return Err(Response::error("400 Bad Request", "the server does not support tenants"));
+    }
+    let schema = input.schema().clone();
+    let mut posted = Vec::new();
+    while let Some(line) = input.next() {
+        let mut tx_id = None;
+        let sent = Transaction::parse_with_aliases(&line, &schema, config.amount_scale, config.aliases.as_deref())
+            .map_err(|err| err.describe())
+            .and_then(|mut tx| {
+                if let Some(reference) = &config.reference {
+                    tx.set_attributes(reference.attributes(tx.tenant(), tx.client_id()).cloned());
+                }
+                tx_id = Some(tx.tx_id());
+                send(tx)
+            });
+        posted.push(Posted { line: input.line(), tx_id, sent });
+    }
+    Ok(posted)
+}
+
+// The accounts with respect to the JSON accounts output, with the tenant column when `tenanted`.
+pub(crate) fn json<'a>(config: &Config, format: JsonFormat, tenanted: bool, accounts: impl IntoIterator<Item = &'a Account>)
+                       -> String {
+    let mut out = Vec::new();
+    let mut sink = JsonSink::new(&mut out, format, config);
+    // Writing into memory can not fail.
+    let _ = sink.start(tenanted);
+    for account in accounts {
+        let _ = sink.account(account);
+    }
+    let _ = sink.finish();
+    drop(sink);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Reads a stream until its deadline, whatever the pace the bytes are sent at.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "the request was not received in time"));
+        }
+        self.stream.set_read_timeout(Some(left.min(TIMEOUT)))?;
+        self.stream.read(buf)
+    }
+}
+
+// Why a request was not read: the connection failed, and is dropped, or the request is refused
+// with a response.
+pub(crate) enum ReadError {
+    Io(io::Error),
+    Refused(Response),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        ReadError::Io(err)
+    }
+}
+
+// Reads the request line, the headers and the body the `Content-Length` header announces. A head
+// larger than `MAX_HEAD` or with more than `MAX_HEADERS` headers is refused with a 431, and a body
+// larger than `MAX_BODY` with a 413.
+pub(crate) fn read_request(stream: &mut TcpStream) -> Result<Request, ReadError> {
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(Deadline { stream, until: Instant::now() + REQUEST_DEADLINE });
+    let mut left = MAX_HEAD;
+    let request_line = read_line(&mut reader, &mut left)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(ReadError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "missing request line")))
+    };
+
+    let (mut length, mut authorization) = (0, None);
+    for count in 0.. {
+        let header = read_line(&mut reader, &mut left)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(ReadError::Refused(head_too_large()));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = match value.trim().parse::<usize>() {
+                    Ok(length) => length,
+                    Err(_) => return Err(ReadError::Refused(Response::error("400 Bad Request", "invalid Content-Length")))
+                };
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(ReadError::Refused(Response::error("413 Payload Too Large",
+                                                      &format!("the body exceeds {} bytes", MAX_BODY))));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, authorization, body: String::from_utf8_lossy(&body).into_owned() })
+}
+
+// Reads a line of the head, out of the `left` bytes it may still take.
+fn read_line(reader: &mut impl BufRead, left: &mut u64) -> Result<String, ReadError> {
+    let mut line = String::new();
+    let read = reader.take(*left).read_line(&mut line)?;
+    *left -= read as u64;
+    if *left == 0 && !line.ends_with('\n') {
+        return Err(ReadError::Refused(head_too_large()));
+    }
+    Ok(line)
+}
+
+fn head_too_large() -> Response {
+    Response::error("431 Request Header Fields Too Large", &format!("the head exceeds {} bytes or {} headers",
+                                                                    MAX_HEAD, MAX_HEADERS))
+}
+
+pub(crate) fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           response.status, response.body.len(), response.body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Collector;
+
+    // Sends a request, returning the status line and the body of the response.
+    fn request(port: u16, method: &str, path: &str, body: &str) -> (String, String) {
+        request_with(port, method, path, "", body)
+    }
+
+    fn request_with(port: u16, method: &str, path: &str, headers: &str, body: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}", method, path, headers,
+               body.len(), body)
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    // Waits for the transactions sent so far to be applied.
+    fn account(port: u16, client: &str, expected: &str) {
+        for _ in 0..100 {
+            if request(port, "GET", &format!("/accounts/{}", client), "").1.contains(expected) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("The account of client {} never got {}.", client, expected);
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let collector = Collector::new();
+        let config = Config { account_sinks: vec![collector.factory()], ..Config::default() };
+        let server = thread::spawn(move || serve(listener, State::default(), config, None));
+
+        let (status, body) = request(port, "POST", "/transactions",
+                                     "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,-1.0\ndeposit,2,3,1.0\n\
+                                      withdrawal,2,6,5.0\n");
+        assert_eq!(status, "HTTP/1.1 202 Accepted");
+        assert_eq!(body, "{\"accepted\":3,\"invalid\":[{\"line\":3,\"reason\":\"the amount is negative\"}],\"records\":[\
+                          {\"line\":2,\"tx\":1,\"ack\":\"applied\"},{\"line\":3,\"nack\":\"the amount is negative\"},\
+                          {\"line\":4,\"tx\":3,\"ack\":\"applied\"},{\"line\":5,\"tx\":6,\"nack\":\"insufficient_funds\"}]}");
+        account(port, "1", "\"available\":2.0000");
+        let (status, body) = request(port, "POST", "/adjustments", "type,client,tx,amount\ndebit,1,4,0.5\ndeposit,1,5,1.0\n");
+        assert_eq!(status, "HTTP/1.1 202 Accepted");
+        assert!(body.starts_with("{\"accepted\":1,\"invalid\":[{\"line\":3,"));
+        assert!(body.contains("\"records\":[{\"line\":2,\"tx\":4,\"ack\":\"applied\"},{\"line\":3,\"tx\":5,\"nack\":"));
+        account(port, "1", "\"available\":1.5000");
+        let (status, body) = request(port, "GET", "/accounts", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body.matches("\"client\"").count(), 2);
+
+        assert_eq!(request(port, "GET", "/accounts/3", "").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(request(port, "GET", "/transactions", "").0, "HTTP/1.1 405 Method Not Allowed");
+        assert_eq!(request(port, "POST", "/transactions", "deposit,1,1,1.0\n").0, "HTTP/1.1 400 Bad Request");
+        assert_eq!(request(port, "POST", "/shutdown", "").0, "HTTP/1.1 200 OK");
+
+        let run_report = server.join().unwrap().unwrap();
+        assert_eq!((run_report.records, run_report.volumes.adjustments.count), (4, 1));
+        let mut accounts: Vec<String> = collector.take().iter().map(|account| account.to_string()).collect();
+        accounts.sort();
+        assert_eq!(accounts, vec!["1,1.5000,0.0000,1.5000,false,active", "2,1.0000,0.0000,1.0000,false,active"]);
+    }
+
+    #[test]
+    fn test_serve_api_token() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = Config { api_token: Some(String::from("secret")), ..Config::default() };
+        let server = thread::spawn(move || serve(listener, State::default(), config, None));
+
+        let deposit = "type,client,tx,amount\ndeposit,1,1,2.0\n";
+        assert_eq!(request(port, "POST", "/adjustments", "type,client,tx,amount\ncredit,1,2,5.0\n").0,
+                   "HTTP/1.1 401 Unauthorized");
+        assert_eq!(request_with(port, "POST", "/transactions", "Authorization: Bearer secreT\r\n", deposit).0,
+                   "HTTP/1.1 401 Unauthorized");
+        assert_eq!(request(port, "POST", "/shutdown", "").0, "HTTP/1.1 401 Unauthorized");
+        assert_eq!(request_with(port, "POST", "/transactions", "Authorization: Bearer secret\r\n", deposit).0,
+                   "HTTP/1.1 202 Accepted");
+        assert_eq!(request_with(port, "POST", "/shutdown", "authorization: Bearer secret\r\n", "").0, "HTTP/1.1 200 OK");
+        assert_eq!(server.join().unwrap().unwrap().records, 1);
+    }
+
+    // Reads a request out of the raw bytes a client sent, returning the status it is refused with.
+    fn refused(raw: Vec<u8>) -> Option<String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let _ = stream.write_all(&raw);
+            stream
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        let refused = match read_request(&mut stream) {
+            Err(ReadError::Refused(response)) => Some(response.status.to_string()),
+            _ => None
+        };
+        drop(client.join().unwrap());
+        refused
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let mut endless = b"GET /".to_vec();
+        endless.resize(MAX_HEAD as usize + 1, b'a');
+        assert_eq!(refused(endless).as_deref(), Some("431 Request Header Fields Too Large"));
+        let mut header = b"GET /accounts HTTP/1.1\r\nX-Filler: ".to_vec();
+        header.resize(MAX_HEAD as usize + 1, b'a');
+        assert_eq!(refused(header).as_deref(), Some("431 Request Header Fields Too Large"));
+        let headers = format!("GET /accounts HTTP/1.1\r\n{}\r\n", "X-Filler: a\r\n".repeat(MAX_HEADERS + 1));
+        assert_eq!(refused(headers.into_bytes()).as_deref(), Some("431 Request Header Fields Too Large"));
+        let headers = format!("GET /accounts HTTP/1.1\r\n{}\r\n", "X-Filler: a\r\n".repeat(MAX_HEADERS));
+        assert_eq!(refused(headers.into_bytes()), None);
+
+        let large = format!("POST /transactions HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1);
+        assert_eq!(refused(large.into_bytes()).as_deref(), Some("413 Payload Too Large"));
+        let invalid = b"POST /transactions HTTP/1.1\r\nContent-Length: -1\r\n\r\n".to_vec();
+        assert_eq!(refused(invalid).as_deref(), Some("400 Bad Request"));
+    }
+
+    #[test]
+    fn test_serve_slow_client() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || serve(listener, State::default(), Config::default(), None));
+
+        // A client that sends half of its request and then nothing does not hold the others.
+        let mut slow = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(slow, "POST /transactions HTTP/1.1\r\n").unwrap();
+        let started = Instant::now();
+        assert_eq!(request(port, "POST", "/transactions", "type,client,tx,amount\ndeposit,1,1,2.0\n").0,
+                   "HTTP/1.1 202 Accepted");
+        account(port, "1", "\"available\":2.0000");
+        assert!(started.elapsed() < TIMEOUT);
+        drop(slow);
+
+        assert_eq!(request(port, "POST", "/shutdown", "").0, "HTTP/1.1 200 OK");
+        assert_eq!(server.join().unwrap().unwrap().records, 1);
+    }
+}
@@ -0,0 +1,152 @@
+// Horizontal partitioning of a run across worker processes. The coordinator streams the input
+// into one shard file per worker, hashing the clients so that all the records of a client end up
+// in the same shard, runs a worker process per shard, and gathers the state files the workers
+// save into the state of the whole run.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::digest::hash64;
+use crate::input::Schema;
+use crate::state::{self, State};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The header line of the input is missing or invalid.
+    InvalidHeader,
+    // The worker of the shard failed, with its exit code unless it was killed.
+    WorkerFailed(usize, Option<i32>),
+    // The state file saved by the worker of the shard could not be read.
+    State(usize, state::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// The shard of a record. The raw tenant and client fields are hashed, so that the clients of
+// aliased inputs are sharded by their external ids, and invalid records are rejected by a worker.
+pub fn shard(tenant: &str, client: &str, count: usize) -> usize {
+    let mut data = tenant.trim().as_bytes().to_vec();
+    data.push(b',');
+    data.extend_from_slice(client.trim().as_bytes());
+    (hash64(&data) % count as u64) as usize
+}
+
+pub fn shard_path(dir: &Path, shard: usize) -> PathBuf {
+    dir.join(format!("shard-{}.csv", shard))
+}
+
+pub fn state_path(dir: &Path, shard: usize) -> PathBuf {
+    dir.join(format!("shard-{}.state", shard))
+}
+
+// Splits the input into `count` shard files under `dir`, every one starting with the header
+// line of the input, and returns the schema of the input. The records are streamed, the input is
+// never held in memory.
+pub fn split<R: BufRead>(mut reader: R, dir: &Path, count: usize) -> Result<Schema> {
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let schema = Schema::from_header(&header).ok_or(Error::InvalidHeader)?;
+
+    let mut writers = Vec::with_capacity(count);
+    for shard in 0..count {
+        let mut writer = BufWriter::new(File::create(shard_path(dir, shard))?);
+        writer.write_all(header.as_bytes())?;
+        writers.push(writer);
+    }
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        let tenant = schema.tenant().and_then(|idx| fields.get(idx)).copied().unwrap_or("");
+        let client = fields.get(schema.client()).copied().unwrap_or("");
+        writeln!(writers[shard(tenant, client, count)], "{}", line)?;
+    }
+    for writer in &mut writers {
+        writer.flush()?;
+    }
+    Ok(schema)
+}
+
+// Runs a worker process per shard, in parallel, passing it the shard file, the arguments of the
+// run and the state file to save. The output of the workers is discarded.
+pub fn run(program: &Path, args: &[String], dir: &Path, count: usize) -> Result<()> {
+    let mut workers = Vec::with_capacity(count);
+    for shard in 0..count {
+        let worker = Command::new(program)
+            .arg(shard_path(dir, shard))
+            .args(args)
+            .arg("--save-state")
+            .arg(state_path(dir, shard))
+            .stdout(Stdio::null())
+            .spawn()?;
+        workers.push(worker);
+    }
+
+    let mut result = Ok(());
+    for (shard, mut worker) in workers.into_iter().enumerate() {
+        let status = worker.wait()?;
+        if !status.success() && result.is_ok() {
+            result = Err(Error::WorkerFailed(shard, status.code()));
+        }
+    }
+    result
+}
+
+// Gathers the state files of the workers, with the accounts in tenant and client order.
+pub fn gather(dir: &Path, count: usize) -> Result<State> {
+    let mut gathered = State::default();
+    for shard in 0..count {
+        let state = state::read_file(&state_path(dir, shard)).map_err(|err| Error::State(shard, err))?;
+        gathered.accounts.extend(state.accounts);
+        gathered.history.extend(state.history);
+    }
+    gathered.accounts.sort_by(|a, b| (a.tenant(), a.client_id()).cmp(&(b.tenant(), b.client_id())));
+    Ok(gathered)
+}
+
+// Removes the shard files and the state files of the workers.
+pub fn clean(dir: &Path) -> io::Result<()> {
+    fs::remove_dir_all(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{Account, Status};
+    use crate::amount::{from_f64, ZERO};
+
+    #[test]
+    fn test_shard_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = "type,client,tx,amount,tenant\ndeposit,1,1,1.0,acme\ndeposit, 1,2,2.0,acme\ndeposit,1,3,1.0,\n\
+                     deposit,2,4,1.0,acme\n";
+        assert!(split(input.as_bytes(), dir.path(), 3).unwrap().has_tenant());
+
+        let shards: Vec<String> = (0..3).map(|shard| fs::read_to_string(shard_path(dir.path(), shard)).unwrap()).collect();
+        assert!(shards.iter().all(|shard| shard.starts_with("type,client,tx,amount,tenant\n")));
+        assert_eq!(shards.iter().map(|shard| shard.lines().count() - 1).sum::<usize>(), 4);
+        let acme = &shards[shard("acme", "1", 3)];
+        assert!(acme.contains("deposit,1,1,1.0,acme\n") && acme.contains("deposit, 1,2,2.0,acme\n"));
+        assert!(shards[shard("", "1", 3)].contains("deposit,1,3,1.0,\n"));
+
+        assert!(matches!(split("type,client\n".as_bytes(), dir.path(), 3), Err(Error::InvalidHeader)));
+    }
+
+    #[test]
+    fn test_shard_gather() {
+        let dir = tempfile::tempdir().unwrap();
+        state::write_file(&state_path(dir.path(), 0), &[Account::new(2, from_f64(1.0), ZERO, Status::Active)], &[], None).unwrap();
+        state::write_file(&state_path(dir.path(), 1), &[Account::new(1, from_f64(2.0), ZERO, Status::Locked)], &[], None).unwrap();
+        let state = gather(dir.path(), 2).unwrap();
+        assert_eq!(state.accounts.iter().map(Account::client_id).collect::<Vec<u16>>(), vec![1, 2]);
+        assert!(matches!(gather(dir.path(), 3), Err(Error::State(2, _))));
+    }
+}
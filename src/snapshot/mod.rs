@@ -0,0 +1,185 @@
+// Snapshots of the accounts taken while a run is still going, e.g. over a channel source that is
+// fed for hours, so the downstream consumers do not have to wait for the end of the run. The
+// snapshots are built out of the audit entries, which carry the funds and the status of the
+// accounts right after every transaction.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::account::Account;
+use crate::amount::ZERO;
+use crate::audit::{Entry, Sink};
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::report;
+
+// When the snapshots are taken, and whether every snapshot replaces the previous one.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Schedule {
+    // After this many transactions were handled since the previous snapshot.
+    pub every: Option<usize>,
+    // After this much time went by since the previous snapshot, once a transaction is handled.
+    pub interval: Option<Duration>,
+    // Every snapshot is written to its own `<stem>-<unix seconds>-<sequence>.<extension>` file,
+    // instead of replacing the previous one.
+    pub timestamped: bool,
+}
+
+pub struct Snapshots {
+    path: PathBuf,
+    schedule: Schedule,
+    // The rules, the pseudonymizer and the aliases of the run, the accounts being written like its
+    // outputs.
+    config: Config,
+    with_tenant: bool,
+    accounts: BTreeMap<(Option<String>, u16), Account>,
+    handled: usize,
+    clock: Arc<dyn Clock>,
+    // Time of the clock when the previous snapshot was taken.
+    taken_at: Duration,
+    taken: usize,
+}
+
+impl Snapshots {
+    // The snapshots start from the accounts restored for the run, if any.
+    pub fn new(path: PathBuf, schedule: Schedule, config: &Config, with_tenant: bool, restored: &[Account]) -> Self {
+        let clock = config.clock.clone();
+        let config = Config { rules: config.rules.clone(), pseudonymizer: config.pseudonymizer.clone(),
+                              aliases: config.aliases.clone(),
+                              ..Config::default() };
+        let accounts = restored.iter()
+            .map(|account| ((account.tenant().map(String::from), account.client_id()), account.clone()))
+            .collect();
+        let taken_at = clock.elapsed();
+        Snapshots { path, schedule, config, with_tenant, accounts, handled: 0, clock, taken_at, taken: 0 }
+    }
+
+    fn due(&self) -> bool {
+        matches!(self.schedule.every, Some(every) if self.handled >= every)
+            || matches!(self.schedule.interval, Some(interval) if self.clock.elapsed() - self.taken_at >= interval)
+    }
+
+    // Writes the snapshot to a scratch file first, then moves it in place, so the consumers never
+    // read a partial snapshot.
+    fn take(&mut self) -> io::Result<()> {
+        let accounts: Vec<Account> = self.accounts.values().cloned().collect();
+        let path = if self.schedule.timestamped {
+            timestamped_path(&self.path, self.clock.unix_seconds(), self.taken)
+        } else {
+            self.path.clone()
+        };
+        let mut scratch = OsString::from(path.as_os_str());
+        scratch.push(".tmp");
+        let scratch = PathBuf::from(scratch);
+        report::write_file(&scratch, |writer| report::write_accounts(writer, &accounts, &self.config,
+                                                                      self.with_tenant))?;
+        fs::rename(&scratch, &path)?;
+        self.handled = 0;
+        self.taken_at = self.clock.elapsed();
+        self.taken += 1;
+        Ok(())
+    }
+}
+
+// `<stem>-<unix seconds>-<sequence>.<extension>`, next to `path`.
+fn timestamped_path(path: &Path, seconds: u64, sequence: usize) -> PathBuf {
+    let mut name = path.file_stem().map(OsString::from).unwrap_or_default();
+    name.push(format!("-{}-{}", seconds, sequence));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+impl Sink for Snapshots {
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        let mut account = Account::new(entry.client_id, entry.after.available, entry.after.held, entry.status);
+        account.set_tenant(entry.tenant.clone());
+        if self.config.rules.two_step_withdrawals {
+            let pending = entry.after.total - entry.after.available - entry.after.held;
+            if pending > ZERO {
+                let _ = account.add_pending(pending);
+            }
+        }
+        self.accounts.insert((entry.tenant.clone(), entry.client_id), account);
+        self.handled += 1;
+        if self.due() {
+            self.take()?;
+        }
+        Ok(())
+    }
+
+    // The last snapshot holds the final accounts.
+    fn finish(&mut self) -> io::Result<()> {
+        if self.handled > 0 || self.taken == 0 {
+            self.take()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use crate::audit::{Balance, Outcome};
+    use crate::clock::MockClock;
+    use crate::transaction::Type;
+    use crate::amount::{from_f64, Amount, ZERO};
+
+    fn entry(client_id: u16, available: Amount, status: Status) -> Entry {
+        Entry { tenant: None, client_id, tx_id: 1, ttype: Type::Deposit, amount: Some(available),
+                outcome: Outcome::Applied, note: None, before: Balance::default(),
+                after: Balance { available, held: ZERO, total: available }, timestamp: None, locked: false,
+                previous_status: status, status, record: None, source: None }
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.csv");
+        let schedule = Schedule { every: Some(2), ..Schedule::default() };
+        let restored = [Account::new(3, from_f64(1.0), ZERO, Status::Active)];
+        let mut snapshots = Snapshots::new(path.clone(), schedule, &Config::default(), false, &restored);
+        snapshots.record(&entry(1, from_f64(1.0), Status::Active)).unwrap();
+        assert!(!path.exists());
+        snapshots.record(&entry(2, from_f64(2.0), Status::Active)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(),
+                   "client,available,held,total,locked,status\n\
+                    1,1.0000,0.0000,1.0000,false,active\n\
+                    2,2.0000,0.0000,2.0000,false,active\n\
+                    3,1.0000,0.0000,1.0000,false,active\n");
+        snapshots.record(&entry(1, from_f64(0.5), Status::Locked)).unwrap();
+        snapshots.finish().unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("\n1,0.5000,0.0000,0.5000,true,locked\n"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_snapshots_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.csv");
+        let schedule = Schedule { interval: Some(Duration::from_secs(60)), timestamped: true, ..Schedule::default() };
+        let clock = Arc::new(MockClock::new(1_000));
+        let config = Config { clock: clock.clone(), ..Config::default() };
+        let mut snapshots = Snapshots::new(path, schedule, &config, false, &[]);
+        snapshots.record(&entry(1, from_f64(1.0), Status::Active)).unwrap();
+        clock.advance(Duration::from_secs(60));
+        snapshots.record(&entry(2, from_f64(1.0), Status::Active)).unwrap();
+        snapshots.record(&entry(3, from_f64(1.0), Status::Active)).unwrap();
+        assert!(dir.path().join("accounts-1060-0.csv").exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_timestamped_path() {
+        assert_eq!(timestamped_path(Path::new("out/accounts.csv"), 100, 2), Path::new("out/accounts-100-2.csv"));
+        assert_eq!(timestamped_path(Path::new("accounts"), 100, 0), Path::new("accounts-100-0"));
+    }
+}
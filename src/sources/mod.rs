@@ -0,0 +1,122 @@
+// Several sources of transactions feeding a single run, e.g. a batch file along with the
+// transactions an application sends over a channel. Every source is read on a thread of its own,
+// so that a live source waiting for its next transaction does not hold back the others, and the
+// transactions are handed out in the order they arrive. The transactions carry the label of their
+// source into the audit trail, the dead letters and the run report, so that a record can be
+// traced back to its origin, its position being numbered by its source.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use crate::report::SourceStats;
+use crate::transaction::{Transaction, TransactionSource};
+
+// Counters of a source, updated by its reader thread.
+#[derive(Debug, Default)]
+struct Counters {
+    transactions: AtomicUsize,
+    invalid: AtomicUsize,
+}
+
+pub struct Sources {
+    // Handed to the reader threads, and dropped once the transactions are read, so that the run
+    // ends with the last source.
+    sender: Option<SyncSender<Transaction>>,
+    receiver: Receiver<Transaction>,
+    labels: Vec<(String, Arc<Counters>)>,
+    with_tenant: bool,
+}
+
+impl Sources {
+    // Up to `buffer` transactions of all the sources are read ahead of the run.
+    pub fn new(buffer: usize) -> Self {
+        let (sender, receiver) = sync_channel(buffer);
+        Sources { sender: Some(sender), receiver, labels: Vec::new(), with_tenant: false }
+    }
+
+    // Starts reading the source. The transactions that do not come with a source label, e.g. from
+    // a `source` column, get `label`, which is expected to be unique among the sources.
+    pub fn add<S: TransactionSource + Send + 'static>(&mut self, label: &str, mut source: S) {
+        let sender = match &self.sender {
+            Some(sender) => sender.clone(),
+            None => panic!("The sources can not be added to once they are read.")
+        };
+        self.with_tenant |= source.has_tenant();
+        let counters = Arc::new(Counters::default());
+        self.labels.push((label.to_string(), counters.clone()));
+        let label = label.to_string();
+        thread::spawn(move || {
+            while let Some(mut tx) = source.next() {
+                counters.transactions.fetch_add(1, Ordering::Relaxed);
+                counters.invalid.store(source.invalid(), Ordering::Relaxed);
+                if tx.source().is_none() {
+                    tx.set_source(Some(label.clone()));
+                }
+                // The run is over, e.g. aborted.
+                if sender.send(tx).is_err() {
+                    return;
+                }
+            }
+            counters.invalid.store(source.invalid(), Ordering::Relaxed);
+        });
+    }
+}
+
+impl Iterator for Sources {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.sender = None;
+        self.receiver.recv().ok()
+    }
+}
+
+impl TransactionSource for Sources {
+    fn has_tenant(&self) -> bool {
+        self.with_tenant
+    }
+
+    fn invalid(&self) -> usize {
+        self.labels.iter().map(|(_, counters)| counters.invalid.load(Ordering::Relaxed)).sum()
+    }
+
+    fn sources(&self) -> Vec<SourceStats> {
+        self.labels.iter().map(|(label, counters)| {
+            let invalid = counters.invalid.load(Ordering::Relaxed);
+            let records = counters.transactions.load(Ordering::Relaxed) + invalid;
+            SourceStats { label: label.clone(), records, invalid, ..SourceStats::default() }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::input::Input;
+    use crate::transaction::{ChannelSource, TransactionIterator};
+
+    #[test]
+    fn test_sources() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut sources = Sources::new(4);
+        sources.add("batch", TransactionIterator::new(Input::from(&b"type,client,tx,amount\ndeposit,1,1,1.0\nx\n"[..])));
+        sources.add("live", ChannelSource::new(receiver));
+        sender.send(Tx::deposit(2, 2).amount(2.0).build()).unwrap();
+        drop(sender);
+
+        let mut txs: Vec<(Option<String>, u32, Option<u64>)> = sources.by_ref()
+            .map(|tx| (tx.source().map(String::from), tx.tx_id(), tx.record()))
+            .collect();
+        txs.sort();
+        assert_eq!(txs, vec![(Some(String::from("batch")), 1, Some(1)), (Some(String::from("live")), 2, Some(1))]);
+        assert!(!sources.has_tenant());
+        assert_eq!(sources.invalid(), 1);
+        let stats: Vec<(String, usize, usize)> = sources.sources().into_iter()
+            .map(|source| (source.label, source.records, source.invalid))
+            .collect();
+        assert_eq!(stats, vec![(String::from("batch"), 2, 1), (String::from("live"), 1, 0)]);
+    }
+}
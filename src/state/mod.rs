@@ -0,0 +1,412 @@
+// Versioned state files. A state file holds the accounts, their open disputes and their history at
+// the end of a run, so that a later run can resume from them. Every state file starts with a
+// version header, and the files written by older releases are migrated to the current version
+// when they are read.
+//
+// Version 1 is the accounts output of the releases before the state files, i.e.
+// `[tenant,]client,available,held[,pending],total,locked[,status]`, which carries no history.
+// Version 2 is made of `account`, `dispute` and `tx` records:
+//
+//     account,<tenant>,<client>,<available>,<held>,<pending>,<status>,<max exposure>
+//     dispute,<tenant>,<client>,<tx>,<amount>,<reason>,<opened at>
+//     tx,<tenant>,<client>,<tx>,<type>,<amount>,<refunded>,<dispute state>,<dispute hold>,<pending>,
+//        <authorized>,<reason>,<record>,<timestamp>
+//
+// Version 3 adds the optional `checkpoint,<offset>,<records>` record, where the run that wrote the
+// state stopped reading its input.
+// Version 4 appends the `<charged back>` amount to the `tx` records, as the partial chargebacks
+// leave part of a transaction to dispute. The charged back transactions of the earlier versions
+// were charged back for their dispute hold.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::account::{Account, Dispute, FreezeMode, Status};
+use crate::amount::{self, Amount};
+use crate::input::Checkpoint;
+use crate::report::{self, NO_TENANT};
+use crate::transaction::{Transaction, Type};
+
+pub const VERSION: u32 = 4;
+const HEADER: &str = "# transaction-processor state v";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The state file was written by a newer release, it can not be migrated back.
+    UnsupportedVersion(u32),
+    // Neither a state file, nor the accounts output of an earlier release.
+    UnknownFormat,
+    // Number of the line of the state file that could not be read, starting at 1.
+    InvalidRecord(usize),
+}
+
+impl Error {
+    // Tells what is wrong with the state file at `path`, for the operators.
+    pub fn describe(&self, path: &Path) -> String {
+        match self {
+            Error::Io(_) => format!("Could not read the state file {}.", path.display()),
+            Error::UnsupportedVersion(version) => {
+                format!("The state file {} has version {}, while this release reads up to version {}. Please resume \
+                         it with a newer release.", path.display(), version, VERSION)
+            }
+            Error::UnknownFormat => format!("{} is neither a state file, nor an accounts output.", path.display()),
+            Error::InvalidRecord(line) => format!("Line {} of the state file {} is invalid.", line, path.display())
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+// An account along with its history.
+pub type Restored = (Account, Vec<Transaction>);
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct State {
+    pub accounts: Vec<Account>,
+    // Transactions of the histories of all the accounts.
+    pub history: Vec<Transaction>,
+    // Where the run that wrote the state stopped reading its input, if it was read from a file.
+    pub checkpoint: Option<Checkpoint>,
+}
+
+impl State {
+    // Splits the state by tenant and client, in order, pairing every account with its history.
+    pub fn into_tenants(self) -> BTreeMap<Option<String>, BTreeMap<u16, Restored>> {
+        let mut tenants: BTreeMap<Option<String>, BTreeMap<u16, Restored>> = BTreeMap::new();
+        for account in self.accounts {
+            tenants.entry(account.tenant().map(String::from)).or_default()
+                .insert(account.client_id(), (account, Vec::new()));
+        }
+        for tx in self.history {
+            let restored = tenants.get_mut(&tx.tenant().map(String::from))
+                .and_then(|accounts| accounts.get_mut(&tx.client_id()));
+            if let Some((_, history)) = restored {
+                history.push(tx);
+            }
+        }
+        tenants
+    }
+}
+
+fn status_str(status: Status) -> &'static str {
+    match status {
+        Status::Frozen(FreezeMode::Withdrawals) => "frozen-withdrawals",
+        Status::Frozen(FreezeMode::Full) => "frozen-full",
+        status => status.as_str()
+    }
+}
+
+fn dispute_state(tx: &Transaction) -> &'static str {
+    if tx.is_disputed() {
+        "disputed"
+    } else if tx.is_resolved() {
+        "resolved"
+    } else if tx.is_charged_back() {
+        "charged_back"
+    } else if tx.is_represented() {
+        "represented"
+    } else {
+        ""
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Writes the state of the accounts and of their history, in the current version.
+pub fn write<W: Write>(writer: &mut W, accounts: &[Account], history: &[Transaction], checkpoint: Option<Checkpoint>)
+    -> io::Result<()> {
+    writeln!(writer, "{}{}", HEADER, VERSION)?;
+    if let Some(checkpoint) = checkpoint {
+        writeln!(writer, "checkpoint,{},{}", checkpoint.offset, checkpoint.records)?;
+    }
+    for account in accounts {
+        let tenant = account.tenant().unwrap_or("");
+        writeln!(writer, "account,{},{},{},{},{},{},{}", tenant, account.client_id(), amount::fmt(account.available()),
+                 amount::fmt(account.held()), amount::fmt(account.pending()), status_str(account.status()),
+                 amount::fmt(account.max_exposure()))?;
+        for dispute in account.open_disputes() {
+            writeln!(writer, "dispute,{},{},{},{},{},{}", tenant, account.client_id(), dispute.tx_id,
+                     amount::fmt(dispute.amount), dispute.reason.as_deref().unwrap_or(""), optional(dispute.opened_at))?;
+        }
+    }
+    for tx in history {
+        writeln!(writer, "tx,{},{},{},{},{},{},{},{},{},{},{},{},{},{}", tx.tenant().unwrap_or(""), tx.client_id(),
+                 tx.tx_id(), tx.transaction_type().as_str(), optional(tx.amount().map(amount::fmt)),
+                 amount::fmt(tx.refunded()), dispute_state(tx), optional(tx.dispute_hold().map(amount::fmt)),
+                 tx.is_pending(), tx.is_authorized(), tx.reason().unwrap_or(""), optional(tx.record()),
+                 optional(tx.timestamp()), amount::fmt(tx.charged_back()))?;
+    }
+    Ok(())
+}
+
+pub fn write_file(path: &Path, accounts: &[Account], history: &[Transaction], checkpoint: Option<Checkpoint>)
+    -> io::Result<()> {
+    report::write_file(path, |writer| write(writer, accounts, history, checkpoint))
+}
+
+// Reads a state file of any supported version, migrating it to the current one.
+pub fn read<R: BufRead>(reader: R) -> Result<State> {
+    let mut lines = reader.lines();
+    let header = match lines.next() {
+        Some(header) => header?,
+        None => return Err(Error::UnknownFormat)
+    };
+    match header.strip_prefix(HEADER).map(|version| version.trim_end().parse::<u32>()) {
+        Some(Ok(version @ 2..=4)) => read_records(lines, version),
+        Some(Ok(version)) if version > VERSION => Err(Error::UnsupportedVersion(version)),
+        Some(_) => Err(Error::UnknownFormat),
+        None => migrate_v1(&header, lines)
+    }
+}
+
+pub fn read_file(path: &Path) -> Result<State> {
+    read(BufReader::new(File::open(path)?))
+}
+
+fn text(value: &str) -> Option<String> {
+    if value.is_empty() { None } else { Some(String::from(value)) }
+}
+
+// Reads the records of version 2 to 4, the files of version 2 having no checkpoint.
+fn read_records<L: Iterator<Item = io::Result<String>>>(lines: L, version: u32) -> Result<State> {
+    let tx_fields = if version < 4 { 14 } else { 15 };
+    let mut state = State::default();
+    // Position of every account in the state, by tenant and client.
+    let mut positions: HashMap<(Option<String>, u16), usize> = HashMap::new();
+    for (idx, line) in lines.enumerate() {
+        let line = line?;
+        // The header is the first line.
+        let invalid = || Error::InvalidRecord(idx + 2);
+        let fields: Vec<&str> = line.split(',').collect();
+        let field = |idx: usize| fields.get(idx).copied().ok_or_else(invalid);
+        let number = |idx: usize| -> Result<Option<u64>> {
+            match field(idx)? {
+                "" => Ok(None),
+                value => value.parse().map(Some).map_err(|_| invalid())
+            }
+        };
+        let money = |idx: usize| -> Result<Option<Amount>> {
+            match field(idx)? {
+                "" => Ok(None),
+                value => amount::parse(value).map(Some).ok_or_else(invalid)
+            }
+        };
+        let flag = |idx: usize| field(idx)?.parse::<bool>().map_err(|_| invalid());
+        if fields[0] == "checkpoint" && fields.len() == 3 && state.checkpoint.is_none() {
+            let offset = number(1)?.ok_or_else(invalid)?;
+            let records = number(2)?.ok_or_else(invalid)?;
+            state.checkpoint = Some(Checkpoint { offset, records });
+            continue;
+        }
+        let tenant = text(field(1)?);
+        let client_id = field(2)?.parse::<u16>().map_err(|_| invalid())?;
+        match fields[0] {
+            "account" if fields.len() == 8 => {
+                let status = Status::parse(field(6)?).ok_or_else(invalid)?;
+                let mut account = Account::new(client_id, money(3)?.ok_or_else(invalid)?,
+                                               money(4)?.ok_or_else(invalid)?, status);
+                let _ = account.add_pending(money(5)?.ok_or_else(invalid)?);
+                account.restore_max_exposure(money(7)?.ok_or_else(invalid)?);
+                account.set_tenant(tenant.clone());
+                if positions.insert((tenant, client_id), state.accounts.len()).is_some() {
+                    return Err(invalid());
+                }
+                state.accounts.push(account);
+            }
+            "dispute" if fields.len() == 7 => {
+                let dispute = Dispute {
+                    tx_id: field(3)?.parse().map_err(|_| invalid())?,
+                    amount: money(4)?.ok_or_else(invalid)?,
+                    reason: text(field(5)?),
+                    opened_at: number(6)?
+                };
+                let position = positions.get(&(tenant, client_id)).ok_or_else(invalid)?;
+                state.accounts[*position].restore_dispute(dispute);
+            }
+            "tx" if fields.len() == tx_fields => {
+                if !positions.contains_key(&(tenant.clone(), client_id)) {
+                    return Err(invalid());
+                }
+                let ttype = Type::from(field(4)?);
+                if ttype == Type::ERR {
+                    return Err(invalid());
+                }
+                let mut tx = match money(5)? {
+                    Some(amount) => Transaction::new_with_amount(ttype, client_id, field(3)?.parse().map_err(|_| invalid())?,
+                                                                 amount),
+                    None => Transaction::new(ttype, client_id, field(3)?.parse().map_err(|_| invalid())?)
+                };
+                tx.add_refunded(money(6)?.ok_or_else(invalid)?);
+                match field(7)? {
+                    "" => (),
+                    "disputed" => tx.mark_disputed(),
+                    "resolved" => tx.mark_resolved(),
+                    "charged_back" => tx.mark_charged_back(),
+                    "represented" => tx.mark_represented(),
+                    _ => return Err(invalid())
+                }
+                if let Some(hold) = money(8)? {
+                    tx.set_held_amount(hold);
+                }
+                if flag(9)? {
+                    tx.mark_pending();
+                }
+                if flag(10)? {
+                    tx.mark_authorized();
+                }
+                tx.set_reason(text(field(11)?));
+                tx.set_record(number(12)?);
+                tx.set_timestamp(number(13)?);
+                match fields.get(14) {
+                    Some(_) => tx.set_charged_back(money(14)?.ok_or_else(invalid)?),
+                    None if tx.is_charged_back() => tx.set_charged_back(tx.held_amount()),
+                    None => ()
+                }
+                tx.set_tenant(tenant);
+                state.history.push(tx);
+            }
+            _ => return Err(invalid())
+        }
+    }
+    Ok(state)
+}
+
+// Reads the accounts output of an earlier release as opening balances, without any history.
+fn migrate_v1<L: Iterator<Item = io::Result<String>>>(header: &str, lines: L) -> Result<State> {
+    let columns: Vec<&str> = header.trim_end().split(',').collect();
+    let column = |name: &str| columns.iter().position(|column| *column == name);
+    let (client, available, held, locked) = match (column("client"), column("available"), column("held"),
+                                                   column("locked")) {
+        (Some(client), Some(available), Some(held), Some(locked)) => (client, available, held, locked),
+        _ => return Err(Error::UnknownFormat)
+    };
+    let (tenant, pending, status) = (column("tenant"), column("pending"), column("status"));
+
+    let mut state = State::default();
+    for (idx, line) in lines.enumerate() {
+        let line = line?;
+        let invalid = || Error::InvalidRecord(idx + 2);
+        let fields: Vec<&str> = line.trim_end().split(',').collect();
+        if fields.len() != columns.len() {
+            return Err(invalid());
+        }
+        let money = |idx: usize| amount::parse(fields[idx]).ok_or_else(invalid);
+        let status = match status {
+            Some(status) => Status::parse(fields[status]).ok_or_else(invalid)?,
+            None => match fields[locked] {
+                "true" => Status::Locked,
+                "false" => Status::Active,
+                _ => return Err(invalid())
+            }
+        };
+        let client_id = fields[client].parse::<u16>().map_err(|_| invalid())?;
+        let mut account = Account::new(client_id, money(available)?, money(held)?, status);
+        if let Some(pending) = pending {
+            let _ = account.add_pending(money(pending)?);
+        }
+        account.set_tenant(tenant.map(|tenant| fields[tenant]).filter(|tenant| *tenant != NO_TENANT).map(String::from));
+        state.accounts.push(account);
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Tx;
+    use crate::amount::{from_f64, ZERO};
+
+    fn state() -> State {
+        let mut account = Account::new(1, from_f64(10.5), from_f64(2.0), Status::Frozen(FreezeMode::Full));
+        account.set_tenant(Some(String::from("acme")));
+        account.restore_max_exposure(from_f64(3.0));
+        account.restore_dispute(Dispute { tx_id: 2, amount: from_f64(2.0), reason: Some(String::from("fraud")),
+                                           opened_at: Some(7) });
+        let mut deposit = Tx::deposit(1, 1).amount(12.5).build();
+        deposit.set_tenant(Some(String::from("acme")));
+        deposit.set_record(Some(1));
+        let mut disputed = Tx::deposit(1, 2).amount(4.0).build();
+        disputed.set_tenant(Some(String::from("acme")));
+        disputed.add_refunded(from_f64(1.0));
+        disputed.mark_disputed();
+        disputed.set_held_amount(from_f64(2.0));
+        disputed.set_reason(Some(String::from("fraud")));
+        disputed.set_timestamp(Some(7));
+        let locked = Account::new(2, ZERO, ZERO, Status::Locked);
+        State { accounts: vec![account, locked], history: vec![deposit, disputed],
+                checkpoint: Some(Checkpoint { offset: 120, records: 4 }) }
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let state = state();
+        let mut out = Vec::new();
+        write(&mut out, &state.accounts, &state.history, state.checkpoint).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert_eq!(written, "# transaction-processor state v4\n\
+                             checkpoint,120,4\n\
+                             account,acme,1,10.5000,2.0000,0.0000,frozen-full,3.0000\n\
+                             dispute,acme,1,2,2.0000,fraud,7\n\
+                             account,,2,0.0000,0.0000,0.0000,locked,0.0000\n\
+                             tx,acme,1,1,deposit,12.5000,0.0000,,,false,false,,1,,0.0000\n\
+                             tx,acme,1,2,deposit,4.0000,1.0000,disputed,2.0000,false,false,fraud,,7,0.0000\n");
+        assert_eq!(read(written.as_bytes()).unwrap(), state);
+
+        let tenants = read(written.as_bytes()).unwrap().into_tenants();
+        assert_eq!(tenants[&Some(String::from("acme"))][&1].1.len(), 2);
+        assert!(tenants[&None][&2].1.is_empty());
+    }
+
+    #[test]
+    fn test_state_migrate_v3() {
+        // The transactions charged back before version 4 were charged back for their dispute hold.
+        let state = read(&b"# transaction-processor state v3\n\
+                            account,,1,6.0000,0.0000,0.0000,locked,0.0000\n\
+                            tx,,1,1,deposit,10.0000,0.0000,charged_back,4.0000,false,false,,,\n\
+                            tx,,1,2,deposit,3.0000,0.0000,,,false,false,,,\n"[..]).unwrap();
+        assert_eq!(state.history[0].charged_back(), from_f64(4.0));
+        assert_eq!(state.history[0].disputable_amount(), from_f64(6.0));
+        assert_eq!(state.history[1].charged_back(), ZERO);
+    }
+
+    #[test]
+    fn test_state_migrate_v1() {
+        let state = read(&b"tenant,client,available,held,pending,total,locked,status\n\
+                            default,1,1.5000,0.5000,1.0000,3.0000,false,frozen\n\
+                            acme,2,0.0000,0.0000,0.0000,0.0000,true,locked\n"[..]).unwrap();
+        assert_eq!(state.accounts.len(), 2);
+        assert!(state.history.is_empty());
+        assert_eq!((state.accounts[0].tenant(), state.accounts[0].total()), (None, from_f64(3.0)));
+        assert_eq!(state.accounts[0].status(), Status::Frozen(FreezeMode::Withdrawals));
+        assert_eq!((state.accounts[1].tenant(), state.accounts[1].status()), (Some("acme"), Status::Locked));
+
+        // The spec mode output has no status column.
+        let state = read(&b"client,available,held,total,locked\n1,1.0,0.0,1.0,true\n"[..]).unwrap();
+        assert!(state.accounts[0].is_locked());
+    }
+
+    #[test]
+    fn test_state_refused() {
+        assert!(matches!(read(&b"# transaction-processor state v5\n"[..]), Err(Error::UnsupportedVersion(5))));
+        assert!(matches!(read(&b"# transaction-processor state vX\n"[..]), Err(Error::UnknownFormat)));
+        assert!(matches!(read(&b"type,client,tx,amount\n"[..]), Err(Error::UnknownFormat)));
+        assert!(matches!(read(&b""[..]), Err(Error::UnknownFormat)));
+        assert!(matches!(read(&b"# transaction-processor state v2\naccount,,1,1.0,0.0,0.0,gone,0.0\n"[..]),
+                         Err(Error::InvalidRecord(2))));
+        // A transaction of an account that is not part of the state.
+        assert!(matches!(read(&b"# transaction-processor state v2\ntx,,1,1,deposit,1.0,0.0,,,false,false,,,\n"[..]),
+                         Err(Error::InvalidRecord(2))));
+        assert!(matches!(read(&b"client,available,held,total,locked\n1,1.0,0.0,1.0\n"[..]), Err(Error::InvalidRecord(2))));
+    }
+}
@@ -0,0 +1,117 @@
+// Customer facing statements, derived from the audit trail.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::amount;
+use crate::audit::{Entry, Outcome, Sink};
+use crate::config::Labels;
+use crate::transaction::Type;
+
+// Writes one `<client>.csv` statement per client, under a `<tenant>` sub directory for the
+// clients of a tenant, listing the applied transactions in order with respect to the
+// `tx,type,amount,available,held,total,annotation` schema. The statements are kept in memory
+// until the end of the run, since the entries of different clients interleave.
+pub struct Statements {
+    dir: PathBuf,
+    labels: Labels,
+    entries: BTreeMap<(Option<String>, u16), Vec<Entry>>,
+}
+
+impl Statements {
+    pub fn new(dir: PathBuf, labels: Labels) -> Self {
+        Statements { dir, labels, entries: BTreeMap::new() }
+    }
+
+    fn write_statement(&self, tenant: Option<&str>, client_id: u16, entries: &[Entry]) -> io::Result<()> {
+        let dir = match tenant {
+            Some(tenant) => self.dir.join(tenant),
+            None => self.dir.clone()
+        };
+        fs::create_dir_all(&dir)?;
+        let label = self.labels.label(client_id);
+
+        let mut writer = BufWriter::new(File::create(dir.join(format!("{}.csv", label)))?);
+        writeln!(writer, "tx,type,amount,available,held,total,annotation")?;
+        for entry in entries {
+            let amount = entry.amount.map(|amount| amount::fmt(amount).to_string()).unwrap_or_default();
+            writeln!(writer, "{},{},{},{},{},{},{}", entry.tx_id, entry.ttype.as_str(), amount,
+                     amount::fmt(entry.after.available), amount::fmt(entry.after.held), amount::fmt(entry.after.total),
+                     annotation(entry))?;
+        }
+        writer.flush()
+    }
+}
+
+// Describes where a transaction stands in the dispute lifecycle, along with its audit note.
+fn annotation(entry: &Entry) -> String {
+    let lifecycle = match entry.ttype {
+        Type::Dispute => "dispute opened",
+        Type::Resolve => "dispute resolved",
+        Type::Chargeback => "charged back",
+        Type::Representment => "chargeback reversed",
+        _ => ""
+    };
+    match (lifecycle, entry.note.as_deref()) {
+        ("", Some(note)) => note.to_string(),
+        (lifecycle, Some(note)) => format!("{}; {}", lifecycle, note),
+        (lifecycle, None) => lifecycle.to_string()
+    }
+}
+
+impl Sink for Statements {
+    // Rejected transactions did not touch the account, so they are left out.
+    fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        if entry.outcome == Outcome::Applied {
+            self.entries.entry((entry.tenant.clone(), entry.client_id)).or_default().push(entry.clone());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        for ((tenant, client_id), entries) in self.entries.iter() {
+            self.write_statement(tenant.as_deref(), *client_id, entries)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Status;
+    use crate::audit::Balance;
+    use crate::amount::from_f64;
+
+    fn entry(tenant: Option<&str>, client_id: u16, tx_id: u32, ttype: Type, outcome: Outcome,
+             note: Option<&str>) -> Entry {
+        Entry { tenant: tenant.map(String::from), client_id, tx_id, ttype, amount: None, outcome,
+                note: note.map(String::from), before: Balance::default(),
+                after: Balance { available: from_f64(1.0), held: from_f64(2.0), total: from_f64(3.0) },
+                timestamp: None, locked: false,
+                previous_status: Status::Active, status: Status::Active, record: None, source: None }
+    }
+
+    #[test]
+    fn test_statements() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut statements = Statements::new(dir.path().to_path_buf(), Labels::default());
+        let mut deposit = entry(None, 1, 1, Type::Deposit, Outcome::Applied, None);
+        deposit.amount = Some(from_f64(3.0));
+        statements.record(&deposit).unwrap();
+        statements.record(&entry(Some("acme"), 1, 1, Type::Deposit, Outcome::Applied, None)).unwrap();
+        statements.record(&entry(None, 1, 2, Type::Withdrawal, Outcome::Rejected("insufficient_funds"), None)).unwrap();
+        statements.record(&entry(None, 1, 1, Type::Dispute, Outcome::Applied, Some("reason fraud"))).unwrap();
+        statements.record(&entry(None, 1, 1, Type::Resolve, Outcome::Applied, None)).unwrap();
+        statements.finish().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.path().join("1.csv")).unwrap(),
+                   "tx,type,amount,available,held,total,annotation\n\
+                   1,deposit,3.0000,1.0000,2.0000,3.0000,\n\
+                   1,dispute,,1.0000,2.0000,3.0000,dispute opened; reason fraud\n\
+                   1,resolve,,1.0000,2.0000,3.0000,dispute resolved\n");
+        assert!(dir.path().join("acme").join("1.csv").exists());
+    }
+}
@@ -0,0 +1,164 @@
+// Timings of the stages of the pipeline of a run, for telling whether the reading and parsing of
+// the input, the handing of the transactions to the tenant dispatchers or their application by
+// the account admins is the bottleneck. The queue wait of a transaction is the time from its
+// hand-off by the driver to the moment its account admin picks it up, so it grows when the
+// account admins fall behind.
+
+use std::time::{Duration, Instant};
+
+// Sub-buckets of every power of two of the histogram, which bounds the error of the percentiles
+// to an eighth of their value.
+const SUB_BUCKETS: u64 = 8;
+
+// Histogram of durations, in microseconds, in logarithmic buckets, so that it takes the same
+// memory however many durations it records.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        let idx = bucket(micros);
+        if self.buckets.len() <= idx {
+            self.buckets.resize(idx + 1, 0);
+        }
+        self.buckets[idx] += 1;
+        self.count += 1;
+        self.max = self.max.max(micros);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (idx, count) in other.buckets.iter().enumerate() {
+            self.buckets[idx] += count;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max)
+    }
+
+    // The duration that `percent` of the recorded durations do not exceed, rounded up to the
+    // bound of its bucket.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        let rank = ((percent / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (idx, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(upper_bound(idx).min(self.max));
+            }
+        }
+        Duration::from_micros(self.max)
+    }
+}
+
+// The first buckets hold a value each, the others the values sharing their highest bits.
+fn bucket(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let exponent = 63 - micros.leading_zeros() as u64;
+    let shift = exponent - 3;
+    ((exponent - 2) * SUB_BUCKETS + ((micros >> shift) & (SUB_BUCKETS - 1))) as usize
+}
+
+fn upper_bound(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < SUB_BUCKETS {
+        return idx;
+    }
+    let (exponent, sub) = (idx / SUB_BUCKETS + 2, idx % SUB_BUCKETS);
+    let shift = exponent - 3;
+    ((SUB_BUCKETS + sub + 1) << shift) - 1
+}
+
+// Cumulative time spent in every stage. The account admins run concurrently, so the time spent
+// applying the transactions may exceed the duration of the run.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StageTimings {
+    // Reading and parsing the records of the sources.
+    pub read: Duration,
+    // Handing the transactions to the tenant dispatchers, which blocks once their buffer is full.
+    pub dispatch: Duration,
+    // Applying the transactions in the account admins.
+    pub apply: Duration,
+    pub queue_wait: Histogram,
+}
+
+impl StageTimings {
+    pub fn merge(&mut self, other: &StageTimings) {
+        self.read += other.read;
+        self.dispatch += other.dispatch;
+        self.apply += other.apply;
+        self.queue_wait.merge(&other.queue_wait);
+    }
+
+    // Describes the timings for the logs.
+    pub fn describe(&self) -> String {
+        format!("read {}ms, dispatch {}ms, apply {}ms, queue wait p50 {}us, p99 {}us, max {}us",
+                self.read.as_millis(), self.dispatch.as_millis(), self.apply.as_millis(),
+                self.queue_wait.percentile(50.0).as_micros(), self.queue_wait.percentile(99.0).as_micros(),
+                self.queue_wait.max().as_micros())
+    }
+}
+
+// Time a transaction was handed off by the driver, when the run measures its stages. It is not
+// part of what the transaction is, so any two stamps are equal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stamp(Option<Instant>);
+
+impl Stamp {
+    pub fn now() -> Self {
+        Stamp(Some(Instant::now()))
+    }
+
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.0.map(|instant| instant.elapsed())
+    }
+}
+
+impl PartialEq for Stamp {
+    fn eq(&self, _: &Stamp) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        for micros in [0, 7, 8, 9, 15, 16, 100, 1000, 123_456].iter() {
+            let idx = bucket(*micros);
+            assert!(upper_bound(idx) >= *micros && (idx == 0 || upper_bound(idx - 1) < *micros));
+        }
+
+        let mut histogram = Histogram::default();
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.percentile(50.0), Duration::from_micros(51));
+        assert_eq!(histogram.percentile(99.0), Duration::from_micros(100));
+        assert_eq!(histogram.max(), Duration::from_micros(100));
+        let mut merged = Histogram::default();
+        merged.record(Duration::from_millis(5));
+        merged.merge(&histogram);
+        assert_eq!((merged.count(), merged.max()), (101, Duration::from_millis(5)));
+        assert_eq!(Histogram::default().percentile(50.0), Duration::ZERO);
+    }
+}
@@ -1,36 +1,222 @@
 // Primitives around transactions.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use csv::ReaderBuilder;
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
 
-use crate::account::{Account, AccountAdmin, Error as ClientError};
-use crate::input::Input;
+use crate::aggregate::Aggregates;
+use crate::alias::Aliases;
+use crate::amount::{self, Amount, ZERO};
+use crate::archive::Archiver;
+use crate::backfill::{Corrections, Recorded};
+use crate::account::{Account, AccountAdmin, Error as ClientError, Rules, Status};
+use crate::compliance::SuspiciousActivity;
+use crate::deadletter;
+use crate::events::EventFile;
+use crate::house;
+use crate::notify::{Alert, Alerter};
+use crate::output;
+use crate::partition::{self, PartitionedAudit};
+use crate::reference::{Attributes, Reference};
+use crate::replay::Memory;
+use crate::retry::{Metrics, Retrier};
+use crate::sample::Sampler;
+use crate::audit::{AuditFile, AuditWriter, Entry, Sink};
+use crate::config::{Config, Labels};
+use crate::input::{Checkpoint, Input, Schema};
+use crate::logger::{self, Fields};
+use crate::report::{self, Failure, RunReport, SourceStats};
+use crate::state::{self, Restored, State};
+use crate::snapshot::Snapshots;
+use crate::statement::Statements;
+use crate::timing::{Stamp, StageTimings};
+use crate::watchdog::{Limits, Watchdog};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
+    // The line is not a CSV record, e.g. it is empty.
     InvalidRecord,
-    Send(SendError<Transaction>),
+    // The record has no such column.
+    MissingField(&'static str),
+    // The value of the column can not be parsed, or is not allowed.
+    InvalidField(&'static str),
+    // The amount has more decimal places than the scale allows.
+    InvalidAmountScale { decimals: usize, scale: usize },
+    NegativeAmount,
+    // The transaction moves funds, so it can not go without an amount.
+    MissingAmount(Type),
+    // Too many of the first records of the input are invalid.
+    TooManyInvalidRecords { invalid: usize, records: usize },
+    // A strict run stopped at an invalid record or a rejected transaction.
+    Strict(Violation),
+    Send(Box<SendError<Transaction>>),
     Client(ClientError)
 }
 
+impl Error {
+    // Tells why a record was rejected, for the operators.
+    pub fn describe(&self) -> String {
+        match self {
+            Error::InvalidRecord => "the line is not a CSV record".to_string(),
+            Error::MissingField(column) => format!("the record has no `{}` column", column),
+            Error::InvalidField(column) => format!("the `{}` column is invalid", column),
+            Error::InvalidAmountScale { decimals, scale } => {
+                format!("the amount has {} decimal places, while at most {} are allowed", decimals, scale)
+            }
+            Error::NegativeAmount => "the amount is negative".to_string(),
+            Error::MissingAmount(ttype) => format!("the {} has no amount", ttype.as_str()),
+            Error::TooManyInvalidRecords { invalid, records } => {
+                format!("{} of the first {} records are invalid", invalid, records)
+            }
+            Error::Strict(violation) => violation.describe(),
+            Error::Send(_) => "the transaction could not be sent to its account admin".to_string(),
+            Error::Client(err) => format!("the transaction was rejected: {:?}", err)
+        }
+    }
+}
+
+// How a run handles the invalid records and the transactions its account admins reject. A
+// lenient run skips them, as the payments spec requires, while a strict one, e.g. an audit run,
+// stops at the first of them and fails.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mode {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+// The error a strict run stopped at.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    // A record could not be parsed, its line and the reason being logged as it is skipped.
+    InvalidRecord,
+    // A transaction was rejected by its account admin, for the reason of the audit trail. The
+    // client is labelled as it appears in the outputs.
+    Rejected { record: Option<u64>, client: String, tx_id: u32, reason: &'static str },
+}
+
+impl Violation {
+    pub fn describe(&self) -> String {
+        match self {
+            Violation::InvalidRecord => "a record is invalid".to_string(),
+            Violation::Rejected { record, client, tx_id, reason } => {
+                let record = record.map(|record| format!(", at record {},", record)).unwrap_or_default();
+                format!("tx {} of client {}{} was rejected with {}", tx_id, client, record, reason)
+            }
+        }
+    }
+}
+
+// Refuses to run over an input whose first `sample` records have more than `max_rate` percent of
+// invalid records, which usually means the delimiter or the encoding of the file is wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidRateGuard {
+    pub sample: usize,
+    pub max_rate: f64,
+}
+
+// What the tenant dispatchers do with a transaction that refers to a previous one, e.g. a dispute,
+// of a client without an account.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UnknownAccountPolicy {
+    // An empty account is opened for the client, which then rejects the transaction.
+    #[default]
+    Open,
+    // The transaction is rejected without opening an account.
+    Reject,
+    // The transaction is held back until the client shows up, in case the transaction it refers to
+    // comes later in a merged stream. It is rejected once `window` more transactions of the tenant
+    // were dispatched in the meantime, or at the end of the run.
+    Buffer { window: usize },
+}
+
 // Abstraction over transaction types.
 #[derive(PartialEq, Debug, Clone)]
+#[non_exhaustive]
 pub enum Type {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Freeze,
+    Unfreeze,
+    // Lifts the lock of a charged back account, when the run allows it.
+    Unlock,
+    Settle,
+    Cancel,
+    Authorize,
+    Capture,
+    Refund,
+    Reversal,
+    Representment,
+    Fee,
+    Credit,
+    Debit,
     ERR
 }
 
+impl Type {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Type::Deposit => "deposit",
+            Type::Withdrawal => "withdrawal",
+            Type::Dispute => "dispute",
+            Type::Resolve => "resolve",
+            Type::Chargeback => "chargeback",
+            Type::Freeze => "freeze",
+            Type::Unfreeze => "unfreeze",
+            Type::Unlock => "unlock",
+            Type::Settle => "settle",
+            Type::Cancel => "cancel",
+            Type::Authorize => "authorize",
+            Type::Capture => "capture",
+            Type::Refund => "refund",
+            Type::Reversal => "reversal",
+            Type::Representment => "representment",
+            Type::Fee => "fee",
+            Type::Credit => "credit",
+            Type::Debit => "debit",
+            Type::ERR => "error"
+        }
+    }
+
+    // Whether the transaction starts a new transaction of the client, under a tx id of its own.
+    pub fn starts_new(&self) -> bool {
+        matches!(self, Type::Deposit | Type::Withdrawal | Type::Authorize | Type::Fee | Type::Credit | Type::Debit)
+    }
+
+    // Whether the transaction moves an amount of its own, rather than the amount of the transaction
+    // it refers to.
+    pub fn requires_amount(&self) -> bool {
+        matches!(self, Type::Deposit | Type::Withdrawal | Type::Authorize | Type::Fee | Type::Refund | Type::Credit
+                 | Type::Debit)
+    }
+
+    // Whether the transaction is an operator command rather than a customer transaction.
+    pub fn is_admin(&self) -> bool {
+        matches!(self, Type::Freeze | Type::Unfreeze | Type::Unlock | Type::Reversal | Type::Credit | Type::Debit)
+    }
+
+    // Whether the transaction refers to a previous transaction of the client, by its tx id.
+    pub fn refers_to_previous(&self) -> bool {
+        matches!(self, Type::Dispute | Type::Resolve | Type::Chargeback | Type::Representment | Type::Settle
+                 | Type::Cancel | Type::Capture | Type::Refund | Type::Reversal)
+    }
+}
+
 impl From<&str> for Type {
     fn from(transaction_type: &str) -> Self {
         match transaction_type {
@@ -39,60 +225,204 @@ impl From<&str> for Type {
             "chargeback" => Type::Chargeback,
             "deposit" => Type::Deposit,
             "withdrawal" => Type::Withdrawal,
+            "freeze" => Type::Freeze,
+            "unfreeze" => Type::Unfreeze,
+            "unlock" => Type::Unlock,
+            "settle" => Type::Settle,
+            "cancel" => Type::Cancel,
+            "authorize" => Type::Authorize,
+            "capture" => Type::Capture,
+            "refund" => Type::Refund,
+            "reversal" => Type::Reversal,
+            "representment" => Type::Representment,
+            "fee" => Type::Fee,
+            "credit" => Type::Credit,
+            "debit" => Type::Debit,
             _ => Type::ERR
         }
     }
 }
 
+// Where a transaction stands in the dispute lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    // A charged back transaction whose chargeback was successfully challenged.
+    Represented,
+}
+
+impl DisputeState {
+    // Whether a transaction in this state may move to `next`. A dispute is settled by a resolve or
+    // a chargeback, and only a chargeback can be represented, while a resolved transaction can be
+    // disputed again if `redisputes` allows it, as some payment networks do.
+    pub fn allows(self, next: DisputeState, redisputes: bool) -> bool {
+        match (self, next) {
+            (DisputeState::Undisputed, DisputeState::Disputed) => true,
+            (DisputeState::Disputed, DisputeState::Resolved) | (DisputeState::Disputed, DisputeState::ChargedBack) => true,
+            (DisputeState::Resolved, DisputeState::Disputed) => redisputes,
+            (DisputeState::ChargedBack, DisputeState::Represented) => true,
+            _ => false
+        }
+    }
+}
+
 // Wrapper over a line from the input file.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Transaction {
     ttype: Type,
     client_id: u16,
     tx_id: u32,
-    amount: Option<f64>,
-    disputed: bool,
-    resolved: bool,
-    charged_back: bool,
+    amount: Option<Amount>,
+    dispute_state: DisputeState,
+    // A withdrawal waiting to be settled or cancelled.
+    pending: bool,
+    // An authorization waiting to be captured.
+    authorized: bool,
+    // Amount refunded so far out of a deposit.
+    refunded: Amount,
+    // Amount charged back so far out of the transaction, by its partial disputes.
+    charged_back: Amount,
+    // Amount held by the dispute of the transaction, when it is less than its disputable amount.
+    dispute_hold: Option<Amount>,
+    tenant: Option<String>,
+    // Reason code of a dispute, also kept on the disputed transaction.
+    reason: Option<String>,
+    // Seconds since the epoch the transaction happened at.
+    timestamp: Option<u64>,
+    // Position of the record among all the records of the input, starting at 1.
+    record: Option<u64>,
+    // Byte offset the record starts at in the input.
+    offset: Option<u64>,
+    // Position of the record among the records of its client, as numbered by the producer.
+    sequence: Option<u64>,
+    // Label of the source the record was read from, when the run has several.
+    source: Option<String>,
+    // The tx id was already used by an earlier transaction, as remembered by the replay protection.
+    replayed: bool,
+    // Attributes of the client, as joined from the reference data of the run.
+    attributes: Option<Arc<Attributes>>,
+    // When the driver handed the transaction off, for the runs measuring their stages.
+    queued_at: Stamp,
 }
 
 impl Transaction {
     pub fn new_with_amount(ttype: Type, client_id: u16, tx_id: u32,
-                           amount: f64) -> Self {
-        Transaction { ttype, client_id, tx_id, amount: Some(amount), disputed: false,
-            resolved: false, charged_back: false }
+                           amount: Amount) -> Self {
+        Transaction { ttype, client_id, tx_id, amount: Some(amount),
+            dispute_state: DisputeState::Undisputed, pending: false, authorized: false, refunded: ZERO,
+            charged_back: ZERO, dispute_hold: None,
+            tenant: None, reason: None, timestamp: None, record: None, offset: None, sequence: None, source: None, replayed: false,
+            attributes: None, queued_at: Stamp::default() }
     }
 
     pub fn new(ttype: Type, client_id: u16, tx_id: u32) -> Self {
-        Transaction { ttype, client_id, tx_id, amount: None, disputed: false, resolved: false,
-            charged_back: false }
+        Transaction { ttype, client_id, tx_id, amount: None, dispute_state: DisputeState::Undisputed,
+            pending: false, authorized: false, refunded: ZERO, charged_back: ZERO, dispute_hold: None,
+            tenant: None, reason: None, timestamp: None, record: None, offset: None, sequence: None, source: None, replayed: false,
+            attributes: None, queued_at: Stamp::default() }
     }
 
     pub fn mark_disputed(&mut self) {
-        self.disputed = true;
-        self.resolved = false;
-        self.charged_back = false;
+        self.dispute_state = DisputeState::Disputed;
     }
 
     pub fn mark_resolved(&mut self) {
-        self.resolved = true;
-        self.disputed = false;
-        self.charged_back = false;
+        self.dispute_state = DisputeState::Resolved;
     }
 
     pub fn mark_charged_back(&mut self) {
-        self.charged_back = true;
-        self.disputed = false;
-        self.resolved = false;
+        self.dispute_state = DisputeState::ChargedBack;
+    }
+
+    pub fn mark_represented(&mut self) {
+        self.dispute_state = DisputeState::Represented;
+    }
+
+    pub fn dispute_state(&self) -> DisputeState {
+        self.dispute_state
+    }
+
+    pub fn mark_pending(&mut self) {
+        self.pending = true;
+    }
+
+    pub fn clear_pending(&mut self) {
+        self.pending = false;
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn mark_authorized(&mut self) {
+        self.authorized = true;
+    }
+
+    pub fn clear_authorized(&mut self) {
+        self.authorized = false;
+    }
+
+    pub fn is_authorized(&self) -> bool {
+        self.authorized
+    }
+
+    pub fn add_refunded(&mut self, amount: Amount) {
+        self.refunded += amount;
+    }
+
+    pub fn refunded(&self) -> Amount {
+        self.refunded
+    }
+
+    pub fn add_charged_back(&mut self, amount: Amount) {
+        self.charged_back += amount;
+    }
+
+    pub fn set_charged_back(&mut self, amount: Amount) {
+        self.charged_back = amount;
     }
 
-    // A flag is considered one of the `disputed`, `resolved` or `charged_back` states.
+    pub fn charged_back(&self) -> Amount {
+        self.charged_back
+    }
+
+    // The part of the transaction amount that was neither refunded nor charged back yet.
+    pub fn disputable_amount(&self) -> Amount {
+        self.amount.unwrap_or(ZERO) - self.refunded - self.charged_back
+    }
+
+    // Whether the transaction may be disputed, with respect to its dispute lifecycle. What is left
+    // of a transaction after a partial chargeback can be disputed in turn.
+    pub fn is_disputable(&self, redisputes: bool) -> bool {
+        self.dispute_state.allows(DisputeState::Disputed, redisputes)
+            || self.dispute_state == DisputeState::ChargedBack && self.disputable_amount() > ZERO
+    }
+
+    // The amount moved to the held funds by the dispute of the transaction.
+    pub fn held_amount(&self) -> Amount {
+        self.dispute_hold.unwrap_or_else(|| self.disputable_amount())
+    }
+
+    pub fn set_held_amount(&mut self, amount: Amount) {
+        self.dispute_hold = Some(amount);
+    }
+
+    // The amount held by the dispute, when it was set explicitly.
+    pub fn dispute_hold(&self) -> Option<Amount> {
+        self.dispute_hold
+    }
+
+    // Whether the transaction was never disputed.
     pub fn is_emtpy_flags(&self) -> bool {
-        return !self.disputed && !self.resolved && !self.charged_back
+        self.dispute_state == DisputeState::Undisputed
     }
 
     pub fn is_disputed(&self) -> bool {
-        self.disputed
+        self.dispute_state == DisputeState::Disputed
     }
 
     pub fn tx_id(&self) -> u32 {
@@ -103,7 +433,7 @@ impl Transaction {
         self.ttype.clone()
     }
 
-    pub fn amount(&self) -> Option<f64> {
+    pub fn amount(&self) -> Option<Amount> {
         self.amount
     }
 
@@ -111,47 +441,192 @@ impl Transaction {
         self.client_id
     }
 
+    pub fn set_client_id(&mut self, client_id: u16) {
+        self.client_id = client_id;
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn set_tenant(&mut self, tenant: Option<String>) {
+        self.tenant = tenant;
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub fn set_reason(&mut self, reason: Option<String>) {
+        self.reason = reason;
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Option<u64>) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn record(&self) -> Option<u64> {
+        self.record
+    }
+
+    pub fn set_record(&mut self, record: Option<u64>) {
+        self.record = record;
+    }
+
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    pub fn set_offset(&mut self, offset: Option<u64>) {
+        self.offset = offset;
+    }
+
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence
+    }
+
+    pub fn set_sequence(&mut self, sequence: Option<u64>) {
+        self.sequence = sequence;
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn set_source(&mut self, source: Option<String>) {
+        self.source = source;
+    }
+
+    pub fn attributes(&self) -> Option<&Attributes> {
+        self.attributes.as_deref()
+    }
+
+    pub fn set_attributes(&mut self, attributes: Option<Arc<Attributes>>) {
+        self.attributes = attributes;
+    }
+
+    pub fn mark_queued(&mut self) {
+        self.queued_at = Stamp::now();
+    }
+
+    // Time since the transaction was handed off, if the run measures its stages.
+    pub fn queued_for(&self) -> Option<Duration> {
+        self.queued_at.elapsed()
+    }
+
+    pub fn mark_replayed(&mut self) {
+        self.replayed = true;
+    }
+
+    pub fn is_replayed(&self) -> bool {
+        self.replayed
+    }
+
     // CSV records String to Transaction convertor. We avoid implementing the From<String> trait
     // because we want to propagate parsing errors.
     pub fn from(line: String) -> Result<Transaction> {
+        Transaction::parse(&line, &Schema::default())
+    }
+
+    // Parses a CSV record laid out according to `schema`, with amounts of at most four decimal
+    // places.
+    pub fn parse(line: &str, schema: &Schema) -> Result<Transaction> {
+        Transaction::parse_with_scale(line, schema, amount::DECIMALS)
+    }
+
+    // Same as `parse`, with amounts of at most `scale` decimal places.
+    pub fn parse_with_scale(line: &str, schema: &Schema, scale: usize) -> Result<Transaction> {
+        Transaction::parse_with_aliases(line, schema, scale, None)
+    }
+
+    // Same as `parse_with_scale`, the client ids being the external identifiers of `aliases`.
+    // Records of clients without an alias are invalid.
+    pub fn parse_with_aliases(line: &str, schema: &Schema, scale: usize, aliases: Option<&Aliases>)
+                              -> Result<Transaction> {
         let mut rdr = ReaderBuilder::new()
             .has_headers(false)
             .from_reader(line.as_bytes());
 
         // We can not use serde deserialization because of
         // https://github.com/BurntSushi/rust-csv/issues/113.
-        for result in rdr.records() {
+        if let Some(result) = rdr.records().next() {
             return match result {
                 Ok(str_record) => {
-                    if str_record.len() < 3 {
-                        return Err(Error::InvalidRecord);
-                    }
+                    let field = |idx: usize, name| str_record.get(idx).ok_or(Error::MissingField(name));
 
-                    // We know for sure that the record has at least three elements.
-                    let ttype = Type::from(str_record.get(0).unwrap());
+                    let ttype = Type::from(field(schema.ttype(), "type")?);
                     if ttype == Type::ERR {
-                        return Err(Error::InvalidRecord);
+                        return Err(Error::InvalidField("type"));
                     }
 
-                    let client_id = str_record.get(1)
-                        .unwrap().parse::<u16>()
-                        .map_err(|_| Error::InvalidRecord)?;
-
-                    let tx_id = str_record.get(2)
-                        .unwrap().parse::<u32>()
-                        .map_err(|_| Error::InvalidRecord)?;
-
-                    if str_record.len() == 4 {
-                        let amount = str_record.get(3)
-                            .unwrap()
-                            .parse::<f64>().map_err(|_| Error::InvalidRecord)?;
-                        return Ok(Transaction::new_with_amount(ttype,
-                                                               client_id,
-                                                               tx_id,
-                                                               amount));
-                    }
+                    let client = field(schema.client(), "client")?;
+                    let client_id = match aliases {
+                        Some(aliases) => aliases.internal(client),
+                        None => client.parse::<u16>().ok()
+                    }.ok_or(Error::InvalidField("client"))?;
+
+                    let tx_id = field(schema.tx(), "tx")?
+                        .parse::<u32>()
+                        .map_err(|_| Error::InvalidField("tx"))?;
 
-                    Ok(Transaction::new(ttype, client_id, tx_id))
+                    // Tenants name the per tenant output files, so they are restricted to
+                    // characters that are safe to use in file names.
+                    let tenant = match schema.tenant() {
+                        Some(idx) => {
+                            let tenant = field(idx, "tenant")?;
+                            if tenant.is_empty() || !is_safe_name(tenant) {
+                                return Err(Error::InvalidField("tenant"));
+                            }
+                            Some(tenant.to_string())
+                        }
+                        None => None
+                    };
+
+                    // Reason codes and source labels end up in the reports as they are, so they
+                    // obey the same rule.
+                    let safe_name = |idx: Option<usize>, column| match idx.and_then(|idx| str_record.get(idx)) {
+                        Some(name) if !name.is_empty() => {
+                            if !is_safe_name(name) {
+                                return Err(Error::InvalidField(column));
+                            }
+                            Ok(Some(name.to_string()))
+                        }
+                        _ => Ok(None)
+                    };
+                    let reason = safe_name(schema.reason(), "reason")?;
+                    let source = safe_name(schema.source(), "source")?;
+
+                    let optional_u64 = |idx: Option<usize>, column| match idx.and_then(|idx| str_record.get(idx)) {
+                        Some(value) if !value.is_empty() => value.parse::<u64>().map(Some).map_err(|_| Error::InvalidField(column)),
+                        _ => Ok(None)
+                    };
+                    let timestamp = optional_u64(schema.timestamp(), "timestamp")?;
+                    let sequence = optional_u64(schema.sequence(), "sequence")?;
+
+                    // The amount is missing or left empty for the transactions that refer to a
+                    // previous one.
+                    let mut tx = match str_record.get(schema.amount()) {
+                        Some(amount) if !amount.is_empty() => {
+                            let amount = amount::parse_transaction(amount, scale).map_err(|invalid| match invalid {
+                                amount::Invalid::Malformed => Error::InvalidField("amount"),
+                                amount::Invalid::Negative => Error::NegativeAmount,
+                                amount::Invalid::Scale { decimals } => Error::InvalidAmountScale { decimals, scale }
+                            })?;
+                            Transaction::new_with_amount(ttype, client_id, tx_id, amount)
+                        }
+                        _ if ttype.requires_amount() => return Err(Error::MissingAmount(ttype)),
+                        _ => Transaction::new(ttype, client_id, tx_id)
+                    };
+                    tx.set_tenant(tenant);
+                    tx.set_reason(reason);
+                    tx.set_timestamp(timestamp);
+                    tx.set_sequence(sequence);
+                    tx.set_source(source);
+                    Ok(tx)
                 }
                 Err(_) => Err(Error::InvalidRecord)
             };
@@ -160,228 +635,1766 @@ impl Transaction {
         Err(Error::InvalidRecord)
     }
 
-    #[cfg(test)]
     pub fn is_resolved(&self) -> bool {
-        self.resolved
+        self.dispute_state == DisputeState::Resolved
     }
 
-    #[cfg(test)]
     pub fn is_charged_back(&self) -> bool {
-        self.charged_back
+        self.dispute_state == DisputeState::ChargedBack
+    }
+
+    pub fn is_represented(&self) -> bool {
+        self.dispute_state == DisputeState::Represented
     }
 
     #[cfg(test)]
     pub fn clear_flags(&mut self) {
-        self.disputed = false;
-        self.resolved = false;
-        self.charged_back = false;
+        self.dispute_state = DisputeState::Undisputed;
+    }
+}
+
+// Writes the transaction as a `type,client,tx,amount` record, the amount being left empty for the
+// transactions that refer to a previous one. The tenant, the reason and the timestamp are not part
+// of the record.
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},", self.ttype.as_str(), self.client_id, self.tx_id)?;
+        match self.amount {
+            Some(amount) => write!(f, "{}", amount::fmt(amount)),
+            None => Ok(())
+        }
+    }
+}
+
+// Parses a `type,client,tx,amount` record, the inverse of `Display`.
+impl FromStr for Transaction {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Transaction> {
+        Transaction::parse(line, &Schema::default())
     }
 }
 
+// Names safe to use in file names and CSV fields, e.g. the tenants and the source labels.
+pub fn is_safe_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Iterates through the valid records of an input, skipping the invalid ones.
 pub struct TransactionIterator {
-    input: Input
+    input: Input,
+    schema: Schema,
+    // Transactions read ahead by `check_invalid_rate`.
+    buffered: VecDeque<Transaction>,
+    // Maximum number of decimal places of the amounts.
+    scale: usize,
+    aliases: Option<Arc<Aliases>>,
+    reference: Option<Arc<Reference>>,
+    records: usize,
+    invalid: usize,
 }
 
 impl TransactionIterator {
     pub fn new(input: Input) -> Self {
-        TransactionIterator { input }
+        TransactionIterator::new_with_scale(input, amount::DECIMALS)
     }
-}
-
-impl Iterator for TransactionIterator {
-    type Item = Transaction;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(res) = self.input.next().map(Transaction::from) {
-            return match res {
-                Ok(tx) => Some(tx),
-                Err(_) => None
-            };
-        }
-        None
+    pub fn new_with_scale(input: Input, scale: usize) -> Self {
+        let schema = input.schema().clone();
+        TransactionIterator { input, schema, buffered: VecDeque::new(), scale, aliases: None, reference: None, records: 0,
+                              invalid: 0 }
     }
-}
 
-// Entry point into transactions execution, iterating through each tx from the provided input.
-pub fn drill(input: Input, multi_threaded_runtime: bool, tx_delay: Option<Duration>, dump_accounts: bool) {
-    let record_iter = TransactionIterator::new(input);
-    let rt = if multi_threaded_runtime {
-        tokio::runtime::Builder::new_multi_thread().build().expect("Could not initialize multi threaded runtime.")
-    } else {
-        tokio::runtime::Builder::new_current_thread().build().expect("Could not initialize single threaded runtime.")
-    };
+    pub fn set_aliases(&mut self, aliases: Option<Arc<Aliases>>) {
+        self.aliases = aliases;
+    }
 
-    rt.block_on(async move {
-        let mut pipes = HashMap::new();
-        let mut worker_handlers: Vec<JoinHandle<Option<Account>>> = Vec::new();
-        for tx in record_iter {
-            let client_id = tx.client_id();
-            // If the sender for a specific client was already created, send the tx on the channel.
-            if pipes.contains_key(&client_id) {
-                let sender: &Sender<Transaction> = pipes.get_mut(&client_id).unwrap();
-                // Handle errors gracefully. When an account is locked the receiver is closed.
-                // However, we still need to keep the sender in scope because otherwise we wouldn't
-                // know that there were already an account for the client with the account locked,
-                // which means that we will create a new account for that client, which is not the
-                // expected behavior of handling transactions.
-                match sender.send(tx).await {
-                    Ok(_) => (),
-                    Err(_) => ()
-                };
-            } else { // Otherwise, create the channel and spawn a task with the client waiting for
-                // transactions to handle. The client will stop waiting for transactions when the
-                // the channel is closed.
-                let (sender, receiver) = tokio::sync::mpsc::channel(32);
-                sender.send(tx).await.unwrap();
-                let _ = pipes.insert(client_id, sender);
-                // Store the tasks handle.
-                worker_handlers.push(tokio::spawn(async move {
-                    let mut account_admin = AccountAdmin::new(client_id, receiver);
-                    loop {
-                        if tx_delay.is_some() {
-                            thread::sleep(tx_delay.unwrap());
-                        }
+    // The transactions are joined to the attributes of their client in the reference data.
+    pub fn set_reference(&mut self, reference: Option<Arc<Reference>>) {
+        self.reference = reference;
+    }
 
-                        let account = match account_admin.handle().await {
-                            Ok(_) => None,
-                            Err(ClientError::Handle(acc)) => Some(acc),
-                            Err(_) => None,
-                        };
+    // Number of invalid records met so far.
+    pub fn invalid(&self) -> usize {
+        self.invalid
+    }
 
-                        if account.is_some() {
-                            return account;
-                        }
-                    }
-                }));
+    fn read(&mut self) -> Option<Result<Transaction>> {
+        let offset = self.input.checkpoint().offset;
+        let line = self.input.next()?;
+        self.records += 1;
+        // The records and the lines are numbered from the start of the input, even when it is
+        // read from a checkpoint on.
+        let record = self.input.checkpoint().records;
+        let mut tx = Transaction::parse_with_aliases(&line, &self.schema, self.scale, self.aliases.as_deref());
+        match &mut tx {
+            Ok(tx) => {
+                tx.set_record(Some(record));
+                tx.set_offset(Some(offset));
+                if let Some(reference) = &self.reference {
+                    tx.set_attributes(reference.attributes(tx.tenant(), tx.client_id()).cloned());
+                }
+            }
+            Err(err) => {
+                self.invalid += 1;
+                log::warn!("Line {}: {}.", self.input.line(), err.describe());
             }
         }
+        Some(tx)
+    }
 
-        // Close the senders and implicitly, stop the clients from waiting for transactions.
-        for _ in pipes {
+    // Reads ahead the sample of the guard, before any of its transactions is handed out.
+    pub fn check_invalid_rate(&mut self, guard: &InvalidRateGuard) -> Result<()> {
+        while self.records < guard.sample {
+            match self.read() {
+                Some(Ok(tx)) => self.buffered.push_back(tx),
+                Some(Err(_)) => (),
+                None => break
+            }
         }
-
-        if dump_accounts {
-            // Print the accounts contents.
-            println!("client,available,held,total,locked");
+        if self.records > 0 && self.invalid as f64 * 100.0 / self.records as f64 > guard.max_rate {
+            return Err(Error::TooManyInvalidRecords { invalid: self.invalid, records: self.records });
         }
+        Ok(())
+    }
+}
 
-        for handle in worker_handlers {
-            let res = handle.await.unwrap();
-            match res {
-                Some(account) => if dump_accounts {
-                    println!("{},{:.4},{:.4},{:.4},{}", account.client_id(), account.available(), account.held(), account.total(), account.is_locked());
-                }
-                None => unreachable!()
-            };
+impl Iterator for TransactionIterator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(tx) = self.buffered.pop_front() {
+            return Some(tx);
+        }
+        loop {
+            if let Ok(tx) = self.read()? {
+                return Some(tx);
+            }
         }
-    });
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::io::{Seek, SeekFrom, Write};
-    use crate::transaction::{Transaction, TransactionIterator, Type};
-    use crate::input::Input;
-    use tempfile::tempfile;
+// Source of the transactions of a run, handed out in the order they are applied.
+pub trait TransactionSource: Iterator<Item = Transaction> {
+    // The transactions belong to tenants, so the accounts are printed with a tenant column.
+    fn has_tenant(&self) -> bool;
 
-    #[test]
-    fn test_type_from_str() {
-        assert_eq!(Type::Deposit, Type::from("deposit"));
-        assert_eq!(Type::Withdrawal, Type::from("withdrawal"));
-        assert_eq!(Type::Dispute, Type::from("dispute"));
-        assert_eq!(Type::Resolve, Type::from("resolve"));
-        assert_eq!(Type::Chargeback, Type::from("chargeback"));
+    // Number of records skipped so far because they were invalid.
+    fn invalid(&self) -> usize {
+        0
     }
 
-    #[test]
-    fn test_tx_new_with_amount() {
-        let tx = Transaction::new_with_amount(Type::Withdrawal, 1, 2, 2.0);
-        assert_eq!(tx.ttype, Type::Withdrawal);
-        assert_eq!(tx.client_id, 1);
-        assert_eq!(tx.tx_id, 2);
-        assert_eq!(tx.amount, Some(2.0));
+    // Records of the labelled sources the transactions are read from, if there are several.
+    fn sources(&self) -> Vec<SourceStats> {
+        Vec::new()
     }
 
-    #[test]
-    fn test_tx_new() {
-        let tx = Transaction::new(Type::Withdrawal, 1, 2);
-        assert_eq!(tx.ttype, Type::Withdrawal);
-        assert_eq!(tx.client_id, 1);
-        assert_eq!(tx.tx_id, 2);
-        assert_eq!(tx.amount,None);
+    // Where the reading of the input is at, for the sources that can be read from a checkpoint on.
+    fn checkpoint(&self) -> Option<Checkpoint> {
+        None
     }
+}
 
-    #[test]
-    fn test_tx_disputed() {
-        let mut tx = Transaction::new(Type::Deposit, 1, 1);
-        assert_eq!(tx.is_emtpy_flags(), true);
-        tx.mark_disputed();
-        assert_eq!(tx.is_emtpy_flags(), false);
-        assert_eq!(tx.is_disputed(), true);
-        assert_eq!(tx.is_resolved(), false);
-        assert_eq!(tx.is_charged_back(), false);
+impl TransactionSource for TransactionIterator {
+    fn has_tenant(&self) -> bool {
+        self.schema.has_tenant()
     }
 
-    #[test]
-    fn test_tx_resolved() {
-        let mut tx = Transaction::new(Type::Deposit, 1, 1);
-        assert_eq!(tx.is_emtpy_flags(), true);
-        tx.mark_resolved();
-        assert_eq!(tx.is_emtpy_flags(), false);
-        assert_eq!(tx.is_disputed(), false);
-        assert_eq!(tx.is_resolved(), true);
-        assert_eq!(tx.is_charged_back(), false);
+    fn invalid(&self) -> usize {
+        self.invalid
     }
 
-    #[test]
-    fn test_tx_charged_back() {
-        let mut tx = Transaction::new(Type::Deposit, 1, 1);
-        assert_eq!(tx.is_emtpy_flags(), true);
-        tx.mark_charged_back();
-        assert_eq!(tx.is_emtpy_flags(), false);
-        assert_eq!(tx.is_disputed(), false);
-        assert_eq!(tx.is_resolved(), false);
-        assert_eq!(tx.is_charged_back(), true);
+    // The transactions read ahead are not handed out yet, so the checkpoint is right before them.
+    fn checkpoint(&self) -> Option<Checkpoint> {
+        match self.buffered.front().and_then(|tx| tx.offset().zip(tx.record())) {
+            Some((offset, record)) => Some(Checkpoint { offset, records: record - 1 }),
+            None => Some(self.input.checkpoint())
+        }
     }
+}
 
-    #[test]
-    fn test_tx_getters() {
-        let tx = Transaction::new(Type::Deposit, 10, 2);
-        assert_eq!(tx.tx_id(), 2);
-        assert_eq!(tx.client_id(), 10);
-        assert_eq!(tx.amount(), None);
-        assert_eq!(tx.transaction_type(), Type::Deposit);
+// Transactions built at runtime by an embedding application, received over a channel. The run
+// goes on until every sender is dropped. The transactions that do not come with a record position
+// get the position they were received at.
+pub struct ChannelSource {
+    receiver: std::sync::mpsc::Receiver<Transaction>,
+    with_tenant: bool,
+    received: u64,
+}
+
+impl ChannelSource {
+    pub fn new(receiver: std::sync::mpsc::Receiver<Transaction>) -> Self {
+        ChannelSource { receiver, with_tenant: false, received: 0 }
     }
 
-    #[test]
-    fn test_tx_from_str() {
-        assert_eq!(Transaction::from(String::from("deposit,1,1,1.0")).unwrap(),
-                   Transaction::new_with_amount(Type::Deposit, 1, 1, 1.0));
-        assert_eq!(Transaction::from(String::from("resolve,1,1")).unwrap(),
-                   Transaction::new(Type::Resolve, 1, 1));
-        assert!(Transaction::from(String::from("")).is_err());
-        assert!(Transaction::from(String::from("Dispute,1,1,1.0")).is_err());
-        assert!(Transaction::from(String::from("1,1,1.0")).is_err());
-        assert!(Transaction::from(String::from("dispute,1.0,1,1.0")).is_err());
+    pub fn new_with_tenant(receiver: std::sync::mpsc::Receiver<Transaction>) -> Self {
+        ChannelSource { receiver, with_tenant: true, received: 0 }
     }
+}
 
-    #[test]
-    fn test_tx_iterator() {
-        let mut tmp_file = tempfile().unwrap();
-        writeln!(tmp_file, "type,client,tx,amount").unwrap();
-        writeln!(tmp_file, "deposit,0,0,1.0").unwrap();
-        writeln!(tmp_file, "dispute,0,0").unwrap();
-        writeln!(tmp_file, "resolve,0,0").unwrap();
-        writeln!(tmp_file, "error,0,0").unwrap();
-        tmp_file.seek(SeekFrom::Start(0)).unwrap();
+impl Iterator for ChannelSource {
+    type Item = Transaction;
 
-        let mut tx_iter = TransactionIterator::new(Input::from(tmp_file));
-        assert_eq!(Transaction::new_with_amount(Type::Deposit, 0, 0, 1.0), tx_iter.next().unwrap());
-        assert_eq!(Transaction::new(Type::Dispute, 0, 0), tx_iter.next().unwrap());
-        assert_eq!(Transaction::new(Type::Resolve, 0, 0), tx_iter.next().unwrap());
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tx = self.receiver.recv().ok()?;
+        self.received += 1;
+        if tx.record().is_none() {
+            tx.set_record(Some(self.received));
+        }
+        Some(tx)
+    }
+}
 
-        // Errors are handled gracefully.
-        assert!(tx_iter.next().is_none());
-        assert!(tx_iter.next().is_none());
+impl TransactionSource for ChannelSource {
+    fn has_tenant(&self) -> bool {
+        self.with_tenant
+    }
+}
+
+// Holds back up to `window` transactions of a client, releasing them by ascending sequence
+// numbers, so that the records a network source swapped are still applied in order. The
+// transactions without a sequence number are not held back.
+struct Reorder {
+    window: usize,
+    held: VecDeque<Transaction>,
+}
+
+impl Reorder {
+    // Returns the transaction to apply next, if any.
+    fn push(&mut self, tx: Transaction) -> Option<Transaction> {
+        let sequence = match tx.sequence() {
+            Some(sequence) => sequence,
+            None => return Some(tx)
+        };
+        let idx = self.held.partition_point(|held| held.sequence() <= Some(sequence));
+        self.held.insert(idx, tx);
+        if self.held.len() > self.window { self.held.pop_front() } else { None }
+    }
+}
+
+// Runs an account admin until its channel is closed or its account gets closed, returning the
+// final state of the account and its history. The transactions refused because the account is
+// locked or closed go to the dead letters, along with those still queued once it is closed. The
+// events logged while a transaction is applied carry the fields of the client, along with those
+// of the transaction.
+async fn run_account_admin(mut account_admin: AccountAdmin, client: Fields, tx_delay: Option<Duration>,
+                           workers: Option<Arc<Semaphore>>, mut reorder: Option<Reorder>, reports: Reports)
+                           -> Restored {
+    let Reports { dead_letters, violations, duplicates, timings } = reports;
+    let mut measured = StageTimings::default();
+    let report = |measured: &StageTimings| {
+        if let Some(timings) = &timings {
+            timings.lock().unwrap().merge(measured);
+        }
+    };
+    loop {
+        let tx = match (account_admin.recv().await, &mut reorder) {
+            (Some(tx), None) => tx,
+            (Some(tx), Some(reorder)) => match reorder.push(tx) {
+                Some(tx) => tx,
+                None => continue
+            },
+            // Once the channel is closed, the transactions held back are applied in order.
+            (None, reorder) => match reorder.as_mut().and_then(|reorder| reorder.held.pop_front()) {
+                Some(tx) => tx,
+                None => {
+                    report(&measured);
+                    return (account_admin.account().clone(), account_admin.take_history());
+                }
+            }
+        };
+        if let Some(wait) = tx.queued_for() {
+            measured.queue_wait.record(wait);
+        }
+
+        // A worker slot of the tenant is held only while the transaction is applied, so idle
+        // account admins do not starve the busy ones.
+        let _permit = match &workers {
+            Some(workers) => workers.acquire().await.ok(),
+            None => None
+        };
+
+        if let Some(delay) = tx_delay {
+            thread::sleep(delay);
+        }
+
+        // The task may move to another thread at every `.await`, so the span is only entered
+        // around the transaction being applied.
+        let span = logger::enter(Fields { tx_id: Some(tx.tx_id()), tx_type: Some(tx.transaction_type().as_str()),
+                                          ..client.clone() });
+        let refusable = matches!(account_admin.account().status(), Status::Locked | Status::Closed)
+            || account_admin.may_route();
+        let dead_letter = dead_letters.as_ref().filter(|_| refusable).map(|dead_letters| (dead_letters, tx.clone()));
+        let (record, tx_id) = (tx.record(), tx.tx_id());
+        let started = timings.as_ref().map(|_| Instant::now());
+        let result = account_admin.apply(tx).map(|_| ());
+        measured.apply += started.map(|started| started.elapsed()).unwrap_or_default();
+        // The transactions routed by the policy are not errors, but the decision of a rule.
+        if let (Some(violations), Err(err)) = (&violations, &result) {
+            if !matches!(err, ClientError::Handle(_) | ClientError::PolicyRouted) {
+                let client = client.client_id.clone().unwrap_or_default();
+                let _ = violations.send(Violation::Rejected { record, client, tx_id, reason: err.as_str() });
+            }
+        }
+        if let Err(ClientError::TxDuplicated) = result {
+            log::warn!("Tx {} of client {} reuses the tx id of an earlier transaction, it is rejected as a duplicate.",
+                       tx_id, client.client_id.as_deref().unwrap_or_default());
+            duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+        match result {
+            Err(ClientError::Handle(acc)) => {
+                drop(span);
+                let mut queued = account_admin.close().await;
+                queued.extend(reorder.take().into_iter().flat_map(|reorder| reorder.held));
+                if let Some((dead_letters, tx)) = dead_letter {
+                    for tx in std::iter::once(tx).chain(queued) {
+                        let _ = dead_letters.send(tx);
+                    }
+                }
+                report(&measured);
+                return (*acc, account_admin.take_history());
+            }
+            Err(ClientError::AccountLocked) | Err(ClientError::PolicyRouted) => {
+                if let Some((dead_letters, tx)) = dead_letter {
+                    let _ = dead_letters.send(tx);
+                }
+            }
+            _ => ()
+        }
+    }
+}
+
+// What the account admins report to the driver while they run: the transactions that go to the
+// dead letters, the rejections of a strict run, the number of transactions rejected for reusing a
+// tx id, and the queue waits and the application times of a run measuring its stages, added once
+// the account admin stops.
+struct Reports {
+    dead_letters: Option<std::sync::mpsc::Sender<Transaction>>,
+    violations: Option<std::sync::mpsc::Sender<Violation>>,
+    duplicates: Arc<AtomicUsize>,
+    timings: Option<Arc<Mutex<StageTimings>>>,
+}
+
+// Final accounts of a tenant dispatcher, their history, the transactions of clients without an
+// account it rejected and the clients whose account admin panicked.
+type TenantRun = (Vec<Account>, Vec<Transaction>, usize, Vec<u16>);
+
+// Routes the transactions of a tenant to its account admins, spawning an account admin for every
+// new client. Every tenant gets its own dispatcher, so a tenant whose account admins fall behind
+// only stalls its own transactions, once its buffer fills up.
+struct TenantDispatcher {
+    tenant: Option<String>,
+    pipes: HashMap<u16, Sender<Transaction>>,
+    // The priority lanes of the account admins, for the administrative operations.
+    lanes: Option<HashMap<u16, Sender<Transaction>>>,
+    worker_handlers: Vec<(u16, JoinHandle<Restored>)>,
+    workers: Option<Arc<Semaphore>>,
+    tx_delay: Option<Duration>,
+    sequence_window: Option<usize>,
+    rules: Rules,
+    audit: Option<std::sync::mpsc::Sender<Entry>>,
+    archive: Option<std::sync::mpsc::Sender<Transaction>>,
+    dead_letters: Option<std::sync::mpsc::Sender<Transaction>>,
+    // The account admins of a strict run report the transactions they reject.
+    violations: Option<std::sync::mpsc::Sender<Violation>>,
+    // The account admins of a run measuring its stages report their timings.
+    timings: Option<Arc<Mutex<StageTimings>>>,
+    // The account admins count the transactions they reject for reusing a tx id.
+    duplicates: Arc<AtomicUsize>,
+    // The tx ids of the transactions starting a new one dispatched so far, whatever their client,
+    // unless the run allows duplicate tx ids.
+    tx_ids: Option<HashSet<u32>>,
+    labels: Labels,
+    quota: Option<usize>,
+    accepted: usize,
+    dropped: usize,
+    unknown_account: UnknownAccountPolicy,
+    // Transactions of clients without an account held back by the buffer policy, along with the
+    // number of accepted transactions they expire at.
+    parked: VecDeque<(usize, Transaction)>,
+    // Transactions of clients without an account that were rejected by the policy.
+    orphaned: usize,
+}
+
+impl TenantDispatcher {
+    fn new(tenant: Option<String>, config: &Config, audit: Option<std::sync::mpsc::Sender<Entry>>,
+           archive: Option<std::sync::mpsc::Sender<Transaction>>,
+           dead_letters: Option<std::sync::mpsc::Sender<Transaction>>) -> Self {
+        TenantDispatcher {
+            audit,
+            archive,
+            dead_letters,
+            violations: None,
+            timings: None,
+            duplicates: Arc::default(),
+            tx_ids: config.reject_duplicates.then(HashSet::new),
+            tenant,
+            pipes: HashMap::new(),
+            lanes: if config.admin_lane { Some(HashMap::new()) } else { None },
+            worker_handlers: Vec::new(),
+            workers: config.tenant_workers.map(|workers| Arc::new(Semaphore::new(workers))),
+            tx_delay: config.tx_delay,
+            sequence_window: config.sequence_window,
+            rules: config.rules.clone(),
+            labels: config.labels(),
+            quota: config.tenant_quota,
+            accepted: 0,
+            dropped: 0,
+            unknown_account: config.unknown_account,
+            parked: VecDeque::new(),
+            orphaned: 0,
+        }
+    }
+
+    async fn dispatch(&mut self, mut tx: Transaction) {
+        if matches!(self.quota, Some(quota) if self.accepted >= quota) {
+            self.dropped += 1;
+            return;
+        }
+        self.accepted += 1;
+
+        // The first of the transactions sharing a tx id is the one applied, the account admins
+        // rejecting the others as duplicates, even those of another client.
+        if let Some(tx_ids) = self.tx_ids.as_mut().filter(|_| tx.transaction_type().starts_new()) {
+            if !tx_ids.insert(tx.tx_id()) {
+                tx.mark_replayed();
+            }
+        }
+
+        self.expire_parked();
+        let client_id = tx.client_id();
+        // If the sender for a specific client was already created, send the tx on the channel, or
+        // on the priority lane of the client for the administrative operations.
+        let lane = self.lanes.as_ref().filter(|_| tx.transaction_type().is_admin()).and_then(|lanes| lanes.get(&client_id));
+        if let Some(sender) = lane.or_else(|| self.pipes.get(&client_id)) {
+            // Handle errors gracefully. When an account is closed the receiver is closed, and the
+            // transaction goes to the dead letters. However, we still need to keep the sender in
+            // scope because otherwise we wouldn't know that there were already an account for the
+            // client with the account closed, which means that we will create a new account for
+            // that client, which is not the expected behavior of handling transactions.
+            // The receiver of an account admin that panicked is closed too.
+            if let Err(SendError(tx)) = sender.send(tx).await {
+                self.dead_letter(tx);
+            }
+        } else { // Otherwise, create the channel and spawn a task with the client waiting for
+            // transactions to handle. The client will stop waiting for transactions when the
+            // the channel is closed.
+            if tx.transaction_type().refers_to_previous() {
+                match self.unknown_account {
+                    UnknownAccountPolicy::Open => (),
+                    UnknownAccountPolicy::Reject => {
+                        self.orphaned += 1;
+                        return;
+                    }
+                    UnknownAccountPolicy::Buffer { window } => {
+                        self.parked.push_back((self.accepted + window, tx));
+                        return;
+                    }
+                }
+            }
+            if let Err(SendError(tx)) = self.spawn(client_id, None).send(tx).await {
+                self.dead_letter(tx);
+            }
+            self.release_parked(client_id).await;
+        }
+    }
+
+    fn dead_letter(&self, tx: Transaction) {
+        if let Some(dead_letters) = &self.dead_letters {
+            let _ = dead_letters.send(tx);
+        }
+    }
+
+    // Rejects the parked transactions whose window ran out.
+    fn expire_parked(&mut self) {
+        while matches!(self.parked.front(), Some((expires_at, _)) if *expires_at < self.accepted) {
+            self.parked.pop_front();
+            self.orphaned += 1;
+        }
+    }
+
+    // Sends the parked transactions of a client that just got an account, in their order.
+    async fn release_parked(&mut self, client_id: u16) {
+        let mut idx = 0;
+        while idx < self.parked.len() {
+            if self.parked[idx].1.client_id() != client_id {
+                idx += 1;
+                continue;
+            }
+            let (_, tx) = self.parked.remove(idx).unwrap();
+            let _ = self.pipes[&client_id].send(tx).await;
+        }
+    }
+
+    // Spawns the account admin of a client, resuming it from a restored account and history.
+    fn spawn(&mut self, client_id: u16, restored: Option<Restored>) -> &Sender<Transaction> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(32);
+        let mut account_admin = AccountAdmin::new_with_tenant(client_id, self.tenant.clone(), receiver);
+        account_admin.set_rules(self.rules.clone());
+        if let Some(lanes) = &mut self.lanes {
+            let (lane, control) = tokio::sync::mpsc::channel(32);
+            account_admin.set_control(control);
+            lanes.insert(client_id, lane);
+        }
+        if let Some(audit) = &self.audit {
+            account_admin.set_audit(audit.clone());
+        }
+        if let Some(archive) = &self.archive {
+            account_admin.set_archive(archive.clone());
+        }
+        if let Some((account, history)) = restored {
+            if let Some(tx_ids) = &mut self.tx_ids {
+                tx_ids.extend(history.iter().filter(|tx| tx.transaction_type().starts_new()).map(Transaction::tx_id));
+            }
+            account_admin.restore(account, history);
+        }
+        // The client is logged as it appears in the outputs.
+        let client = Fields {
+            tenant: self.tenant.clone(),
+            client_id: Some(self.labels.label(client_id)),
+            ..Fields::default()
+        };
+        let reorder = self.sequence_window.map(|window| Reorder { window, held: VecDeque::new() });
+        let reports = Reports { dead_letters: self.dead_letters.clone(), violations: self.violations.clone(),
+                                duplicates: self.duplicates.clone(), timings: self.timings.clone() };
+        // Store the tasks handle.
+        self.worker_handlers.push((client_id, tokio::spawn(
+            run_account_admin(account_admin, client, self.tx_delay, self.workers.clone(), reorder, reports))));
+        self.pipes.entry(client_id).or_insert(sender)
+    }
+
+    // Returns the final accounts and their history, along with the number of transactions
+    // rejected for referring to clients without an account. An account admin that panicked is
+    // reported as failed, the other accounts are returned all the same.
+    async fn finish(mut self) -> TenantRun {
+        let tenant = self.tenant.as_deref().unwrap_or(report::NO_TENANT);
+        if self.dropped > 0 {
+            log::warn!("Tenant {} exceeded its quota of {} transactions, {} transactions were dropped.",
+                       tenant, self.accepted, self.dropped);
+        }
+        self.orphaned += self.parked.len();
+        if self.orphaned > 0 {
+            log::warn!("Tenant {} had {} transactions of clients without an account, which were rejected.",
+                       tenant, self.orphaned);
+        }
+
+        // Close the senders and implicitly, stop the clients from waiting for transactions.
+        drop(self.pipes);
+
+        let mut accounts = Vec::with_capacity(self.worker_handlers.len());
+        let mut history = Vec::new();
+        let mut failed = Vec::new();
+        for (client_id, handle) in self.worker_handlers {
+            match handle.await {
+                Ok((account, account_history)) => {
+                    accounts.push(account);
+                    history.extend(account_history);
+                }
+                Err(_) => {
+                    log::error!("The account admin of client {} of tenant {} panicked, its account is left out.",
+                                self.labels.label(client_id), tenant);
+                    failed.push(client_id);
+                }
+            }
+        }
+        (accounts, history, self.orphaned, failed)
+    }
+}
+
+// Entry point into transactions execution, iterating through each tx from the provided input.
+// Nothing is applied when the input is rejected by the invalid rate guard. A strict run fails with
+// the first invalid record or rejected transaction it notices.
+pub fn drill(input: Input, config: &Config) -> Result<RunReport> {
+    resume(State::default(), input, config)
+}
+
+// Same as `drill`, for transactions coming from any source. Reading from the source blocks the
+// driver, so on a single threaded runtime the account admins only make progress while the
+// driver waits on them.
+pub fn drill_source<S: TransactionSource>(record_iter: S, config: &Config) -> Result<RunReport> {
+    resume_source(State::default(), record_iter, config)
+}
+
+// Same as `drill`, resuming the accounts and their history from the state of an earlier run.
+pub fn resume(state: State, input: Input, config: &Config) -> Result<RunReport> {
+    resume_source(state, input_source(input, config)?, config)
+}
+
+// The transactions of a CSV input, as read by a run with the config, i.e. with its amount scale,
+// client aliases and reference data, once the input passes the invalid rate guard.
+pub fn input_source(input: Input, config: &Config) -> Result<TransactionIterator> {
+    let mut record_iter = TransactionIterator::new_with_scale(input, config.amount_scale);
+    record_iter.set_aliases(config.aliases.clone());
+    record_iter.set_reference(config.reference.clone());
+    if let Some(guard) = &config.invalid_rate_guard {
+        record_iter.check_invalid_rate(guard)?;
+    }
+    Ok(record_iter)
+}
+
+pub fn resume_source<S: TransactionSource>(state: State, mut record_iter: S, config: &Config) -> Result<RunReport> {
+    let with_tenant = record_iter.has_tenant() || state.accounts.iter().any(|account| account.tenant().is_some());
+    let rt = if config.multi_threaded_runtime {
+        tokio::runtime::Builder::new_multi_thread().build().expect("Could not initialize multi threaded runtime.")
+    } else {
+        tokio::runtime::Builder::new_current_thread().build().expect("Could not initialize single threaded runtime.")
+    };
+
+    // Every output derived from the audit entries is a sink of the audit writer.
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    if let Some(path) = &config.audit_path {
+        let file = if config.audit_chain { AuditFile::create_chained(path) } else { AuditFile::create(path) };
+        match file {
+            Ok(file) => sinks.push(Box::new(file)),
+            Err(_) => log::error!("Could not create the audit trail {}.", path.display())
+        }
+    }
+    for observer in &config.observers {
+        match observer() {
+            Ok(sink) => sinks.push(sink),
+            Err(_) => log::error!("Could not set up an observer of the run.")
+        }
+    }
+    if let Some(dir) = config.partition_dir.as_ref().filter(|_| config.partitioning.audit) {
+        match PartitionedAudit::create(dir, &config.partitioning, config.audit_chain) {
+            Ok(audit) => sinks.push(Box::new(audit)),
+            Err(_) => log::error!("Could not create the audit trail partitions under {}.", dir.display())
+        }
+    }
+    if let Some(dir) = &config.statements_dir {
+        sinks.push(Box::new(Statements::new(dir.clone(), config.labels())));
+    }
+    if let Some(path) = &config.aggregates_path {
+        sinks.push(Box::new(Aggregates::new(path.clone(), config.aggregate_bucket, config.labels())));
+    }
+    if let Some(path) = &config.suspicious_activity_path {
+        sinks.push(Box::new(SuspiciousActivity::new(path.clone(), config.suspicious_activity_thresholds.clone(),
+                                                    config.labels())));
+    }
+    if let Some(path) = &config.account_events_path {
+        match EventFile::create(path, config.labels()) {
+            Ok(events) => sinks.push(Box::new(events)),
+            Err(_) => log::error!("Could not create the account events {}.", path.display())
+        }
+    }
+    if let Some(path) = &config.backfill_path {
+        sinks.push(Box::new(Corrections::new(path.clone(), config.labels())));
+    }
+    if let Some(path) = &config.snapshot_path {
+        sinks.push(Box::new(Snapshots::new(path.clone(), config.snapshot_schedule.clone(), config, with_tenant,
+                                           &state.accounts)));
+    }
+    // The alerts about the run itself are raised by the driver.
+    let mut alerts = None;
+    let webhook_metrics = Arc::new(Metrics::default());
+    if !config.alerts.is_empty() {
+        let retrier = Retrier::new(config.webhook_retry.clone(), config.clock.clone(), webhook_metrics.clone());
+        match Alerter::spawn(&config.alerts, &retrier, config.labels()) {
+            Ok(alerter) => {
+                alerts = alerter.sender();
+                sinks.push(Box::new(alerter));
+            }
+            Err(_) => log::error!("Could not set up the alert sinks.")
+        }
+    }
+    let audit = if sinks.is_empty() { None } else { Some(AuditWriter::spawn(sinks)) };
+    let archiver = match &config.archive_path {
+        Some(path) => match Archiver::create(path) {
+            Ok(archiver) => Some(archiver),
+            Err(_) => {
+                log::error!("Could not create the history archive {}.", path.display());
+                None
+            }
+        },
+        None => None
+    };
+
+    // The dead letters are kept for the whole run, since they are few unless accounts get locked.
+    let (dead_letters, dead_letter_receiver) = std::sync::mpsc::channel::<Transaction>();
+    // The account admins of a strict run report their rejections to the driver, which stops reading
+    // once it notices one.
+    let (violations, violation_receiver) = std::sync::mpsc::channel::<Violation>();
+    let strict = config.mode == Mode::Strict;
+    let duplicates = Arc::new(AtomicUsize::new(0));
+    let timings = config.stage_timings.then(|| Arc::new(Mutex::new(StageTimings::default())));
+
+    // The tx ids are remembered by the driver, in the order of the input, so that the first of the
+    // transactions sharing a tx id is the one applied, whatever the order the clients run in. The
+    // history of the resumed accounts is remembered first.
+    let recorded = config.backfill_path.as_ref().map(|_| Recorded::new(&state.history));
+    let mut replay = config.replay.map(Memory::new);
+    if let Some(memory) = &mut replay {
+        for tx in state.history.iter().filter(|tx| tx.transaction_type().starts_new()) {
+            memory.remember(tx.tenant(), tx.client_id(), tx.tx_id(), tx.timestamp());
+        }
+    }
+
+    rt.block_on(async {
+        // Accounts are keyed by the tenant too, so that the same client id can be used by
+        // different tenants without collisions.
+        let mut tenants: HashMap<Option<String>, Sender<Transaction>> = HashMap::new();
+        let mut tenant_handlers: Vec<(Option<String>, JoinHandle<TenantRun>)> = Vec::new();
+        let mut spawn_tenant = |tenant: &Option<String>, restored: BTreeMap<u16, Restored>| {
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(config.tenant_buffer);
+            let mut dispatcher = TenantDispatcher::new(tenant.clone(), config,
+                                                       audit.as_ref().map(AuditWriter::sender),
+                                                       archiver.as_ref().map(Archiver::sender),
+                                                       Some(dead_letters.clone()));
+            if strict {
+                dispatcher.violations = Some(violations.clone());
+            }
+            dispatcher.timings = timings.clone();
+            dispatcher.duplicates = duplicates.clone();
+            tenant_handlers.push((tenant.clone(), tokio::spawn(async move {
+                // The restored accounts are part of the run, even without any new transaction.
+                for (client_id, restored) in restored {
+                    dispatcher.spawn(client_id, Some(restored));
+                }
+                while let Some(tx) = receiver.recv().await {
+                    dispatcher.dispatch(tx).await;
+                }
+                dispatcher.finish().await
+            })));
+            sender
+        };
+        for (tenant, restored) in state.into_tenants() {
+            let sender = spawn_tenant(&tenant, restored);
+            tenants.insert(tenant, sender);
+        }
+
+        let (mut records, mut backfilled) = (0, 0);
+        let mut sampler = config.sample.map(Sampler::new);
+        // The limits are checked before every record is read, so that the checkpoint of an aborted
+        // run is right after the last record it applied.
+        let mut watchdog = Some(config.limits).filter(Limits::is_set)
+            .map(|limits| Watchdog::new(limits, config.clock.clone()));
+        let mut aborted = None;
+        let mut violation = None;
+        // The time the driver spends reading and handing off the transactions.
+        let (mut read, mut dispatch) = (Duration::ZERO, Duration::ZERO);
+        loop {
+            if let Some(exceeded) = watchdog.as_mut().and_then(Watchdog::check) {
+                log::error!("Aborted the run after {} records, {}.", records, exceeded.describe());
+                aborted = Some(exceeded);
+                break;
+            }
+            let started = timings.as_ref().map(|_| Instant::now());
+            let next = record_iter.next();
+            read += started.map(|started| started.elapsed()).unwrap_or_default();
+            // The invalid records are skipped by the source, so they are noticed by their count.
+            if strict && record_iter.invalid() > 0 {
+                violation = Some(Violation::InvalidRecord);
+                break;
+            }
+            if let Some(rejected) = violation_receiver.try_recv().ok().filter(|_| strict) {
+                violation = Some(rejected);
+                break;
+            }
+            let mut tx = match next {
+                Some(tx) => tx,
+                None => break
+            };
+            records += 1;
+            if sampler.as_mut().is_some_and(|sampler| !sampler.keeps(&tx)) {
+                continue;
+            }
+            if recorded.as_ref().is_some_and(|recorded| recorded.contains(&tx)) {
+                backfilled += 1;
+                continue;
+            }
+            if let Some(memory) = replay.as_mut().filter(|_| tx.transaction_type().starts_new()) {
+                if !memory.remember(tx.tenant(), tx.client_id(), tx.tx_id(), tx.timestamp()) {
+                    tx.mark_replayed();
+                }
+            }
+            let sender = tenants.entry(tx.tenant().map(String::from))
+                .or_insert_with_key(|tenant| spawn_tenant(tenant, BTreeMap::new()));
+            let started = timings.as_ref().map(|_| {
+                tx.mark_queued();
+                Instant::now()
+            });
+            // The tenant dispatchers only stop once their sender is dropped, unless they panicked.
+            if let Err(SendError(tx)) = sender.send(tx).await {
+                let _ = dead_letters.send(tx);
+            }
+            dispatch += started.map(|started| started.elapsed()).unwrap_or_default();
+        }
+
+        // Close the senders and implicitly, stop the tenant dispatchers.
+        drop(tenants);
+        if let Some((sampled, clients)) = sampler.map(|sampler| sampler.clients()) {
+            log::warn!("Sampled {} of the {} clients of the input, the outputs are approximate.", sampled, clients);
+        }
+
+        let invalid = record_iter.invalid();
+        if invalid > 0 {
+            log::warn!("{} invalid records were skipped.", invalid);
+        }
+        // The alerter only stops once every sender is dropped.
+        if let Some(alerts) = alerts.take() {
+            if invalid > 0 {
+                let _ = alerts.send(Alert::invalid_records(invalid));
+            }
+        }
+
+        let mut accounts = Vec::new();
+        let mut history = Vec::new();
+        let mut orphaned = 0;
+        let mut failed = Vec::new();
+        for (tenant, handle) in tenant_handlers {
+            match handle.await {
+                Ok((tenant_accounts, tenant_history, tenant_orphaned, tenant_failed)) => {
+                    accounts.extend(tenant_accounts);
+                    history.extend(tenant_history);
+                    orphaned += tenant_orphaned;
+                    failed.extend(tenant_failed.into_iter()
+                        .map(|client_id| Failure { tenant: tenant.clone(), client_id: Some(client_id) }));
+                }
+                Err(_) => {
+                    log::error!("The dispatcher of tenant {} panicked, its accounts are left out.",
+                                tenant.as_deref().unwrap_or(report::NO_TENANT));
+                    failed.push(Failure { tenant, client_id: None });
+                }
+            }
+        }
+        // Every account admin counted its duplicates once its tenant dispatcher finished.
+        let duplicates = duplicates.load(Ordering::Relaxed);
+        if duplicates > 0 {
+            log::warn!("{} transactions reusing the tx id of an earlier one were rejected.", duplicates);
+        }
+
+        // A strict run fails without any of the outputs written at the end of the run, the audit
+        // trail and the archive being written as the transactions are applied.
+        drop(violations);
+        if let Some(violation) = violation.or_else(|| violation_receiver.try_iter().next()).filter(|_| strict) {
+            if let Some(audit) = audit {
+                let _ = audit.finish();
+            }
+            if let Some(archiver) = archiver {
+                let _ = archiver.finish();
+            }
+            return Err(Error::Strict(violation));
+        }
+
+        // Every other sender is owned by the tenant dispatchers and their account admins.
+        drop(dead_letters);
+        let mut dead_letters: Vec<Transaction> = dead_letter_receiver.iter().collect();
+        dead_letters.sort_by_key(Transaction::record);
+        if !dead_letters.is_empty() {
+            log::warn!("{} transactions of locked or closed accounts were not applied.", dead_letters.len());
+        }
+        if let Some(path) = &config.dead_letters_path {
+            if report::write_file(path, |writer| deadletter::write(writer, &dead_letters, with_tenant, config.aliases.as_deref())).is_err() {
+                log::error!("Could not write the dead letters {}.", path.display());
+            }
+        }
+
+        if let Some(audit) = audit {
+            if audit.finish().is_err() {
+                log::error!("Could not write the outputs derived from the audit trail.");
+            }
+        }
+
+        if let Some(archiver) = archiver {
+            if archiver.finish().is_err() {
+                log::error!("Could not write the history archive.");
+            }
+        }
+
+        if let Some(path) = &config.state_path {
+            if state::write_file(path, &accounts, &history, record_iter.checkpoint()).is_err() {
+                log::error!("Could not write the state file {}.", path.display());
+            }
+        }
+
+        if let Some(path) = &config.open_disputes_path {
+            if report::write_file(path, |writer| report::write_open_disputes(writer, &accounts, config)).is_err() {
+                log::error!("Could not write the open disputes {}.", path.display());
+            }
+        }
+
+        if let Some(path) = &config.top_accounts_path {
+            if report::write_file(path, |writer| report::write_top_accounts(writer, &accounts, config.top_count, config)).is_err() {
+                log::error!("Could not write the top accounts {}.", path.display());
+            }
+        }
+
+        if let Some(path) = &config.exposure_path {
+            if report::write_file(path, |writer| report::write_exposures(writer, &accounts, config)).is_err() {
+                log::error!("Could not write the exposure report {}.", path.display());
+            }
+        }
+
+        if let Some(dir) = &config.tenant_output_dir {
+            if report::write_accounts_per_tenant(dir, &accounts, config).is_err() {
+                log::error!("Could not write the per tenant accounts under {}.", dir.display());
+            }
+        }
+
+        if let Some(dir) = &config.partition_dir {
+            if partition::write_accounts(dir, &accounts, config, with_tenant).is_err() {
+                log::error!("Could not write the account partitions under {}.", dir.display());
+            }
+        }
+
+        let mut run_report = RunReport::new(&accounts);
+        run_report.invalid = invalid;
+        run_report.rejected += orphaned as u64;
+        run_report.dead_letters = deadletter::volume(&dead_letters);
+        run_report.records = records + invalid;
+        run_report.backfilled = backfilled;
+        run_report.duplicates = duplicates;
+        run_report.failed = failed;
+        run_report.webhook_retries = webhook_metrics.stats();
+        run_report.sources = record_iter.sources();
+        run_report.aborted = aborted;
+        // Every account admin reported its timings once its tenant dispatcher finished.
+        run_report.timings = timings.map(|timings| {
+            let mut timings = timings.lock().unwrap().clone();
+            timings.read = read;
+            timings.dispatch = dispatch;
+            log::info!("Stage timings: {}.", timings.describe());
+            timings
+        });
+        for source in &mut run_report.sources {
+            for tx in &dead_letters {
+                if tx.source() == Some(source.label.as_str()) {
+                    source.dead_letters.add(tx.amount().unwrap_or(ZERO));
+                }
+            }
+        }
+        run_report.house_accounts = house::balances(&accounts, &config.house_routes);
+        if let Some(path) = &config.house_accounts_path {
+            if report::write_file(path, |writer| house::write(writer, &run_report.house_accounts)).is_err() {
+                log::error!("Could not write the house accounts {}.", path.display());
+            }
+        }
+        if let Some(path) = &config.summary_path {
+            if report::write_file(path, |writer| run_report.write_summary(writer)).is_err() {
+                log::error!("Could not write the run summary {}.", path.display());
+            }
+        }
+        for (idx, result) in output::write_all(&config.account_sinks, &accounts, with_tenant).into_iter().enumerate() {
+            if result.is_err() {
+                log::error!("Could not write the accounts into account sink {}.", idx + 1);
+            }
+        }
+        run_report.final_accounts = accounts;
+        run_report.with_tenant = with_tenant;
+        Ok(run_report)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::account::{Account, Error as ClientError, Status};
+    use crate::alias::Aliases;
+    use crate::amount::{from_f64, to_f64, Amount, ZERO};
+    use crate::builder::Tx;
+    use crate::engine::Engine;
+    use crate::replay::{Protection, Scope, Window};
+    use crate::state::State;
+    use crate::config::Config;
+    use crate::audit::{self, Entry};
+    use crate::output::Collector;
+    use crate::sources::Sources;
+    use crate::report::{Failure, RunReport};
+    use crate::transaction::{drill, drill_source, input_source, resume, resume_source, ChannelSource, Error, InvalidRateGuard, Mode, Transaction,
+                             TransactionIterator, TransactionSource, TenantDispatcher, Type, UnknownAccountPolicy, Violation};
+    use crate::input::{Checkpoint, Input, Schema};
+    use crate::policy::Policy;
+    use crate::reference::Reference;
+    use crate::watchdog::{Exceeded, Limits};
+    use tempfile::tempfile;
+
+    #[test]
+    fn test_type_from_str() {
+        assert_eq!(Type::Deposit, Type::from("deposit"));
+        assert_eq!(Type::Withdrawal, Type::from("withdrawal"));
+        assert_eq!(Type::Dispute, Type::from("dispute"));
+        assert_eq!(Type::Resolve, Type::from("resolve"));
+        assert_eq!(Type::Chargeback, Type::from("chargeback"));
+        assert_eq!(Type::Freeze, Type::from("freeze"));
+        assert_eq!(Type::Unfreeze, Type::from("unfreeze"));
+        assert_eq!(Type::Unlock, Type::from("unlock"));
+        assert_eq!(Type::Settle, Type::from("settle"));
+        assert_eq!(Type::Cancel, Type::from("cancel"));
+        assert_eq!(Type::Authorize, Type::from("authorize"));
+        assert_eq!(Type::Capture, Type::from("capture"));
+        assert_eq!(Type::Refund, Type::from("refund"));
+        assert_eq!(Type::Reversal, Type::from("reversal"));
+        assert_eq!(Type::Credit, Type::from("credit"));
+        assert_eq!(Type::Debit, Type::from("debit"));
+        for ttype in [Type::Deposit, Type::Chargeback, Type::Authorize, Type::Reversal, Type::Debit].iter() {
+            assert_eq!(Type::from(ttype.as_str()), *ttype);
+        }
+    }
+
+    #[test]
+    fn test_tx_new_with_amount() {
+        let tx = Tx::withdrawal(1, 2).amount(2.0).build();
+        assert_eq!(tx.ttype, Type::Withdrawal);
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.tx_id, 2);
+        assert_eq!(tx.amount, Some(from_f64(2.0)));
+    }
+
+    #[test]
+    fn test_tx_new() {
+        let tx = Tx::withdrawal(1, 2).build();
+        assert_eq!(tx.ttype, Type::Withdrawal);
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.tx_id, 2);
+        assert_eq!(tx.amount,None);
+    }
+
+    #[test]
+    fn test_tx_parse_aliases() {
+        let aliases = Aliases::parse(&b"external,client\nbank-a-17,1\n"[..]).unwrap();
+        let schema = Schema::default();
+        let tx = Transaction::parse_with_aliases("deposit,bank-a-17,3,1.5", &schema, 4, Some(&aliases)).unwrap();
+        assert_eq!(tx, Tx::deposit(1, 3).amount(1.5).build());
+        assert!(matches!(Transaction::parse_with_aliases("deposit,1,3,1.5", &schema, 4, Some(&aliases)),
+                         Err(Error::InvalidField("client"))));
+    }
+
+    #[test]
+    fn test_tx_parse_errors() {
+        let parse = |line: &str| Transaction::parse(line, &Schema::default()).err().unwrap().describe();
+        assert_eq!(parse(""), "the line is not a CSV record");
+        assert_eq!(parse("deposit,1"), "the record has no `tx` column");
+        assert_eq!(parse("deposit;1;2;1.0"), "the `type` column is invalid");
+        assert_eq!(parse("deposit,1,two,1.0"), "the `tx` column is invalid");
+        assert_eq!(parse("deposit,1,2,1.0x"), "the `amount` column is invalid");
+        assert_eq!(parse("deposit,1,2,1.00001"), "the amount has 5 decimal places, while at most 4 are allowed");
+    }
+
+    #[test]
+    fn test_tx_display_from_str() {
+        let deposit = Tx::deposit(1, 2).amount(1.5).build();
+        assert_eq!(deposit.to_string(), "deposit,1,2,1.5000");
+        assert_eq!("deposit,1,2,1.5000".parse::<Transaction>().unwrap(), deposit);
+        let dispute = Tx::dispute(1, 2).build();
+        assert_eq!(dispute.to_string(), "dispute,1,2,");
+        assert_eq!(dispute.to_string().parse::<Transaction>().unwrap(), dispute);
+        assert!("dispute,1".parse::<Transaction>().is_err());
+    }
+
+    #[test]
+    fn test_tx_disputed() {
+        let mut tx = Tx::deposit(1, 1).build();
+        assert!(tx.is_emtpy_flags());
+        tx.mark_disputed();
+        assert!(!tx.is_emtpy_flags());
+        assert!(tx.is_disputed());
+        assert!(!tx.is_resolved());
+        assert!(!tx.is_charged_back());
+    }
+
+    #[test]
+    fn test_tx_resolved() {
+        let mut tx = Tx::deposit(1, 1).build();
+        assert!(tx.is_emtpy_flags());
+        tx.mark_resolved();
+        assert!(!tx.is_emtpy_flags());
+        assert!(!tx.is_disputed());
+        assert!(tx.is_resolved());
+        assert!(!tx.is_charged_back());
+    }
+
+    #[test]
+    fn test_tx_charged_back() {
+        let mut tx = Tx::deposit(1, 1).build();
+        assert!(tx.is_emtpy_flags());
+        tx.mark_charged_back();
+        assert!(!tx.is_emtpy_flags());
+        assert!(!tx.is_disputed());
+        assert!(!tx.is_resolved());
+        assert!(tx.is_charged_back());
+    }
+
+    #[test]
+    fn test_tx_represented() {
+        let mut tx = Tx::deposit(1, 1).build();
+        tx.mark_charged_back();
+        tx.mark_represented();
+        assert!(!tx.is_emtpy_flags());
+        assert!(!tx.is_charged_back());
+        assert!(tx.is_represented());
+    }
+
+    #[test]
+    fn test_dispute_state_allows() {
+        use super::DisputeState::*;
+        assert!(Undisputed.allows(Disputed, false));
+        assert!(Disputed.allows(Resolved, false) && Disputed.allows(ChargedBack, false));
+        assert!(ChargedBack.allows(Represented, false));
+        assert!(!Resolved.allows(Disputed, false) && Resolved.allows(Disputed, true));
+        assert!(!Undisputed.allows(Resolved, true) && !Resolved.allows(ChargedBack, true));
+        assert!(!ChargedBack.allows(Disputed, true) && !Represented.allows(Disputed, true));
+    }
+
+    #[test]
+    fn test_tx_refunded() {
+        let mut tx = Tx::deposit(1, 1).amount(5.0).build();
+        assert_eq!(tx.disputable_amount(), from_f64(5.0));
+        tx.add_refunded(from_f64(1.5));
+        tx.add_refunded(from_f64(1.0));
+        assert_eq!(tx.refunded(), from_f64(2.5));
+        assert_eq!(tx.disputable_amount(), from_f64(2.5));
+
+        // What is left after a partial chargeback can be disputed again.
+        tx.mark_charged_back();
+        tx.add_charged_back(from_f64(1.0));
+        assert_eq!(tx.disputable_amount(), from_f64(1.5));
+        assert!(tx.is_disputable(false));
+        tx.add_charged_back(from_f64(1.5));
+        assert!(!tx.is_disputable(true));
+    }
+
+    #[test]
+    fn test_tx_getters() {
+        let tx = Tx::deposit(10, 2).build();
+        assert_eq!(tx.tx_id(), 2);
+        assert_eq!(tx.client_id(), 10);
+        assert_eq!(tx.amount(), None);
+        assert_eq!(tx.transaction_type(), Type::Deposit);
+    }
+
+    #[test]
+    fn test_tx_from_str() {
+        assert_eq!(Transaction::from(String::from("deposit,1,1,1.0")).unwrap(),
+                   Tx::deposit(1, 1).amount(1.0).build());
+        assert_eq!(Transaction::from(String::from("resolve,1,1")).unwrap(),
+                   Tx::resolve(1, 1).build());
+        assert!(Transaction::from(String::from("")).is_err());
+        assert!(Transaction::from(String::from("Dispute,1,1,1.0")).is_err());
+        assert!(Transaction::from(String::from("1,1,1.0")).is_err());
+        assert!(Transaction::from(String::from("dispute,1.0,1,1.0")).is_err());
+    }
+
+    #[test]
+    fn test_tx_parse_with_tenant() {
+        let schema = Schema::from_header("type,client,tx,amount,tenant").unwrap();
+        let tx = Transaction::parse("deposit,1,1,1.0,acme", &schema).unwrap();
+        assert_eq!(tx.tenant(), Some("acme"));
+        assert_eq!(tx.amount(), Some(from_f64(1.0)));
+        let tx = Transaction::parse("dispute,1,1,,acme", &schema).unwrap();
+        assert_eq!(tx.tenant(), Some("acme"));
+        assert_eq!(tx.amount(), None);
+        assert!(Transaction::parse("dispute,1,1,,", &schema).is_err());
+        assert!(Transaction::parse("dispute,1,1,,../acme", &schema).is_err());
+        assert!(Transaction::parse("dispute,1,1", &schema).is_err());
+        assert_eq!(Transaction::parse("deposit,1,1,1.0", &Schema::default()).unwrap().tenant(), None);
+    }
+
+    #[test]
+    fn test_tx_parse_with_reason() {
+        let schema = Schema::from_header("type,client,tx,amount,reason").unwrap();
+        assert_eq!(Transaction::parse("dispute,1,1,,fraud", &schema).unwrap().reason(), Some("fraud"));
+        assert_eq!(Transaction::parse("dispute,1,1,,", &schema).unwrap().reason(), None);
+        assert_eq!(Transaction::parse("dispute,1,1", &schema).unwrap().reason(), None);
+        assert!(Transaction::parse("dispute,1,1,,not a code", &schema).is_err());
+    }
+
+    #[test]
+    fn test_tx_parse_with_timestamp() {
+        let schema = Schema::from_header("type,client,tx,amount,timestamp").unwrap();
+        assert_eq!(Transaction::parse("deposit,1,1,1.0,1633046400", &schema).unwrap().timestamp(), Some(1633046400));
+        assert_eq!(Transaction::parse("dispute,1,1,,", &schema).unwrap().timestamp(), None);
+        assert!(Transaction::parse("deposit,1,1,1.0,yesterday", &schema).is_err());
+    }
+
+    fn at_record(mut tx: Transaction, record: u64, offset: u64) -> Transaction {
+        tx.set_record(Some(record));
+        tx.set_offset(Some(offset));
+        tx
+    }
+
+    #[test]
+    fn test_tx_iterator() {
+        let mut tmp_file = tempfile().unwrap();
+        writeln!(tmp_file, "type,client,tx,amount").unwrap();
+        writeln!(tmp_file, "deposit,0,0,1.0").unwrap();
+        writeln!(tmp_file, "dispute,0,0").unwrap();
+        writeln!(tmp_file, "resolve,0,0").unwrap();
+        writeln!(tmp_file, "error,0,0").unwrap();
+        tmp_file.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut tx_iter = TransactionIterator::new(Input::from(tmp_file));
+        assert_eq!(at_record(Tx::deposit(0, 0).amount(1.0).build(), 1, 22), tx_iter.next().unwrap());
+        assert_eq!(at_record(Tx::dispute(0, 0).build(), 2, 38), tx_iter.next().unwrap());
+        assert_eq!(at_record(Tx::resolve(0, 0).build(), 3, 50), tx_iter.next().unwrap());
+
+        // Errors are handled gracefully.
+        assert!(tx_iter.next().is_none());
+        assert!(tx_iter.next().is_none());
+        assert_eq!(tx_iter.invalid(), 1);
+    }
+
+    #[test]
+    fn test_tx_iterator_skips_invalid() {
+        let mut tmp_file = tempfile().unwrap();
+        writeln!(tmp_file, "type,client,tx,amount").unwrap();
+        writeln!(tmp_file, "deposit;0;0;1.0").unwrap();
+        writeln!(tmp_file, "deposit,0,1,1.0").unwrap();
+        tmp_file.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut tx_iter = TransactionIterator::new(Input::from(tmp_file));
+        assert_eq!(at_record(Tx::deposit(0, 1).amount(1.0).build(), 2, 38), tx_iter.next().unwrap());
+        assert!(tx_iter.next().is_none());
+        assert_eq!(tx_iter.invalid(), 1);
+
+        // A line that is not valid UTF-8 does not end the input.
+        let input = Input::from(&b"type,client,tx,amount\ndeposit,0,\xff,1.0\ndeposit,0,1,1.0\n"[..]);
+        let mut tx_iter = TransactionIterator::new(input);
+        assert_eq!(tx_iter.next().unwrap().tx_id(), 1);
+        assert_eq!(tx_iter.invalid(), 1);
+    }
+
+    #[test]
+    fn test_tx_amount_scale() {
+        let schema = Schema::default();
+        assert!(Transaction::parse("deposit,0,0,1.0001", &schema).is_ok());
+        assert!(Transaction::parse("deposit,0,0,1.50000", &schema).is_ok());
+        assert!(matches!(Transaction::parse("deposit,0,0,1.00001", &schema),
+                         Err(Error::InvalidAmountScale { decimals: 5, scale: 4 })));
+        assert!(matches!(Transaction::parse_with_scale("deposit,0,0,1.5", &schema, 0),
+                         Err(Error::InvalidAmountScale { decimals: 1, scale: 0 })));
+        assert!(Transaction::parse_with_scale("dispute,0,0,", &schema, 0).is_ok());
+        assert!(matches!(Transaction::parse("withdrawal,0,0,-1.0", &schema), Err(Error::NegativeAmount)));
+        assert!(matches!(Transaction::parse("deposit,0,0,inf", &schema), Err(Error::InvalidField("amount"))));
+        assert!(matches!(Transaction::parse("deposit,0,0,", &schema), Err(Error::MissingAmount(Type::Deposit))));
+        assert!(matches!(Transaction::parse("withdrawal,0,0", &schema), Err(Error::MissingAmount(Type::Withdrawal))));
+
+        let input = Input::from(&b"type,client,tx,amount\ndeposit,0,0,1.25\ndeposit,0,1,1.2\n"[..]);
+        let mut tx_iter = TransactionIterator::new_with_scale(input, 1);
+        assert_eq!(tx_iter.next().unwrap().tx_id(), 1);
+        assert_eq!(tx_iter.invalid(), 1);
+    }
+
+    #[test]
+    fn test_tx_iterator_invalid_rate() {
+        let mut tmp_file = tempfile().unwrap();
+        writeln!(tmp_file, "type,client,tx,amount").unwrap();
+        writeln!(tmp_file, "deposit,0,0,1.0").unwrap();
+        writeln!(tmp_file, "deposit;0;1;1.0").unwrap();
+        writeln!(tmp_file, "deposit,0,2,1.0").unwrap();
+        writeln!(tmp_file, "deposit;0;3;1.0").unwrap();
+        tmp_file.seek(SeekFrom::Start(0)).unwrap();
+        let mut tx_iter = TransactionIterator::new(Input::from(tmp_file.try_clone().unwrap()));
+        assert!(matches!(tx_iter.check_invalid_rate(&InvalidRateGuard { sample: 2, max_rate: 40.0 }),
+                         Err(Error::TooManyInvalidRecords { invalid: 1, records: 2 })));
+
+        // The transactions read ahead are still handed out.
+        tmp_file.seek(SeekFrom::Start(0)).unwrap();
+        let mut tx_iter = TransactionIterator::new(Input::from(tmp_file));
+        tx_iter.check_invalid_rate(&InvalidRateGuard { sample: 3, max_rate: 40.0 }).unwrap();
+        assert_eq!(tx_iter.by_ref().map(|tx| tx.tx_id()).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(tx_iter.invalid(), 2);
+    }
+
+    #[test]
+    fn test_channel_source() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let producer = std::thread::spawn(move || {
+            for tx_id in 0..3 {
+                sender.send(Tx::deposit(tx_id as u16 % 2, tx_id).amount(1.0).build()).unwrap();
+            }
+            sender.send(Tx::withdrawal(0, 3).amount(0.5).build()).unwrap();
+            sender.send(Tx::withdrawal(1, 4).amount(5.0).build()).unwrap();
+        });
+        let source = ChannelSource::new(receiver);
+        assert!(!source.has_tenant());
+        let config = Config::default();
+        let run_report = drill_source(source, &config).unwrap();
+        producer.join().unwrap();
+        assert_eq!(run_report.accounts, 2);
+        assert_eq!(run_report.volumes.deposits.count, 3);
+        assert_eq!(run_report.volumes.withdrawals.amount, from_f64(0.5));
+        assert_eq!((run_report.records, run_report.invalid, run_report.rejected), (5, 0, 1));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut recorded = Tx::dispute(1, 1).build();
+        recorded.set_record(Some(7));
+        sender.send(Tx::dispute(1, 0).build()).unwrap();
+        sender.send(recorded).unwrap();
+        drop(sender);
+        let records: Vec<Option<u64>> = ChannelSource::new_with_tenant(receiver).map(|tx| tx.record()).collect();
+        assert_eq!(records, vec![Some(1), Some(7)]);
+    }
+
+    fn source(txs: Vec<Transaction>) -> ChannelSource {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for tx in txs {
+            sender.send(tx).unwrap();
+        }
+        ChannelSource::new(receiver)
+    }
+
+    // Deposits numbered by client, the clients being interleaved.
+    fn sequenced_input(clients: u16, per_client: u64, with_tenant: bool) -> String {
+        let mut input = String::from(if with_tenant { "type,client,tx,amount,sequence,tenant\n" }
+                                     else { "type,client,tx,amount,sequence\n" });
+        for sequence in 0..per_client {
+            for client_id in 0..clients {
+                let tx_id = sequence * clients as u64 + client_id as u64;
+                input.push_str(&format!("deposit,{},{},1.0,{}", client_id, tx_id, sequence));
+                if with_tenant {
+                    input.push_str(if client_id % 2 == 0 { ",acme" } else { ",globex" });
+                }
+                input.push('\n');
+            }
+        }
+        input
+    }
+
+    #[test]
+    fn test_per_client_fifo() {
+        // The account admins reject the transactions of a client applied out of order, so every
+        // way of scheduling them must end up with no rejection.
+        let configs = [
+            Config { multi_threaded_runtime: false, ..Config::default() },
+            Config::default(),
+            Config { tenant_workers: Some(2), ..Config::default() },
+            Config { tenant_buffer: 1, tx_delay: Some(std::time::Duration::from_micros(10)), ..Config::default() },
+        ];
+        for config in configs {
+            for with_tenant in [false, true] {
+                let input = sequenced_input(20, 50, with_tenant);
+                let run_report = drill(Input::from(input.as_bytes()), &config).unwrap();
+                assert_eq!(run_report.accounts, 20);
+                assert_eq!(run_report.volumes.deposits.count, 1000);
+                assert_eq!(run_report.rejected, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequence_window() {
+        let input = "type,client,tx,amount,sequence\n\
+                     withdrawal,1,2,1.0,2\n\
+                     deposit,1,1,2.0,1\n\
+                     deposit,2,3,1.0,\n";
+        let run = |sequence_window| {
+            let config = Config { sequence_window, ..Config::default() };
+            let run_report = drill(Input::from(input.as_bytes()), &config).unwrap();
+            (run_report.volumes.deposits.amount, run_report.volumes.withdrawals.amount, run_report.rejected)
+        };
+        // The withdrawal is rejected for the lack of funds, then the deposit for coming late.
+        assert_eq!(run(None), (from_f64(1.0), ZERO, 2));
+        assert_eq!(run(Some(1)), (from_f64(3.0), from_f64(1.0), 0));
+        // The transactions held back are applied once the input is over.
+        assert_eq!(run(Some(10)), (from_f64(3.0), from_f64(1.0), 0));
+    }
+
+    #[test]
+    fn test_replay_protection() {
+        let state = State {
+            accounts: vec![Account::new(1, from_f64(1.0), ZERO, Status::Active)],
+            history: vec![Tx::deposit(1, 1).amount(1.0).build()],
+            checkpoint: None,
+        };
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,2,1,2.0\n\
+                     deposit,2,2,2.0\n\
+                     withdrawal,2,2,1.0\n\
+                     dispute,2,2,\n";
+        // The replay protection on its own, without the tx ids being compared across clients.
+        let run = |replay| {
+            let config = Config { replay, reject_duplicates: false, ..Config::default() };
+            let run_report = resume(state.clone(), Input::from(input.as_bytes()), &config).unwrap();
+            (run_report.volumes.deposits.amount, run_report.rejected, run_report.duplicates)
+        };
+        // The account admins reject the tx ids still in the history of their client, the resumed
+        // deposit included, and disputes reuse the tx id they refer to.
+        assert_eq!(run(None), (from_f64(4.0), 2, 2));
+        let protection = |scope| Some(Protection { scope, window: Window::Unlimited });
+        assert_eq!(run(protection(Scope::Client)), (from_f64(4.0), 2, 2));
+        assert_eq!(run(protection(Scope::Global)), (from_f64(2.0), 3, 3));
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let input = "type,client,tx,amount,tenant\n\
+                     deposit,1,1,1.0,acme\n\
+                     deposit,2,1,2.0,acme\n\
+                     withdrawal,2,1,1.0,acme\n\
+                     deposit,2,2,3.0,acme\n\
+                     dispute,2,2,,acme\n\
+                     deposit,2,1,4.0,globex\n";
+        let run = |reject_duplicates| {
+            let config = Config { reject_duplicates, ..Config::default() };
+            drill(Input::from(input.as_bytes()), &config).unwrap()
+        };
+        // The tx ids are compared across the clients of a tenant, not across the tenants.
+        let run_report = run(true);
+        let available: Vec<_> = run_report.final_accounts.iter()
+            .map(|account| (account.tenant().map(String::from), account.client_id(), account.available()))
+            .collect();
+        assert_eq!(available, vec![(Some(String::from("acme")), 1, from_f64(1.0)),
+                                   (Some(String::from("acme")), 2, ZERO),
+                                   (Some(String::from("globex")), 2, from_f64(4.0))]);
+        assert_eq!((run_report.rejected, run_report.duplicates), (2, 2));
+
+        // Unless the run allows them, the withdrawal reusing the tx id of the deposit of the same
+        // client being rejected all the same.
+        let run_report = run(false);
+        assert_eq!((run_report.volumes.deposits.amount, run_report.rejected, run_report.duplicates),
+                   (from_f64(10.0), 1, 1));
+
+        // The engine compares the tx ids the same way.
+        let mut engine = Engine::new(&Config::default());
+        engine.apply(Tx::deposit(1, 1).amount(1.0).build()).unwrap();
+        assert!(matches!(engine.apply(Tx::deposit(2, 1).amount(2.0).build()), Err(ClientError::TxDuplicated)));
+        engine.apply(Tx::deposit(2, 1).amount(2.0).tenant("acme").build()).unwrap();
+    }
+
+    #[test]
+    fn test_backfill() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corrections.csv");
+        let mut disputed = Tx::deposit(1, 1).amount(1.0).build();
+        disputed.mark_disputed();
+        let state = State {
+            accounts: vec![Account::new(1, from_f64(2.0), from_f64(1.0), Status::Active)],
+            history: vec![disputed, Tx::deposit(1, 2).amount(2.0).build()],
+            checkpoint: None,
+        };
+        // The corrected input has the deposit the earlier run missed.
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,2,2.0\n\
+                     deposit,1,3,5.0\n\
+                     dispute,1,1,\n";
+        let config = Config { backfill_path: Some(path.clone()), ..Config::default() };
+        let run_report = resume(state, Input::from(input.as_bytes()), &config).unwrap();
+        assert_eq!((run_report.backfilled, run_report.rejected, run_report.volumes.deposits.amount), (2, 1, from_f64(5.0)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+                   "tenant,client,tx,type,amount,before,after\n,1,3,deposit,5.0000,3.0000,8.0000\n");
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead_letters.csv");
+        let state = State {
+            accounts: vec![Account::new(1, from_f64(1.0), ZERO, Status::Active),
+                           Account::new(2, from_f64(1.0), ZERO, Status::Locked), Account::new(3, from_f64(1.0), ZERO, Status::Closed)],
+            history: Vec::new(),
+            checkpoint: None,
+        };
+        let txs = (1..4).chain(1..4).enumerate().map(|(tx_id, client_id)| {
+            let mut tx = Tx::deposit(client_id, tx_id as u32).amount(2.0).build();
+            tx.set_record(Some(tx_id as u64));
+            tx
+        }).collect();
+        let config = Config { dead_letters_path: Some(path.clone()), ..Config::default() };
+        let run_report = resume_source(state, source(txs), &config).unwrap();
+        assert_eq!((run_report.dead_letters.count, run_report.dead_letters.amount), (4, from_f64(8.0)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+                   "type,client,tx,amount,reason,timestamp\n\
+                    deposit,2,1,2.0000,,\n\
+                    deposit,3,2,2.0000,,\n\
+                    deposit,2,4,2.0000,,\n\
+                    deposit,3,5,2.0000,,\n");
+    }
+
+    #[test]
+    fn test_drill_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let (audit_path, dead_letters_path) = (dir.path().join("audit.csv"), dir.path().join("dead_letters.csv"));
+        let config = Config { audit_path: Some(audit_path.clone()), dead_letters_path: Some(dead_letters_path.clone()),
+                              ..Config::default() };
+        let state = State { accounts: vec![Account::new(2, from_f64(1.0), ZERO, Status::Locked)], history: Vec::new(), checkpoint: None };
+        let mut sources = Sources::new(8);
+        let batch = Input::from(&b"type,client,tx,amount\ndeposit,1,1,1.0\ninvalid\nwithdrawal,1,2,5.0\n"[..]);
+        sources.add("batch", input_source(batch, &config).unwrap());
+        sources.add("live", source(vec![Tx::deposit(2, 3).amount(2.0).build()]));
+        let run_report = resume_source(state, sources, &config).unwrap();
+
+        assert_eq!((run_report.records, run_report.invalid), (4, 1));
+        let stats: Vec<(&str, usize, usize, u64)> = run_report.sources.iter()
+            .map(|source| (source.label.as_str(), source.records, source.invalid, source.dead_letters.count))
+            .collect();
+        assert_eq!(stats, vec![("batch", 3, 1, 0), ("live", 1, 0, 1)]);
+        // The rejected withdrawal is traced back to the third record of the batch.
+        let audit = std::fs::read_to_string(&audit_path).unwrap();
+        assert!(audit.lines().any(|line| line.starts_with(",1,2,withdrawal,") && line.ends_with(",3,,batch")));
+        assert_eq!(std::fs::read_to_string(&dead_letters_path).unwrap(),
+                   "type,client,tx,amount,reason,timestamp,source\ndeposit,2,3,2.0000,,,live\n");
+    }
+
+    #[test]
+    fn test_resume_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.state");
+        let config = Config { state_path: Some(path.clone()), ..Config::default() };
+        let txs = vec![Tx::deposit(1, 1).amount(3.0).build(),
+                       Tx::deposit(2, 2).amount(1.0).build()];
+        drill_source(source(txs), &config).unwrap();
+
+        // The deposit of the first run can be disputed, and the account without new transactions
+        // is still part of the run.
+        let state = crate::state::read_file(&path).unwrap();
+        let txs = vec![Tx::dispute(1, 1).build()];
+        let run_report = resume_source(state, source(txs), &config).unwrap();
+        assert_eq!((run_report.accounts, run_report.volumes.disputes_opened.amount), (2, from_f64(3.0)));
+        let state = crate::state::read_file(&path).unwrap();
+        assert_eq!(state.accounts.iter().map(|account| account.held()).sum::<Amount>(), from_f64(3.0));
+        assert!(state.history.iter().any(|tx| tx.tx_id() == 1 && tx.is_disputed()));
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.state");
+        let config = Config { state_path: Some(path.clone()), ..Config::default() };
+        let mut csv = String::from("type,client,tx,amount\ndeposit,1,1,3.0\ndeposit,1,2,1.0\n");
+        drill(Input::from(csv.as_bytes()), &config).unwrap();
+        let state = crate::state::read_file(&path).unwrap();
+        assert_eq!(state.checkpoint, Some(Checkpoint { offset: csv.len() as u64, records: 2 }));
+
+        // The input grew since, only the new records are read, and they are numbered after the
+        // earlier ones.
+        csv.push_str("withdrawal,1,3,2.0\n");
+        let input = Input::try_new_at(std::io::Cursor::new(csv.clone().into_bytes()), state.checkpoint.unwrap()).unwrap();
+        let run_report = resume(state, input, &config).unwrap();
+        assert_eq!(run_report.final_accounts[0].available(), from_f64(2.0));
+        let state = crate::state::read_file(&path).unwrap();
+        assert_eq!(state.checkpoint, Some(Checkpoint { offset: csv.len() as u64, records: 3 }));
+        assert_eq!(state.history.iter().find(|tx| tx.tx_id() == 3).unwrap().record(), Some(3));
+        let withdrawal = TransactionIterator::new(Input::from(csv.as_bytes())).nth(2).unwrap();
+        assert_eq!(withdrawal.offset(), Some(54));
+    }
+
+    #[test]
+    fn test_drill_reference() {
+        let reference = Reference::parse(&b"client,tier\n1,gold\n"[..], None).unwrap();
+        let mut config = Config { reference: Some(Arc::new(reference)), ..Config::default() };
+        config.rules.policy = Some(Arc::new(Policy::parse("waived: reject type=fee attr.tier=gold").unwrap()));
+        let csv = "type,client,tx,amount\ndeposit,1,1,2.0\nfee,1,2,0.5\ndeposit,2,3,2.0\nfee,2,4,0.5\n";
+        let tx = TransactionIterator::new(Input::from(csv.as_bytes())).next().unwrap();
+        assert!(tx.attributes().is_none());
+        let mut record_iter = input_source(Input::from(csv.as_bytes()), &config).unwrap();
+        assert_eq!(record_iter.next().unwrap().attributes().unwrap().get("tier"), Some("gold"));
+
+        let mut accounts = drill(Input::from(csv.as_bytes()), &config).unwrap().final_accounts;
+        accounts.sort_by_key(Account::client_id);
+        assert_eq!(accounts.iter().map(Account::available).collect::<Vec<_>>(), vec![from_f64(2.0), from_f64(1.5)]);
+    }
+
+    #[test]
+    fn test_drill_strict() {
+        let collector = Collector::new();
+        let config = Config { mode: Mode::Strict, account_sinks: vec![collector.factory()], ..Config::default() };
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\ndeposit,2,3,1.0\n";
+        assert!(matches!(drill(Input::from(csv.as_bytes()), &config),
+                         Err(Error::Strict(Violation::Rejected { record: Some(2), tx_id: 2, reason: "insufficient_funds", .. }))));
+        assert!(collector.take().is_empty());
+
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,two,1.0\n";
+        assert!(matches!(drill(Input::from(csv.as_bytes()), &config), Err(Error::Strict(Violation::InvalidRecord))));
+
+        // The lenient runs skip both.
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\ndeposit,1,two,1.0\n";
+        let run_report = drill(Input::from(csv.as_bytes()), &Config::default()).unwrap();
+        assert_eq!((run_report.invalid, run_report.rejected), (1, 1));
+    }
+
+    #[test]
+    fn test_drill_aborted_by_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.state");
+        let config = Config {
+            state_path: Some(path.clone()),
+            limits: Limits { wall_clock: None, memory: Some(1) },
+            invalid_rate_guard: Some(InvalidRateGuard { sample: 2, max_rate: 100.0 }),
+            ..Config::default()
+        };
+        let csv = "type,client,tx,amount\ndeposit,1,1,3.0\ndeposit,1,2,1.0\n";
+        let run_report = drill(Input::from(csv.as_bytes()), &config).unwrap();
+        assert!(matches!(run_report.aborted, Some(Exceeded::Memory { limit: 1, .. })));
+        assert!(run_report.final_accounts.is_empty());
+        // The records read ahead were never applied, so the checkpoint is right before them.
+        let state = crate::state::read_file(&path).unwrap();
+        assert_eq!(state.checkpoint, Some(Checkpoint { offset: 22, records: 0 }));
+    }
+
+    #[test]
+    fn test_drill_accounts() {
+        let collector = Collector::new();
+        let config = Config { account_sinks: vec![collector.factory()], ..Config::default() };
+        let input = Input::from(&b"type,client,tx,amount,tenant\ndeposit,1,1,1.5,acme\nwithdrawal,1,2,0.5,acme\n"[..]);
+        let run_report = drill(input, &config).unwrap();
+        assert!(run_report.with_tenant);
+        assert_eq!(run_report.final_accounts.len(), 1);
+        let account = &run_report.final_accounts[0];
+        assert_eq!((account.tenant(), account.client_id(), account.available()), (Some("acme"), 1, from_f64(1.0)));
+        // The sink got the same accounts.
+        assert_eq!(collector.take(), run_report.final_accounts);
+    }
+
+    struct Observer(Arc<Mutex<Vec<Entry>>>);
+
+    impl audit::Sink for Observer {
+        fn record(&mut self, entry: &Entry) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(entry.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_drill_observers() {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let observed = entries.clone();
+        let observer: audit::SinkFactory = Arc::new(move || Ok(Box::new(Observer(observed.clone()))));
+        let config = Config { observers: vec![observer], ..Config::default() };
+        let input = Input::from(&b"type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\nchargeback,1,1,\n"[..]);
+        drill(input, &config).unwrap();
+
+        let entries = entries.lock().unwrap();
+        let deltas: Vec<(Type, Amount, Amount, bool)> = entries.iter()
+            .map(|entry| (entry.ttype.clone(), entry.delta().available, entry.delta().held, entry.transitioned()))
+            .collect();
+        assert_eq!(deltas, vec![(Type::Deposit, from_f64(2.0), ZERO, false), (Type::Dispute, from_f64(-2.0), from_f64(2.0), false),
+                                (Type::Chargeback, ZERO, from_f64(-2.0), true)]);
+        assert_eq!((entries[2].previous_status, entries[2].status), (Status::Active, Status::Locked));
+        assert_eq!(entries[2].after.total, ZERO);
+    }
+
+    #[test]
+    fn test_drill_stage_timings() {
+        let input = || Input::from(&b"type,client,tx,amount
+deposit,1,1,2.0
+deposit,2,2,1.0
+withdrawal,1,3,1.0
+"[..]);
+        assert_eq!(drill(input(), &Config::default()).unwrap().timings, None);
+
+        let config = Config { stage_timings: true, ..Config::default() };
+        let timings = drill(input(), &config).unwrap().timings.unwrap();
+        assert_eq!(timings.queue_wait.count(), 3);
+        assert!(timings.read > Duration::ZERO && timings.apply > Duration::ZERO);
+        let mut out = Vec::new();
+        let run_report = RunReport { timings: Some(timings), ..Default::default() };
+        run_report.write_summary(&mut out).unwrap();
+        let summary = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = summary.lines().skip(11).map(|line| line.split(',').next().unwrap()).collect();
+        assert_eq!(rows, vec!["stage:read", "stage:dispatch", "stage:apply", "queue_wait:p50", "queue_wait:p90",
+                              "queue_wait:p99", "queue_wait:max"]);
+    }
+
+    // Runs the same workload under many schedules, varying the runtime, the worker slots and the
+    // buffers, so that the order the account admins are scheduled and shut down in changes. Every
+    // client gets locked halfway by a chargeback, the later deposits being rejected, so a lost or
+    // misrouted transaction shows up in the volumes.
+    #[test]
+    fn test_drill_schedules() {
+        let (clients, deposits) = (8u16, 6u32);
+        for schedule in 0..24 {
+            let config = Config {
+                multi_threaded_runtime: schedule % 2 == 0,
+                tenant_workers: [None, Some(1), Some(3)][schedule % 3],
+                tenant_buffer: 1 + schedule % 4,
+                ..Config::default()
+            };
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let producer = std::thread::spawn(move || {
+                for round in 0..deposits {
+                    for client_id in 0..clients {
+                        let tx_id = round * clients as u32 + client_id as u32;
+                        sender.send(Tx::deposit(client_id, tx_id).amount(1.0).build()).unwrap();
+                        if round == deposits / 2 {
+                            sender.send(Tx::dispute(client_id, client_id as u32).build()).unwrap();
+                            sender.send(Tx::chargeback(client_id, client_id as u32).build()).unwrap();
+                        }
+                    }
+                }
+            });
+            let run_report = drill_source(ChannelSource::new(receiver), &config).unwrap();
+            producer.join().unwrap();
+            assert_eq!(run_report.accounts, clients as usize);
+            // The deposits up to the chargeback round are applied, the chargeback included.
+            assert_eq!(run_report.volumes.deposits.count, clients as u64 * (deposits as u64 / 2 + 1));
+            assert_eq!(run_report.volumes.chargebacks.count, clients as u64);
+            assert_eq!(run_report.volumes.chargebacks.amount, from_f64(clients as f64));
+        }
+    }
+
+    #[test]
+    fn test_account_admin_panic() {
+        // The total of the restored account of client 1 is out of range, e.g. out of a corrupt
+        // state file, which panics its account admin at its first transaction.
+        let max = Amount::from_minor(i64::MAX);
+        let state = State { accounts: vec![Account::new(1, max, max, Status::Active)], ..State::default() };
+        let txs = vec![Tx::deposit(2, 2).amount(1.0).build(),
+                       Tx::deposit(1, 3).amount(1.0).build(),
+                       Tx::deposit(2, 4).amount(2.0).build()];
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.state");
+        let config = Config { state_path: Some(path.clone()), ..Config::default() };
+        let run_report = resume_source(state, source(txs), &config).unwrap();
+        assert_eq!(run_report.failed, vec![Failure { tenant: None, client_id: Some(1) }]);
+        assert_eq!(run_report.accounts, 1);
+        let mut summary = Vec::new();
+        run_report.write_summary(&mut summary).unwrap();
+        assert!(String::from_utf8(summary).unwrap().ends_with("dead_letter,0,0.0000\nfailed,1,\n"));
+
+        let state = crate::state::read_file(&path).unwrap();
+        assert_eq!(state.accounts.len(), 1);
+        assert_eq!((state.accounts[0].client_id(), state.accounts[0].available()), (2, from_f64(3.0)));
+    }
+
+    #[test]
+    fn test_tenant_dispatcher_quota() {
+        let config = Config { tenant_quota: Some(2), tenant_workers: Some(1), ..Config::default() };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut dispatcher = TenantDispatcher::new(Some(String::from("acme")), &config, None, None, None);
+            dispatcher.dispatch(Tx::deposit(1, 1).amount(1.0).build()).await;
+            dispatcher.dispatch(Tx::deposit(2, 2).amount(2.0).build()).await;
+            dispatcher.dispatch(Tx::deposit(1, 3).amount(3.0).build()).await;
+            assert_eq!(dispatcher.accepted, 2);
+            assert_eq!(dispatcher.dropped, 1);
+            let (accounts, _, _, _) = dispatcher.finish().await;
+            assert_eq!(accounts.len(), 2);
+            assert_eq!(accounts[0].available(), from_f64(1.0));
+            assert_eq!(accounts[0].tenant(), Some("acme"));
+            assert_eq!(accounts[1].available(), from_f64(2.0));
+        });
+    }
+
+    #[test]
+    fn test_tenant_dispatcher_unknown_account() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let dispatch = |unknown_account: UnknownAccountPolicy| rt.block_on(async {
+            let config = Config { unknown_account, ..Config::default() };
+            let mut dispatcher = TenantDispatcher::new(None, &config, None, None, None);
+            // The dispute of client 1 comes ahead of its deposit, the one of client 2 never has one.
+            dispatcher.dispatch(Tx::dispute(1, 1).build()).await;
+            dispatcher.dispatch(Tx::dispute(2, 2).build()).await;
+            dispatcher.dispatch(Tx::deposit(1, 1).amount(1.0).build()).await;
+            dispatcher.dispatch(Tx::deposit(3, 3).amount(1.0).build()).await;
+            let (mut accounts, _, orphaned, _) = dispatcher.finish().await;
+            accounts.sort_by_key(Account::client_id);
+            let balances: Vec<(u16, f64, f64)> = accounts.iter()
+                .map(|account| (account.client_id(), to_f64(account.available()), to_f64(account.held())))
+                .collect();
+            (balances, orphaned)
+        });
+
+        assert_eq!(dispatch(UnknownAccountPolicy::Open),
+                   (vec![(1, 1.0, 0.0), (2, 0.0, 0.0), (3, 1.0, 0.0)], 0));
+        assert_eq!(dispatch(UnknownAccountPolicy::Reject), (vec![(1, 1.0, 0.0), (3, 1.0, 0.0)], 2));
+        assert_eq!(dispatch(UnknownAccountPolicy::Buffer { window: 2 }),
+                   (vec![(1, 0.0, 1.0), (3, 1.0, 0.0)], 1));
+        // The dispute of client 1 expires before its deposit comes.
+        assert_eq!(dispatch(UnknownAccountPolicy::Buffer { window: 1 }),
+                   (vec![(1, 1.0, 0.0), (3, 1.0, 0.0)], 2));
     }
 }
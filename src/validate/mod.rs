@@ -0,0 +1,103 @@
+// Pre-flight checks of an input, e.g. of a vendor file vetted before it is run against real state.
+// Every record is parsed as a run parses it, and its amount is checked against its type, but no
+// transaction is applied, so that neither a state file nor an output is touched.
+
+use std::io::{self, Write};
+
+use crate::alias::Aliases;
+use crate::amount::ZERO;
+use crate::input::Input;
+use crate::transaction::Transaction;
+
+// A record a run would skip, or whose transaction would be meaningless, on the line it is on,
+// the header being line 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Validation {
+    pub records: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl Validation {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    // Writes one line per problem, followed by the overall verdict.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for problem in &self.problems {
+            writeln!(writer, "Line {}: {}.", problem.line, problem.reason)?;
+        }
+        if self.passed() {
+            writeln!(writer, "All {} records are valid.", self.records)
+        } else {
+            writeln!(writer, "{} of {} records are invalid.", self.problems.len(), self.records)
+        }
+    }
+}
+
+// Checks every record of the input, with amounts of at most `scale` decimal places and the client
+// ids being the external identifiers of `aliases`, if any.
+pub fn validate(mut input: Input, scale: usize, aliases: Option<&Aliases>) -> Validation {
+    let schema = input.schema().clone();
+    let mut validation = Validation::default();
+    while let Some(line) = input.next() {
+        validation.records += 1;
+        let reason = match Transaction::parse_with_aliases(&line, &schema, scale, aliases) {
+            Ok(tx) => check(&tx),
+            Err(err) => Some(err.describe())
+        };
+        if let Some(reason) = reason {
+            validation.problems.push(Problem { line: input.line(), reason });
+        }
+    }
+    validation
+}
+
+// Tells what is wrong with the amount of a parsed transaction, if anything. A missing amount is
+// already a parse error.
+fn check(tx: &Transaction) -> Option<String> {
+    match tx.amount() {
+        Some(amount) if amount <= ZERO => Some(String::from("the amount is not positive")),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let input = Input::from(&b"type,client,tx,amount\n\
+                                   deposit,1,1,1.0\n\
+                                   deposit,1,2,\n\
+                                   withdrawal,1,3,-1.0\n\
+                                   dispute,1,1,\n\
+                                   deposit,70000,4,1.0\n\
+                                   transfer,1,5,1.0\n\
+                                   deposit,1,6,1.00001\n\
+                                   fee,1,7,0\n"[..]);
+        let validation = validate(input, 4, None);
+        assert!(!validation.passed());
+        let mut out = Vec::new();
+        validation.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "Line 3: the deposit has no amount.\n\
+                    Line 4: the amount is negative.\n\
+                    Line 6: the `client` column is invalid.\n\
+                    Line 7: the `type` column is invalid.\n\
+                    Line 8: the amount has 5 decimal places, while at most 4 are allowed.\n\
+                    Line 9: the amount is not positive.\n\
+                    6 of 8 records are invalid.\n");
+
+        let validation = validate(Input::from(&b"type,client,tx,amount\ndeposit,1,1,1.0\n"[..]), 4, None);
+        assert!(validation.passed());
+        assert_eq!(validation.records, 1);
+    }
+}
@@ -0,0 +1,135 @@
+// Write-ahead log of the batches the server accepts, so that the transactions it acknowledged
+// survive a crash. Every batch is appended and synced to disk before the server answers for it,
+// and the batches are applied again when the server is restarted on the same log, on top of the
+// state the log was cleared at. A batch is a `<endpoint> <length>` line, followed by the body of
+// the request and a line feed.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // Number of the batch of the log that could not be read, starting at 1.
+    InvalidBatch(usize),
+}
+
+impl Error {
+    pub fn describe(&self, path: &Path) -> String {
+        match self {
+            Error::Io(_) => format!("Could not read the write-ahead log {}.", path.display()),
+            Error::InvalidBatch(batch) => format!("Batch {} of the write-ahead log {} is invalid.", batch, path.display())
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    // The endpoint the batch was posted to, e.g. `transactions`.
+    pub endpoint: String,
+    pub body: String,
+}
+
+pub struct Wal {
+    file: File,
+}
+
+impl Wal {
+    // Opens the log, creating it if needed, and returns the batches it records, in order. A batch
+    // whose append was cut short by a crash was never acknowledged, so it is dropped from the log.
+    pub fn open(path: &Path) -> Result<(Wal, Vec<Batch>)> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+            .map_err(Error::Io)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).map_err(Error::Io)?;
+
+        let (mut batches, mut offset) = (Vec::new(), 0);
+        while let Some((endpoint, body, end)) = frame(&content, offset, batches.len() + 1)? {
+            let body = String::from_utf8(body.to_vec()).map_err(|_| Error::InvalidBatch(batches.len() + 1))?;
+            batches.push(Batch { endpoint, body });
+            offset = end;
+        }
+        if offset < content.len() {
+            file.set_len(offset as u64).map_err(Error::Io)?;
+        }
+        file.seek(SeekFrom::Start(offset as u64)).map_err(Error::Io)?;
+        Ok((Wal { file }, batches))
+    }
+
+    // Appends the batch, returning once it is on disk.
+    pub fn append(&mut self, endpoint: &str, body: &str) -> io::Result<()> {
+        let mut frame = format!("{} {}\n", endpoint, body.len()).into_bytes();
+        frame.extend(body.as_bytes());
+        frame.push(b'\n');
+        self.file.write_all(&frame)?;
+        self.file.sync_data()
+    }
+
+    // Drops every batch, once they are all part of a saved state.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_data()
+    }
+}
+
+// The endpoint and the body of the batch at the offset, and the offset of the next one, or `None`
+// at the end of the log or of its complete batches.
+fn frame(content: &[u8], offset: usize, batch: usize) -> Result<Option<(String, &[u8], usize)>> {
+    let rest = &content[offset..];
+    let header_end = match rest.iter().position(|byte| *byte == b'\n') {
+        Some(header_end) => header_end,
+        None => return Ok(None)
+    };
+    let header = std::str::from_utf8(&rest[..header_end]).map_err(|_| Error::InvalidBatch(batch))?;
+    let (endpoint, length) = header.split_once(' ')
+        .and_then(|(endpoint, length)| Some((endpoint, length.parse::<usize>().ok()?)))
+        .ok_or(Error::InvalidBatch(batch))?;
+    let start = header_end + 1;
+    match rest.get(start + length) {
+        Some(b'\n') => Ok(Some((String::from(endpoint), &rest[start..start + length], offset + start + length + 1))),
+        Some(_) => Err(Error::InvalidBatch(batch)),
+        None => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_wal_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.wal");
+        let (mut wal, batches) = Wal::open(&path).unwrap();
+        assert!(batches.is_empty());
+        wal.append("transactions", "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        wal.append("adjustments", "type,client,tx,amount\ncredit,1,2,0.5\n").unwrap();
+        drop(wal);
+
+        // A crash in the middle of an append leaves an incomplete batch behind.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"transactions 40\ntype,client").unwrap();
+        drop(file);
+        let (mut wal, batches) = Wal::open(&path).unwrap();
+        assert_eq!(batches, vec![
+            Batch { endpoint: String::from("transactions"), body: String::from("type,client,tx,amount\ndeposit,1,1,1.0\n") },
+            Batch { endpoint: String::from("adjustments"), body: String::from("type,client,tx,amount\ncredit,1,2,0.5\n") },
+        ]);
+        wal.append("transactions", "type,client,tx,amount\ndeposit,2,3,1.0\n").unwrap();
+        drop(wal);
+        assert_eq!(Wal::open(&path).unwrap().1.len(), 3);
+
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        wal.clear().unwrap();
+        drop(wal);
+        assert!(Wal::open(&path).unwrap().1.is_empty());
+
+        fs::write(&path, "transactions x\n\n").unwrap();
+        assert!(matches!(Wal::open(&path), Err(Error::InvalidBatch(1))));
+    }
+}
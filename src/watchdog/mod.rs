@@ -0,0 +1,134 @@
+// Limits of the resources a run may use, so that a pathological input can not hold the batch
+// window indefinitely. The driver checks them between the records it reads: once a limit is
+// exceeded, no further record is read, and the run ends, writing its outputs and the checkpoint
+// of its state file, as if the input stopped there.
+
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+// Reading the memory of the process costs a system call, so it is only checked that often.
+const MEMORY_CHECK_EVERY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    // Wall clock time the run may take, as of the clock of the run.
+    pub wall_clock: Option<Duration>,
+    // Resident memory of the process, in bytes.
+    pub memory: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_set(&self) -> bool {
+        self.wall_clock.is_some() || self.memory.is_some()
+    }
+}
+
+// The limit a run was aborted for, along with the usage that exceeded it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exceeded {
+    WallClock { limit: Duration, elapsed: Duration },
+    Memory { limit: u64, resident: u64 },
+}
+
+impl Exceeded {
+    // Tells which limit was exceeded, for the operators.
+    pub fn describe(&self) -> String {
+        match self {
+            Exceeded::WallClock { limit, elapsed } => {
+                format!("the run took {}s, while at most {}s are allowed", elapsed.as_secs(), limit.as_secs())
+            }
+            Exceeded::Memory { limit, resident } => {
+                format!("the run uses {} MiB of memory, while at most {} MiB are allowed", resident >> 20, limit >> 20)
+            }
+        }
+    }
+}
+
+// Tracks the usage of a run against its limits, from the moment it is created.
+pub struct Watchdog {
+    limits: Limits,
+    clock: Arc<dyn Clock>,
+    started: Duration,
+    checks: usize,
+    memory: fn() -> Option<u64>,
+}
+
+impl Watchdog {
+    pub fn new(limits: Limits, clock: Arc<dyn Clock>) -> Self {
+        let started = clock.elapsed();
+        Watchdog { limits, clock, started, checks: 0, memory: resident_memory }
+    }
+
+    // Returns the limit exceeded, if any. The memory is checked on the first call, then on every
+    // `MEMORY_CHECK_EVERY` calls.
+    pub fn check(&mut self) -> Option<Exceeded> {
+        if let Some(limit) = self.limits.wall_clock {
+            let elapsed = self.clock.elapsed().saturating_sub(self.started);
+            if elapsed > limit {
+                return Some(Exceeded::WallClock { limit, elapsed });
+            }
+        }
+        let checks = self.checks;
+        self.checks += 1;
+        match self.limits.memory {
+            Some(limit) if checks % MEMORY_CHECK_EVERY == 0 => match (self.memory)() {
+                Some(resident) if resident > limit => Some(Exceeded::Memory { limit, resident }),
+                _ => None
+            },
+            _ => None
+        }
+    }
+}
+
+// Resident memory of the process, as reported by the `VmRSS` line of `/proc/self/status`. It is
+// unknown on the platforms without procfs, where the memory limit is never exceeded.
+pub fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kibibytes = line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse::<u64>().ok()?;
+    Some(kibibytes << 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_watchdog_wall_clock() {
+        let clock = Arc::new(MockClock::new(0));
+        clock.advance(Duration::from_secs(100));
+        let mut watchdog = Watchdog::new(Limits { wall_clock: Some(Duration::from_secs(60)), memory: None }, clock.clone());
+        assert_eq!(watchdog.check(), None);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(watchdog.check(), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(watchdog.check(),
+                   Some(Exceeded::WallClock { limit: Duration::from_secs(60), elapsed: Duration::from_secs(61) }));
+    }
+
+    #[test]
+    fn test_watchdog_memory() {
+        let limits = Limits { wall_clock: None, memory: Some(1 << 20) };
+        let mut watchdog = Watchdog::new(limits, Arc::new(MockClock::new(0)));
+        watchdog.memory = || Some(2 << 20);
+        assert_eq!(watchdog.check(), Some(Exceeded::Memory { limit: 1 << 20, resident: 2 << 20 }));
+        // The memory is read again once enough checks went by.
+        for _ in 1..MEMORY_CHECK_EVERY {
+            assert_eq!(watchdog.check(), None);
+        }
+        assert!(watchdog.check().is_some());
+        assert_eq!(Exceeded::Memory { limit: 1 << 20, resident: 2 << 20 }.describe(),
+                   "the run uses 2 MiB of memory, while at most 1 MiB are allowed");
+    }
+
+    #[test]
+    fn test_resident_memory() {
+        if cfg!(target_os = "linux") {
+            assert!(resident_memory().unwrap() > 0);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+// End to end coverage of the fixed-point amounts, from the parsing of the input to the outputs.
+
+use transaction_processor::account::AccountAdmin;
+use transaction_processor::amount;
+use transaction_processor::config::Config;
+use transaction_processor::input::Schema;
+use transaction_processor::report::{write_accounts, RunReport};
+use transaction_processor::transaction::Transaction;
+
+#[test]
+fn test_fixed_point_exact_arithmetic() {
+    let (_, receiver) = tokio::sync::mpsc::channel(1);
+    let mut account_admin = AccountAdmin::new(1, receiver);
+    for (tx_id, line) in ["deposit,1,1,0.1", "deposit,1,2,0.2", "withdrawal,1,3,0.3"].iter().enumerate() {
+        let tx = Transaction::parse(line, &Schema::default()).unwrap();
+        assert_eq!(tx.tx_id(), tx_id as u32 + 1);
+        account_admin.apply(tx).unwrap();
+    }
+    assert_eq!(account_admin.account().available(), amount::ZERO);
+
+    let accounts = [account_admin.account().clone()];
+    let mut out = Vec::new();
+    write_accounts(&mut out, &accounts, &Config::default(), false).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "client,available,held,total,locked,status\n1,0.0000,0.0000,0.0000,false,active\n");
+    assert_eq!(RunReport::new(&accounts).volumes.deposits.amount, amount::from_f64(0.3));
+}
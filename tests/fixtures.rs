@@ -0,0 +1,64 @@
+// Runs the fixtures of `tests/fixtures` through every engine implementation, each of them being
+// expected to end with the accounts of the fixture.
+
+use std::fs;
+use std::path::Path;
+
+use transaction_processor::account::Account;
+use transaction_processor::config::Config;
+use transaction_processor::engine::Engine;
+use transaction_processor::input::Input;
+use transaction_processor::processor::ProcessorBuilder;
+use transaction_processor::report::write_accounts;
+use transaction_processor::transaction::{drill, TransactionIterator};
+
+// Runs the transactions of the input into the final accounts.
+type Implementation = fn(&[u8]) -> Vec<Account>;
+
+const IMPLEMENTATIONS: [(&str, Implementation); 3] = [
+    ("drill on the single threaded runtime", drill_single_threaded),
+    ("drill on the multi threaded runtime", drill_multi_threaded),
+    ("engine", engine),
+];
+
+fn drill_single_threaded(input: &[u8]) -> Vec<Account> {
+    let config = ProcessorBuilder::new().multi_threaded_runtime(false).build();
+    drill(Input::from(input), &config).unwrap().final_accounts
+}
+
+fn drill_multi_threaded(input: &[u8]) -> Vec<Account> {
+    let config = ProcessorBuilder::new().multi_threaded_runtime(true).build();
+    drill(Input::from(input), &config).unwrap().final_accounts
+}
+
+// The transactions are applied one at a time, the rejected ones being left out.
+fn engine(input: &[u8]) -> Vec<Account> {
+    let mut engine = Engine::new(&Config::default());
+    for tx in TransactionIterator::new(Input::from(input)) {
+        let _ = engine.apply(tx);
+    }
+    engine.accounts().cloned().collect()
+}
+
+#[test]
+fn test_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+    let mut fixtures: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+
+    for fixture in fixtures {
+        let input = fs::read(fixture.join("input.csv")).unwrap();
+        let expected = fs::read_to_string(fixture.join("expected.csv")).unwrap();
+        for (name, run) in IMPLEMENTATIONS.iter() {
+            let accounts = run(&input);
+            let with_tenant = accounts.iter().any(|account| account.tenant().is_some());
+            let mut out = Vec::new();
+            write_accounts(&mut out, &accounts, &Config::default(), with_tenant).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), expected, "{} with {}", fixture.display(), name);
+        }
+    }
+}
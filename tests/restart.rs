@@ -0,0 +1,67 @@
+// Kills the server in the middle of a stream of batches and restarts it on its write-ahead log,
+// the way a crash and a restart by a supervisor would, while the producer retries the batch it is
+// not sure was applied.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+fn start(dir: &Path) -> (Child, u16) {
+    let port = TcpListener::bind(("127.0.0.1", 0)).unwrap().local_addr().unwrap().port();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transaction-processor"))
+        .args(["serve", "--listen", &format!("127.0.0.1:{}", port), "--wal"]).arg(dir.join("server.wal"))
+        .arg("--save-state").arg(dir.join("server.state"))
+        .arg("--accounts-output").arg(dir.join("accounts.csv"))
+        .stdout(Stdio::null()).stderr(Stdio::null())
+        .spawn().unwrap();
+    for _ in 0..500 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return (child, port);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    panic!("The server never listened on port {}.", port);
+}
+
+// Posts the body, returning the status line of the response.
+fn post(port: u16, path: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", path, body.len(), body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn test_restart_applies_every_batch_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut server, port) = start(dir.path());
+    assert_eq!(post(port, "/transactions", "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n"),
+               "HTTP/1.1 202 Accepted");
+    assert_eq!(post(port, "/transactions", "type,client,tx,amount\nwithdrawal,1,3,3.0\ndispute,2,2,\n"),
+               "HTTP/1.1 202 Accepted");
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    let (mut server, port) = start(dir.path());
+    // The producer retries the last batch, which the log already holds.
+    assert_eq!(post(port, "/transactions", "type,client,tx,amount\nwithdrawal,1,3,3.0\ndispute,2,2,\n"),
+               "HTTP/1.1 202 Accepted");
+    assert_eq!(post(port, "/adjustments", "type,client,tx,amount\ncredit,1,4,1.0\n"), "HTTP/1.1 202 Accepted");
+    assert_eq!(post(port, "/shutdown", ""), "HTTP/1.1 200 OK");
+    assert!(server.wait().unwrap().success());
+
+    let accounts = fs::read_to_string(dir.path().join("accounts.csv")).unwrap();
+    let mut lines: Vec<&str> = accounts.lines().skip(1).collect();
+    lines.sort_unstable();
+    assert_eq!(lines, vec!["1,8.0000,0.0000,8.0000,false,active", "2,0.0000,5.0000,5.0000,false,active"]);
+    // The batches are part of the saved state, the next run resumes from.
+    assert_eq!(fs::metadata(dir.path().join("server.wal")).unwrap().len(), 0);
+    assert!(fs::read_to_string(dir.path().join("server.state")).unwrap().contains("tx,,1,3,withdrawal"));
+}
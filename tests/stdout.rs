@@ -0,0 +1,61 @@
+// Runs the binary, whose logs, about the records or the options, must leave stdout to the
+// accounts, e.g. for `transaction-processor input.csv > accounts.csv`.
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn test_stdout_holds_only_the_accounts() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.csv");
+    fs::write(&path, "type,client,tx,amount\n\
+                      deposit,1,1,5.0\n\
+                      deposit,1,x,1.0\n\
+                      deposit,1,1,5.0\n\
+                      withdrawal,2,2,1.0\n").unwrap();
+
+    let expected = [
+        (&[][..], "client,available,held,total,locked,status\n\
+                   1,5.0000,0.0000,5.0000,false,active\n\
+                   2,0.0000,0.0000,0.0000,false,active\n"),
+        (&["--spec"][..], "client,available,held,total,locked\n\
+                           1,5.0000,0.0000,5.0000,false\n\
+                           2,0.0000,0.0000,0.0000,false\n"),
+    ];
+    for (args, accounts) in expected.iter() {
+        let output = Command::new(env!("CARGO_BIN_EXE_transaction-processor"))
+            .arg(&path)
+            .args(args.iter())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        // The clients are printed in the order they appeared in.
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), *accounts);
+        // The invalid record and the duplicate are logged all the same.
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("WARN: Line 3:"), "{}", stderr);
+        assert!(stderr.contains("rejected as a duplicate"), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_process_is_the_default_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.csv");
+    fs::write(&path, "type,client,tx,amount\ndeposit,1,1,5.0\n").unwrap();
+
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_transaction-processor")).args(args).arg(&path)
+        .output().unwrap();
+    let (implicit, explicit) = (run(&["--spec"]), run(&["process", "--spec"]));
+    assert!(implicit.status.success() && explicit.status.success());
+    assert_eq!(implicit.stdout, explicit.stdout);
+    assert_eq!(String::from_utf8(implicit.stdout).unwrap(), "client,available,held,total,locked\n\
+                                                            1,5.0000,0.0000,5.0000,false\n");
+
+    // The invalid options are logged, leaving stdout empty.
+    for args in [&["--unknown"][..], &["--runtime", "unknown"], &["--tenant-quota", "0"]].iter() {
+        let output = run(args);
+        assert!(!output.status.success() && output.stdout.is_empty());
+        assert!(String::from_utf8(output.stderr).unwrap().contains("Invalid arguments."));
+    }
+}